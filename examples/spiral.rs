@@ -0,0 +1,101 @@
+// fracmd
+// Example plugin: a spiraling formula and a banded coloring
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Built with `cargo build --release --example spiral` into
+// target/release/examples/libspiral.so, then
+//
+//   fracmd --plugin target/release/examples/libspiral.so --fractal spiral --coloring bands
+//
+// spiral is z^2 + c turned a little every step, so each bulb winds round.
+// The turn is the plugin's own setting, reached through state.
+
+use fracmd::{
+    plugin::{Color, Host, PluginColoring, PluginFractal, VERSION},
+    PixelData,
+};
+use num::complex::Complex;
+use std::ffi::c_void;
+
+struct Spiral {
+    turn: Complex<f32>,
+}
+
+static SPIRAL: Spiral = Spiral {
+    turn: Complex::new(0.9950042, 0.09983342),
+};
+
+fn spiral(state: *mut c_void) -> &'static Spiral {
+    unsafe { &*(state as *const Spiral) }
+}
+
+extern "C" fn step(state: *mut c_void, z: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+    z * z * spiral(state).turn + c
+}
+
+extern "C" fn deriv(
+    state: *mut c_void,
+    z: Complex<f32>,
+    dz: Complex<f32>,
+    _c: Complex<f32>,
+) -> Complex<f32> {
+    2.0 * z * dz * spiral(state).turn + 1.0
+}
+
+extern "C" fn zderiv(state: *mut c_void, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+    2.0 * z * dz * spiral(state).turn
+}
+
+// Stripes of the smooth iteration count, light and dark
+extern "C" fn bands(_state: *mut c_void, p: *const PixelData, _limit: f32) -> Color {
+    let p = unsafe { &*p };
+    let v = 0.5 + 0.5 * (p.smooth * 0.5).sin();
+    Color {
+        r: v,
+        g: v * 0.6,
+        b: 1.0 - v * 0.8,
+        a: 1.0,
+    }
+}
+
+/// # Safety
+/// Called by fracmd with a valid host for the duration of the call
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_register(host: *const Host) -> u32 {
+    let host = &*host;
+    if host.version != VERSION {
+        return VERSION;
+    }
+    let state = &SPIRAL as *const Spiral as *mut c_void;
+    let fractal = PluginFractal {
+        name: c"spiral".as_ptr(),
+        state,
+        step,
+        deriv: Some(deriv),
+        zderiv: Some(zderiv),
+        start: None,
+        map: None,
+        degree: 2.0,
+    };
+    (host.fractal)(host.context, &fractal);
+    let coloring = PluginColoring {
+        name: c"bands".as_ptr(),
+        state: std::ptr::null_mut(),
+        color: bands,
+    };
+    (host.coloring)(host.context, &coloring);
+    VERSION
+}
@@ -0,0 +1,65 @@
+/* fracmd
+ * Example embedding: renders a view into memory and saves it as a PPM
+ * (C) 2022 by Jacob (ahhhh6980@gmail.com)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+/* cargo build --release -p fracmd-c
+ * cc fracmd-c/examples/render.c -Ifracmd-c/include -Ltarget/release -lfracmd_c -o render
+ * LD_LIBRARY_PATH=target/release ./render out.ppm */
+
+#include <fracmd.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static int progress(void *user, size_t done, size_t total) {
+    fprintf(stderr, "\r%s %zu/%zu", (const char *)user, done, total);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    const char *path = argc > 1 ? argv[1] : "out.ppm";
+    FracmdRenderer *r = fracmd_new();
+    if (fracmd_set_size(r, 800, 600) || fracmd_set_view(r, -0.745, 0.113, 40) ||
+        fracmd_set_number(r, "limit", 800) || fracmd_set(r, "seed", "1")) {
+        fprintf(stderr, "%s\n", fracmd_error(r));
+        return FRACMD_INVALID;
+    }
+    uint32_t w, h;
+    if (fracmd_size(r, &w, &h)) {
+        fprintf(stderr, "%s\n", fracmd_error(r));
+        return FRACMD_INVALID;
+    }
+    size_t len = (size_t)w * h * 4;
+    uint8_t *rgba = malloc(len);
+    int status = fracmd_render8(r, rgba, len, progress, "rendering");
+    fprintf(stderr, "\n");
+    if (status) {
+        fprintf(stderr, "%s\n", fracmd_error(r));
+        return status;
+    }
+    FILE *out = fopen(path, "wb");
+    if (!out) {
+        perror(path);
+        return FRACMD_IO;
+    }
+    fprintf(out, "P6\n%u %u\n255\n", w, h);
+    for (size_t i = 0; i < len; i += 4)
+        fwrite(rgba + i, 1, 3, out);
+    fclose(out);
+    free(rgba);
+    fracmd_free(r);
+    return 0;
+}
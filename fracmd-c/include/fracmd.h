@@ -0,0 +1,111 @@
+/* fracmd
+ * C API for embedding the render core
+ * (C) 2022 by Jacob (ahhhh6980@gmail.com)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+/* A renderer holds a render's settings, fracmd's flags by their long
+ * names, and renders them into memory the caller owns. Calls returning int
+ * give 0 on success or fracmd's exit code: FRACMD_INVALID for settings
+ * that are wrong, FRACMD_IO for files that can't be read, FRACMD_FAILED
+ * otherwise, with fracmd_error saying why.
+ *
+ * One renderer is used by one thread at a time, different renderers can be
+ * used at once. Rendering itself runs on a thread pool shared by the
+ * process, sized by RAYON_NUM_THREADS and every core otherwise. */
+
+#ifndef FRACMD_H
+#define FRACMD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define FRACMD_FAILED 1
+#define FRACMD_INVALID 2
+#define FRACMD_IO 3
+
+typedef struct FracmdRenderer FracmdRenderer;
+
+/* What iterating found at one point, as --dump-data has it */
+typedef struct FracmdPixel {
+    /* Iteration count, equal to the limit inside the set */
+    float iter;
+    /* Sum of exp(-|z|^2) over the orbit */
+    float s;
+    /* Continuous iteration count */
+    float smooth;
+    /* Final z */
+    float z_re, z_im;
+    /* Exterior distance estimate, 0 inside the set */
+    float de;
+    /* Closest approach of the orbit to 0 */
+    float trap;
+    /* 1 when the orbit stopped by converging, smooth then being the
+     * smooth convergence count, otherwise 0 */
+    float converged;
+} FracmdPixel;
+
+/* Called as tiles finish, from render threads but never two at once.
+ * Returning nonzero stops the render, leaving unfinished tiles
+ * transparent and making fracmd_render return FRACMD_FAILED. */
+typedef int (*FracmdProgress)(void *user, size_t done, size_t total);
+
+/* A renderer with every setting at its default, free it with fracmd_free */
+FracmdRenderer *fracmd_new(void);
+void fracmd_free(FracmdRenderer *renderer);
+
+/* Sets the flag named, like "zoom" or "origin", to value as the command
+ * line would take it, "2.5" or "-0.75,0.1". An empty value gives flags
+ * that take none, like "no-notify", and NULL goes back to the default.
+ * A value that doesn't parse is refused and the setting kept. */
+int fracmd_set(FracmdRenderer *renderer, const char *name, const char *value);
+
+/* fracmd_set for numbers */
+int fracmd_set_number(FracmdRenderer *renderer, const char *name, double value);
+
+/* --width and --height, and --origin and --zoom, at once */
+int fracmd_set_size(FracmdRenderer *renderer, uint32_t width, uint32_t height);
+int fracmd_set_view(FracmdRenderer *renderer, double re, double im, double zoom);
+
+/* Size of the image the settings make */
+int fracmd_size(FracmdRenderer *renderer, uint32_t *width, uint32_t *height);
+
+/* Renders into rgba, width * height * 4 values rows top to bottom, sRGB
+ * and alpha from 0 to 65535. len is how many values rgba holds. progress
+ * can be NULL, user is handed to it. */
+int fracmd_render(FracmdRenderer *renderer, uint16_t *rgba, size_t len,
+                  FracmdProgress progress, void *user);
+
+/* Like fracmd_render, but 8 bit, as most image APIs take it */
+int fracmd_render8(FracmdRenderer *renderer, uint8_t *rgba, size_t len,
+                   FracmdProgress progress, void *user);
+
+/* What iterating found for every pixel's center, width * height of them
+ * rows top to bottom. len is how many pixels holds. */
+int fracmd_render_data(FracmdRenderer *renderer, FracmdPixel *pixels, size_t len);
+
+/* Why the last call on the renderer failed, "" when it didn't. Owned by
+ * the renderer and valid until its next call. */
+const char *fracmd_error(const FracmdRenderer *renderer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
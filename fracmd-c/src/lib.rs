@@ -0,0 +1,328 @@
+// fracmd-c
+// The C API declared in include/fracmd.h
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A FracmdRenderer is the flags set so far, as (name, value) pairs, every
+// change parsed straight away so a bad one is refused where it's made.
+// Rendering parses them once more, loads the plugins and scripts they
+// name and checks them the way the command line does. Panics are caught
+// at the boundary and reported as FRACMD_FAILED, unwinding into C would be
+// undefined.
+
+use fracmd::{
+    default_functs, error, plugin, script, Args, FracError, Image16, PixelData, Renderer,
+};
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    sync::Mutex,
+};
+
+pub struct FracmdRenderer {
+    flags: Vec<(String, String)>,
+    error: CString,
+}
+
+pub type FracmdProgress = Option<unsafe extern "C" fn(*mut c_void, usize, usize) -> c_int>;
+
+// A failed call's exit code and reason
+struct Failure(c_int, String);
+
+impl From<FracError> for Failure {
+    fn from(e: FracError) -> Failure {
+        Failure(e.exit_code(), e.to_string())
+    }
+}
+
+fn invalid(message: String) -> Failure {
+    Failure(error::INVALID, message)
+}
+
+impl FracmdRenderer {
+    fn argv(flags: &[(String, String)]) -> Vec<String> {
+        let mut argv = vec![String::from("fracmd")];
+        argv.extend(flags.iter().map(|(name, value)| match value.is_empty() {
+            true => format!("--{}", name),
+            false => format!("--{}={}", name, value),
+        }));
+        argv
+    }
+
+    fn parse(flags: &[(String, String)]) -> Result<Args, Failure> {
+        Args::try_parse_argv(FracmdRenderer::argv(flags)).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn set(&mut self, name: &str, value: Option<String>) -> Result<(), Failure> {
+        let mut flags = self.flags.clone();
+        flags.retain(|(n, _)| n != name);
+        if let Some(value) = value {
+            flags.push((name.to_string(), value));
+        }
+        FracmdRenderer::parse(&flags)?;
+        self.flags = flags;
+        Ok(())
+    }
+
+    // Ready to render, with what the flags name loaded
+    fn prepare(&self) -> Result<Renderer, Failure> {
+        let mut args = FracmdRenderer::parse(&self.flags)?;
+        plugin::load_all(&args)?;
+        script::load_all(&mut args)?;
+        let problems = args.problems();
+        if !problems.is_empty() {
+            return Err(invalid(problems.join("\n")));
+        }
+        Ok(Renderer::new(args.clone(), default_functs(&args)))
+    }
+}
+
+// Runs f for the renderer, recording how it went in its error
+unsafe fn call<F>(renderer: *mut FracmdRenderer, f: F) -> c_int
+where
+    F: FnOnce(&mut FracmdRenderer) -> Result<(), Failure>,
+{
+    let Some(renderer) = renderer.as_mut() else {
+        return error::INVALID;
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(renderer)))
+        .unwrap_or_else(|_| Err(Failure(error::FAILED, String::from("fracmd panicked"))));
+    let (code, message) = match result {
+        Ok(()) => (0, String::new()),
+        Err(Failure(code, message)) => (code, message),
+    };
+    renderer.error = CString::new(message.replace('\0', " ")).unwrap();
+    code
+}
+
+unsafe fn text(s: *const c_char) -> Result<String, Failure> {
+    match s.is_null() {
+        true => Err(invalid(String::from("a flag name can't be NULL"))),
+        false => Ok(CStr::from_ptr(s).to_string_lossy().into_owned()),
+    }
+}
+
+// How many of what a width by height image takes at per a pixel, if the
+// buffer holds that many
+fn room(len: usize, (width, height): (u32, u32), per: usize, what: &str) -> Result<usize, Failure> {
+    let needed = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(per))
+        .ok_or_else(|| {
+            invalid(format!(
+                "a {}x{} image is too big to address",
+                width, height
+            ))
+        })?;
+    match len < needed {
+        true => Err(invalid(format!(
+            "the buffer holds {} {}, the image needs {}",
+            len, what, needed
+        ))),
+        false => Ok(needed),
+    }
+}
+
+// The whole image, calling progress as tiles finish
+fn render(
+    renderer: &Renderer,
+    progress: FracmdProgress,
+    user: *mut c_void,
+) -> Result<Image16, Failure> {
+    let (width, height) = renderer.dimensions();
+    let tiles = fracmd::scheduler::tiles(width, height, fracmd::scheduler::TILE_SIZE);
+    let image = Image16::new(width, height);
+    // Raw pointers aren't Sync, the caller promised it's fine to share
+    let user = user as usize;
+    let serial = Mutex::new(());
+    let (image, done) = renderer.render_while(image, vec![false; tiles.len()], |p| {
+        progress.is_none_or(|progress| {
+            let _serial = serial.lock().unwrap();
+            unsafe { progress(user as *mut c_void, p.done, p.total) == 0 }
+        })
+    });
+    match done.iter().all(|&d| d) {
+        true => Ok(image),
+        false => Err(Failure(
+            error::FAILED,
+            String::from("stopped by the progress callback"),
+        )),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_new() -> *mut FracmdRenderer {
+    Box::into_raw(Box::new(FracmdRenderer {
+        flags: Vec::new(),
+        error: CString::default(),
+    }))
+}
+
+/// # Safety
+/// renderer comes from fracmd_new and isn't used again
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_free(renderer: *mut FracmdRenderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
+
+/// # Safety
+/// name and value are NUL terminated or value is NULL
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_set(
+    renderer: *mut FracmdRenderer,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    call(renderer, |r| {
+        let value =
+            (!value.is_null()).then(|| CStr::from_ptr(value).to_string_lossy().into_owned());
+        r.set(&text(name)?, value)
+    })
+}
+
+/// # Safety
+/// name is NUL terminated
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_set_number(
+    renderer: *mut FracmdRenderer,
+    name: *const c_char,
+    value: f64,
+) -> c_int {
+    call(renderer, |r| r.set(&text(name)?, Some(value.to_string())))
+}
+
+/// # Safety
+/// renderer comes from fracmd_new
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_set_size(
+    renderer: *mut FracmdRenderer,
+    width: u32,
+    height: u32,
+) -> c_int {
+    call(renderer, |r| {
+        r.set("width", Some(width.to_string()))?;
+        r.set("height", Some(height.to_string()))
+    })
+}
+
+/// # Safety
+/// renderer comes from fracmd_new
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_set_view(
+    renderer: *mut FracmdRenderer,
+    re: f64,
+    im: f64,
+    zoom: f64,
+) -> c_int {
+    call(renderer, |r| {
+        r.set("origin", Some(format!("{},{}", re, im)))?;
+        r.set("zoom", Some(zoom.to_string()))
+    })
+}
+
+/// # Safety
+/// width and height point at writable u32s
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_size(
+    renderer: *mut FracmdRenderer,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    call(renderer, |r| {
+        let (w, h) = r.prepare()?.dimensions();
+        if let Some(width) = width.as_mut() {
+            *width = w;
+        }
+        if let Some(height) = height.as_mut() {
+            *height = h;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// rgba holds len u16s, progress is NULL or safe to call with user from
+/// any thread
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_render(
+    renderer: *mut FracmdRenderer,
+    rgba: *mut u16,
+    len: usize,
+    progress: FracmdProgress,
+    user: *mut c_void,
+) -> c_int {
+    call(renderer, |r| {
+        let renderer = r.prepare()?;
+        let needed = room(len, renderer.dimensions(), 4, "values")?;
+        let image = render(&renderer, progress, user)?;
+        assert_eq!(image.as_raw().len(), needed);
+        ptr::copy_nonoverlapping(image.as_raw().as_ptr(), rgba, needed);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// As fracmd_render, rgba holding len bytes
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_render8(
+    renderer: *mut FracmdRenderer,
+    rgba: *mut u8,
+    len: usize,
+    progress: FracmdProgress,
+    user: *mut c_void,
+) -> c_int {
+    call(renderer, |r| {
+        let renderer = r.prepare()?;
+        let needed = room(len, renderer.dimensions(), 4, "bytes")?;
+        let image = render(&renderer, progress, user)?;
+        assert_eq!(image.as_raw().len(), needed);
+        let out = slice::from_raw_parts_mut(rgba, needed);
+        for (o, v) in out.iter_mut().zip(image.as_raw()) {
+            *o = (v >> 8) as u8;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// pixels holds len FracmdPixels
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_render_data(
+    renderer: *mut FracmdRenderer,
+    pixels: *mut PixelData,
+    len: usize,
+) -> c_int {
+    call(renderer, |r| {
+        let renderer = r.prepare()?;
+        let needed = room(len, renderer.dimensions(), 1, "pixels")?;
+        let data = renderer.render_data();
+        assert_eq!(data.len(), needed);
+        ptr::copy_nonoverlapping(data.as_ptr(), pixels, needed);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// renderer comes from fracmd_new
+#[no_mangle]
+pub unsafe extern "C" fn fracmd_error(renderer: *const FracmdRenderer) -> *const c_char {
+    match renderer.as_ref() {
+        Some(renderer) => renderer.error.as_ptr(),
+        None => c"no renderer".as_ptr(),
+    }
+}
@@ -0,0 +1,109 @@
+# fracmd
+# Python bindings for fracmd's render core
+# (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+# This program is free software: you can redistribute it and/or modify
+# it under the terms of the GNU General Public License as published by
+# the Free Software Foundation, either version 3 of the License, or
+# (at your option) any later version.
+
+# This program is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+# GNU General Public License for more details.
+
+# You should have received a copy of the GNU General Public License
+# along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+"""Render fracmd fractals straight into numpy arrays.
+
+    import fracmd
+
+    r = fracmd.Renderer(width=800, height=600, origin=-0.75 + 0.1j, zoom=2, limit=500)
+    image = r.render()              # (600, 800, 4) uint16, sRGB
+    data = r.render_data()          # (600, 800) record array
+    escaped = data["iter"] < 500
+
+Settings are fracmd's flags as keyword arguments, dashes written as
+underscores: `set_color="#000000ff"`, `julia=0.285 + 0.01j`, `plugin=[path]`.
+"""
+
+from . import _fracmd
+
+# What render_data hands back per pixel, fracmd's PixelData
+DATA_FIELDS = [
+    ("iter", "f4"),
+    ("s", "f4"),
+    ("smooth", "f4"),
+    ("z", "c8"),
+    ("de", "f4"),
+    ("trap", "f4"),
+    ("converged", "f4"),
+]
+
+
+def _value(value):
+    if isinstance(value, complex):
+        return f"{value.real},{value.imag}"
+    if isinstance(value, (list, tuple)):
+        return ",".join(_value(v) for v in value)
+    return str(value)
+
+
+def flags(**settings):
+    """The command line flags for some settings, as fracmd would take them"""
+    out = []
+    for name, value in settings.items():
+        flag = "--" + name.replace("_", "-")
+        if value is True:
+            out.append(flag)
+        elif value is not None and value is not False:
+            out.append(f"{flag}={_value(value)}")
+    return out
+
+
+class Renderer:
+    """A render's settings, rendered again by every call"""
+
+    def __init__(self, **settings):
+        self.settings = dict(settings)
+
+    def set(self, **settings):
+        """Changes some settings, a setting of None goes back to the default"""
+        self.settings.update(settings)
+        return self
+
+    def copy(self, **settings):
+        """Another renderer with these settings changed"""
+        return Renderer(**{**self.settings, **settings})
+
+    def flags(self):
+        return flags(**self.settings)
+
+    def problems(self):
+        """What's wrong with the settings, empty when they're fine"""
+        return _fracmd.problems(*self.flags())
+
+    def render_bytes(self):
+        """The image as 16 bit RGBA bytes, rows top to bottom, and its size"""
+        return _fracmd.render(*self.flags())
+
+    def render(self):
+        """The image, a (height, width, 4) uint16 array of sRGB and alpha"""
+        import numpy
+
+        data, width, height = self.render_bytes()
+        return numpy.frombuffer(data, dtype=numpy.uint16).reshape(height, width, 4)
+
+    def render_data(self):
+        """What iterating found for the center of every pixel, a (height,
+        width) record array with DATA_FIELDS, iter being the limit inside
+        the set"""
+        import numpy
+
+        data, width, height = _fracmd.render_data(*self.flags())
+        return numpy.frombuffer(data, dtype=numpy.dtype(DATA_FIELDS)).reshape(height, width)
+
+    def __repr__(self):
+        inner = ", ".join(f"{k}={v!r}" for k, v in self.settings.items())
+        return f"Renderer({inner})"
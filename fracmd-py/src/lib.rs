@@ -0,0 +1,332 @@
+// fracmd-py
+// The fracmd._fracmd extension module, written against Python's C API
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Three functions taking fracmd's flags as separate strings:
+//
+//   render(*flags) -> (bytes, width, height)
+//   render_data(*flags) -> (bytes, width, height)
+//   problems(*flags) -> [str]
+//
+// render's bytes are the image as 16 bit RGBA, rows top to bottom, and
+// render_data's are PixelData for every pixel in the same order, eight
+// floats each. python/fracmd turns both into numpy arrays. The GIL is let
+// go while rendering, so other Python threads carry on.
+//
+// Only the handful of C API calls used are declared, they've been stable
+// since Python 3.3. Errors become ValueError for bad settings, OSError for
+// files that can't be read and RuntimeError otherwise.
+//
+// It's written against the C API directly rather than with pyo3, the
+// readme has why and what that asks of the code: the structs below have to
+// match CPython's layout, references borrowed from Python are copied out
+// before the GIL is let go, and nothing in released may call into Python.
+
+use fracmd::{default_functs, error, plugin, script, Args, FracError, Renderer};
+use std::{
+    ffi::{c_char, c_int, c_long, c_void, CStr, CString},
+    panic, ptr, slice,
+};
+
+#[repr(C)]
+struct PyObject {
+    ob_refcnt: isize,
+    ob_type: *mut c_void,
+}
+
+type PyCFunction = unsafe extern "C" fn(*mut PyObject, *mut PyObject) -> *mut PyObject;
+
+#[repr(C)]
+struct PyMethodDef {
+    ml_name: *const c_char,
+    ml_meth: Option<PyCFunction>,
+    ml_flags: c_int,
+    ml_doc: *const c_char,
+}
+
+#[repr(C)]
+struct PyModuleDefBase {
+    ob_base: PyObject,
+    m_init: *mut c_void,
+    m_index: isize,
+    m_copy: *mut PyObject,
+}
+
+#[repr(C)]
+struct PyModuleDef {
+    m_base: PyModuleDefBase,
+    m_name: *const c_char,
+    m_doc: *const c_char,
+    m_size: isize,
+    m_methods: *mut PyMethodDef,
+    m_slots: *mut c_void,
+    m_traverse: *mut c_void,
+    m_clear: *mut c_void,
+    m_free: *mut c_void,
+}
+
+const METH_VARARGS: c_int = 1;
+const PYTHON_API_VERSION: c_int = 1013;
+
+extern "C" {
+    static PyExc_ValueError: *mut PyObject;
+    static PyExc_OSError: *mut PyObject;
+    static PyExc_RuntimeError: *mut PyObject;
+    static PyExc_TypeError: *mut PyObject;
+
+    fn PyModule_Create2(def: *mut PyModuleDef, api: c_int) -> *mut PyObject;
+    fn PyTuple_Size(tuple: *mut PyObject) -> isize;
+    fn PyTuple_GetItem(tuple: *mut PyObject, i: isize) -> *mut PyObject;
+    fn PyTuple_New(len: isize) -> *mut PyObject;
+    fn PyTuple_SetItem(tuple: *mut PyObject, i: isize, item: *mut PyObject) -> c_int;
+    fn PyList_New(len: isize) -> *mut PyObject;
+    fn PyList_SetItem(list: *mut PyObject, i: isize, item: *mut PyObject) -> c_int;
+    fn PyUnicode_AsUTF8AndSize(unicode: *mut PyObject, len: *mut isize) -> *const c_char;
+    fn PyUnicode_FromStringAndSize(s: *const c_char, len: isize) -> *mut PyObject;
+    fn PyBytes_FromStringAndSize(s: *const c_char, len: isize) -> *mut PyObject;
+    fn PyLong_FromLong(v: c_long) -> *mut PyObject;
+    fn PyErr_SetString(kind: *mut PyObject, message: *const c_char);
+    fn PyErr_Occurred() -> *mut PyObject;
+    fn Py_DecRef(object: *mut PyObject);
+    fn PyEval_SaveThread() -> *mut c_void;
+    fn PyEval_RestoreThread(state: *mut c_void);
+}
+
+// Why a call failed, raised as the matching Python exception
+enum Failure {
+    Type(String),
+    Fracmd(FracError),
+    Clap(String),
+    Problems(Vec<String>),
+    Runtime(String),
+}
+
+impl From<FracError> for Failure {
+    fn from(e: FracError) -> Failure {
+        Failure::Fracmd(e)
+    }
+}
+
+unsafe fn raise(failure: Failure) -> *mut PyObject {
+    let (kind, message) = match failure {
+        Failure::Type(message) => (PyExc_TypeError, message),
+        Failure::Clap(message) => (PyExc_ValueError, message),
+        Failure::Problems(problems) => (PyExc_ValueError, problems.join("\n")),
+        Failure::Runtime(message) => (PyExc_RuntimeError, message),
+        Failure::Fracmd(e) => {
+            let kind = match e.exit_code() {
+                error::INVALID => PyExc_ValueError,
+                error::IO => PyExc_OSError,
+                _ => PyExc_RuntimeError,
+            };
+            (kind, e.to_string())
+        }
+    };
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    PyErr_SetString(kind, message.as_ptr());
+    ptr::null_mut()
+}
+
+// The tuple of strings a function was called with
+unsafe fn flags(tuple: *mut PyObject) -> Result<Vec<String>, Failure> {
+    let mut argv = vec![String::from("fracmd")];
+    for i in 0..PyTuple_Size(tuple) {
+        let mut len = 0;
+        let text = PyUnicode_AsUTF8AndSize(PyTuple_GetItem(tuple, i), &mut len);
+        if text.is_null() {
+            return Err(Failure::Type(String::from("flags must be strings")));
+        }
+        let bytes = slice::from_raw_parts(text as *const u8, len as usize);
+        argv.push(String::from_utf8_lossy(bytes).into_owned());
+    }
+    Ok(argv)
+}
+
+// Parsed and checked the way the command line does, with the plugins and
+// scripts the flags name loaded
+fn settings(argv: Vec<String>) -> Result<Args, Failure> {
+    let mut args = Args::try_parse_argv(argv).map_err(|e| Failure::Clap(e.to_string()))?;
+    plugin::load_all(&args)?;
+    script::load_all(&mut args)?;
+    let problems = args.problems();
+    if !problems.is_empty() {
+        return Err(Failure::Problems(problems));
+    }
+    Ok(args)
+}
+
+// Runs f without the GIL, catching panics so they can't unwind into
+// Python
+unsafe fn released<T, F: FnOnce() -> Result<T, Failure> + panic::UnwindSafe>(
+    f: F,
+) -> Result<T, Failure> {
+    let state = PyEval_SaveThread();
+    let result = panic::catch_unwind(f);
+    PyEval_RestoreThread(state);
+    result.unwrap_or_else(|_| Err(Failure::Runtime(String::from("fracmd panicked"))))
+}
+
+// A tuple of the items, which it takes over, or NULL with Python's
+// MemoryError set when any of them or the tuple couldn't be made
+unsafe fn tuple(items: &[*mut PyObject]) -> *mut PyObject {
+    let tuple = PyTuple_New(items.len() as isize);
+    if tuple.is_null() || items.contains(&ptr::null_mut()) {
+        // Py_DecRef skips NULLs
+        items.iter().for_each(|&item| Py_DecRef(item));
+        Py_DecRef(tuple);
+        return ptr::null_mut();
+    }
+    for (i, &item) in items.iter().enumerate() {
+        PyTuple_SetItem(tuple, i as isize, item);
+    }
+    tuple
+}
+
+// The bytes are copied, so the image can go once this returns
+unsafe fn image_tuple(bytes: &[u8], width: u32, height: u32) -> *mut PyObject {
+    tuple(&[
+        PyBytes_FromStringAndSize(bytes.as_ptr() as *const c_char, bytes.len() as isize),
+        PyLong_FromLong(width as c_long),
+        PyLong_FromLong(height as c_long),
+    ])
+}
+
+unsafe extern "C" fn render(_module: *mut PyObject, args: *mut PyObject) -> *mut PyObject {
+    let rendered = flags(args).and_then(|argv| {
+        released(move || {
+            let args = settings(argv)?;
+            Ok(Renderer::new(args.clone(), default_functs(&args)).render())
+        })
+    });
+    match rendered {
+        Ok(image) => {
+            let raw = image.as_raw();
+            let bytes = slice::from_raw_parts(raw.as_ptr() as *const u8, raw.len() * 2);
+            image_tuple(bytes, image.width(), image.height())
+        }
+        Err(failure) => raise(failure),
+    }
+}
+
+unsafe extern "C" fn render_data(_module: *mut PyObject, args: *mut PyObject) -> *mut PyObject {
+    let rendered = flags(args).and_then(|argv| {
+        released(move || {
+            let args = settings(argv)?;
+            let renderer = Renderer::new(args.clone(), default_functs(&args));
+            Ok((renderer.render_data(), renderer.dimensions()))
+        })
+    });
+    match rendered {
+        Ok((data, (width, height))) => {
+            let bytes = slice::from_raw_parts(
+                data.as_ptr() as *const u8,
+                std::mem::size_of_val(data.as_slice()),
+            );
+            image_tuple(bytes, width, height)
+        }
+        Err(failure) => raise(failure),
+    }
+}
+
+unsafe extern "C" fn problems(_module: *mut PyObject, args: *mut PyObject) -> *mut PyObject {
+    let found = flags(args).and_then(|argv| match settings(argv) {
+        Ok(_) => Ok(Vec::new()),
+        Err(Failure::Problems(problems)) => Ok(problems),
+        Err(Failure::Clap(message)) => Ok(vec![message]),
+        Err(failure) => Err(failure),
+    });
+    match found {
+        Ok(found) => {
+            let list = PyList_New(found.len() as isize);
+            if list.is_null() {
+                return list;
+            }
+            for (i, problem) in found.iter().enumerate() {
+                let item = PyUnicode_FromStringAndSize(
+                    problem.as_ptr() as *const c_char,
+                    problem.len() as isize,
+                );
+                if item.is_null() {
+                    Py_DecRef(list);
+                    return item;
+                }
+                PyList_SetItem(list, i as isize, item);
+            }
+            list
+        }
+        Err(failure) => raise(failure),
+    }
+}
+
+/// # Safety
+/// Called by the Python interpreter when importing fracmd._fracmd
+#[no_mangle]
+pub unsafe extern "C" fn PyInit__fracmd() -> *mut c_void {
+    let method = |name: &'static CStr, f: PyCFunction, doc: &'static CStr| PyMethodDef {
+        ml_name: name.as_ptr(),
+        ml_meth: Some(f),
+        ml_flags: METH_VARARGS,
+        ml_doc: doc.as_ptr(),
+    };
+    // Python keeps pointers to both for as long as the module lives
+    let methods = Box::leak(Box::new([
+        method(
+            c"render",
+            render,
+            c"render(*flags) -> (bytes, width, height), 16 bit RGBA",
+        ),
+        method(
+            c"render_data",
+            render_data,
+            c"render_data(*flags) -> (bytes, width, height), PixelData per pixel",
+        ),
+        method(
+            c"problems",
+            problems,
+            c"problems(*flags) -> what's wrong with them, one string each",
+        ),
+        PyMethodDef {
+            ml_name: ptr::null(),
+            ml_meth: None,
+            ml_flags: 0,
+            ml_doc: ptr::null(),
+        },
+    ]));
+    let def = Box::leak(Box::new(PyModuleDef {
+        m_base: PyModuleDefBase {
+            ob_base: PyObject {
+                ob_refcnt: 1,
+                ob_type: ptr::null_mut(),
+            },
+            m_init: ptr::null_mut(),
+            m_index: 0,
+            m_copy: ptr::null_mut(),
+        },
+        m_name: c"fracmd._fracmd".as_ptr(),
+        m_doc: c"fracmd's render core, see the fracmd package".as_ptr(),
+        m_size: -1,
+        m_methods: methods.as_mut_ptr(),
+        m_slots: ptr::null_mut(),
+        m_traverse: ptr::null_mut(),
+        m_clear: ptr::null_mut(),
+        m_free: ptr::null_mut(),
+    }));
+    let mut module = PyModule_Create2(def, PYTHON_API_VERSION);
+    if module.is_null() && PyErr_Occurred().is_null() {
+        module = raise(Failure::Runtime(String::from("couldn't create the module")));
+    }
+    module as *mut c_void
+}
@@ -0,0 +1,47 @@
+// fracmd-wasm
+// Example wasm plugin: the tricorn and a two-tone coloring
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Built with
+//
+//   cargo build --release --target wasm32-unknown-unknown -p fracmd-wasm --example tricorn
+//
+// into target/wasm32-unknown-unknown/release/examples/tricorn.wasm, then
+//
+//   fracmd --plugin target/wasm32-unknown-unknown/release/examples/tricorn.wasm \
+//     --fractal tricorn --coloring tricorn
+//
+// The tricorn is the Mandelbrot set with z conjugated every step, which
+// folds it into three-fold symmetry.
+
+use fracmd_wasm::{plugin, Color, Complex, Pixel};
+
+fn step(z: Complex, c: Complex) -> Complex {
+    z.conj() * z.conj() + c
+}
+
+// Blue to orange by the smooth count, darker near the set
+fn two_tone(p: &Pixel, limit: f32) -> Color {
+    let t = (p.smooth / limit).sqrt().min(1.0);
+    let shade = (p.de * 400.0).min(1.0);
+    Color::rgb(t * shade, (0.2 + 0.4 * t) * shade, (1.0 - t) * shade)
+}
+
+plugin! {
+    step: step,
+    degree: 2.0,
+    color: two_tone,
+}
@@ -0,0 +1,306 @@
+// fracmd-wasm
+// The interface for writing fracmd plugins as WebAssembly modules
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Write a fractal, a coloring or both as plain Rust functions, hand them
+//! to [`plugin!`], and build the crate as a `cdylib` for
+//! `wasm32-unknown-unknown`. fracmd then loads the `.wasm` with
+//! `--plugin`, named after the file.
+//!
+//! ```ignore
+//! use fracmd_wasm::{plugin, Color, Complex, Pixel};
+//!
+//! fn step(z: Complex, c: Complex) -> Complex {
+//!     z * z + c
+//! }
+//!
+//! fn color(p: &Pixel, limit: f32) -> Color {
+//!     let t = p.smooth / limit;
+//!     Color::rgb(t, t, 1.0)
+//! }
+//!
+//! plugin! { step: step, color: color }
+//! ```
+//!
+//! The module runs sandboxed: it can't import anything, so no printing,
+//! files or clocks, and a call that traps or runs too long stops the
+//! plugin for the rest of the render. `std` is fine otherwise.
+
+#![no_std]
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+/// The interface version this crate writes, fracmd refuses others
+pub const VERSION: i32 = 2;
+
+/// A complex number, the way fracmd hands them over
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub const fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    /// |z|², the square of the distance from 0
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, o: Complex) -> Complex {
+        let n = o.norm_sqr();
+        Complex::new(
+            (self.re * o.re + self.im * o.im) / n,
+            (self.im * o.re - self.re * o.im) / n,
+        )
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl Add<f32> for Complex {
+    type Output = Complex;
+    fn add(self, o: f32) -> Complex {
+        Complex::new(self.re + o, self.im)
+    }
+}
+
+impl Mul<f32> for Complex {
+    type Output = Complex;
+    fn mul(self, o: f32) -> Complex {
+        Complex::new(self.re * o, self.im * o)
+    }
+}
+
+impl Mul<Complex> for f32 {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        o * self
+    }
+}
+
+/// What iterating found about an escaped point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pixel {
+    /// Iteration count
+    pub iter: f32,
+    /// Sum of exp(-|z|²) over the orbit, what the built-in hue coloring uses
+    pub s: f32,
+    /// Continuous iteration count
+    pub smooth: f32,
+    /// Final z
+    pub z: Complex,
+    /// Exterior distance estimate
+    pub de: f32,
+    /// Closest approach of the orbit to 0
+    pub trap: f32,
+    /// 1 for orbits stopped by converging, whose `smooth` is the smooth
+    /// convergence count, 0 for the rest
+    pub converged: f32,
+}
+
+/// A color in fracmd's working color space, before the sRGB curve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Color {
+        Color { r, g, b, a: 1.0 }
+    }
+
+    pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+// Results go back through here, a module only returns single values
+struct Io(UnsafeCell<[f32; 16]>);
+
+// A wasm module runs on one thread, each of fracmd's has its own instance
+unsafe impl Sync for Io {}
+
+static IO: Io = Io(UnsafeCell::new([0.0; 16]));
+
+#[doc(hidden)]
+pub fn io() -> *mut f32 {
+    IO.0.get().cast()
+}
+
+#[doc(hidden)]
+pub fn put(values: &[f32]) {
+    let io = unsafe { &mut *IO.0.get() };
+    io[..values.len()].copy_from_slice(values);
+}
+
+#[doc(hidden)]
+pub fn put_complex(z: Complex) {
+    put(&[z.re, z.im]);
+}
+
+#[doc(hidden)]
+pub fn put_color(c: Color) {
+    put(&[c.r, c.g, c.b, c.a]);
+}
+
+/// Exports a plugin's functions, each key optional but at least one of
+/// `step` and `color` needed:
+///
+/// * `step: fn(z: Complex, c: Complex) -> Complex`, the next z
+/// * `deriv: fn(z: Complex, dz: Complex, c: Complex) -> Complex`, the next
+///   derivative by c, estimated from `step` when left out
+/// * `zderiv: fn(z: Complex, dz: Complex) -> Complex`, by the starting z
+/// * `start: fn(c: Complex) -> Complex`, where the orbit starts
+/// * `map: fn(c: Complex) -> Complex`, applied to each point first
+/// * `degree: f32`, how fast orbits escape, 2 when left out
+/// * `color: fn(p: &Pixel, limit: f32) -> Color`
+///
+/// Use it once, at the top level of the crate.
+#[macro_export]
+macro_rules! plugin {
+    ($($key:ident: $value:expr),* $(,)?) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_version() -> i32 {
+            $crate::VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fracmd_io() -> *mut f32 {
+            $crate::io()
+        }
+
+        $($crate::plugin!(@ $key $value);)*
+    };
+    (@ step $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_step(zr: f32, zi: f32, cr: f32, ci: f32) {
+            let f: fn($crate::Complex, $crate::Complex) -> $crate::Complex = $f;
+            $crate::put_complex(f($crate::Complex::new(zr, zi), $crate::Complex::new(cr, ci)));
+        }
+    };
+    (@ deriv $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_deriv(zr: f32, zi: f32, dzr: f32, dzi: f32, cr: f32, ci: f32) {
+            let f: fn($crate::Complex, $crate::Complex, $crate::Complex) -> $crate::Complex = $f;
+            $crate::put_complex(f(
+                $crate::Complex::new(zr, zi),
+                $crate::Complex::new(dzr, dzi),
+                $crate::Complex::new(cr, ci),
+            ));
+        }
+    };
+    (@ zderiv $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_zderiv(zr: f32, zi: f32, dzr: f32, dzi: f32) {
+            let f: fn($crate::Complex, $crate::Complex) -> $crate::Complex = $f;
+            $crate::put_complex(f($crate::Complex::new(zr, zi), $crate::Complex::new(dzr, dzi)));
+        }
+    };
+    (@ start $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_start(cr: f32, ci: f32) {
+            let f: fn($crate::Complex) -> $crate::Complex = $f;
+            $crate::put_complex(f($crate::Complex::new(cr, ci)));
+        }
+    };
+    (@ map $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_map(cr: f32, ci: f32) {
+            let f: fn($crate::Complex) -> $crate::Complex = $f;
+            $crate::put_complex(f($crate::Complex::new(cr, ci)));
+        }
+    };
+    (@ degree $d:expr) => {
+        #[no_mangle]
+        pub extern "C" fn fracmd_degree() -> f32 {
+            $d
+        }
+    };
+    (@ color $f:expr) => {
+        #[no_mangle]
+        #[allow(clippy::too_many_arguments)]
+        pub extern "C" fn fracmd_color(
+            iter: f32,
+            s: f32,
+            smooth: f32,
+            zr: f32,
+            zi: f32,
+            de: f32,
+            trap: f32,
+            converged: f32,
+            limit: f32,
+        ) {
+            let f: fn(&$crate::Pixel, f32) -> $crate::Color = $f;
+            let p = $crate::Pixel {
+                iter,
+                s,
+                smooth,
+                z: $crate::Complex::new(zr, zi),
+                de,
+                trap,
+                converged,
+            };
+            $crate::put_color(f(&p, limit));
+        }
+    };
+}
@@ -0,0 +1,145 @@
+// fracmd-web
+// fracmd's render core, exported for JavaScript
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Built for wasm32-unknown-unknown this is a module importing nothing, so
+// a page instantiates it with no glue. Everything crosses as numbers and
+// bytes in its memory: the page asks fracmd_input for room, writes the
+// flags there as UTF-8, the same ones fracmd takes on the command line,
+// and calls fracmd_render. Then fracmd_image points at width * height
+// RGBA bytes, rows top to bottom, ready for an ImageData. When rendering
+// fails fracmd_error points at the reason instead. fracmd_script reads the
+// input as a --script file would be. See index.html.
+
+use fracmd::{default_functs, plugin, script, Args, Renderer};
+use std::{cell::RefCell, sync::Arc};
+
+#[derive(Default)]
+struct State {
+    input: Vec<u8>,
+    image: Vec<u8>,
+    width: u32,
+    height: u32,
+    error: String,
+}
+
+// A page runs the module on one thread
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Room for len bytes of input, overwritten by the next call
+#[no_mangle]
+pub extern "C" fn fracmd_input(len: usize) -> *mut u8 {
+    STATE.with(|state| {
+        let input = &mut state.borrow_mut().input;
+        input.clear();
+        input.resize(len, 0);
+        input.as_mut_ptr()
+    })
+}
+
+/// Renders with the flags in the input, returning 0 once the image is
+/// ready or 2 with the error
+#[no_mangle]
+pub extern "C" fn fracmd_render() -> i32 {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let flags = String::from_utf8_lossy(&state.input).into_owned();
+        match render(&flags) {
+            Ok((image, width, height)) => {
+                state.image = image;
+                state.width = width;
+                state.height = height;
+                state.error.clear();
+                0
+            }
+            Err(e) => {
+                state.error = e;
+                2
+            }
+        }
+    })
+}
+
+/// Reads the input as a script, registering what it defines as `script`
+/// for --fractal and --coloring to pick. Returns 1 when that's a fractal,
+/// 2 a coloring and 3 both, or -1 with the error when it doesn't parse
+#[no_mangle]
+pub extern "C" fn fracmd_script() -> i32 {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let text = String::from_utf8_lossy(&state.input).into_owned();
+        match script::parse(&text) {
+            Ok(parsed) => {
+                let mut brings = 0;
+                if let Some(fractal) = parsed.fractal {
+                    plugin::register_fractal("script", Arc::new(fractal));
+                    brings |= 1;
+                }
+                if let Some(coloring) = parsed.coloring {
+                    plugin::register_coloring("script", Arc::new(coloring));
+                    brings |= 2;
+                }
+                state.error.clear();
+                brings
+            }
+            Err(e) => {
+                state.error = e.to_string();
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_image() -> *const u8 {
+    STATE.with(|state| state.borrow().image.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_width() -> u32 {
+    STATE.with(|state| state.borrow().width)
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_height() -> u32 {
+    STATE.with(|state| state.borrow().height)
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_error() -> *const u8 {
+    STATE.with(|state| state.borrow().error.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn fracmd_error_len() -> usize {
+    STATE.with(|state| state.borrow().error.len())
+}
+
+// Flags split on whitespace, there's no shell to quote for
+fn render(flags: &str) -> Result<(Vec<u8>, u32, u32), String> {
+    let argv = std::iter::once("fracmd").chain(flags.split_whitespace());
+    let args = Args::try_parse_argv(argv.map(String::from)).map_err(|e| e.to_string())?;
+    let problems = args.problems();
+    if !problems.is_empty() {
+        return Err(problems.join("\n"));
+    }
+    let image = Renderer::new(args.clone(), default_functs(&args)).render();
+    let (width, height) = image.dimensions();
+    let rgba = image.as_raw().iter().map(|v| (v >> 8) as u8).collect();
+    Ok((rgba, width, height))
+}
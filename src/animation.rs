@@ -0,0 +1,570 @@
+// fracmd
+// Animation
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num::complex::Complex;
+use std::{
+    f32::consts::TAU,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Stdio},
+    str::FromStr,
+};
+
+use crate::{
+    audio::{AudioError, Drive, Target},
+    colorspace::Colorspace,
+    config, coords,
+    fractal::Multibrot,
+    keyframe::{KeyError, Keyframes},
+    manifest,
+    metadata::{self, MetadataError},
+    nucleus,
+    scheduler::Image16,
+    sizes::Size,
+    Args, FracError,
+};
+
+// Where --julia goes over an animation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JuliaPath {
+    Line(Complex<f32>, Complex<f32>),
+    // Once round, starting on the right
+    Circle { center: Complex<f32>, radius: f32 },
+}
+
+impl JuliaPath {
+    pub fn at(&self, t: f32) -> Complex<f32> {
+        match *self {
+            JuliaPath::Line(from, to) => from + (to - from) * t,
+            JuliaPath::Circle { center, radius } => center + Complex::from_polar(radius, TAU * t),
+        }
+    }
+
+    // Whether the end is back at the start
+    pub fn closed(&self) -> bool {
+        matches!(self, JuliaPath::Circle { .. })
+    }
+}
+
+// line:A:B, circle:CENTER:RADIUS, or misiurewicz:K:P:NEAR:RADIUS for a
+// circle around the Misiurewicz point of preperiod K and period P nearest
+// NEAR, a point of the z^2 + c set
+impl FromStr for JuliaPath {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = string.split(':').collect();
+        let radius = |text: &str| match text.parse::<f32>() {
+            Ok(r) if r > 0.0 && r.is_finite() => Ok(r),
+            _ => Err(format!("radius must be more than 0, got {:?}", text)),
+        };
+        let count = |text: &str, least: usize| match text.parse::<usize>() {
+            Ok(n) if n >= least => Ok(n),
+            _ => Err(format!(
+                "expected a whole number of {} or more, got {:?}",
+                least, text
+            )),
+        };
+        match parts[..] {
+            ["line", from, to] => Ok(JuliaPath::Line(
+                coords::parse_f32(from)?,
+                coords::parse_f32(to)?,
+            )),
+            ["circle", center, r] => Ok(JuliaPath::Circle {
+                center: coords::parse_f32(center)?,
+                radius: radius(r)?,
+            }),
+            ["misiurewicz", k, p, near, r] => {
+                // Preperiods 0 and 1 are nuclei, counting z_0 = 0
+                let (k, p) = (count(k, 2)?, count(p, 1)?);
+                let near = coords::parse_f32(near)?;
+                let center =
+                    nucleus::misiurewicz(near, k, p, &Multibrot::default()).ok_or_else(|| {
+                        format!(
+                            "no Misiurewicz point of preperiod {} and period {} near {}",
+                            k,
+                            p,
+                            coords::format(near)
+                        )
+                    })?;
+                Ok(JuliaPath::Circle {
+                    center,
+                    radius: radius(r)?,
+                })
+            }
+            _ => Err(format!(
+                "expected line:A:B, circle:CENTER:RADIUS or misiurewicz:K:P:NEAR:RADIUS, got {:?}",
+                string
+            )),
+        }
+    }
+}
+
+pub struct Animation {
+    pub frames: usize,
+    pub fps: u16,
+    // Quality for video encoded with ffmpeg, lower is better
+    pub crf: u8,
+    pub zoom_to: Option<f32>,
+    // Degrees the view turns by over the animation
+    pub spin: Option<f32>,
+    // Motion blur, in degrees of a frame the shutter stays open for
+    pub shutter: f32,
+    pub subframes: usize,
+    pub julia_path: Option<JuliaPath>,
+    // Play forwards then backwards so the last frame leads into the first
+    pub bounce: bool,
+    // Zoom and spin end a frame short, where the view repeats itself
+    pub looping: bool,
+    pub keys: Option<Keyframes>,
+    pub audio: Option<Drive>,
+    // Frames shrunk to fit inside this, with the sampling cut down
+    pub preview: Option<Size>,
+}
+
+pub const PREVIEW: Size = Size {
+    width: 320,
+    height: 180,
+};
+
+impl Animation {
+    // Position of a frame along the animation in 0..=1. Frames can be
+    // fractional, for the moments between them motion blur samples.
+    pub fn time(&self, frame: f32) -> f32 {
+        if self.frames < 2 {
+            return 0.0;
+        }
+        if self.bounce {
+            let half = self.frames as f32 / 2.0;
+            1.0 - (frame - half).abs() / half
+        } else {
+            frame / (self.frames - 1) as f32
+        }
+    }
+
+    // Like time, but for motion that ends back where it started, so the
+    // last frame stops one short of the first and the animation loops
+    pub fn loop_time(&self, frame: f32) -> f32 {
+        if self.bounce {
+            self.time(frame)
+        } else {
+            frame / self.frames.max(1) as f32
+        }
+    }
+
+    // Where a frame falls along the animation, in frames. That's the frame
+    // itself unless audio drives the speed, when each frame moves on by
+    // its level and the steps are scaled to cover the same ground.
+    pub fn position(&self, frame: usize) -> f32 {
+        let drive = match &self.audio {
+            Some(drive) if drive.target == Target::Speed => drive,
+            _ => return frame as f32,
+        };
+        let span = if self.looping || self.bounce {
+            self.frames
+        } else {
+            self.frames.saturating_sub(1)
+        };
+        let step = |j: usize| drive.level(j as f32).max(0.0);
+        let total: f32 = (0..span).map(step).sum();
+        if total <= 0.0 {
+            return frame as f32;
+        }
+        (0..frame).map(step).sum::<f32>() * span as f32 / total
+    }
+
+    // Where the subframes of a frame fall, spread evenly over the time the
+    // shutter is open, which is centered on the frame
+    pub fn subframes(&self, frame: usize) -> Vec<f32> {
+        let at = self.position(frame);
+        let count = match self.preview {
+            Some(_) => self.subframes.min(2),
+            None => self.subframes,
+        };
+        if self.shutter <= 0.0 || count < 2 {
+            return vec![at];
+        }
+        let open = self.shutter.min(360.0) / 360.0 * (self.position(frame + 1) - at);
+        (0..count)
+            .map(|i| at + open * ((i as f32 + 0.5) / count as f32 - 0.5))
+            .collect()
+    }
+
+    pub fn frame_args(&self, base: &Args, frame: usize) -> Result<Args, FracError> {
+        self.frame_args_at(base, self.position(frame))
+    }
+
+    // Parameters at a point in the animation, zoom moves exponentially so
+    // the apparent speed stays constant. Keyframes stretch over the whole
+    // animation and win over the other motions. Keyed values clap won't
+    // take are blamed on the key they come from.
+    pub fn frame_args_at(&self, base: &Args, frame: f32) -> Result<Args, FracError> {
+        let t = self.time(frame);
+        let mut args = base.clone();
+        if let Some(to) = self.zoom_to {
+            let along = if self.looping {
+                self.loop_time(frame)
+            } else {
+                t
+            };
+            let (from, to) = (base.zoom.log10(), to.log10());
+            args.zoom = 10f32.powf(from + (to - from) * along);
+        }
+        if let Some(spin) = self.spin {
+            let along = if spin % 360.0 == 0.0 || self.looping {
+                self.loop_time(frame)
+            } else {
+                t
+            };
+            args.rotate = base.rotate + spin * along;
+        }
+        let drive = self.audio.as_ref();
+        if let Some(path) = &self.julia_path {
+            let along = if path.closed() {
+                self.loop_time(frame)
+            } else {
+                t
+            };
+            let mut path = *path;
+            if let (Some(drive), JuliaPath::Circle { radius, .. }) = (drive, &mut path) {
+                if drive.target == Target::Radius {
+                    *radius *= 1.0 + drive.level(frame);
+                }
+            }
+            args.julia = Some(path.at(along));
+        }
+        if let Some(keys) = &self.keys {
+            let mut flags: Vec<String> = config::settings(&args)
+                .iter()
+                .map(|(k, v)| config::flag(k, v))
+                .collect();
+            let position = t * keys.last();
+            flags.extend(keys.flags_at(position));
+            let mut keyed = Args::try_from_params(&[], &flags)
+                .map_err(|e| KeyError::BadKey(keys.key_at(position), e))?;
+            keyed.threads = args.threads;
+            args = keyed;
+        }
+        if let Some(drive) = drive {
+            if let Target::Flag(name) = &drive.target {
+                let settings = config::settings(&args);
+                let mut flags: Vec<String> =
+                    settings.iter().map(|(k, v)| config::flag(k, v)).collect();
+                if let Some((_, text)) = settings.iter().find(|(k, _)| k == name) {
+                    let value = text.parse::<f32>().unwrap_or(0.0) + drive.level(frame);
+                    flags.push(config::number_flag(name, value));
+                }
+                let mut driven = Args::try_from_params(&[], &flags)
+                    .map_err(|e| AudioError::BadValue(name.clone(), e))?;
+                driven.threads = args.threads;
+                args = driven;
+            }
+        }
+        // Last, so keyed sizes shrink too
+        if let Some(fit) = self.preview {
+            let scale = (fit.width as f32 / args.width as f32)
+                .min(fit.height as f32 / args.height as f32)
+                .min(1.0);
+            args.width = ((args.width as f32 * scale).round() as i32).max(1);
+            args.height = ((args.height as f32 * scale).round() as i32).max(1);
+            args.samples = 1;
+        }
+        Ok(args)
+    }
+
+    // "-" streams y4m to stdout, .y4m and .rgb/.raw paths become raw video,
+    // .mp4, .mkv, .mov and .webm are encoded by ffmpeg, .png and .apng an
+    // animated png, anything else a frame directory
+    pub fn sink(&self, path: &Path) -> io::Result<Box<dyn FrameSink>> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let is = |name: &str| ext.eq_ignore_ascii_case(name);
+        let video = |path: &Path| -> io::Result<Box<dyn Write>> {
+            Ok(if path == Path::new("-") {
+                Box::new(BufWriter::new(io::stdout()))
+            } else {
+                Box::new(BufWriter::new(File::create(path)?))
+            })
+        };
+        Ok(if path == Path::new("-") || is("y4m") {
+            Box::new(Y4m {
+                out: video(path)?,
+                fps: self.fps,
+                header: false,
+            })
+        } else if is("rgb") || is("raw") {
+            Box::new(RawRgb { out: video(path)? })
+        } else if ["mp4", "mkv", "mov", "webm"].iter().any(|e| is(e)) {
+            Box::new(Ffmpeg {
+                path: path.to_path_buf(),
+                fps: self.fps,
+                crf: self.crf,
+                child: None,
+            })
+        } else if is("png") || is("apng") {
+            Box::new(Apng {
+                path: path.to_path_buf(),
+                fps: self.fps,
+                frames: Vec::new(),
+                args: None,
+            })
+        } else {
+            Box::new(PngSequence {
+                dir: path.to_path_buf(),
+            })
+        })
+    }
+}
+
+// Whether sink writes path as a directory of numbered frames
+pub fn writes_frames(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let files = [
+        "y4m", "rgb", "raw", "mp4", "mkv", "mov", "webm", "png", "apng",
+    ];
+    path != Path::new("-") && !files.iter().any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+// Receives finished frames in order, though a frame directory takes them
+// in any order and with gaps where frames were already done
+pub trait FrameSink {
+    fn frame(&mut self, index: usize, image: Image16, args: &Args) -> Result<(), MetadataError>;
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        Ok(())
+    }
+}
+
+// Numbered png files in a directory
+pub struct PngSequence {
+    pub dir: PathBuf,
+}
+
+impl FrameSink for PngSequence {
+    fn frame(&mut self, index: usize, image: Image16, args: &Args) -> Result<(), MetadataError> {
+        fs::create_dir_all(&self.dir)?;
+        metadata::save_png(manifest::frame_path(&self.dir, index), &image, args)
+    }
+}
+
+// Animated png, frames are kept in memory so this is meant for short loops
+pub struct Apng {
+    pub path: PathBuf,
+    pub fps: u16,
+    pub frames: Vec<Image16>,
+    pub args: Option<Args>,
+}
+
+impl FrameSink for Apng {
+    fn frame(&mut self, index: usize, image: Image16, args: &Args) -> Result<(), MetadataError> {
+        if index == 0 {
+            self.args = Some(args.clone());
+        }
+        self.frames.push(image);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        let (first, args) = match (self.frames.first(), &self.args) {
+            (Some(first), Some(args)) => (first, args),
+            _ => return Ok(()),
+        };
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut encoder = metadata::encoder(file, first.width(), first.height(), args)?;
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        encoder.set_frame_delay(1, self.fps)?;
+        let mut writer = encoder.write_header()?;
+        for frame in &self.frames {
+            let data: Vec<u8> = frame
+                .as_raw()
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect();
+            writer.write_image_data(&data)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn rgb8(image: &Image16, x: u32, y: u32) -> [f32; 3] {
+    let p = image.get_pixel(x, y).0;
+    [p[0], p[1], p[2]].map(|v| (v >> 8) as f32 / 255.0)
+}
+
+// yuv4mpeg2 with BT.709 limited range 4:2:0, the frame rate goes in the
+// header so `ffmpeg -i -` needs no extra flags
+pub struct Y4m {
+    pub out: Box<dyn Write>,
+    pub fps: u16,
+    pub header: bool,
+}
+
+impl FrameSink for Y4m {
+    fn frame(&mut self, _index: usize, image: Image16, _args: &Args) -> Result<(), MetadataError> {
+        let (w, h) = (image.width(), image.height());
+        if !self.header {
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+                w, h, self.fps
+            )?;
+            self.header = true;
+        }
+        let luma = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let mut y_plane = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            for x in 0..w {
+                y_plane.push((16.0 + 219.0 * luma(rgb8(&image, x, y))).round() as u8);
+            }
+        }
+        // Chroma is averaged over each 2x2 block, edge blocks use what's there
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let mut u_plane = Vec::with_capacity((cw * ch) as usize);
+        let mut v_plane = Vec::with_capacity((cw * ch) as usize);
+        for cy in 0..ch {
+            for cx in 0..cw {
+                let mut sum = [0.0; 3];
+                let mut n = 0.0;
+                for y in 2 * cy..(2 * cy + 2).min(h) {
+                    for x in 2 * cx..(2 * cx + 2).min(w) {
+                        let p = rgb8(&image, x, y);
+                        sum = [0, 1, 2].map(|i| sum[i] + p[i]);
+                        n += 1.0;
+                    }
+                }
+                let [r, g, b] = sum.map(|v| v / n);
+                let y = luma([r, g, b]);
+                u_plane.push((128.0 + 224.0 * (b - y) / 1.8556).round() as u8);
+                v_plane.push((128.0 + 224.0 * (r - y) / 1.5748).round() as u8);
+            }
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&y_plane)?;
+        self.out.write_all(&u_plane)?;
+        self.out.write_all(&v_plane)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+// Headerless packed rgb24, for `ffmpeg -f rawvideo -pix_fmt rgb24`
+pub struct RawRgb {
+    pub out: Box<dyn Write>,
+}
+
+impl FrameSink for RawRgb {
+    fn frame(&mut self, _index: usize, image: Image16, _args: &Args) -> Result<(), MetadataError> {
+        let data: Vec<u8> = image
+            .pixels()
+            .flat_map(|p| [p.0[0], p.0[1], p.0[2]].map(|v| (v >> 8) as u8))
+            .collect();
+        Ok(self.out.write_all(&data)?)
+    }
+
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+// Pipes full 16 bit frames into ffmpeg, which converts them to 4:2:0 with
+// the BT.709 matrix, or BT.2020's for rec2020, and tags the primaries and
+// the sRGB transfer curve the frames were rendered with
+pub struct Ffmpeg {
+    pub path: PathBuf,
+    pub fps: u16,
+    pub crf: u8,
+    pub child: Option<Child>,
+}
+
+impl Ffmpeg {
+    fn spawn(&self, width: u32, height: u32, colorspace: Colorspace) -> io::Result<Child> {
+        let (primaries, matrix) = match colorspace {
+            Colorspace::Srgb => ("bt709", "bt709"),
+            Colorspace::DisplayP3 => ("smpte432", "bt709"),
+            Colorspace::Rec2020 => ("bt2020", "bt2020nc"),
+        };
+        let webm = self
+            .path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("webm"));
+        let mut ffmpeg = process::Command::new("ffmpeg");
+        ffmpeg
+            .args(["-hide_banner", "-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb48le"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &self.fps.to_string(), "-i", "-"])
+            .args(["-c:v", if webm { "libvpx-vp9" } else { "libx264" }])
+            .args(["-crf", &self.crf.to_string()]);
+        if webm {
+            // Constant quality, vp9 otherwise treats crf as a bitrate cap
+            ffmpeg.args(["-b:v", "0"]);
+        }
+        ffmpeg
+            .args([
+                "-vf",
+                &format!("scale=out_color_matrix={}:out_range=tv", matrix),
+            ])
+            .args(["-pix_fmt", "yuv420p", "-colorspace", matrix])
+            .args(["-color_primaries", primaries, "-color_trc", "iec61966-2-1"])
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    e.kind(),
+                    "ffmpeg isn't on the PATH, install it or write frames to a directory",
+                ),
+                _ => e,
+            })
+    }
+}
+
+impl FrameSink for Ffmpeg {
+    fn frame(&mut self, _index: usize, image: Image16, args: &Args) -> Result<(), MetadataError> {
+        if self.child.is_none() {
+            self.child = Some(self.spawn(image.width(), image.height(), args.colorspace)?);
+        }
+        let data: Vec<u8> = image
+            .pixels()
+            .flat_map(|p| [p.0[0], p.0[1], p.0[2]])
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let stdin = self.child.as_mut().and_then(|c| c.stdin.as_mut());
+        match stdin {
+            Some(stdin) => Ok(stdin.write_all(&data)?),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+        // Closing stdin is what tells ffmpeg the video is over
+        drop(child.stdin.take());
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg failed with {}", status)).into())
+        }
+    }
+}
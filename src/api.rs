@@ -0,0 +1,415 @@
+// fracmd
+// An HTTP API rendering json parameter documents, for `fracmd serve-api`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A parameter document is a json object of flags like --params-stdin
+// takes, {"zoom": 40, "origin": "-0.745+0.11i", "format": "gif"}, over the
+// flags the server was started with:
+//
+//   POST /render          renders it and answers with the image
+//   POST /jobs            queues it, answering 202 with {"id": "..."}
+//   GET  /jobs/<id>       {"status": "queued" | "rendering" | "done" |
+//                         "failed", "done": tiles, "total": tiles} and
+//                         "error" once failed
+//   GET  /jobs/<id>/image the image once done
+//
+// Only flags saying what to render are taken, nothing naming files to read
+// or write or commands to run. --max-pixels caps how big a render can get
+// and --max-limit how long each pixel can take, connections that stop
+// sending are dropped, only so many are answered at once and request lines
+// and headers are held to serve::read_request's caps, so the server is
+// safe to put in front of strangers. Bad documents get a 400 with
+// {"error": "..."}. Queued jobs render one at a time, the --keep most
+// recent finished ones are kept for polling, and a job posted while
+// MOST_PENDING are waiting gets a 503.
+
+use serde_json::{json, Map, Value};
+use std::{
+    collections::VecDeque,
+    io::{BufReader, Read},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    cli, config, default_functs, metrics,
+    output::write_output,
+    scheduler::{self, TILE_SIZE},
+    serve::{self, Response, ServeError},
+    validate, value_name, Args, Format, FracError, Renderer, PARAM_KEYS,
+};
+
+// Flags a document can give besides the render parameters in PARAM_KEYS
+const EXTRA: &[&str] = &[
+    "re",
+    "im",
+    "zoom-exp",
+    "stretch",
+    "skew",
+    "region",
+    "autocenter",
+    "deterministic",
+    "format",
+    "colors",
+    "quality",
+    "creator",
+];
+
+// Longest request body read
+const MOST_BODY: usize = 1 << 20;
+// Jobs queued or rendering at once, more are turned away with a 503
+const MOST_PENDING: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Queued,
+    Rendering,
+    Done,
+    Failed,
+}
+
+struct Job {
+    id: String,
+    status: Status,
+    done: usize,
+    total: usize,
+    image: Option<(&'static str, Vec<u8>)>,
+    error: Option<String>,
+}
+
+struct Server {
+    args: Args,
+    max_pixels: u64,
+    max_limit: f32,
+    keep: usize,
+    jobs: Mutex<VecDeque<Job>>,
+    queue: Mutex<mpsc::Sender<(String, Args)>>,
+}
+
+fn error(status: &'static str, message: String) -> Response {
+    Response::json(status, json!({ "error": message }))
+}
+
+fn content_type(format: Format) -> &'static str {
+    match format {
+        Format::Png | Format::IndexedPng => "image/png",
+        Format::Gif => "image/gif",
+        Format::Jpeg => "image/jpeg",
+        Format::Webp => "image/webp",
+        Format::Ppm | Format::Pam => "image/x-portable-anymap",
+        _ => "application/octet-stream",
+    }
+}
+
+// The job a document asks for, or why it can't be rendered
+fn parse(server: &Server, body: &[u8]) -> Result<Args, String> {
+    let document: Map<String, Value> = match serde_json::from_slice(body) {
+        Ok(Value::Object(document)) => document,
+        Ok(_) => return Err(String::from("the document must be a json object")),
+        Err(e) => return Err(format!("the document isn't json: {}", e)),
+    };
+    let allowed: Vec<&str> = PARAM_KEYS
+        .iter()
+        .chain(EXTRA)
+        .copied()
+        .filter(|k| *k != "threads")
+        .collect();
+    if let Some(key) = document
+        .keys()
+        .find(|k| !allowed.contains(&k.replace('_', "-").as_str()))
+    {
+        return Err(format!("{} can't be set through the api", key));
+    }
+    let flags = config::from_json(body).map_err(|e| e.to_string())?;
+    let mut argv = vec![String::from("fracmd")];
+    argv.extend(
+        config::settings(&server.args)
+            .iter()
+            .map(|(k, v)| config::flag(k, v)),
+    );
+    argv.extend(flags);
+    let mut job = Args::try_parse_argv(argv)
+        .map_err(|e| e.to_string().lines().next().unwrap_or_default().to_string())?;
+    job.threads = server.args.threads;
+    if let Some(problem) = validate::check(&job).first() {
+        return Err(problem.to_string());
+    }
+    if matches!(job.format, Format::Exr | Format::Tiff) {
+        return Err(format!(
+            "{} can't be sent back, use png, indexed-png, gif, jpeg, webp, ppm, pam or farbfeld",
+            value_name(job.format)
+        ));
+    }
+    let pixels = job.width as u64 * job.height as u64 * job.samples as u64;
+    if pixels > server.max_pixels {
+        return Err(format!(
+            "{}x{} with {} samples is over the {} pixels --max-pixels allows",
+            job.width, job.height, job.samples, server.max_pixels
+        ));
+    }
+    if job.limit > server.max_limit {
+        return Err(format!(
+            "a limit of {} is over the {} --max-limit allows",
+            job.limit, server.max_limit
+        ));
+    }
+    cli::framed(&job).map_err(|e| e.to_string())
+}
+
+// The image in the job's format, progress getting tiles done and the total
+fn render<F: Fn(usize, usize) + Sync>(
+    job: &Args,
+    progress: F,
+) -> Result<(&'static str, Vec<u8>), FracError> {
+    let renderer = Renderer::new(job.clone(), default_functs(job));
+    let tiles = scheduler::tiles(job.width as u32, job.height as u32, TILE_SIZE);
+    progress(0, tiles.len());
+    let image = renderer.render_with(|p| progress(p.done, p.total));
+    let mut bytes = Vec::new();
+    write_output(job, &mut bytes, &image)?;
+    Ok((content_type(job.format), bytes))
+}
+
+fn update<F: FnOnce(&mut Job)>(server: &Server, id: &str, f: F) {
+    if let Some(job) = server.jobs.lock().unwrap().iter_mut().find(|j| j.id == id) {
+        f(job);
+    }
+}
+
+// Renders queued jobs one after another
+fn work(server: Arc<Server>, queue: mpsc::Receiver<(String, Args)>) {
+    for (id, job) in queue {
+        metrics::QUEUE.add(-1);
+        metrics::IN_FLIGHT.set(1);
+        update(&server, &id, |j| j.status = Status::Rendering);
+        let rendered = render(&job, |done, total| {
+            update(&server, &id, |j| (j.done, j.total) = (done, total))
+        });
+        metrics::IN_FLIGHT.set(0);
+        match rendered {
+            Ok(_) => metrics::JOBS_DONE.inc(),
+            Err(_) => metrics::JOBS_FAILED.inc(),
+        }
+        update(&server, &id, |j| match rendered {
+            Ok(image) => {
+                j.status = Status::Done;
+                j.image = Some(image);
+            }
+            Err(e) => {
+                j.status = Status::Failed;
+                j.error = Some(e.to_string());
+            }
+        });
+        // Forgets the oldest finished jobs past --keep
+        let mut jobs = server.jobs.lock().unwrap();
+        let finished = |j: &Job| matches!(j.status, Status::Done | Status::Failed);
+        while jobs.iter().filter(|j| finished(j)).count() > server.keep {
+            let oldest = jobs.iter().position(finished).unwrap();
+            jobs.remove(oldest);
+        }
+    }
+}
+
+fn status(job: &Job) -> Value {
+    let name = match job.status {
+        Status::Queued => "queued",
+        Status::Rendering => "rendering",
+        Status::Done => "done",
+        Status::Failed => "failed",
+    };
+    let mut value = json!({
+        "id": job.id,
+        "status": name,
+        "done": job.done,
+        "total": job.total,
+    });
+    if let Some(e) = &job.error {
+        value["error"] = json!(e);
+    }
+    value
+}
+
+fn route(server: &Server, method: &str, path: &str, body: &[u8]) -> Response {
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, parts.as_slice()) {
+        ("GET", [""]) => Response::text(
+            "200 OK",
+            String::from(
+                "POST a json parameter document to /render for the image, or to /jobs to poll /jobs/<id>\n",
+            ),
+        ),
+        ("POST", ["render"]) => match parse(server, body) {
+            Ok(job) => match render(&job, |_, _| ()) {
+                Ok((kind, body)) => Response {
+                    status: "200 OK",
+                    kind,
+                    cache: false,
+                    body,
+                },
+                Err(e) => error("500 Internal Server Error", e.to_string()),
+            },
+            Err(e) => error("400 Bad Request", e),
+        },
+        ("POST", ["jobs"]) => match parse(server, body) {
+            Ok(job) => {
+                let id = format!("{:016x}", rand::random::<u64>());
+                let entry = Job {
+                    id: id.clone(),
+                    status: Status::Queued,
+                    done: 0,
+                    total: 0,
+                    image: None,
+                    error: None,
+                };
+                let value = status(&entry);
+                let mut jobs = server.jobs.lock().unwrap();
+                let pending = jobs
+                    .iter()
+                    .filter(|j| matches!(j.status, Status::Queued | Status::Rendering))
+                    .count();
+                if pending >= MOST_PENDING {
+                    return error(
+                        "503 Service Unavailable",
+                        format!("{} jobs are waiting already, try again later", pending),
+                    );
+                }
+                jobs.push_back(entry);
+                drop(jobs);
+                metrics::QUEUE.add(1);
+                server.queue.lock().unwrap().send((id, job)).unwrap();
+                Response::json("202 Accepted", value)
+            }
+            Err(e) => error("400 Bad Request", e),
+        },
+        ("GET", ["jobs", id]) => match server.jobs.lock().unwrap().iter().find(|j| j.id == *id) {
+            Some(job) => Response::json("200 OK", status(job)),
+            None => error("404 Not Found", format!("no job {}", id)),
+        },
+        ("GET", ["jobs", id, "image"]) => {
+            match server.jobs.lock().unwrap().iter().find(|j| j.id == *id) {
+                Some(Job {
+                    image: Some((kind, body)),
+                    ..
+                }) => Response {
+                    status: "200 OK",
+                    kind,
+                    cache: false,
+                    body: body.clone(),
+                },
+                Some(job) => error(
+                    "409 Conflict",
+                    format!("job {} isn't done", job.id),
+                ),
+                None => error("404 Not Found", format!("no job {}", id)),
+            }
+        }
+        ("GET" | "POST", _) => error("404 Not Found", format!("nothing at {}", path)),
+        _ => error("405 Method Not Allowed", String::from("only GET and POST")),
+    }
+}
+
+fn answer(server: &Server, stream: TcpStream) -> Result<(), ServeError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = serve::read_request(&mut reader)?;
+    let length = request
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let words: Vec<&str> = request.words.iter().map(String::as_str).collect();
+    let response = match words.as_slice() {
+        _ if length > MOST_BODY => error(
+            "413 Payload Too Large",
+            format!("documents can be at most {} bytes", MOST_BODY),
+        ),
+        [method, path, _] => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            route(server, method, path, &body)
+        }
+        _ => error("400 Bad Request", String::from("not an http request")),
+    };
+    Ok(response.send(stream)?)
+}
+
+pub fn run(
+    args: &Args,
+    host: &str,
+    port: u16,
+    max_pixels: u64,
+    max_limit: f32,
+    keep: usize,
+) -> Result<(), FracError> {
+    let address = format!("{}:{}", host, port);
+    let listener =
+        TcpListener::bind(&address).map_err(|e| ServeError::Listen(address.clone(), e))?;
+    let (queue, queued) = mpsc::channel();
+    let server = Arc::new(Server {
+        args: args.clone(),
+        max_pixels,
+        max_limit,
+        keep,
+        jobs: Mutex::new(VecDeque::new()),
+        queue: Mutex::new(queue),
+    });
+    let worker = server.clone();
+    thread::spawn(move || work(worker, queued));
+    args.log(&format!("Serving the render api on http://{}", address));
+    serve::accept(listener, move |stream| answer(&server, stream));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        Server {
+            args: Args::parse_argv(["fracmd", "--width=64", "--height=48"].map(String::from)),
+            max_pixels: 1 << 20,
+            max_limit: 4096.0,
+            keep: 4,
+            jobs: Mutex::new(VecDeque::new()),
+            queue: Mutex::new(mpsc::channel().0),
+        }
+    }
+
+    #[test]
+    fn render_parameters_the_server_left_unset_can_be_given() {
+        let job = parse(
+            &server(),
+            br#"{"bail": 8, "sampler": "adaptive", "samples": 8}"#,
+        )
+        .unwrap();
+        assert_eq!(job.bail, Some(8.0));
+        assert_eq!(job.sampler, crate::sampler::SamplerKind::Adaptive);
+    }
+
+    #[test]
+    fn other_flags_are_refused() {
+        for document in [
+            r#"{"output": "x.png"}"#,
+            r#"{"threads": 64}"#,
+            r#"{"plugin": "a.so"}"#,
+        ] {
+            assert!(
+                parse(&server(), document.as_bytes()).is_err(),
+                "{}",
+                document
+            );
+        }
+    }
+}
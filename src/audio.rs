@@ -0,0 +1,284 @@
+// fracmd
+// Driving animations from the loudness of a wav file for --audio
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The envelope is the RMS loudness of every frame's stretch of audio, all
+// channels mixed, scaled so the loudest frame is 1. It jumps up at once
+// and falls back over --audio-smooth seconds, so beats hit on their frame
+// and then fade rather than flicker. What it drives gets offset plus gain
+// times the envelope.
+
+use err_derive::Error;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{config, error, FracError};
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "not a wav file: {}", _0)]
+    NotWav(&'static str),
+    #[error(display = "{} bit wav files of format {} aren't supported", _0, _1)]
+    Unsupported(u16, u16),
+    #[error(display = "--audio-drives {} isn't a flag with a number value", _0)]
+    NotNumeric(String),
+    #[error(display = "`{}` can't drive --audio-drives {}", _0, _1)]
+    CantDrive(&'static str, String),
+    // The driven flag and clap's complaint about where the audio took it
+    #[error(display = "--audio-drives {}: {}", _0, _1)]
+    BadValue(String, String),
+}
+
+// What the envelope moves
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    // How fast the animation moves through everything else it animates
+    Speed,
+    // The radius of a circling --julia-path, scaled by 1 plus the level
+    Radius,
+    // Where a color cycle is along the palette, on top of its motion
+    Phase,
+    // Added to a flag with a number value
+    Flag(String),
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Target::Speed => write!(f, "speed"),
+            Target::Radius => write!(f, "radius"),
+            Target::Phase => write!(f, "phase"),
+            Target::Flag(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        Ok(match string {
+            "speed" => Target::Speed,
+            "radius" => Target::Radius,
+            "phase" => Target::Phase,
+            flag => Target::Flag(flag.trim_start_matches('-').to_string()),
+        })
+    }
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct AudioArgs {
+    /// Wav file whose loudness drives --audio-drives, --frames defaults to
+    /// its length
+    #[clap(long)]
+    pub audio: Option<PathBuf>,
+
+    /// speed, the radius of a circling --julia-path, the palette phase of
+    /// a cycle, or any flag with a number value
+    #[clap(long, default_value = "speed")]
+    pub audio_drives: Target,
+
+    /// How much the loudest moment adds
+    #[clap(long, default_value = "1", allow_hyphen_values = true)]
+    pub audio_gain: f32,
+
+    /// What silence leaves it at
+    #[clap(long, default_value = "0", allow_hyphen_values = true)]
+    pub audio_offset: f32,
+
+    /// Seconds a beat takes to fade
+    #[clap(long, default_value = "0.2")]
+    pub audio_smooth: f32,
+}
+
+impl AudioArgs {
+    // The drive for a command's animation at fps, if it can move the target
+    pub fn drive(
+        &self,
+        command: &'static str,
+        fps: u16,
+        targets: &[Target],
+        flags: bool,
+    ) -> Result<Option<Drive>, FracError> {
+        let Some(path) = &self.audio else {
+            return Ok(None);
+        };
+        let target = self.audio_drives.clone();
+        let flag = flags && matches!(target, Target::Flag(_));
+        if !(flag || targets.contains(&target)) {
+            return Err(AudioError::CantDrive(command, target.to_string()).into());
+        }
+        Ok(Some(Drive {
+            target,
+            envelope: envelope(path, fps, self.audio_smooth).map_err(error::at(path))?,
+            offset: self.audio_offset,
+            gain: self.audio_gain,
+        }))
+    }
+}
+
+pub struct Drive {
+    pub target: Target,
+    // One value per frame in 0..=1
+    pub envelope: Vec<f32>,
+    pub offset: f32,
+    pub gain: f32,
+}
+
+impl Drive {
+    // The driven value at a point in the animation, between frames too
+    pub fn level(&self, frame: f32) -> f32 {
+        let last = self.envelope.len().saturating_sub(1);
+        let frame = frame.clamp(0.0, last as f32);
+        let (i, f) = (frame.floor() as usize, frame.fract());
+        let a = self.envelope.get(i).copied().unwrap_or(0.0);
+        let b = self.envelope.get((i + 1).min(last)).copied().unwrap_or(a);
+        self.offset + self.gain * (a + (b - a) * f)
+    }
+
+    // A flag target has to be a number, which only the settings can tell
+    pub fn check(&self, settings: &[(&str, String)]) -> Result<(), AudioError> {
+        if let Target::Flag(name) = &self.target {
+            let numeric = settings
+                .iter()
+                .find(|(k, _)| k == name)
+                .is_some_and(|(_, v)| v.parse::<f32>().is_ok());
+            if config::takes_value(name) != Some(true) || !numeric {
+                return Err(AudioError::NotNumeric(name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Wav {
+    rate: u32,
+    channels: usize,
+    // Interleaved, in -1..1
+    samples: Vec<f32>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+// PCM at 8, 16, 24 or 32 bits and 32 bit float, extensible headers too
+fn read_wav(data: &[u8]) -> Result<Wav, AudioError> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(AudioError::NotWav("no RIFF WAVE header"));
+    }
+    let mut format = None;
+    let mut at = 12;
+    while at + 8 <= data.len() {
+        let size = u32_at(data, at + 4) as usize;
+        let body = at + 8;
+        let end = (body + size).min(data.len());
+        match &data[at..at + 4] {
+            b"fmt " if size >= 16 && end - body >= 16 => {
+                let mut tag = u16_at(data, body);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real tag in its sub format
+                if tag == 0xfffe && size >= 26 && end - body >= 26 {
+                    tag = u16_at(data, body + 24);
+                }
+                let channels = u16_at(data, body + 2) as usize;
+                let rate = u32_at(data, body + 4);
+                let bits = u16_at(data, body + 14);
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => {
+                let (tag, channels, rate, bits) =
+                    format.ok_or(AudioError::NotWav("data before the fmt chunk"))?;
+                if channels == 0 || rate == 0 {
+                    return Err(AudioError::NotWav("no channels"));
+                }
+                let bytes = &data[body..end];
+                let samples: Vec<f32> = match (tag, bits) {
+                    (1, 8) => bytes.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+                    (1, 16) => bytes
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                        .collect(),
+                    (1, 24) => bytes
+                        .chunks_exact(3)
+                        .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0)
+                        .collect(),
+                    (1, 32) => bytes
+                        .chunks_exact(4)
+                        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0)
+                        .collect(),
+                    (3, 32) => bytes
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                        .collect(),
+                    (tag, bits) => return Err(AudioError::Unsupported(bits, tag)),
+                };
+                return Ok(Wav {
+                    rate,
+                    channels,
+                    samples,
+                });
+            }
+            _ => (),
+        }
+        // Chunks are padded to an even length
+        at = body + size + (size & 1);
+    }
+    Err(AudioError::NotWav("no data chunk"))
+}
+
+// The envelope at fps, one value per frame the audio lasts
+pub fn envelope<P: AsRef<Path>>(path: P, fps: u16, smooth: f32) -> Result<Vec<f32>, AudioError> {
+    let wav = read_wav(&fs::read(path)?)?;
+    let per_frame = wav.rate as f64 / fps.max(1) as f64;
+    let frames = wav.samples.len() / wav.channels;
+    let count = (frames as f64 / per_frame).ceil() as usize;
+    let mut levels: Vec<f32> = (0..count)
+        .map(|i| {
+            let start = (i as f64 * per_frame) as usize;
+            let end = (((i + 1) as f64 * per_frame) as usize).min(frames);
+            let square: f64 = wav.samples[start * wav.channels..end * wav.channels]
+                .iter()
+                .map(|&v| (v as f64).powi(2))
+                .sum();
+            (square / ((end - start) * wav.channels).max(1) as f64).sqrt() as f32
+        })
+        .collect();
+    let release = if smooth > 0.0 {
+        (-1.0 / (smooth * fps as f32)).exp()
+    } else {
+        0.0
+    };
+    let mut held: f32 = 0.0;
+    for level in levels.iter_mut() {
+        held = level.max(held * release);
+        *level = held;
+    }
+    let loudest = levels.iter().cloned().fold(0.0, f32::max);
+    if loudest > 0.0 {
+        for level in levels.iter_mut() {
+            *level /= loudest;
+        }
+    }
+    Ok(levels)
+}
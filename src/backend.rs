@@ -0,0 +1,182 @@
+// fracmd
+// Render backends, what turns a tile of the view into pixels
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The renderer schedules tiles, a sampler picks the points of each one, a
+// backend iterates them and the renderer colors what it found. Scalar
+// iterates every point on its own through the Fractal trait. Simd runs the
+// built-in z^2 + c orbits with the square bailout LANES points at a time,
+// in plain arrays the compiler turns into vector instructions; any other
+// formula or bailout it hands to scalar. It does the same float operations
+// in the same order, so the two give the same image bit for bit and the
+// choice is only about speed.
+//
+// --backend auto picks the fastest there is, which is simd. Nothing drives
+// a GPU yet: gpu is taken by clap so asking for it gets a clear no from
+// validate, rather than a render on the CPU someone thinks is on the GPU.
+
+use clap::ValueEnum;
+use num::complex::Complex;
+use std::sync::Arc;
+
+use crate::{abs, bailout::Norm, PixelData, Renderer};
+
+pub trait RenderBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The iteration stage: what Renderer::iterate finds at each of the
+    // points, in order. The sampler picks the points, a tile's worth at a
+    // time, and the renderer colors what's found.
+    fn iterate(&self, renderer: &Renderer, points: &[Complex<f32>]) -> Vec<PixelData>;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Auto,
+    Scalar,
+    Simd,
+    Gpu,
+}
+
+pub struct Scalar;
+
+impl RenderBackend for Scalar {
+    fn name(&self) -> &'static str {
+        "scalar"
+    }
+
+    fn iterate(&self, renderer: &Renderer, points: &[Complex<f32>]) -> Vec<PixelData> {
+        points.iter().map(|&c| renderer.iterate(c)).collect()
+    }
+}
+
+// Points iterated together, an AVX register of f32s or two of SSE's
+const LANES: usize = 8;
+
+pub struct Simd;
+
+impl RenderBackend for Simd {
+    fn name(&self) -> &'static str {
+        "simd"
+    }
+
+    fn iterate(&self, renderer: &Renderer, points: &[Complex<f32>]) -> Vec<PixelData> {
+        let quadratic = renderer
+            .functs
+            .fractal
+            .as_multibrot()
+            .is_some_and(|m| m.power == 2.0);
+        let bailout = renderer.bailout;
+        if !quadratic || bailout.norm != Norm::Square || bailout.converge.is_some() {
+            return Scalar.iterate(renderer, points);
+        }
+        let mut records = Vec::with_capacity(points.len());
+        for lanes in points.chunks(LANES) {
+            records.extend(lanes_of(renderer, lanes));
+        }
+        records
+    }
+}
+
+// Renderer::iterate for z^2 + c at up to LANES points. Lanes that have
+// escaped keep their values while the rest go on, so each ends where the
+// scalar loop would have stopped it.
+fn lanes_of(renderer: &Renderer, points: &[Complex<f32>]) -> Vec<PixelData> {
+    let (bail, limit, julia) = (
+        renderer.bailout.bail,
+        renderer.args.limit,
+        renderer.args.julia,
+    );
+    let mut zr = [0.0f32; LANES];
+    let mut zi = [0.0f32; LANES];
+    let mut cr = [0.0f32; LANES];
+    let mut ci = [0.0f32; LANES];
+    let mut dr = [1.0f32; LANES];
+    let mut di = [0.0f32; LANES];
+    let mut norm = [0.0f32; LANES];
+    let mut iter = [0.0f32; LANES];
+    let mut s = [0.0f32; LANES];
+    let mut trap = [0.0f32; LANES];
+    let mut active = [false; LANES];
+    for (l, &point) in points.iter().enumerate() {
+        let (z, c) = match julia {
+            Some(julia) => (point, julia),
+            None => (point, point),
+        };
+        (zr[l], zi[l], cr[l], ci[l]) = (z.re, z.im, c.re, c.im);
+        norm[l] = abs(z);
+        trap[l] = norm[l].sqrt();
+        active[l] = norm[l] < bail && 0.0 < limit;
+    }
+    // The derivative of a Julia set is with respect to the starting z, so
+    // it has no + 1
+    let one = match julia {
+        Some(_) => 0.0,
+        None => 1.0,
+    };
+    while active.contains(&true) {
+        // Branch free so it vectorizes, 2z * dz + 1 then z^2 + c as
+        // num's Complex works them out
+        for l in 0..LANES {
+            let (tr, ti) = (2.0 * zr[l], 2.0 * zi[l]);
+            let ndr = tr * dr[l] - ti * di[l] + one;
+            let ndi = tr * di[l] + ti * dr[l];
+            let nzr = zr[l] * zr[l] - zi[l] * zi[l] + cr[l];
+            let nzi = zr[l] * zi[l] + zi[l] * zr[l] + ci[l];
+            let keep = active[l];
+            dr[l] = if keep { ndr } else { dr[l] };
+            di[l] = if keep { ndi } else { di[l] };
+            zr[l] = if keep { nzr } else { zr[l] };
+            zi[l] = if keep { nzi } else { zi[l] };
+            norm[l] = zr[l] * zr[l] + zi[l] * zi[l];
+            iter[l] += if keep { 1.0 } else { 0.0 };
+            trap[l] = if keep {
+                trap[l].min(norm[l].sqrt())
+            } else {
+                trap[l]
+            };
+        }
+        // exp has no vector instruction, only the lanes still going pay
+        // for it
+        for l in 0..LANES {
+            if active[l] {
+                s[l] += (-norm[l]).exp();
+                active[l] = norm[l] < bail && iter[l] < limit;
+            }
+        }
+    }
+    (0..points.len())
+        .map(|l| {
+            renderer.escaped(
+                iter[l],
+                s[l],
+                Complex::new(zr[l], zi[l]),
+                Complex::new(dr[l], di[l]),
+                trap[l],
+                None,
+            )
+        })
+        .collect()
+}
+
+// The backend asked for. validate refuses gpu, so a renderer is only ever
+// made with it by a program that skipped the checks, and gets simd.
+pub fn select(backend: Backend) -> Arc<dyn RenderBackend> {
+    match backend {
+        Backend::Scalar => Arc::new(Scalar),
+        Backend::Simd | Backend::Auto | Backend::Gpu => Arc::new(Simd),
+    }
+}
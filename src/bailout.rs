@@ -0,0 +1,181 @@
+// fracmd
+// When an orbit stops: escaping past the bailout, or converging
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// An orbit has escaped once the norm of z reaches --bail. Every norm is
+// squared, so --bail 16 is a radius of 4 whichever is measured:
+//
+//   square     re^2 + im^2, the distance from 0
+//   manhattan  (|re| + |im|)^2
+//   max        the larger of re^2 and im^2, |re| or |im| past the radius
+//   real       re^2
+//   imag       im^2
+//
+// Formulas know the bailout they want, so when --bail or --bail-norm isn't
+// given the fractal's own is used, 16 and square for the multibrot. With
+// --converge eps, or a formula that converges like Newton's method, an
+// orbit also stops once a step moves z less than eps, whichever of the two
+// happens first. Those get a smooth count of their own: steps shrink
+// about as d' = d^p close to a root, so the last two steps say where
+// between them the step size went past eps.
+
+use clap::ValueEnum;
+use num::complex::Complex;
+
+use crate::{fractal::Fractal, Args};
+
+// The multibrot's, and any formula's that doesn't say
+pub const DEFAULT_BAIL: f32 = 16.0;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Norm {
+    Square,
+    Manhattan,
+    Max,
+    Real,
+    Imag,
+}
+
+impl Norm {
+    pub fn of(self, z: Complex<f32>) -> f32 {
+        match self {
+            Norm::Square => z.re * z.re + z.im * z.im,
+            Norm::Manhattan => {
+                let m = z.re.abs() + z.im.abs();
+                m * m
+            }
+            Norm::Max => (z.re * z.re).max(z.im * z.im),
+            Norm::Real => z.re * z.re,
+            Norm::Imag => z.im * z.im,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bailout {
+    pub norm: Norm,
+    pub bail: f32,
+    // Squared, like the norms
+    pub converge: Option<f32>,
+}
+
+impl Bailout {
+    // The flags', with the fractal's where they aren't given
+    pub fn resolve(args: &Args, fractal: &dyn Fractal) -> Bailout {
+        Bailout {
+            norm: args.bail_norm.unwrap_or_else(|| fractal.bail_norm()),
+            bail: args.bail.unwrap_or_else(|| fractal.bail()),
+            converge: args
+                .converge
+                .or_else(|| fractal.converge())
+                .map(|eps| eps * eps),
+        }
+    }
+
+    // Written so anything that isn't a number stops the orbit, as the
+    // plain |z|^2 < bail did
+    pub fn inside(&self, z: Complex<f32>) -> bool {
+        self.norm.of(z) < self.bail
+    }
+
+    // How far a step moved z, squared
+    pub fn step(last: Complex<f32>, z: Complex<f32>) -> f32 {
+        let d = z - last;
+        d.re * d.re + d.im * d.im
+    }
+
+    pub fn converged(&self, step: f32) -> bool {
+        self.converge.is_some_and(|eps| step < eps)
+    }
+
+    // The smooth count of an orbit that converged after i steps, the last
+    // two of them this long. Steps that don't shrink like that, or an orbit
+    // that converged straight away, keep the plain count.
+    pub fn smooth(&self, i: f32, before: f32, last: f32) -> f32 {
+        let Some(eps) = self.converge else {
+            return i;
+        };
+        let t = (eps.ln() / before.ln()).ln() / (last.ln() / before.ln()).ln();
+        if t.is_finite() && (0.0..=1.0).contains(&t) {
+            i - 1.0 + t
+        } else {
+            i
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // --bail 16 is a radius of 4 measured each norm's way: the points just
+    // inside it stay and the ones on it escape
+    #[test]
+    fn each_norm_escapes_at_its_radius() {
+        let cases = [
+            (
+                Norm::Square,
+                [(2.8, 2.8), (3.99, 0.0)],
+                [(2.83, 2.83), (0.0, -4.0)],
+            ),
+            (
+                Norm::Manhattan,
+                [(2.0, -1.99), (0.0, 3.99)],
+                [(2.0, -2.0), (-4.0, 0.0)],
+            ),
+            (
+                Norm::Max,
+                [(3.99, -3.99), (-3.99, 0.0)],
+                [(3.99, 4.0), (-4.0, 0.0)],
+            ),
+            (
+                Norm::Real,
+                [(3.99, 100.0), (-3.99, 0.0)],
+                [(-4.0, 0.0), (4.0, 0.0)],
+            ),
+            (
+                Norm::Imag,
+                [(100.0, 3.99), (0.0, -3.99)],
+                [(0.0, -4.0), (0.0, 4.0)],
+            ),
+        ];
+        for (norm, inside, escaped) in cases {
+            let bailout = Bailout {
+                norm,
+                bail: DEFAULT_BAIL,
+                converge: None,
+            };
+            for (re, im) in inside {
+                assert!(
+                    bailout.inside(Complex::new(re, im)),
+                    "{:?} {} {}",
+                    norm,
+                    re,
+                    im
+                );
+            }
+            for (re, im) in escaped {
+                assert!(
+                    !bailout.inside(Complex::new(re, im)),
+                    "{:?} {} {}",
+                    norm,
+                    re,
+                    im
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// fracmd
+// Batch rendering from a job file
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Job files are json, either a bare list of jobs or { "jobs": [ ... ] },
+// where every job is an object of long flag names to values, e.g.
+//
+//   { "name": "seahorse", "origin": "-0.745+0.11i", "zoom": 40, "aovs": true }
+//
+// Flags a job doesn't give fall back to the ones passed before `batch`, so
+// a templated --output like "renders/{name}.png" names every job's file.
+
+use err_derive::Error;
+use serde_json::{json, Value};
+use std::{fs, path::Path, time::Duration};
+
+use crate::{config, validate, Args};
+
+#[derive(Debug, Error)]
+pub enum BatchError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "job {}: {}", _0, _1)]
+    BadJob(usize, String),
+}
+
+// Json scalars as the text clap would get on the command line, false
+// switches are left out
+fn job_flag(index: usize, key: &str, value: &Value) -> Result<Option<String>, BatchError> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(true) => String::from("true"),
+        Value::Bool(false) => return Ok(None),
+        _ => {
+            return Err(BatchError::BadJob(
+                index,
+                format!("\"{}\" must be a string, number or boolean", key),
+            ))
+        }
+    };
+    Ok(Some(config::flag(key, &text)))
+}
+
+// Every job's full parameters, parsed before anything renders so a typo
+// late in the file doesn't waste a night
+pub fn load<P: AsRef<Path>>(path: P, base: &Args) -> Result<Vec<Args>, BatchError> {
+    let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let jobs = match &json {
+        Value::Array(jobs) => jobs,
+        Value::Object(file) => match file.get("jobs") {
+            Some(Value::Array(jobs)) => jobs,
+            _ => return Err(BatchError::BadJob(0, String::from("no \"jobs\" list"))),
+        },
+        _ => return Err(BatchError::BadJob(0, String::from("not a list of jobs"))),
+    };
+    let inherited: Vec<String> = config::settings(base)
+        .iter()
+        .map(|(key, value)| config::flag(key, value))
+        .collect();
+    jobs.iter()
+        .enumerate()
+        .map(|(i, job)| {
+            let job = job
+                .as_object()
+                .ok_or_else(|| BatchError::BadJob(i + 1, String::from("not an object")))?;
+            let mut flags = inherited.clone();
+            for (key, value) in job {
+                flags.extend(job_flag(i + 1, key, value)?);
+            }
+            let args =
+                Args::try_from_params(&[], &flags).map_err(|e| BatchError::BadJob(i + 1, e))?;
+            match validate::check(&args).first() {
+                Some(problem) => Err(BatchError::BadJob(i + 1, problem.to_string())),
+                None => Ok(args),
+            }
+        })
+        .collect()
+}
+
+pub struct Outcome {
+    pub name: String,
+    pub output: Option<String>,
+    pub time: Duration,
+    pub error: Option<String>,
+}
+
+pub fn summary(outcomes: &[Outcome]) -> String {
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    let total: Duration = outcomes.iter().map(|o| o.time).sum();
+    let mut text = String::new();
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let result = match (&outcome.error, &outcome.output) {
+            (Some(e), _) => format!("FAILED {}", e),
+            (None, Some(output)) => output.clone(),
+            (None, None) => String::new(),
+        };
+        text.push_str(&format!(
+            "{:>4} {:<24} {:>9.1}s  {}\n",
+            i + 1,
+            outcome.name,
+            outcome.time.as_secs_f64(),
+            result
+        ));
+    }
+    text.push_str(&format!(
+        "{} of {} jobs rendered in {:.1}s",
+        outcomes.len() - failed,
+        outcomes.len(),
+        total.as_secs_f64()
+    ));
+    if failed > 0 {
+        text.push_str(&format!(", {} failed", failed));
+    }
+    text
+}
+
+pub fn save_report<P: AsRef<Path>>(path: P, outcomes: &[Outcome]) -> Result<(), BatchError> {
+    let jobs: Vec<Value> = outcomes
+        .iter()
+        .map(|o| {
+            json!({
+                "name": o.name,
+                "output": o.output,
+                "seconds": o.time.as_secs_f64(),
+                "error": o.error,
+            })
+        })
+        .collect();
+    Ok(fs::write(
+        path,
+        serde_json::to_string_pretty(&json!({ "jobs": jobs }))?,
+    )?)
+}
@@ -0,0 +1,246 @@
+// fracmd
+// Personal location bookmarks
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Bookmarks live in one json file, <data dir>/fracmd/bookmarks.json, which
+// export and import also use:
+//
+//   { "bookmarks": [ { "name": "my-seahorse", "added": "2022-03-14",
+//       "note": "...", "palette": "palettes/fire.map",
+//       "params": { "origin": "-0.745+0.11i", "zoom": "60", ... } } ] }
+//
+// Every render parameter is stored, not just the view, so a bookmark still
+// reproduces its image after the defaults change.
+
+use err_derive::Error;
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{template, Args};
+
+#[derive(Debug, Error)]
+pub enum BookmarkError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "no bookmark named {:?}", _0)]
+    NotFound(String),
+    #[error(display = "a bookmark named {:?} already exists", _0)]
+    Exists(String),
+    #[error(display = "{:?} is not a bookmark file", _0)]
+    BadFile(PathBuf),
+    #[error(display = "no user data directory on this system")]
+    NoDataDir,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bookmark {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub palette: Option<String>,
+    pub note: Option<String>,
+    pub added: String,
+}
+
+impl Bookmark {
+    pub fn new(name: &str, args: &Args, palette: Option<String>, note: Option<String>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Bookmark {
+            name: name.to_string(),
+            params: args
+                .params()
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            palette,
+            note,
+            added: template::date(now),
+        }
+    }
+
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Only the render parameters, a bookmark file edited by hand or
+    // imported from someone else could name any flag
+    pub fn flags(&self) -> Vec<String> {
+        Args::known_params(self.params.clone())
+            .0
+            .into_iter()
+            .map(|(k, v)| format!("--{}={}", k, v))
+            .collect()
+    }
+
+    fn to_json(&self) -> Value {
+        let params: Map<String, Value> = self
+            .params
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        let mut entry = json!({ "name": self.name, "added": self.added, "params": params });
+        if let Some(note) = &self.note {
+            entry["note"] = json!(note);
+        }
+        if let Some(palette) = &self.palette {
+            entry["palette"] = json!(palette);
+        }
+        entry
+    }
+
+    fn from_json(entry: &Value) -> Option<Bookmark> {
+        let text = |key| entry.get(key).and_then(Value::as_str).map(String::from);
+        let params = entry
+            .get("params")?
+            .as_object()?
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (k.clone(), v)
+            })
+            .collect();
+        Some(Bookmark {
+            name: text("name")?,
+            params,
+            palette: text("palette"),
+            note: text("note"),
+            added: text("added").unwrap_or_default(),
+        })
+    }
+}
+
+pub fn store() -> Result<PathBuf, BookmarkError> {
+    Ok(dirs::data_dir()
+        .ok_or(BookmarkError::NoDataDir)?
+        .join("fracmd")
+        .join("bookmarks.json"))
+}
+
+// A missing file is an empty collection
+pub fn read(path: &Path) -> Result<Vec<Bookmark>, BookmarkError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let bad = || BookmarkError::BadFile(path.to_path_buf());
+    json.get("bookmarks")
+        .and_then(Value::as_array)
+        .ok_or_else(bad)?
+        .iter()
+        .map(|entry| Bookmark::from_json(entry).ok_or_else(bad))
+        .collect()
+}
+
+pub fn write(path: &Path, bookmarks: &[Bookmark]) -> Result<(), BookmarkError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let entries: Vec<Value> = bookmarks.iter().map(Bookmark::to_json).collect();
+    let text = serde_json::to_string_pretty(&json!({ "bookmarks": entries }))?;
+    Ok(fs::write(path, text + "\n")?)
+}
+
+pub fn add(bookmark: Bookmark, replace: bool) -> Result<PathBuf, BookmarkError> {
+    let path = store()?;
+    let mut bookmarks = read(&path)?;
+    match bookmarks.iter().position(|b| b.name == bookmark.name) {
+        Some(_) if !replace => return Err(BookmarkError::Exists(bookmark.name)),
+        Some(i) => bookmarks[i] = bookmark,
+        None => bookmarks.push(bookmark),
+    }
+    write(&path, &bookmarks)?;
+    Ok(path)
+}
+
+pub fn find(name: &str) -> Result<Bookmark, BookmarkError> {
+    read(&store()?)?
+        .into_iter()
+        .find(|b| b.name == name)
+        .ok_or_else(|| BookmarkError::NotFound(name.to_string()))
+}
+
+pub fn remove(name: &str) -> Result<(), BookmarkError> {
+    let path = store()?;
+    let mut bookmarks = read(&path)?;
+    let before = bookmarks.len();
+    bookmarks.retain(|b| b.name != name);
+    if bookmarks.len() == before {
+        return Err(BookmarkError::NotFound(name.to_string()));
+    }
+    write(&path, &bookmarks)
+}
+
+// Merges another bookmark file in, returning how many were added and a
+// warning for each parameter left out for not being a render parameter.
+// Names already in the store are kept unless replace is set.
+pub fn import(file: &Path, replace: bool) -> Result<(usize, Vec<String>), BookmarkError> {
+    let path = store()?;
+    let mut bookmarks = read(&path)?;
+    let mut added = 0;
+    let mut warnings = Vec::new();
+    for mut bookmark in read(file)? {
+        let (known, rest) = Args::known_params(bookmark.params);
+        bookmark.params = known;
+        warnings.extend(rest.iter().map(|key| {
+            format!(
+                "{}: ignoring {}, it isn't a render parameter",
+                bookmark.name, key
+            )
+        }));
+        match bookmarks.iter().position(|b| b.name == bookmark.name) {
+            Some(i) if replace => bookmarks[i] = bookmark,
+            Some(_) => continue,
+            None => bookmarks.push(bookmark),
+        }
+        added += 1;
+    }
+    write(&path, &bookmarks)?;
+    Ok((added, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_leave_out_other_flags() {
+        let entry = json!({
+            "name": "shared",
+            "params": { "zoom": "60", "exec": "touch /tmp/pwned", "plugin": "evil.so" }
+        });
+        let bookmark = Bookmark::from_json(&entry).unwrap();
+        assert_eq!(bookmark.flags(), vec!["--zoom=60"]);
+    }
+
+    #[test]
+    fn entry_without_params_is_rejected() {
+        assert!(Bookmark::from_json(&json!({ "name": "empty" })).is_none());
+        assert!(Bookmark::from_json(&json!({ "params": {} })).is_none());
+    }
+}
@@ -0,0 +1,91 @@
+// fracmd
+// Built in catalog of famous locations
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use toml::Value;
+
+const CATALOG: &str = include_str!("../locations/catalog.toml");
+
+#[derive(Debug, Error)]
+pub enum CatalogError {
+    #[error(display = "no location called {:?}, `fracmd locations` lists them", _0)]
+    Unknown(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub name: String,
+    pub title: String,
+    pub origin: String,
+    pub zoom: f64,
+    pub limit: Option<f64>,
+    pub palette: Option<String>,
+    pub about: Option<String>,
+}
+
+fn number(value: &Value) -> Option<f64> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|i| i as f64))
+}
+
+// The catalog ships inside the binary, so a malformed entry is a bug here
+// rather than something to report
+pub fn all() -> Vec<Location> {
+    let table: toml::value::Table = toml::from_str(CATALOG).expect("catalog is valid toml");
+    let entries = table["location"].as_array().expect("catalog has locations");
+    entries
+        .iter()
+        .map(|entry| {
+            let text = |key: &str| entry.get(key).and_then(Value::as_str).map(String::from);
+            let name = text("name").expect("location has a name");
+            Location {
+                title: text("title").unwrap_or_else(|| name.clone()),
+                origin: text("origin").expect("location has an origin"),
+                zoom: entry
+                    .get("zoom")
+                    .and_then(number)
+                    .expect("location has a zoom"),
+                limit: entry.get("limit").and_then(number),
+                palette: text("palette"),
+                about: text("about"),
+                name,
+            }
+        })
+        .collect()
+}
+
+pub fn find(name: &str) -> Result<Location, CatalogError> {
+    all()
+        .into_iter()
+        .find(|l| l.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| CatalogError::Unknown(name.to_string()))
+}
+
+impl Location {
+    // Lowest precedence layer, so --zoom and friends still override it
+    pub fn flags(&self) -> Vec<String> {
+        let mut flags = vec![
+            format!("--origin={}", self.origin),
+            format!("--zoom={}", self.zoom),
+        ];
+        if let Some(limit) = self.limit {
+            flags.push(format!("--limit={}", limit));
+        }
+        flags
+    }
+}
@@ -0,0 +1,211 @@
+// fracmd
+// Interrupted render checkpoints
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config,
+    metadata::{self, MetadataError},
+    scheduler::Image16,
+    Args, PARAM_KEYS,
+};
+
+// A checkpoint is a 16 bit png of the partial render with the usual
+// parameter chunks, plus the output it belongs to, the indices of the
+// finished tiles in scheduler::tiles order and the output settings
+const OUTPUT_KEY: &str = "fracmd-checkpoint:output";
+const TILES_KEY: &str = "fracmd-checkpoint:tiles";
+const SETTING_PREFIX: &str = "fracmd-setting:";
+
+// Output settings restored along with the render parameters, the ones that
+// name no other file and run nothing. A checkpoint can't pick a plugin,
+// script or where anything else is written.
+const SETTINGS: &[&str] = &["format", "colors", "quality", "creator", "collision"];
+
+fn restored(key: &str) -> bool {
+    PARAM_KEYS.contains(&key) || SETTINGS.contains(&key)
+}
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error(display = "{}", _0)]
+    Metadata(#[error(source)] MetadataError),
+    #[error(display = "image error: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "{:?} is not a fracmd checkpoint", _0)]
+    NotCheckpoint(PathBuf),
+    #[error(display = "checkpoint has {} tiles but the render has {}", _0, _1)]
+    WrongTiles(usize, usize),
+    #[error(
+        display = "checkpoint is {}x{} but the render is {}x{}",
+        _0,
+        _1,
+        _2,
+        _3
+    )]
+    WrongSize(u32, u32, u32, u32),
+}
+
+pub struct Checkpoint {
+    pub output: String,
+    pub image: Image16,
+    pub done: Vec<bool>,
+}
+
+pub fn path_for(output: &str) -> PathBuf {
+    PathBuf::from(format!("{}.checkpoint.png", output))
+}
+
+pub fn save(path: &Path, checkpoint: &Checkpoint, args: &Args) -> Result<(), MetadataError> {
+    let tiles: Vec<String> = (0..checkpoint.done.len())
+        .filter(|&i| checkpoint.done[i])
+        .map(|i| i.to_string())
+        .collect();
+    let mut text = vec![
+        (OUTPUT_KEY.to_string(), checkpoint.output.clone()),
+        (
+            TILES_KEY.to_string(),
+            format!("{}/{}", tiles.join(","), checkpoint.done.len()),
+        ),
+    ];
+    // The stored parameters are already cropped to the region
+    text.extend(
+        config::settings(args)
+            .into_iter()
+            .filter(|(key, _)| restored(key))
+            .map(|(key, value)| (format!("{}{}", SETTING_PREFIX, key), value)),
+    );
+    metadata::write_png_with(
+        BufWriter::new(File::create(path)?),
+        &checkpoint.image,
+        args,
+        &text,
+    )
+}
+
+// Flags restoring the interrupted run's parameters and output settings,
+// then the keys of stored settings that are neither
+pub fn flags(path: &Path) -> Result<(Vec<String>, Vec<String>), CheckpointError> {
+    let (flags, rest): (Vec<_>, Vec<_>) = metadata::read_text(path)?
+        .into_iter()
+        .filter_map(|(keyword, text)| {
+            let key = keyword.strip_prefix(SETTING_PREFIX)?;
+            Some((key.to_string(), text))
+        })
+        .partition(|(key, _)| restored(key));
+    if flags.is_empty() {
+        return Err(CheckpointError::NotCheckpoint(path.to_path_buf()));
+    }
+    Ok((
+        flags.iter().map(|(k, v)| config::flag(k, v)).collect(),
+        rest.into_iter().map(|(k, _)| k).collect(),
+    ))
+}
+
+// The render being resumed is width x height in tiles tiles. Only the
+// stored output's file name is kept, the output goes beside the checkpoint.
+pub fn load(
+    path: &Path,
+    width: u32,
+    height: u32,
+    tiles: usize,
+) -> Result<Checkpoint, CheckpointError> {
+    let text = metadata::read_text(path)?;
+    let get = |key| {
+        text.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .ok_or_else(|| CheckpointError::NotCheckpoint(path.to_path_buf()))
+    };
+    let output = Path::new(&get(OUTPUT_KEY)?)
+        .file_name()
+        .map(|name| path.with_file_name(name).display().to_string())
+        .ok_or_else(|| CheckpointError::NotCheckpoint(path.to_path_buf()))?;
+    let done_text = get(TILES_KEY)?;
+    let (list, total) = done_text
+        .rsplit_once('/')
+        .ok_or_else(|| CheckpointError::NotCheckpoint(path.to_path_buf()))?;
+    let total: usize = total
+        .parse()
+        .map_err(|_| CheckpointError::NotCheckpoint(path.to_path_buf()))?;
+    if total != tiles {
+        return Err(CheckpointError::WrongTiles(total, tiles));
+    }
+    let mut done = vec![false; total];
+    for i in list.split(',').filter(|i| !i.is_empty()) {
+        match i.parse::<usize>() {
+            Ok(i) if i < total => done[i] = true,
+            _ => return Err(CheckpointError::NotCheckpoint(path.to_path_buf())),
+        }
+    }
+    let image = image::open(path)?.into_rgba16();
+    if image.dimensions() != (width, height) {
+        let (w, h) = image.dimensions();
+        return Err(CheckpointError::WrongSize(w, h, width, height));
+    }
+    Ok(Checkpoint {
+        output,
+        image,
+        done,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    #[test]
+    fn only_parameters_and_output_settings_come_back() {
+        let args = Args::parse_argv(
+            [
+                "fracmd",
+                "--zoom=2",
+                "--width=8",
+                "--height=8",
+                "--quality=70",
+                "--plugin=evil.so",
+                "--output=/etc/x.png",
+            ]
+            .map(String::from),
+        );
+        let file = TempFile::new("settings.checkpoint.png", []);
+        let checkpoint = Checkpoint {
+            output: String::from("/etc/x.png"),
+            image: Image16::new(args.width as u32, args.height as u32),
+            done: vec![false; 4],
+        };
+        save(&file.0, &checkpoint, &args).unwrap();
+        let (flags, _) = flags(&file.0).unwrap();
+        assert!(flags.contains(&String::from("--zoom=2")));
+        assert!(flags.contains(&String::from("--quality=70")));
+        assert!(!flags
+            .iter()
+            .any(|f| f.starts_with("--plugin") || f.starts_with("--output")));
+
+        let (width, height) = (args.width as u32, args.height as u32);
+        let loaded = load(&file.0, width, height, 4).unwrap();
+        assert_eq!(Path::new(&loaded.output), file.0.with_file_name("x.png"));
+        let wider = load(&file.0, width + 10, height, 4);
+        assert!(matches!(wider, Err(CheckpointError::WrongSize(..))));
+    }
+}
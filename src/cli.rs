@@ -0,0 +1,1838 @@
+// fracmd
+// The subcommands of the fracmd binary
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// run layers the parameters up from every source, then hands them to the
+// subcommand asked for or renders a still. Each subcommand is a function
+// here reporting failure as a FracError, which main turns into an exit code.
+
+use image::{ImageBuffer, Rgba};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    animation::{self, Animation, FrameSink},
+    api,
+    audio::Target,
+    batch, bookmark, catalog, checkpoint,
+    coloring::{Coloring, Gradient},
+    config, control, coords, default_functs,
+    depth::{self, Buffer, OutputPixel},
+    diff, error, estimate, explore, expmap, farm, fractint, gallery, hooks,
+    keyframe::Keyframes,
+    kf, locate,
+    manifest::{self, Manifest},
+    metadata, nucleus, orbit,
+    output::{save_output, write_outputs, write_sizes},
+    palette::Palette,
+    plugin, preset,
+    progress::{self, Reporter},
+    queue,
+    recolor::Recolor,
+    reuse,
+    scheduler::{self, Image16, Throttle, Tile, TILE_SIZE},
+    script, serve, sheet, sidecar, sizes, sweep, template, terminal, tiles, upr, validate, verify,
+    viewer, window, wizard, xmp, Args, BookmarkAction, Command, Format, FracData, FracError,
+    PresetAction, Renderer,
+};
+
+fn recolor(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Recolor {
+        data,
+        palette,
+        transfer,
+        cycles,
+    }) = &args.command
+    {
+        let dump = FracData::load(data).map_err(error::at(data))?;
+        let mut stored = dump.args().map_err(error::at(data))?;
+        if args.given("cexp") {
+            stored.cexp = args.cexp;
+        }
+        if args.given("set-color") {
+            stored.set_color = args.set_color;
+        }
+        let to_space = stored.colorspace.matrix_from_srgb();
+        let palette = palette
+            .as_ref()
+            .map(|p| Palette::load(p).map_err(error::at(p)))
+            .transpose()?
+            .map(|p| p.transform(&to_space));
+        let coloring: Arc<dyn Coloring> = match palette {
+            Some(palette) => Arc::new(Gradient {
+                palette,
+                transfer: *transfer,
+                cycles: *cycles,
+                phase: 0.0,
+            }),
+            None => default_functs(&stored).coloring,
+        };
+        let output = Recolor::new(&stored, coloring).apply(&dump);
+        let name = args
+            .output
+            .as_ref()
+            .map_or_else(|| data.with_extension("png"), PathBuf::from);
+        metadata::save_png(&name, &output, &stored).map_err(error::at(&name))?;
+        let message = format!("Recolored {} into {}", data.display(), name.display());
+        println!("{}", message);
+        hooks::run(args, &name, &message);
+    }
+    Ok(())
+}
+
+fn render_tiles(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Tiles { zooms, size, cache }) = &args.command {
+        let dir = cache.join(tiles::cache_key(args));
+        start_threads(args)?;
+        for z in zooms.min..=zooms.max {
+            let n = 1u32 << z;
+            let count = n as u64 * n as u64;
+            args.log(&format!("Rendering zoom level {} ({} tiles)...", z, count));
+            for x in 0..n {
+                for y in 0..n {
+                    tiles::render_tile(args, default_functs(args), &dir, z, x, y, *size)?;
+                }
+            }
+        }
+        args.log(&format!("Tiles written to {}", dir.display()));
+    }
+    Ok(())
+}
+
+fn stitch(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Stitch {
+        level,
+        size,
+        cache,
+        max_pixels,
+    }) = &args.command
+    {
+        // Checked before anything is allocated or rendered
+        let side = tiles::stitched_side(*level, *size);
+        let pixels = side.saturating_mul(side);
+        let side = match u32::try_from(side) {
+            Ok(side) if pixels <= *max_pixels => side,
+            _ => return Err(FracError::TooBig(pixels, *max_pixels)),
+        };
+        let dir = cache.join(tiles::cache_key(args));
+        start_threads(args)?;
+        let n = 1u32 << level;
+        let mut image: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::new(side, side);
+        args.log(&format!("Stitching {} tiles...", n as u64 * n as u64));
+        for x in 0..n {
+            for y in 0..n {
+                let path =
+                    tiles::render_tile(args, default_functs(args), &dir, *level, x, y, *size)?;
+                let tile = image::open(&path).map_err(error::at(&path))?.into_rgba16();
+                image::imageops::overlay(&mut image, &tile, (x * size) as i64, (y * size) as i64);
+            }
+        }
+        // The whole level is the root tile rendered at full size
+        let out = args.output_or("out/stitch.png");
+        metadata::save_png(&out, &image, &tiles::tile_args(args, 0, 0, 0, side))
+            .map_err(error::at(&out))?;
+        let message = format!("Stitched image written to {}", out.display());
+        args.log(&message);
+        hooks::run(args, &out, &message);
+    }
+    Ok(())
+}
+
+fn bench(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Bench { runs }) = &args.command {
+        start_threads(args)?;
+        let renderer = Renderer::new(args.clone(), default_functs(args));
+        let pixels = (args.width * args.height) as f64;
+        let times: Vec<f64> = (0..*runs)
+            .map(|run| {
+                let now = Instant::now();
+                renderer.render();
+                let secs = now.elapsed().as_secs_f64();
+                println!("run {}: {:.0}ms", run + 1, secs * 1000.0);
+                secs
+            })
+            .collect();
+        let best = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let mean = times.iter().sum::<f64>() / times.len().max(1) as f64;
+        println!(
+            "{}x{} with {} samples on {} threads, {} backend: best {:.0}ms, mean {:.0}ms, {:.2} Mpixel/s",
+            args.width,
+            args.height,
+            args.samples,
+            args.threads,
+            renderer.backend().name(),
+            best * 1000.0,
+            mean * 1000.0,
+            pixels / best / 1e6
+        );
+    }
+    Ok(())
+}
+
+fn animate(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Animate {
+        frames,
+        fps,
+        crf,
+        zoom_to,
+        spin,
+        julia_path,
+        bounce,
+        looping,
+        keys,
+        shutter,
+        subframes,
+        reuse,
+        preview,
+        sidecars,
+        farm,
+        farm_retries,
+        farm_token,
+        farm_timeout,
+        metrics,
+        audio,
+    }) = &args.command
+    {
+        let out = &args.output_or(match preview {
+            Some(_) => "out/preview.png",
+            None => "out/animation.png",
+        });
+        let keys = keys
+            .as_ref()
+            .map(|path| Keyframes::load(path).map_err(error::at(path)))
+            .transpose()?;
+        let drive = audio.drive("animate", *fps, &[Target::Speed, Target::Radius], true)?;
+        if let Some(drive) = &drive {
+            drive.check(&config::settings(args))?;
+        }
+        let frames = match (&keys, &drive) {
+            _ if args.given("frames") => *frames,
+            (Some(keys), _) => keys.last() as usize + 1,
+            (None, Some(drive)) => drive.envelope.len(),
+            (None, None) => *frames,
+        };
+        let mut start = args.clone();
+        let (mut zoom_to, mut spin) = (*zoom_to, *spin);
+        if *looping {
+            let found =
+                nucleus::find_misiurewicz(&start, &start.formula()).ok_or(FracError::NoLoop)?;
+            let scale = found.multiplier.norm();
+            start.origin = found.c;
+            zoom_to = Some(start.zoom * scale);
+            spin = Some(-found.multiplier.arg().to_degrees());
+            args.log(&format!(
+                "Looping into the Misiurewicz point of preperiod {} and period {} at {}, zooming {:.4} times and turning {:.2} degrees",
+                found.preperiod,
+                found.period,
+                coords::format(found.c),
+                scale,
+                spin.unwrap_or(0.0)
+            ));
+        }
+        let args = &start;
+        let animation = Animation {
+            frames,
+            fps: *fps,
+            crf: *crf,
+            zoom_to,
+            spin,
+            shutter: *shutter,
+            subframes: *subframes,
+            julia_path: *julia_path,
+            bounce: *bounce,
+            looping: *looping,
+            keys,
+            audio: drive,
+            preview: preview.map(|size| size.unwrap_or(animation::PREVIEW)),
+        };
+        // Every frame is checked before the first renders
+        let frame_args: Vec<Args> = (0..animation.frames)
+            .map(|frame| animation.frame_args(args, frame))
+            .collect::<Result<_, _>>()?;
+        for (frame, frame_args) in frame_args.iter().enumerate() {
+            check(frame_args, &format!("at frame {}, ", frame))?;
+        }
+        if *reuse && !reuse::possible(&animation) {
+            return Err(FracError::NoReuse);
+        }
+        start_threads(args)?;
+        metrics.start(args)?;
+        // A frame directory keeps a manifest, so a rerun picks up where an
+        // interrupted one stopped
+        let mut manifest = match animation::writes_frames(out) {
+            true => {
+                let path = out.join(manifest::FILE);
+                let mut manifest = Manifest::load(out).map_err(error::at(&path))?;
+                manifest.plan(&frame_args);
+                manifest.save().map_err(error::at(&path))?;
+                let done = manifest.count_done();
+                if done > 0 {
+                    args.log(&format!(
+                        "Resuming with {}/{} frames done",
+                        done, animation.frames
+                    ));
+                }
+                scheduler::handle_interrupts();
+                Some(manifest)
+            }
+            false => None,
+        };
+        let mut sink = animation.sink(out).map_err(error::at(out))?;
+        if let Some(dir) = sidecars {
+            sink = Box::new(sidecar::Sidecars {
+                inner: sink,
+                dir: sidecar::dir_for(out, dir.as_deref())?,
+                animation: &animation,
+            });
+        }
+        if *reuse {
+            animate_reusing(args, &animation, sink.as_mut(), manifest.as_mut())?;
+        } else {
+            let pending: Vec<usize> = (0..animation.frames)
+                .filter(|&frame| manifest.as_ref().and_then(|m| m.done(frame)).is_none())
+                .collect();
+            let resumable = manifest.is_some();
+            let mut deliver = |frame: usize, image: Image16| -> Result<(), FracError> {
+                if let Some(manifest) = &mut manifest {
+                    manifest.finish(frame, &image);
+                }
+                sink.frame(frame, image, &frame_args[frame])?;
+                if let Some(manifest) = &manifest {
+                    manifest.save().map_err(error::at(manifest.path()))?;
+                }
+                Ok(())
+            };
+            let stopped = match farm {
+                Some(listen) => {
+                    let jobs = pending
+                        .iter()
+                        .map(|&frame| {
+                            Ok(farm::Job {
+                                frame,
+                                width: frame_args[frame].width as u32,
+                                height: frame_args[frame].height as u32,
+                                subframes: animation
+                                    .subframes(frame)
+                                    .into_iter()
+                                    .map(|at| {
+                                        Ok(config::settings(&animation.frame_args_at(args, at)?)
+                                            .iter()
+                                            .map(|(k, v)| config::flag(k, v))
+                                            .collect())
+                                    })
+                                    .collect::<Result<_, FracError>>()?,
+                            })
+                        })
+                        .collect::<Result<_, FracError>>()?;
+                    let farm = farm::Farm {
+                        listen,
+                        retries: *farm_retries,
+                        token: farm_token.clone(),
+                        timeout: Duration::from_secs((*farm_timeout).max(1)),
+                    };
+                    farm::coordinate(args, farm, jobs, deliver)?
+                }
+                None => {
+                    let mut stopped = false;
+                    for frame in pending {
+                        args.log(&format!(
+                            "Rendering frame {}/{}...",
+                            frame + 1,
+                            animation.frames
+                        ));
+                        let image = render_blurred(args, &animation, frame)?;
+                        // A frame cut short by the interrupt isn't kept
+                        if resumable && scheduler::interrupted() {
+                            stopped = true;
+                            break;
+                        }
+                        deliver(frame, image)?;
+                    }
+                    stopped
+                }
+            };
+            if let (true, Some(manifest)) = (stopped, &manifest) {
+                return stop_animation(args, manifest);
+            }
+        }
+        sink.finish()?;
+        let message = format!("Animation written to {}", out.display());
+        args.log(&message);
+        if !args.streaming() {
+            hooks::run(args, out, &message);
+        }
+    }
+    Ok(())
+}
+
+// The average of the frame's subframes, in the squared space samples are
+// averaged in. Without motion blur that's the frame itself.
+fn render_blurred(args: &Args, animation: &Animation, frame: usize) -> Result<Image16, FracError> {
+    let moments: Vec<Args> = animation
+        .subframes(frame)
+        .into_iter()
+        .map(|at| animation.frame_args_at(args, at))
+        .collect::<Result<_, _>>()?;
+    Ok(render_moments(&moments))
+}
+
+// Renders each set of parameters and averages them into one image
+pub(crate) fn render_moments(moments: &[Args]) -> Image16 {
+    let render = |args: &Args| Renderer::new(args.clone(), default_functs(args)).render();
+    if moments.len() == 1 {
+        return render(&moments[0]);
+    }
+    let mut sum: Vec<f32> = Vec::new();
+    for moment in moments {
+        let image = render(moment);
+        sum.resize(image.len(), 0.0);
+        for (i, v) in image.as_raw().iter().enumerate() {
+            let v = *v as f32 / u16::MAX as f32;
+            // Alpha isn't squared
+            sum[i] += if i % 4 == 3 { v } else { v * v };
+        }
+    }
+    let n = moments.len() as f32;
+    let raw: Vec<u16> = sum
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let v = if i % 4 == 3 { v / n } else { (v / n).sqrt() };
+            (v.clamp(0.0, 1.0) * u16::MAX as f32) as u16
+        })
+        .collect();
+    ImageBuffer::from_raw(moments[0].width as u32, moments[0].height as u32, raw)
+        .expect("the subframes are the size of the frame")
+}
+
+// Zooming in the deepest frame is the last, so the frames are rendered
+// backwards into a staging directory and passed on in order afterwards
+fn animate_reusing(
+    args: &Args,
+    animation: &Animation,
+    sink: &mut dyn FrameSink,
+    mut manifest: Option<&mut Manifest>,
+) -> Result<(), FracError> {
+    let inward = animation.zoom_to.is_some_and(|to| to > args.zoom);
+    // A frame directory takes frames in any order, so only the others
+    // need zooms in staged
+    let staging = (inward && manifest.is_none())
+        .then(|| std::env::temp_dir().join(format!("fracmd-reuse-{}", process::id())));
+    let order: Vec<usize> = if inward {
+        (0..animation.frames).rev().collect()
+    } else {
+        (0..animation.frames).collect()
+    };
+    let staged = manifest::frame_path;
+    let mut deeper: Option<(Image16, Args)> = None;
+    for frame in order {
+        let frame_args = animation.frame_args(args, frame)?;
+        if let Some(image) = manifest.as_deref().and_then(|m| m.done(frame)) {
+            deeper = Some((image, frame_args));
+            continue;
+        }
+        let renderer = Renderer::new(frame_args.clone(), default_functs(&frame_args));
+        let (image, reused) = reuse::render(
+            &renderer,
+            &frame_args,
+            deeper.as_ref().map(|(image, args)| (image, args)),
+        );
+        args.log(&format!(
+            "Rendered frame {}/{}, {:.0}% reused",
+            frame + 1,
+            animation.frames,
+            100.0 * reused as f64 / (image.width() * image.height()).max(1) as f64
+        ));
+        if let Some(dir) = &staging {
+            fs::create_dir_all(dir).map_err(error::at(dir))?;
+            metadata::save_png(staged(dir, frame), &image, &frame_args)
+                .map_err(error::at(staged(dir, frame)))?;
+        } else if let Some(manifest) = manifest.as_deref_mut() {
+            if scheduler::interrupted() {
+                return stop_animation(args, manifest);
+            }
+            manifest.finish(frame, &image);
+            sink.frame(frame, image.clone(), &frame_args)?;
+            manifest.save().map_err(error::at(manifest.path()))?;
+        } else {
+            sink.frame(frame, image.clone(), &frame_args)?;
+        }
+        deeper = Some((image, frame_args));
+    }
+    if let Some(dir) = &staging {
+        for frame in 0..animation.frames {
+            let image = image::open(staged(dir, frame))
+                .map_err(error::at(staged(dir, frame)))?
+                .into_rgba16();
+            sink.frame(frame, image, &animation.frame_args(args, frame)?)?;
+        }
+        fs::remove_dir_all(dir).map_err(error::at(dir))?;
+    }
+    Ok(())
+}
+
+// Ends an animation into a frame directory after an interrupt, with the
+// manifest saved so running the same command again resumes it
+fn stop_animation(args: &Args, manifest: &Manifest) -> Result<(), FracError> {
+    manifest.save().map_err(error::at(manifest.path()))?;
+    args.log(&format!(
+        "Interrupted with {} frames done, run the same command again to resume",
+        manifest.count_done()
+    ));
+    Err(FracError::Interrupted)
+}
+
+fn cycle(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Cycle {
+        data,
+        palette,
+        transfer,
+        cycles,
+        frames,
+        fps,
+        crf,
+        reverse,
+        audio,
+    }) = &args.command
+    {
+        let out = &args.output_or("out/cycle.png");
+        let drive = audio.drive("cycle", *fps, &[Target::Speed, Target::Phase], false)?;
+        let dump = match data {
+            Some(data) => FracData::load(data).map_err(error::at(data))?,
+            None => {
+                start_threads(args)?;
+                args.log("Rendering the iteration buffer...");
+                FracData {
+                    width: args.width as u32,
+                    height: args.height as u32,
+                    params: args
+                        .params()
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v))
+                        .collect(),
+                    data: Renderer::new(args.clone(), default_functs(args)).render_data(),
+                }
+            }
+        };
+        let stored = match data {
+            Some(data) => dump.args().map_err(error::at(data))?,
+            None => dump.args()?,
+        };
+        let to_space = stored.colorspace.matrix_from_srgb();
+        let mut gradient = Gradient {
+            palette: Palette::load(palette)
+                .map_err(error::at(palette))?
+                .transform(&to_space),
+            transfer: *transfer,
+            cycles: *cycles,
+            phase: 0.0,
+        };
+        let frames = match &drive {
+            Some(drive) if !args.given("frames") => drive.envelope.len(),
+            _ => *frames,
+        };
+        let animation = Animation {
+            frames,
+            fps: *fps,
+            crf: *crf,
+            zoom_to: None,
+            spin: None,
+            shutter: 0.0,
+            subframes: 1,
+            julia_path: None,
+            bounce: false,
+            looping: true,
+            keys: None,
+            audio: drive,
+            preview: None,
+        };
+        let mut sink = animation.sink(out).map_err(error::at(out))?;
+        let mut recolor = Recolor::new(&stored, Arc::new(gradient.clone()));
+        for frame in 0..animation.frames {
+            let mut phase = animation.loop_time(animation.position(frame));
+            if let Some(drive) = animation.audio.iter().find(|d| d.target == Target::Phase) {
+                phase += drive.level(frame as f32);
+            }
+            gradient.phase = if *reverse { -phase } else { phase };
+            recolor.set_coloring(Arc::new(gradient.clone()));
+            let image = recolor.apply(&dump);
+            sink.frame(frame, image, &stored)?;
+        }
+        sink.finish()?;
+        let message = format!("Color cycle written to {}", out.display());
+        args.log(&message);
+        if !args.streaming() {
+            hooks::run(args, out, &message);
+        }
+    }
+    Ok(())
+}
+
+fn unroll_strip(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Unroll {
+        strip,
+        frames,
+        fps,
+        crf,
+    }) = &args.command
+    {
+        let out = &args.output_or("out/zoom.png");
+        let image = image::open(strip).map_err(error::at(strip))?.into_rgba16();
+        let stored = metadata::read_args(strip).map_err(error::at(strip))?;
+        if stored.projection != expmap::Projection::Expmap
+            || (stored.width as u32, stored.height as u32) != image.dimensions()
+        {
+            return Err(error::at(strip)(expmap::ExpmapError::NotStrip));
+        }
+        let depth = expmap::depth(&stored, args.width).map_err(error::at(strip))?;
+        let sharp = (expmap::WIDTH_PER_FRAME * args.width as f32).ceil();
+        if (stored.width as f32) < sharp {
+            args.log(&format!(
+                "warning: frames will be softer than direct renders towards their corners, \
+                 a strip {} wide keeps them sharp",
+                sharp
+            ));
+        }
+        let animation = Animation {
+            frames: *frames,
+            fps: *fps,
+            crf: *crf,
+            zoom_to: None,
+            spin: None,
+            shutter: 0.0,
+            subframes: 1,
+            julia_path: None,
+            bounce: false,
+            looping: false,
+            keys: None,
+            audio: None,
+            preview: None,
+        };
+        start_threads(args)?;
+        let mut sink = animation.sink(out).map_err(error::at(out))?;
+        for frame in 0..animation.frames {
+            let mut frame_args = stored.clone();
+            frame_args.projection = expmap::Projection::Plane;
+            frame_args.width = args.width;
+            frame_args.height = args.height;
+            frame_args.zoom = stored.zoom * (depth * animation.time(frame as f32)).exp();
+            let image = expmap::unroll(&image, &stored, &frame_args);
+            sink.frame(frame, image, &frame_args)?;
+        }
+        sink.finish()?;
+        let message = format!(
+            "Unrolled {} into {}, zooming {:.3e} times",
+            strip.display(),
+            out.display(),
+            depth.exp()
+        );
+        args.log(&message);
+        if !args.streaming() {
+            hooks::run(args, out, &message);
+        }
+    }
+    Ok(())
+}
+
+fn render_batch(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Batch {
+        jobs,
+        parallel,
+        report,
+    }) = &args.command
+    {
+        let jobs = batch::load(jobs, args).map_err(error::at(jobs))?;
+        if args.dry_run {
+            let jobs: Vec<_> = jobs.iter().map(|j| (j.name.clone(), j.clone())).collect();
+            return dry_run(args, &jobs, *parallel);
+        }
+        start_threads(args)?;
+        let next = AtomicUsize::new(0);
+        let outcomes: Mutex<Vec<(usize, batch::Outcome)>> = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..(*parallel).max(1) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(i) else { break };
+                    args.log(&format!(
+                        "[{}/{}] Rendering {}...",
+                        i + 1,
+                        jobs.len(),
+                        job.name
+                    ));
+                    let now = Instant::now();
+                    // A failed job is reported in the summary rather than
+                    // ending the whole batch
+                    let result = render_caught(job);
+                    let outcome = batch::Outcome {
+                        name: job.name.clone(),
+                        time: now.elapsed(),
+                        output: result.as_ref().ok().map(|(name, _)| name.clone()),
+                        error: result.err(),
+                    };
+                    outcomes.lock().unwrap().push((i, outcome));
+                });
+            }
+        });
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort_by_key(|(i, _)| *i);
+        let outcomes: Vec<_> = outcomes.into_iter().map(|(_, o)| o).collect();
+        let summary = batch::summary(&outcomes);
+        println!("{}", summary);
+        if let Some(path) = report {
+            batch::save_report(path, &outcomes).map_err(error::at(path))?;
+        }
+        hooks::notify(args, summary.lines().last().unwrap_or_default());
+        let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+        if failed > 0 {
+            return Err(FracError::JobsFailed(failed, outcomes.len()));
+        }
+    }
+    Ok(())
+}
+
+// render_job with a panic turned into its message
+fn render_caught(job: &Args) -> Result<(String, Image16), String> {
+    panic::catch_unwind(AssertUnwindSafe(|| render_job(job))).unwrap_or_else(|e| {
+        Err(e
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| String::from("panicked")))
+    })
+}
+
+fn watch_queue(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Queue {
+        dir,
+        parallel,
+        interval,
+        once,
+        recover,
+    }) = &args.command
+    {
+        if args.dry_run {
+            // Only looks, the directory is left as it is
+            if !dir.is_dir() {
+                return Err(error::at(dir)(queue::QueueError::NotDir));
+            }
+            let pending = queue::pending(dir).map_err(error::at(dir))?;
+            let mut jobs = Vec::new();
+            for path in &pending {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                match queue::load(path, args) {
+                    Ok(job) => jobs.push((name.to_string(), job)),
+                    Err(e) => println!("{}: would fail, {}", name, e),
+                }
+            }
+            return dry_run(args, &jobs, *parallel);
+        }
+        queue::prepare(dir).map_err(error::at(dir))?;
+        if *recover {
+            let recovered = queue::recover(dir).map_err(error::at(dir))?;
+            args.log(&format!(
+                "Put {} jobs left in running/ back in line",
+                recovered
+            ));
+        } else {
+            let running = queue::running(dir).map_err(error::at(dir))?;
+            if !running.is_empty() {
+                args.log(&format!(
+                    "{} jobs are in running/, another queue may have them; --recover puts them back in line if not",
+                    running.len()
+                ));
+            }
+        }
+        start_threads(args)?;
+        scheduler::handle_interrupts();
+        args.log(&format!("Taking jobs from {}", dir.display()));
+        let (rendered, failed) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|scope| -> Result<(), FracError> {
+            let workers: Vec<_> = (0..(*parallel).max(1))
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), FracError> {
+                        while !scheduler::interrupted() {
+                            let pending = queue::pending(dir).map_err(error::at(dir))?;
+                            let Some(running) = pending.iter().find_map(|p| queue::claim(dir, p))
+                            else {
+                                if *once {
+                                    break;
+                                }
+                                thread::sleep(Duration::from_secs_f32(interval.max(0.05)));
+                                continue;
+                            };
+                            let name = running.file_name().unwrap_or_default().to_string_lossy();
+                            args.log(&format!("Rendering {}...", name));
+                            let now = Instant::now();
+                            let result =
+                                queue::load(&running, args).and_then(|job| render_caught(&job));
+                            if scheduler::interrupted() {
+                                // Rendered again from the start next time
+                                fs::rename(&running, dir.join(&*name))
+                                    .map_err(error::at(&running))?;
+                                args.log(&format!("Interrupted, {} goes back in line", name));
+                                break;
+                            }
+                            let outcome = batch::Outcome {
+                                name: name.to_string(),
+                                time: now.elapsed(),
+                                output: result.as_ref().ok().map(|(output, _)| output.clone()),
+                                error: result.err(),
+                            };
+                            let moved = queue::finish(dir, &running, &outcome)
+                                .map_err(error::at(&running))?;
+                            match &outcome.error {
+                                Some(e) => {
+                                    failed.fetch_add(1, Ordering::SeqCst);
+                                    args.log(&format!("{} failed: {}", name, e));
+                                }
+                                None => {
+                                    rendered.fetch_add(1, Ordering::SeqCst);
+                                    args.log(&format!(
+                                        "Rendered {} in {:.1}s, log in {}.log",
+                                        name,
+                                        outcome.time.as_secs_f64(),
+                                        moved.display()
+                                    ));
+                                }
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap()?;
+            }
+            Ok(())
+        })?;
+        let (rendered, failed) = (rendered.into_inner(), failed.into_inner());
+        args.log(&format!("{} jobs rendered, {} failed", rendered, failed));
+        if failed > 0 && *once {
+            return Err(FracError::JobsFailed(failed, rendered + failed));
+        }
+    }
+    Ok(())
+}
+
+// One batch job or sweep combination through the same outputs a plain
+// render writes, returning the output path and image. Errors come back as
+// text for the batch summary.
+fn render_job(job: &Args) -> Result<(String, Image16), String> {
+    try_render_job(job).map_err(|e| e.to_string())
+}
+
+fn try_render_job(job: &Args) -> Result<(String, Image16), FracError> {
+    if job.streaming() {
+        return Err(FracError::CantStream(String::from("batch jobs")));
+    }
+    let job = framed(job)?;
+    let template = job.output.as_deref().unwrap_or(template::DEFAULT);
+    let path = template::expand(template, &job)?;
+    let name = template::prepare(&path, job.collision).map_err(error::at(&path))?;
+    let renderer = Renderer::new(job.clone(), default_functs(&job));
+    let mut output = renderer.render();
+    if let Some(c) = job.plot_orbit {
+        orbit::plot(&mut output, &renderer, c);
+    }
+    write_outputs(&job, &name, &renderer, &output, |_| ())?;
+    hooks::actions(&job, name.as_ref());
+    Ok((name, output))
+}
+
+// The view a job actually renders, after --autocenter and --region
+pub(crate) fn framed(job: &Args) -> Result<Args, FracError> {
+    let mut job = job.clone();
+    if job.autocenter {
+        let formula = job.formula();
+        nucleus::autocenter(&mut job, &formula);
+    }
+    if let Some(region) = job.region.take() {
+        job = region.crop(&job)?;
+    }
+    Ok(job)
+}
+
+// Estimates each job in turn, running at most parallel of them at once,
+// and warns when the whole lot goes over --max-time or --max-memory
+fn dry_run(args: &Args, jobs: &[(String, Args)], parallel: usize) -> Result<(), FracError> {
+    start_threads(args)?;
+    let (mut seconds, mut bytes) = (0.0, 0);
+    for (label, job) in jobs {
+        let job = framed(job)?;
+        let estimate = estimate::estimate(&job, &Renderer::new(job.clone(), default_functs(&job)));
+        println!(
+            "{}: {}x{} with {} samples, {}",
+            label,
+            job.width,
+            job.height,
+            job.samples,
+            estimate.summary()
+        );
+        seconds += estimate.seconds;
+        bytes = bytes.max(estimate.bytes);
+    }
+    // Parallel jobs share the threads, so only the memory adds up
+    let bytes = bytes * parallel.clamp(1, jobs.len().max(1)) as u64;
+    if jobs.len() > 1 {
+        println!(
+            "{} renders in about {}, {} at peak",
+            jobs.len(),
+            progress::format_secs(seconds),
+            estimate::format_bytes(bytes)
+        );
+    }
+    for warning in estimate::over_limits(args, seconds, bytes) {
+        println!("{}", warning);
+    }
+    Ok(())
+}
+
+fn start_threads(args: &Args) -> Result<(), FracError> {
+    start_pool(args.threads)
+}
+
+#[cfg(feature = "parallel")]
+fn start_pool(threads: usize) -> Result<(), FracError> {
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()?)
+}
+
+// Everything runs on this thread
+#[cfg(not(feature = "parallel"))]
+fn start_pool(_threads: usize) -> Result<(), FracError> {
+    Ok(())
+}
+
+fn check(args: &Args, context: &str) -> Result<(), FracError> {
+    let problems = validate::check(args);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(FracError::Invalid(context.to_string(), problems))
+    }
+}
+
+fn render_sweep(args: &Args) -> Result<(), FracError> {
+    let combinations = sweep::combinations(args, &args.sweep)?;
+    for combination in &combinations {
+        check(&combination.args, &format!("with {}, ", combination.label))?;
+    }
+    if args.dry_run {
+        let jobs: Vec<_> = combinations
+            .iter()
+            .map(|c| (c.label.clone(), c.args.clone()))
+            .collect();
+        return dry_run(args, &jobs, 1);
+    }
+    start_threads(args)?;
+    let (width, height) = (args.width as u32, args.height as u32);
+    let mut cells = Vec::new();
+    for (i, combination) in combinations.iter().enumerate() {
+        args.log(&format!(
+            "[{}/{}] Rendering {}...",
+            i + 1,
+            combinations.len(),
+            combination.label
+        ));
+        let (name, image) = try_render_job(&combination.args)?;
+        args.log(&format!("Written to {}", name));
+        if args.sweep_sheet.is_some() {
+            // Sweeping the size still gives an even grid
+            let image = if image.dimensions() == (width, height) {
+                image
+            } else {
+                image::imageops::resize(&image, width, height, image::imageops::Lanczos3)
+            };
+            cells.push((image, combination.label.clone()));
+        }
+    }
+    let message = format!("Rendered {} combinations", combinations.len());
+    if let Some(path) = &args.sweep_sheet {
+        // One row per combination of the other sweeps
+        let columns = args.sweep.last().map_or(1, |s| s.values.len());
+        sheet::compose(&cells, columns, width, height)
+            .save(path)
+            .map_err(error::at(path))?;
+        args.log(&format!("Comparison sheet written to {}", path.display()));
+    }
+    args.log(&message);
+    hooks::notify(args, &message);
+    Ok(())
+}
+
+fn explore(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Explore {
+        count,
+        keep,
+        preview,
+        jobs_file,
+    }) = &args.command
+    {
+        start_threads(args)?;
+        let mut rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+        };
+        let functs = default_functs(args);
+        let mut small = args.clone();
+        small.width = *preview;
+        small.height = (*preview * args.height / args.width).max(1);
+        small.samples = 1;
+        small.region = None;
+        let mut candidates: Vec<explore::Candidate> = (0..*count)
+            .map(|i| {
+                let mut candidate = explore::sample(&mut rng, args, &functs);
+                let image = Renderer::new(candidate.args(&small), default_functs(&small)).render();
+                candidate.score = explore::score(&image);
+                args.log(&format!(
+                    "[{}/{}] {} zoom {:.1} scores {:.3}",
+                    i + 1,
+                    count,
+                    candidate.origin,
+                    candidate.zoom,
+                    candidate.score
+                ));
+                candidate
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        candidates.truncate(*keep);
+        let mut found = Vec::new();
+        for (rank, candidate) in candidates.into_iter().enumerate() {
+            let mut job = candidate.args(args);
+            job.name = format!("{}_explore{}", args.name, rank + 1);
+            let (name, _) = try_render_job(&job)?;
+            args.log(&format!(
+                "#{} scoring {:.3} written to {}",
+                rank + 1,
+                candidate.score,
+                name
+            ));
+            found.push((job.name, candidate));
+        }
+        explore::save_jobs(jobs_file, &found).map_err(error::at(jobs_file))?;
+        let message = format!("Kept {} of {} places", found.len(), count);
+        args.log(&format!(
+            "{}, parameters in {}",
+            message,
+            jobs_file.display()
+        ));
+        hooks::notify(args, &message);
+    }
+    Ok(())
+}
+
+fn verify(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Verify {
+        suite,
+        update,
+        min_psnr,
+    }) = &args.command
+    {
+        let scenes = verify::load_suite(suite).map_err(error::at(suite.join(verify::SUITE)))?;
+        let jobs: Vec<_> = scenes
+            .iter()
+            .map(|scene| verify::scene_args(scene, args.threads))
+            .collect();
+        for (scene, job) in scenes.iter().zip(&jobs) {
+            if let Ok(job) = job {
+                check(job, &format!("in scene {}, ", scene.name))?;
+            }
+        }
+        start_threads(args)?;
+        let hashes = verify::read_hashes(suite);
+        let mut rendered = Vec::new();
+        let mut failed = 0;
+        for (scene, job) in scenes.iter().zip(jobs) {
+            let job = match job {
+                Ok(job) => job,
+                Err(outcome) => {
+                    println!("{}: {}", scene.name, outcome);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let image = Renderer::new(job.clone(), default_functs(&job)).render();
+            if *update {
+                rendered.push((scene, job, image));
+                continue;
+            }
+            let outcome = verify::check(suite, scene, &image, &hashes, *min_psnr);
+            println!("{}: {}", scene.name, outcome);
+            if !outcome.passed() {
+                failed += 1;
+            }
+        }
+        if *update {
+            verify::update(suite, &rendered).map_err(error::at(suite))?;
+            println!(
+                "Updated {} golden images in {}",
+                rendered.len(),
+                suite.display()
+            );
+        }
+        if failed > 0 {
+            return Err(FracError::VerifyFailed(failed, scenes.len()));
+        }
+    }
+    Ok(())
+}
+
+fn diff(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Diff { a, b, gain }) = &args.command {
+        // Before decoding, jpeg's decoder would make the thread pool itself
+        if b.is_none() {
+            start_threads(args)?;
+        }
+        let first = image::open(a).map_err(error::at(a))?.into_rgba16();
+        let second = match b {
+            Some(b) => image::open(b).map_err(error::at(b))?.into_rgba16(),
+            None => {
+                args.log(&format!("Re-rendering {} from its parameters", a.display()));
+                let job = framed(args)?;
+                Renderer::new(job.clone(), default_functs(&job)).render()
+            }
+        };
+        println!("{}", diff::metrics(&first, &second)?);
+        let output = args.output_or("out/diff.png");
+        template::prepare(&output.to_string_lossy(), args.collision).map_err(error::at(&output))?;
+        diff::difference(&first, &second, *gain)?
+            .save(&output)
+            .map_err(error::at(&output))?;
+        args.log(&format!("Difference written to {}", output.display()));
+    }
+    Ok(())
+}
+
+fn watch(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Watch {
+        scene,
+        resolution,
+        interval,
+    }) = &args.command
+    {
+        let output = args.output_or("out/watch.png");
+        template::prepare(&output.to_string_lossy(), template::Collision::Overwrite)
+            .map_err(error::at(&output))?;
+        start_threads(args)?;
+        scheduler::handle_interrupts();
+        args.log(&format!(
+            "Watching {}, previews go to {}",
+            scene.display(),
+            output.display()
+        ));
+        let mut seen = None;
+        while !scheduler::interrupted() {
+            let modified = fs::metadata(scene).and_then(|m| m.modified()).ok();
+            if modified.is_some() && modified != seen {
+                seen = modified;
+                // A mistake in the file is reported and waited out
+                match watch_preview(scene, *resolution, &output) {
+                    Ok(message) => args.log(&message),
+                    Err(e) => e.report(),
+                }
+            }
+            thread::sleep(Duration::from_secs_f32(interval.max(0.05)));
+        }
+    }
+    Ok(())
+}
+
+fn watch_preview(scene: &Path, resolution: i32, output: &Path) -> Result<String, FracError> {
+    // Same order as a normal run, the file then the variables then argv
+    let flags = [
+        config::load(scene).map_err(error::at(scene))?,
+        config::from_env()?,
+        std::env::args().skip(1).collect(),
+    ]
+    .concat();
+    // Half typed values are reported like any other mistake in the file
+    let mut job = Args::try_from_params(&[], &flags)
+        .map_err(FracError::Flag)
+        .map_err(error::at(scene))?;
+    check(&job, "")?;
+    job.height = (job.height as i64 * resolution as i64 / job.width.max(1) as i64).max(1) as i32;
+    job.width = resolution.max(1);
+    let job = framed(&job)?;
+    let now = Instant::now();
+    let image = Renderer::new(job.clone(), default_functs(&job)).render();
+    metadata::save_png(output, &image, &job).map_err(error::at(output))?;
+    if let Some(protocol) = job.preview_term {
+        terminal::preview(io::stdout().lock(), &image, protocol, job.preview_size)?;
+    }
+    Ok(format!(
+        "Rendered {}x{} in {:.2}s",
+        job.width,
+        job.height,
+        now.elapsed().as_secs_f32()
+    ))
+}
+
+fn init(no_preview: bool) -> Result<(), FracError> {
+    let preview = !no_preview && io::stderr().is_terminal();
+    let answers = wizard::Wizard::new(io::stdin().lock(), io::stderr(), preview).run()?;
+    if let Some(path) = &answers.config {
+        config::write(path, &preset::changed(&answers.args)).map_err(error::at(path))?;
+        eprintln!(
+            "Wrote {}, render it with fracmd --config {}",
+            path.display(),
+            path.display()
+        );
+    }
+    println!("{}", wizard::command_line(&answers.args));
+    if let (Some(palette), Some(data)) = (&answers.palette, &answers.args.dump_data) {
+        println!(
+            "fracmd recolor {} -p {}",
+            wizard::shell_word(&data.display().to_string()),
+            wizard::shell_word(&palette.display().to_string())
+        );
+    }
+    Ok(())
+}
+
+fn bookmarks(args: &Args, action: &BookmarkAction) -> Result<(), FracError> {
+    match action {
+        BookmarkAction::Add {
+            bookmark_name,
+            note,
+            palette,
+            replace,
+        } => {
+            let mark = bookmark::Bookmark::new(bookmark_name, args, palette.clone(), note.clone());
+            let path = bookmark::add(mark, *replace)?;
+            println!("Saved bookmark {} to {}", bookmark_name, path.display());
+        }
+        BookmarkAction::List => {
+            let marks = bookmark::read(&bookmark::store()?)?;
+            let width = marks.iter().map(|b| b.name.len()).max().unwrap_or(0);
+            for mark in marks {
+                println!(
+                    "{:<width$}  {:<10}  {} zoom {}{}{}",
+                    mark.name,
+                    mark.added,
+                    mark.param("origin").unwrap_or("?"),
+                    mark.param("zoom").unwrap_or("?"),
+                    mark.palette
+                        .as_ref()
+                        .map(|p| format!(", palette {}", p))
+                        .unwrap_or_default(),
+                    mark.note
+                        .as_ref()
+                        .map(|n| format!("  {}", n))
+                        .unwrap_or_default(),
+                    width = width
+                );
+            }
+        }
+        BookmarkAction::Remove { bookmark_name } => {
+            bookmark::remove(bookmark_name)?;
+            println!("Removed bookmark {}", bookmark_name);
+        }
+        BookmarkAction::Export { file } => {
+            let marks = bookmark::read(&bookmark::store()?)?;
+            bookmark::write(file, &marks).map_err(error::at(file))?;
+            println!("Exported {} bookmarks to {}", marks.len(), file.display());
+        }
+        BookmarkAction::Import { file, replace } => {
+            let (added, warnings) = bookmark::import(file, *replace).map_err(error::at(file))?;
+            for warning in &warnings {
+                args.log(&format!("warning: {}: {}", file.display(), warning));
+            }
+            println!("Imported {} bookmarks from {}", added, file.display());
+        }
+        BookmarkAction::Render { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+fn list_locations() {
+    for location in catalog::all() {
+        let mut line = format!(
+            "{:<22} {} zoom {}",
+            location.name, location.origin, location.zoom
+        );
+        if let Some(limit) = location.limit {
+            line.push_str(&format!(" limit {}", limit));
+        }
+        if let Some(palette) = &location.palette {
+            line.push_str(&format!(" palette {}", palette));
+        }
+        println!("{}", line);
+        match &location.about {
+            Some(about) => println!("{:<22} {}: {}", "", location.title, about),
+            None => println!("{:<22} {}", "", location.title),
+        }
+    }
+}
+
+fn ansi(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Ansi { color, cols, rows }) = &args.command {
+        let size = terminal_size::terminal_size();
+        let cols = cols.unwrap_or_else(|| size.map_or(80, |(w, _)| w.0 as usize));
+        let rows = rows.unwrap_or_else(|| size.map_or(24, |(_, h)| h.0 as usize - 1));
+        start_threads(args)?;
+        let renderer = Renderer::new(args.clone(), default_functs(args));
+        terminal::write_text(io::stdout().lock(), &renderer, cols, rows, *color)?;
+    }
+    Ok(())
+}
+
+fn contact_sheet(args: &Args) -> Result<(), FracError> {
+    if let Some(Command::Sheet {
+        params,
+        columns,
+        cell_width,
+        cell_height,
+    }) = &args.command
+    {
+        let out = &args.output_or("out/sheet.png");
+        start_threads(args)?;
+        let sheet = sheet::Sheet::load(params, args, *cell_width, *cell_height)
+            .map_err(error::at(params))?;
+        let total = sheet.cells.len();
+        let cells: Vec<_> = sheet
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                args.log(&format!("Rendering {}/{}: {}...", i + 1, total, cell.label));
+                (cell.render(), cell.label.clone())
+            })
+            .collect();
+        let columns = columns
+            .or(sheet.columns)
+            .unwrap_or_else(|| (total as f32).sqrt().ceil() as usize);
+        // No parameter metadata, the cells don't share one set of parameters
+        sheet::compose(&cells, columns, *cell_width, *cell_height)
+            .save(out)
+            .map_err(error::at(out))?;
+        let message = format!("Contact sheet written to {}", out.display());
+        args.log(&message);
+        hooks::run(args, out, &message);
+    }
+    Ok(())
+}
+
+// Saves what an interrupted render has as the output, transparent where
+// tiles are missing, plus a checkpoint --resume continues from. Ends the
+// run with FracError::Interrupted once that's done.
+fn save_interrupted<P: OutputPixel>(
+    args: &Args,
+    name: &str,
+    image: Buffer<P>,
+    done: Vec<bool>,
+) -> Result<(), FracError> {
+    eprintln!();
+    save_output(args, name, &image, None)?;
+    let finished = done.iter().filter(|&&d| d).count();
+    let total = done.len();
+    if args.streaming() {
+        args.log(&format!(
+            "Interrupted with {}/{} tiles done",
+            finished, total
+        ));
+    } else {
+        let path = args
+            .resume
+            .clone()
+            .unwrap_or_else(|| checkpoint::path_for(name));
+        let checkpoint = checkpoint::Checkpoint {
+            output: name.to_string(),
+            image: P::image16(&image).into_owned(),
+            done,
+        };
+        checkpoint::save(&path, &checkpoint, args).map_err(error::at(&path))?;
+        args.log(&format!(
+            "Interrupted with {}/{} tiles done, saved {} and resume with --resume {}",
+            finished,
+            total,
+            name,
+            path.display()
+        ));
+    }
+    Err(FracError::Interrupted)
+}
+
+// Flags for the parameters a file carries, leaving out with a warning any
+// that Args::params() wouldn't have written
+fn stored_flags(args: &Args, path: &Path, params: Vec<(String, String)>) -> Vec<String> {
+    let (known, rest) = Args::known_params(params);
+    for key in rest {
+        args.log(&format!(
+            "warning: {}: ignoring {}, it isn't a render parameter",
+            path.display(),
+            key
+        ));
+    }
+    known
+        .into_iter()
+        .map(|(k, v)| format!("--{}={}", k, v))
+        .collect()
+}
+
+pub fn run() -> Result<(), FracError> {
+    // Variables come first so they can name a config file or location too
+    let env = config::from_env()?;
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let mut args = Args::from_params(&[], &[env.clone(), argv.clone()].concat());
+    // Parameters stored in a checkpoint, image or named preset, then
+    // --location, then --preset, then the config file, then FRACGEN_
+    // variables, then --params-stdin, then the command line
+    let mut layers = Vec::new();
+    if let Some(path) = &args.resume {
+        let (flags, rest) = checkpoint::flags(path).map_err(error::at(path))?;
+        for key in rest {
+            args.log(&format!(
+                "warning: {}: ignoring {}, a checkpoint doesn't restore it",
+                path.display(),
+                key
+            ));
+        }
+        layers.extend(flags);
+    }
+    match &args.command {
+        Some(Command::FromImage { image })
+        | Some(Command::Locate {
+            image: Some(image), ..
+        })
+        | Some(Command::Diff {
+            a: image, b: None, ..
+        }) => {
+            if kf::is_kfr(image) {
+                layers.extend(
+                    kf::read_kfr(image, args.width, args.height).map_err(error::at(image))?,
+                );
+            } else if upr::is_upr(image) || fractint::is_par(image) {
+                let imported = match upr::is_upr(image) {
+                    true => upr::read(image, &args).map_err(error::at(image))?,
+                    false => fractint::load(image, &args).map_err(error::at(image))?,
+                };
+                for warning in &imported.warnings {
+                    args.log(&format!("warning: {}: {}", image.display(), warning));
+                }
+                layers.extend(imported.flags);
+            } else {
+                let params = if sidecar::is_sidecar(image) {
+                    sidecar::read_params(image).map_err(error::at(image))?
+                } else if xmp::is_xmp(image) {
+                    xmp::read_params(image).map_err(error::at(image))?
+                } else {
+                    metadata::read_params(image).map_err(error::at(image))?
+                };
+                layers.extend(stored_flags(&args, image, params));
+            }
+        }
+        Some(Command::Preset {
+            action: PresetAction::Apply { preset_name },
+        }) => layers.extend(preset::load(preset_name)?),
+        Some(Command::Bookmark {
+            action: BookmarkAction::Render { bookmark_name },
+        }) => layers.extend(bookmark::find(bookmark_name)?.flags()),
+        _ => (),
+    }
+    if let Some(name) = &args.location {
+        layers.extend(catalog::find(name)?.flags());
+    }
+    for name in &args.preset {
+        layers.extend(preset::load(name)?);
+    }
+    if let Some(path) = &args.config {
+        layers.extend(config::load(path).map_err(error::at(path))?);
+    }
+    layers.extend(env);
+    if args.params_stdin {
+        layers.extend(config::from_json(io::stdin().lock()).map_err(error::at("stdin"))?);
+    }
+    if !layers.is_empty() {
+        args = Args::from_params(&[], &[layers, argv].concat());
+    }
+    plugin::load_all(&args)?;
+    script::load_all(&mut args)?;
+    check(&args, "")?;
+    if args.deterministic {
+        args.seed.get_or_insert(0);
+    }
+    for message in validate::precision(&args) {
+        args.log(&message);
+    }
+    if let Some(message) = validate::cap_threads(&mut args) {
+        args.log(&message);
+    }
+    if args.dry_run
+        && matches!(
+            args.command,
+            Some(
+                Command::Bench { .. }
+                    | Command::Recolor { .. }
+                    | Command::Cycle { .. }
+                    | Command::Unroll { .. }
+                    | Command::Tiles { .. }
+                    | Command::Stitch { .. }
+                    | Command::Serve { .. }
+                    | Command::ServeApi { .. }
+                    | Command::Gallery { .. }
+                    | Command::Animate { .. }
+                    | Command::Worker { .. }
+                    | Command::View
+                    | Command::Ansi { .. }
+                    | Command::Sheet { .. }
+                    | Command::Explore { .. }
+                    | Command::Control { .. }
+            )
+        )
+    {
+        return Err(FracError::NoDryRun);
+    }
+    match &args.command {
+        Some(Command::Preset { action }) => match action {
+            PresetAction::Save { preset_name } => {
+                let path = preset::save(preset_name, &args)?;
+                println!("Saved preset {} to {}", preset_name, path.display());
+                return Ok(());
+            }
+            PresetAction::List => {
+                for (name, flags) in preset::list()? {
+                    println!("{}: {}", name, flags.join(" "));
+                }
+                return Ok(());
+            }
+            PresetAction::Apply { .. } => (),
+        },
+        Some(Command::Locations) => {
+            list_locations();
+            return Ok(());
+        }
+        Some(Command::Bookmark { action }) => match action {
+            BookmarkAction::Render { .. } => (),
+            action => return bookmarks(&args, action),
+        },
+        Some(Command::Bench { .. }) => return bench(&args),
+        Some(Command::Recolor { .. }) => return recolor(&args),
+        Some(Command::Cycle { .. }) => return cycle(&args),
+        Some(Command::Unroll { .. }) => return unroll_strip(&args),
+        Some(Command::Tiles { .. }) => return render_tiles(&args),
+        Some(Command::Stitch { .. }) => return stitch(&args),
+        Some(Command::Serve {
+            port,
+            host,
+            size,
+            cache,
+            max_pixels,
+            max_limit,
+            metrics,
+        }) => {
+            start_threads(&args)?;
+            metrics.start(&args)?;
+            let serve = serve::Serve {
+                host,
+                port: *port,
+                size: *size,
+                cache: cache.clone(),
+                max_pixels: *max_pixels,
+                max_limit: *max_limit,
+            };
+            return serve::run(&args, serve);
+        }
+        Some(Command::ServeApi {
+            port,
+            host,
+            max_pixels,
+            max_limit,
+            keep,
+            metrics,
+        }) => {
+            start_threads(&args)?;
+            metrics.start(&args)?;
+            return api::run(&args, host, *port, *max_pixels, *max_limit, *keep);
+        }
+        Some(Command::Animate { .. }) => return animate(&args),
+        Some(Command::Worker {
+            coordinator,
+            token,
+            metrics,
+        }) => {
+            start_threads(&args)?;
+            metrics.start(&args)?;
+            return farm::work(&args, coordinator, token.as_deref());
+        }
+        Some(Command::View) => {
+            start_threads(&args)?;
+            return viewer::run(&args);
+        }
+        Some(Command::Ansi { .. }) => return ansi(&args),
+        Some(Command::Sheet { .. }) => return contact_sheet(&args),
+        Some(Command::Batch { .. }) => return render_batch(&args),
+        Some(Command::Explore { .. }) => return explore(&args),
+        Some(Command::Verify { .. }) => return verify(&args),
+        Some(Command::Diff { .. }) => return diff(&args),
+        Some(Command::Watch { .. }) => return watch(&args),
+        Some(Command::Queue { .. }) => return watch_queue(&args),
+        Some(Command::Gallery {
+            dir,
+            thumb_size,
+            title,
+        }) => {
+            let out = args.output_or(&dir.join("gallery").to_string_lossy());
+            let gallery = gallery::scan(dir, &out).map_err(error::at(dir))?;
+            if gallery.skipped > 0 {
+                args.log(&format!(
+                    "Left out {} pngs without fracmd parameters",
+                    gallery.skipped
+                ));
+            }
+            let title = title.clone().unwrap_or_else(|| {
+                dir.canonicalize()
+                    .ok()
+                    .and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_string()))
+                    .unwrap_or_else(|| String::from("fracmd renders"))
+            });
+            let index = gallery::write(&gallery, &out, &title, (*thumb_size).max(1))
+                .map_err(error::at(&out))?;
+            args.log(&format!(
+                "Wrote a gallery of {} images to {}",
+                gallery.entries.len(),
+                index.display()
+            ));
+            return Ok(());
+        }
+        Some(Command::Control { socket, command }) => {
+            let reply = control::send(socket, &command.join(" ")).map_err(error::at(socket))?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Command::Init { no_preview }) => return init(*no_preview),
+        Some(Command::Locate { pixel, point, .. }) => {
+            for line in locate::describe(&args, pixel, point) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        Some(Command::Render) | Some(Command::FromImage { .. }) | None => (),
+    }
+    if let Some(path) = &args.write_config {
+        config::save(path, &args).map_err(error::at(path))?;
+        println!("Wrote {}", path.display());
+        return Ok(());
+    }
+    if !args.sweep.is_empty() {
+        return render_sweep(&args);
+    }
+    if !args.sizes.is_empty() {
+        if args.streaming() {
+            return Err(FracError::CantStream(String::from("--sizes")));
+        }
+        let frame = sizes::frame(&args.sizes);
+        args.width = frame.width;
+        args.height = frame.height;
+    }
+    if args.dry_run {
+        return dry_run(&args, &[(args.name.clone(), args.clone())], 1);
+    }
+    if args.autocenter {
+        let formula = args.formula();
+        let message = nucleus::autocenter(&mut args, &formula);
+        args.log(&message);
+    }
+    if let Some(region) = args.region.take() {
+        let (width, height) = (args.width, args.height);
+        args = region.crop(&args)?;
+        args.log(&format!(
+            "Rendering {}x{} region {} of the {}x{} frame",
+            args.width, args.height, region, width, height
+        ));
+    }
+    let tiles = scheduler::tiles(args.width as u32, args.height as u32, TILE_SIZE);
+    let resumed = args
+        .resume
+        .as_ref()
+        .map(|path| {
+            checkpoint::load(path, args.width as u32, args.height as u32, tiles.len())
+                .map_err(error::at(path))
+        })
+        .transpose()?;
+    let name = if args.streaming() {
+        String::from("-")
+    } else if let Some(resumed) = &resumed {
+        resumed.output.clone()
+    } else {
+        let template = args.output.as_deref().unwrap_or(template::DEFAULT);
+        let path = template::expand(template, &args)?;
+        template::prepare(&path, args.collision).map_err(error::at(&path))?
+    };
+    args.log(&format!(
+        "Now processing {} at {} zoom {} with {} threads...",
+        name,
+        args.origin_text(),
+        args.zoom,
+        args.threads
+    ));
+    // With a control socket there's a thread for every core, so the count
+    // can be raised past --threads later
+    let _control = match &args.control {
+        Some(path) => {
+            let pool = args.threads.max(num_cpus::get());
+            start_pool(pool)?;
+            let control = control::start(path, args.threads, pool).map_err(error::at(path))?;
+            args.log(&format!("Answering on control socket {}", path.display()));
+            Some(control)
+        }
+        None => {
+            start_threads(&args)?;
+            None
+        }
+    };
+    // EXR keeps the colors as floats, everything else is 16 bit
+    match args.format {
+        Format::Exr => render_still::<Rgba<f32>>(&args, &name, &tiles, resumed),
+        _ => render_still::<Rgba<u16>>(&args, &name, &tiles, resumed),
+    }
+}
+
+// The render itself, made in P and only converted as each output is written
+fn render_still<P: OutputPixel>(
+    args: &Args,
+    name: &str,
+    tiles: &[Tile],
+    resumed: Option<checkpoint::Checkpoint>,
+) -> Result<(), FracError> {
+    let now = Instant::now();
+
+    let mandelbrot = Renderer::new(args.clone(), default_functs(args));
+    let reporter = Reporter::new(args.progress, args.width as u32, args.height as u32);
+    let (image, done) = match resumed {
+        Some(resumed) => {
+            let done: Vec<_> = tiles
+                .iter()
+                .zip(&resumed.done)
+                .filter(|(_, &d)| d)
+                .collect();
+            args.log(&format!(
+                "Resuming with {}/{} tiles done",
+                done.len(),
+                tiles.len()
+            ));
+            if let Some(reporter) = &reporter {
+                reporter.skip(done.iter().map(|(t, _)| t.w as u64 * t.h as u64).sum());
+            }
+            (depth::from_image16(&resumed.image), resumed.done)
+        }
+        None => (
+            ImageBuffer::new(args.width as u32, args.height as u32),
+            vec![false; tiles.len()],
+        ),
+    };
+    scheduler::handle_interrupts();
+    // Written next to the output and renamed over it, so viewers never pick
+    // up a half written file
+    let live = (args.live && !args.streaming()).then(|| {
+        (
+            format!("{}.partial", name),
+            Throttle::new(Duration::from_secs_f32(args.live_interval)),
+        )
+    });
+    let watching = (args.live_preview && io::stderr().is_terminal()).then(|| {
+        (
+            terminal::LivePreview::new(args.width, args.height),
+            Throttle::new(Duration::from_millis(250)),
+        )
+    });
+    let window = args
+        .preview_window
+        .then(|| window::Preview::open(args.width, args.height))
+        .and_then(|opened| match opened {
+            Ok(window) => Some((window, Throttle::new(Duration::from_millis(100)))),
+            Err(e) => {
+                eprintln!("Couldn't open the preview window: {}", e);
+                None
+            }
+        });
+    control::update(done.iter().filter(|&&d| d).count(), tiles.len());
+    let (output, done) = mandelbrot.render_from(image, done, |progress| {
+        control::update(progress.done, progress.total);
+        if let Some(reporter) = &reporter {
+            reporter.update(&progress);
+        }
+        if let Some((preview, throttle)) = &watching {
+            throttle.run(|| {
+                let image = P::image16(&progress.image.lock().unwrap()).into_owned();
+                let _ = preview.draw(io::stderr().lock(), &image);
+            })
+        }
+        if let Some((window, throttle)) = &window {
+            throttle.run(|| window.draw(&P::image16(&progress.image.lock().unwrap())))
+        }
+        if let Some((partial, throttle)) = &live {
+            throttle.run(|| {
+                let image = progress.image.lock().unwrap().clone();
+                let saved = save_output(args, partial, &image, None)
+                    .and_then(|_| Ok(fs::rename(partial, name)?));
+                if let Err(e) = saved {
+                    eprintln!("Couldn't update the live output: {}", e);
+                }
+            })
+        }
+    });
+    if let Some((preview, _)) = &watching {
+        let _ = preview.draw(io::stderr().lock(), &P::image16(&output));
+    }
+    let stage = |name: &str| {
+        if let Some(reporter) = &reporter {
+            reporter.stage(name)
+        }
+    };
+    if done.contains(&false) {
+        return save_interrupted(args, name, output, done);
+    }
+    if let Some(reporter) = &reporter {
+        reporter.finish();
+    }
+    if let Some(path) = &args.resume {
+        fs::remove_file(path).map_err(error::at(path))?;
+    }
+    let mut output = output;
+    if let Some(c) = args.plot_orbit {
+        orbit::plot(&mut output, &mandelbrot, c);
+    }
+    write_outputs(args, name, &mandelbrot, &output, stage)?;
+    write_sizes(args, name, &output)?;
+    stage("done");
+    let notif = format!("Finished in: {}ms!", now.elapsed().as_millis());
+    args.log(&coords::describe_scale(
+        args.origin,
+        args.zoom,
+        args.width,
+        args.height,
+    ));
+    args.log(&notif);
+    if !args.streaming() {
+        hooks::run(args, name.as_ref(), &notif);
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+// fracmd
+// Colorings of escaped points, as a trait anything can implement
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A Coloring only sees what iterating found, so the same one colors a
+// render as it goes and a --dump-data file long after. Points inside the
+// set never reach it, they get --set-color. Colors are returned in the
+// working color space and before the sRGB curve, like RgbaF::from_hsv.
+
+use crate::{
+    fracdata::PixelData,
+    palette::{Palette, Transfer},
+    rgbaf::RgbaF,
+};
+
+pub trait Coloring: Send + Sync {
+    // Color of a point that escaped before limit iterations
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF;
+}
+
+// The built-in coloring, hue from the orbit's exponential smoothing sum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hue {
+    pub cexp: f32,
+}
+
+impl Coloring for Hue {
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF {
+        let hue = ((1.0 - (p.s / limit)) * 360.0).powf(self.cexp).powf(1.5);
+        RgbaF::from_hsv(hue, 0.5, 1.0, 1.0)
+    }
+}
+
+// A palette stretched over the smooth iteration count, the recolor
+// command's --palette, --transfer and --cycles
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    // Already in the working color space
+    pub palette: Palette,
+    pub transfer: Transfer,
+    pub cycles: f32,
+    // Shifts the palette along by this fraction of its length
+    pub phase: f32,
+}
+
+impl Coloring for Gradient {
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF {
+        let t = self.transfer.apply(p.smooth) / self.transfer.apply(limit);
+        self.palette.sample(t * self.cycles + self.phase)
+    }
+}
+
+// A palette stepped through one color per iteration, the way Fractint
+// colors, blending between neighbours by the smooth count. Past the end
+// it wraps round to the second color, the first being kept for points
+// that escape straight away.
+#[derive(Clone, Debug)]
+pub struct Indexed {
+    // Already in the working color space
+    pub palette: Palette,
+}
+
+impl Indexed {
+    fn at(&self, i: usize) -> RgbaF {
+        let n = self.palette.colors.len();
+        match i < n || n < 2 {
+            true => self.palette.colors[i % n],
+            false => self.palette.colors[(i - 1) % (n - 1) + 1],
+        }
+    }
+}
+
+impl Coloring for Indexed {
+    fn color(&self, p: &PixelData, _limit: f32) -> RgbaF {
+        let t = p.smooth.max(0.0);
+        let f = t.fract();
+        self.at(t as usize) * (1.0 - f) + self.at(t as usize + 1) * f
+    }
+}
@@ -0,0 +1,222 @@
+// fracmd
+// Output color spaces and ICC profiles
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+
+type Mat3 = [[f32; 3]; 3];
+
+// The working space colorings render into. Procedural colors use its
+// primaries directly, so saturated hues get the full gamut, while sRGB
+// inputs (palettes, --set-color) are converted into it. All spaces share
+// the sRGB transfer curve, which the embedded profile declares.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Colorspace {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+}
+
+const D65: (f32, f32) = (0.3127, 0.3290);
+const D50_XYZ: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+fn mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+pub fn apply(m: &Mat3, v: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn invert(m: &Mat3) -> Mat3 {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *m;
+    let det = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g);
+    [
+        [
+            (e * i - f * h) / det,
+            (c * h - b * i) / det,
+            (b * f - c * e) / det,
+        ],
+        [
+            (f * g - d * i) / det,
+            (a * i - c * g) / det,
+            (c * d - a * f) / det,
+        ],
+        [
+            (d * h - e * g) / det,
+            (b * g - a * h) / det,
+            (a * e - b * d) / det,
+        ],
+    ]
+}
+
+fn xy_to_xyz((x, y): (f32, f32)) -> [f32; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+impl Colorspace {
+    fn primaries(self) -> [(f32, f32); 3] {
+        match self {
+            Colorspace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)],
+            Colorspace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+            Colorspace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colorspace::Srgb => "sRGB",
+            Colorspace::DisplayP3 => "Display P3",
+            Colorspace::Rec2020 => "Rec. 2020 (sRGB transfer)",
+        }
+    }
+
+    // Linear RGB to CIE XYZ, D65 white
+    pub fn to_xyz(self) -> Mat3 {
+        let p = self.primaries().map(xy_to_xyz);
+        let m = [
+            [p[0][0], p[1][0], p[2][0]],
+            [p[0][1], p[1][1], p[2][1]],
+            [p[0][2], p[1][2], p[2][2]],
+        ];
+        let s = apply(&invert(&m), xy_to_xyz(D65));
+        [0, 1, 2].map(|i| [0, 1, 2].map(|j| m[i][j] * s[j]))
+    }
+
+    // Linear sRGB into this space's linear RGB
+    pub fn matrix_from_srgb(self) -> Mat3 {
+        mul(&invert(&self.to_xyz()), &Colorspace::Srgb.to_xyz())
+    }
+
+    // ICC v4 matrix/TRC display profile
+    pub fn icc_profile(self) -> Vec<u8> {
+        let chad = bradford(xy_to_xyz(D65), D50_XYZ);
+        let rgb = mul(&chad, &self.to_xyz());
+        let column = |j: usize| xyz_tag([rgb[0][j], rgb[1][j], rgb[2][j]]);
+
+        // sRGB curve as parametric function type 3
+        let mut trc = sig(b"para");
+        trc.extend([0, 0, 0, 0, 0, 3, 0, 0]);
+        for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            trc.extend(s15f16(v));
+        }
+
+        let mut chad_tag = sig(b"sf32");
+        chad_tag.extend([0; 4]);
+        for row in chad {
+            for v in row {
+                chad_tag.extend(s15f16(v));
+            }
+        }
+
+        let tags: Vec<([u8; 4], Vec<u8>)> = vec![
+            (*b"desc", mluc(self.name())),
+            (*b"cprt", mluc("No copyright, use freely")),
+            (*b"wtpt", xyz_tag(D50_XYZ)),
+            (*b"chad", chad_tag),
+            (*b"rXYZ", column(0)),
+            (*b"gXYZ", column(1)),
+            (*b"bXYZ", column(2)),
+            (*b"rTRC", trc.clone()),
+            (*b"gTRC", trc.clone()),
+            (*b"bTRC", trc),
+        ];
+
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let mut offset = 128 + 4 + 12 * tags.len();
+        for (name, tag) in &tags {
+            table.extend(name);
+            table.extend((offset as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            let mut tag = tag.clone();
+            tag.resize(tag.len().div_ceil(4) * 4, 0);
+            offset += tag.len();
+            data.extend(tag);
+        }
+
+        let size = 128 + table.len() + data.len();
+        let mut header = Vec::with_capacity(128);
+        header.extend((size as u32).to_be_bytes());
+        header.extend([0; 4]); // preferred cmm
+        header.extend([4, 0x30, 0, 0]); // version 4.3
+        header.extend(b"mntrRGB XYZ ");
+        header.extend([0x07, 0xe6, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]); // 2022-01-01
+        header.extend(b"acsp");
+        header.extend([0; 24]); // platform, flags, manufacturer, model, attributes
+        header.extend([0; 4]); // perceptual intent
+        header.extend(xyz(D50_XYZ));
+        header.extend([0; 4 + 16 + 28]); // creator, id, reserved
+        header.extend(table);
+        header.extend(data);
+        header
+    }
+}
+
+fn s15f16(v: f32) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn sig(name: &[u8; 4]) -> Vec<u8> {
+    let mut out = name.to_vec();
+    out.extend([0; 4]);
+    out
+}
+
+fn xyz(v: [f32; 3]) -> Vec<u8> {
+    v.iter().flat_map(|&c| s15f16(c)).collect()
+}
+
+fn xyz_tag(v: [f32; 3]) -> Vec<u8> {
+    let mut out = sig(b"XYZ ");
+    out.extend(xyz(v));
+    out
+}
+
+// Single en-US record
+fn mluc(text: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect();
+    let mut out = sig(b"mluc");
+    out.extend(1u32.to_be_bytes());
+    out.extend(12u32.to_be_bytes());
+    out.extend(b"enUS");
+    out.extend((utf16.len() as u32).to_be_bytes());
+    out.extend(28u32.to_be_bytes());
+    out.extend(utf16);
+    out
+}
+
+// Chromatic adaptation matrix between two white points
+fn bradford(from: [f32; 3], to: [f32; 3]) -> Mat3 {
+    let b = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let (s, d) = (apply(&b, from), apply(&b, to));
+    let scale = [
+        [d[0] / s[0], 0.0, 0.0],
+        [0.0, d[1] / s[1], 0.0],
+        [0.0, 0.0, d[2] / s[2]],
+    ];
+    mul(&invert(&b), &mul(&scale, &b))
+}
@@ -0,0 +1,257 @@
+// fracmd
+// TOML scene files and the other places flags can come from
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A config is a flat table of long flag names, with dashes or underscores:
+//
+//   width = 3840
+//   origin = "-0.745+0.11i"
+//   format = "exr"
+//   aovs = true
+//
+// Every key becomes a flag placed before the real command line, so flags
+// given on the command line win. FRACGEN_ variables and a json object
+// piped in with --params-stdin work the same way, for tools that would
+// rather not build an argv.
+
+use clap::CommandFactory;
+use err_derive::Error;
+use std::{env, fs, io::Read, path::Path};
+use toml::Value;
+
+use crate::{coords, value_name, Args};
+
+pub const ENV_PREFIX: &str = "FRACGEN_";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "toml error: {}", _0)]
+    Toml(#[error(source)] toml::de::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "\"{}\" must be a string, number or boolean", _0)]
+    BadValue(String),
+    #[error(display = "{} isn't a fracmd flag", _0)]
+    Unknown(String),
+    #[error(display = "expected a json object of flag names")]
+    NotObject,
+}
+
+// Command line flags equivalent to the file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<String>, ConfigError> {
+    from_table(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+// Command line flags equivalent to one table of a toml file
+pub fn from_table(table: toml::value::Table) -> Result<Vec<String>, ConfigError> {
+    let mut flags = Vec::new();
+    for (key, value) in table {
+        let flag = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::String(s) => flags.push(format!("{}={}", flag, s)),
+            Value::Integer(i) => flags.push(format!("{}={}", flag, i)),
+            Value::Float(f) => flags.push(format!("{}={}", flag, f)),
+            Value::Boolean(true) => flags.push(flag),
+            Value::Boolean(false) => (),
+            _ => return Err(ConfigError::BadValue(key)),
+        }
+    }
+    Ok(flags)
+}
+
+// Whether a long flag name exists and takes a value, switches don't
+pub fn takes_value(key: &str) -> Option<bool> {
+    Args::command()
+        .get_arguments()
+        .filter(|arg| arg.get_id() != "help" && arg.get_id() != "version")
+        .find(|arg| arg.get_long() == Some(key))
+        .map(|arg| arg.is_takes_value_set())
+}
+
+// FRACGEN_SAMPLES=16 is --samples=16, and a switch is on unless it's set
+// to "", "0" or "false". Sorted so the same environment always gives the
+// same flags.
+pub fn from_env() -> Result<Vec<String>, ConfigError> {
+    let mut vars: Vec<(String, String)> = env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .filter(|(k, _)| k.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+    let mut flags = Vec::new();
+    for (var, value) in vars {
+        let key = var[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+        match takes_value(&key) {
+            Some(true) => flags.push(format!("--{}={}", key, value)),
+            Some(false) if !matches!(value.as_str(), "" | "0" | "false") => {
+                flags.push(format!("--{}", key))
+            }
+            Some(false) => (),
+            None => return Err(ConfigError::Unknown(var)),
+        }
+    }
+    Ok(flags)
+}
+
+// Like a config file, except an array repeats the flag, for --sweep and
+// --preset, and null leaves it off the same as false
+pub fn from_json<R: Read>(reader: R) -> Result<Vec<String>, ConfigError> {
+    use serde_json::Value;
+    let value: Value = serde_json::from_reader(reader)?;
+    let table = value.as_object().ok_or(ConfigError::NotObject)?;
+    let mut flags = Vec::new();
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        if takes_value(&name).is_none() {
+            return Err(ConfigError::Unknown(key.clone()));
+        }
+        let values = match value {
+            Value::Array(values) => values.as_slice(),
+            value => std::slice::from_ref(value),
+        };
+        for value in values {
+            match value {
+                Value::String(s) => flags.push(format!("--{}={}", name, s)),
+                Value::Number(n) => flags.push(format!("--{}={}", name, n)),
+                Value::Bool(true) => flags.push(format!("--{}", name)),
+                Value::Bool(false) | Value::Null => (),
+                _ => return Err(ConfigError::BadValue(key.clone())),
+            }
+        }
+    }
+    Ok(flags)
+}
+
+// A settings pair as a command line flag, switches are stored as "true"
+pub fn flag(key: &str, value: &str) -> String {
+    if value == "true" {
+        format!("--{}", key)
+    } else {
+        format!("--{}={}", key, value)
+    }
+}
+
+// A flag set to a number worked out along the way, rounded when the flag
+// only takes whole numbers
+pub fn number_flag(key: &str, value: f32) -> String {
+    let flag = format!("--{}={}", key, value);
+    let fits = Args::command()
+        .try_get_matches_from(["fracmd", &flag])
+        .is_ok();
+    if fits {
+        flag
+    } else {
+        format!("--{}={}", key, value.round().max(0.0) as i64)
+    }
+}
+
+// Everything params() has plus the output settings, unset options and
+// switches that are off are left out
+pub fn settings(args: &Args) -> Vec<(&'static str, String)> {
+    let mut out = args.params();
+    let path = |p: &Option<std::path::PathBuf>| p.as_ref().map(|p| p.display().to_string());
+    let switch = |on: bool| on.then(|| String::from("true"));
+    let optional = [
+        ("format", Some(value_name(args.format))),
+        ("colors", Some(args.colors.to_string())),
+        ("quality", Some(args.quality.to_string())),
+        ("creator", args.creator.clone()),
+        ("index-palette", path(&args.index_palette)),
+        ("output", args.output.clone()),
+        ("collision", Some(value_name(args.collision))),
+        ("aovs", switch(args.aovs)),
+        ("live", switch(args.live)),
+        ("live-interval", Some(args.live_interval.to_string())),
+        ("live-preview", switch(args.live_preview)),
+        ("preview-window", switch(args.preview_window)),
+        ("progress", Some(value_name(args.progress))),
+        ("backend", Some(value_name(args.backend))),
+        ("pyramid", path(&args.pyramid)),
+        ("pyramid-format", Some(value_name(args.pyramid_format))),
+        ("tile-size", Some(args.tile_size.to_string())),
+        (
+            "iiif-id",
+            Some(args.iiif_id.clone()).filter(|s| !s.is_empty()),
+        ),
+        ("plot-orbit", args.plot_orbit.map(coords::format)),
+        ("svg", path(&args.svg)),
+        ("svg-spacing", Some(args.svg_spacing.to_string())),
+        ("mesh", path(&args.mesh)),
+        ("mesh-source", Some(value_name(args.mesh_source))),
+        ("mesh-scale", Some(args.mesh_scale.to_string())),
+        ("mesh-base", Some(args.mesh_base.to_string())),
+        ("mesh-step", Some(args.mesh_step.to_string())),
+        ("thumbnail", args.thumbnail.map(|t| t.to_string())),
+        ("preview-term", args.preview_term.map(value_name)),
+        ("preview-size", Some(args.preview_size.to_string())),
+        ("dump-data", path(&args.dump_data)),
+        ("kfr", path(&args.kfr)),
+        (
+            "sizes",
+            Some(args.sizes.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.join(",")),
+        ),
+        ("region", args.region.map(|r| r.to_string())),
+        (
+            "plugin",
+            Some(
+                args.plugin
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .filter(|p| !p.is_empty())
+            .map(|p| p.join(",")),
+        ),
+        (
+            "script",
+            Some(
+                args.script
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .filter(|p| !p.is_empty())
+            .map(|p| p.join(",")),
+        ),
+        ("autocenter", switch(args.autocenter)),
+        ("no-notify", switch(args.no_notify)),
+        ("open", switch(args.open)),
+    ];
+    out.extend(optional.into_iter().filter_map(|(k, v)| v.map(|v| (k, v))));
+    out
+}
+
+pub fn save<P: AsRef<Path>>(path: P, args: &Args) -> Result<(), ConfigError> {
+    write(path, &settings(args))
+}
+
+// Numbers and booleans are written bare, everything else as a toml string
+pub fn write<P: AsRef<Path>>(path: P, pairs: &[(&str, String)]) -> Result<(), ConfigError> {
+    let mut text = format!("# fracmd {} scene\n", env!("CARGO_PKG_VERSION"));
+    for (key, value) in pairs {
+        let bare = value == "true" || value.parse::<f64>().is_ok_and(|v| v.is_finite());
+        let value = if bare {
+            value.clone()
+        } else {
+            Value::String(value.clone()).to_string()
+        };
+        text.push_str(&format!("{} = {}\n", key, value));
+    }
+    Ok(fs::write(path, text)?)
+}
@@ -0,0 +1,158 @@
+// fracmd
+// Iso-iteration contour export
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{fracdata::PixelData, rgbaf::RgbaF};
+
+type Segment = ((f32, f32), (f32, f32));
+
+// Marching squares over a row-major scalar field, one segment list per level
+pub fn march(values: &[f32], w: usize, h: usize, level: f32) -> Vec<Segment> {
+    let mut out = Vec::new();
+    let v = |x: usize, y: usize| values[y * w + x];
+    // Point along an edge where the field crosses level
+    let lerp = |p0: (f32, f32), v0: f32, p1: (f32, f32), v1: f32| {
+        let t = if v1 != v0 {
+            (level - v0) / (v1 - v0)
+        } else {
+            0.5
+        };
+        (p0.0 + (p1.0 - p0.0) * t, p0.1 + (p1.1 - p0.1) * t)
+    };
+    for y in 0..h.saturating_sub(1) {
+        for x in 0..w.saturating_sub(1) {
+            let (fx, fy) = (x as f32, y as f32);
+            let corners = [
+                ((fx, fy), v(x, y)),
+                ((fx + 1.0, fy), v(x + 1, y)),
+                ((fx + 1.0, fy + 1.0), v(x + 1, y + 1)),
+                ((fx, fy + 1.0), v(x, y + 1)),
+            ];
+            let case = corners
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, (_, v))| acc | (((*v > level) as usize) << i));
+            // Edge n joins corner n and corner n + 1
+            let edge = |n: usize| {
+                let (p0, v0) = corners[n];
+                let (p1, v1) = corners[(n + 1) % 4];
+                lerp(p0, v0, p1, v1)
+            };
+            let pairs: &[(usize, usize)] = match case {
+                1 | 14 => &[(3, 0)],
+                2 | 13 => &[(0, 1)],
+                3 | 12 => &[(3, 1)],
+                4 | 11 => &[(1, 2)],
+                6 | 9 => &[(0, 2)],
+                7 | 8 => &[(2, 3)],
+                5 => &[(3, 0), (1, 2)],
+                10 => &[(0, 1), (2, 3)],
+                _ => &[],
+            };
+            out.extend(pairs.iter().map(|&(a, b)| (edge(a), edge(b))));
+        }
+    }
+    out
+}
+
+fn key(p: (f32, f32)) -> (i64, i64) {
+    ((p.0 * 1024.0).round() as i64, (p.1 * 1024.0).round() as i64)
+}
+
+// Chains segments sharing endpoints into polylines, so plotters don't lift
+// the pen for every cell
+pub fn join(segments: &[Segment]) -> Vec<Vec<(f32, f32)>> {
+    let mut ends: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        ends.entry(key(*a)).or_default().push(i);
+        ends.entry(key(*b)).or_default().push(i);
+    }
+    let mut used = vec![false; segments.len()];
+    // Marks and returns the far end of an unused segment touching p
+    let next = |p: (f32, f32), used: &mut Vec<bool>| {
+        let i = *ends.get(&key(p))?.iter().find(|&&i| !used[i])?;
+        used[i] = true;
+        let (a, b) = segments[i];
+        Some(if key(a) == key(p) { b } else { a })
+    };
+    let mut lines = Vec::new();
+    for i in 0..segments.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let (a, b) = segments[i];
+        let mut line = VecDeque::from([a, b]);
+        while let Some(p) = next(*line.back().unwrap(), &mut used) {
+            line.push_back(p);
+        }
+        while let Some(p) = next(*line.front().unwrap(), &mut used) {
+            line.push_front(p);
+        }
+        lines.push(line.into());
+    }
+    lines
+}
+
+// One svg layer per contour level, every `spacing` smooth iterations
+pub fn save_svg<P: AsRef<Path>>(
+    path: P,
+    data: &[PixelData],
+    w: usize,
+    h: usize,
+    limit: f32,
+    spacing: f32,
+) -> io::Result<()> {
+    let values: Vec<f32> = data.iter().map(|p| p.smooth).collect();
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+         xmlns:inkscape=\"http://www.inkscape.org/namespaces/inkscape\" \
+         width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">"
+    )?;
+    let mut level = spacing;
+    while level < limit {
+        let segments = march(&values, w, h, level);
+        if !segments.is_empty() {
+            let color = RgbaF::from_hsv(level / limit * 360.0, 0.8, 0.8, 1.0).to_arr8();
+            let mut d = String::new();
+            for line in join(&segments) {
+                for (i, (x, y)) in line.iter().enumerate() {
+                    let cmd = if i == 0 { 'M' } else { 'L' };
+                    let _ = write!(d, "{}{:.2} {:.2}", cmd, x, y);
+                }
+            }
+            writeln!(
+                out,
+                "<g inkscape:groupmode=\"layer\" inkscape:label=\"iter {level}\">\
+                 <path fill=\"none\" stroke=\"#{:02x}{:02x}{:02x}\" stroke-width=\"0.5\" d=\"{d}\"/></g>",
+                color[0], color[1], color[2]
+            )?;
+        }
+        level += spacing;
+    }
+    writeln!(out, "</svg>")?;
+    out.flush()
+}
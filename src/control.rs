@@ -0,0 +1,226 @@
+// fracmd
+// The control socket of a running render, and `fracmd control`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A still render started with --control path answers on a Unix socket
+// there until it ends. Every line sent is one command and gets one line
+// of json back, the render's status or {"error": ...}:
+//
+//   progress     tiles done and in total, seconds so far, paused, threads
+//   pause        the tiles rendering finish and no more start
+//   resume
+//   threads 4    how many tiles render at once
+//   stop         what Ctrl-C does, saving the image and a checkpoint
+//
+// The thread pool has a thread per core however many --threads asks for,
+// so `threads` can go back up past it. Pausing and the thread count only
+// hold tiles back before they start, a tile already rendering always
+// finishes. Windows has no Unix sockets in std, so there it's refused.
+
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use err_derive::Error;
+use serde_json::{json, Value};
+#[cfg(unix)]
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    thread,
+};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::{metrics, scheduler};
+
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+static POOL: AtomicUsize = AtomicUsize::new(0);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "another render's control socket, or not a socket")]
+    InUse,
+    #[error(display = "no render answering: {}", _0)]
+    Connect(std::io::Error),
+    #[error(display = "the render answered with something other than json")]
+    BadReply,
+    #[error(display = "the render refused: {}", _0)]
+    Refused(String),
+    #[error(display = "control sockets need Unix sockets, which this platform lacks")]
+    Unsupported,
+}
+
+// Called from the render hook, so progress has something to say
+pub fn update(done: usize, total: usize) {
+    DONE.store(done, Ordering::SeqCst);
+    TOTAL.store(total, Ordering::SeqCst);
+}
+
+fn status() -> Value {
+    let done = DONE.load(Ordering::SeqCst);
+    let total = TOTAL.load(Ordering::SeqCst);
+    json!({
+        "done": done,
+        "total": total,
+        "percent": match total {
+            0 => 0.0,
+            total => done as f64 * 100.0 / total as f64,
+        },
+        "seconds": STARTED.get().map_or(0.0, |s| s.elapsed().as_secs_f64()),
+        "paused": scheduler::paused(),
+        "threads": THREADS.load(Ordering::SeqCst),
+        "stopping": scheduler::interrupted(),
+    })
+}
+
+// The reply to one line
+fn answer(line: &str) -> Value {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["progress"] => (),
+        ["pause"] => {
+            if !scheduler::paused() {
+                eprintln!("\nPaused from the control socket");
+            }
+            scheduler::pause(true);
+        }
+        ["resume"] => {
+            if scheduler::paused() {
+                eprintln!("\nResumed from the control socket");
+            }
+            scheduler::pause(false);
+        }
+        ["threads", count] => {
+            let pool = POOL.load(Ordering::SeqCst);
+            match count.parse::<usize>() {
+                Ok(count) if (1..=pool).contains(&count) => {
+                    THREADS.store(count, Ordering::SeqCst);
+                    scheduler::set_limit(count);
+                    metrics::THREADS.set(count as i64);
+                }
+                _ => return json!({ "error": format!("threads takes 1 to {}", pool) }),
+            }
+        }
+        ["stop"] => {
+            if !scheduler::interrupted() {
+                eprintln!("\nStopping after the current tiles, asked from the control socket");
+            }
+            scheduler::interrupt();
+        }
+        _ => {
+            return json!({
+                "error": format!(
+                    "unknown command {:?}, try progress, pause, resume, threads N or stop",
+                    line.trim()
+                )
+            })
+        }
+    }
+    status()
+}
+
+// Removes the socket when the render is done with it
+pub struct Control {
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Starts answering on path, with threads of the pool's tiles rendering at
+// once to begin with
+#[cfg(unix)]
+pub fn start(path: &Path, threads: usize, pool: usize) -> Result<Control, ControlError> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        // One left behind by a render that was killed is reused
+        if !meta.file_type().is_socket() || UnixStream::connect(path).is_ok() {
+            return Err(ControlError::InUse);
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    POOL.store(pool, Ordering::SeqCst);
+    THREADS.store(threads, Ordering::SeqCst);
+    scheduler::set_limit(threads);
+    STARTED.get_or_init(Instant::now);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let _ = serve(stream);
+            });
+        }
+    });
+    Ok(Control {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn start(_path: &Path, _threads: usize, _pool: usize) -> Result<Control, ControlError> {
+    Err(ControlError::Unsupported)
+}
+
+#[cfg(unix)]
+fn serve(stream: UnixStream) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            writeln!(out, "{}", answer(&line))?;
+        }
+    }
+    Ok(())
+}
+
+// Sends one command to the render at path and returns its reply
+#[cfg(unix)]
+pub fn send(path: &Path, command: &str) -> Result<Value, ControlError> {
+    let mut stream = UnixStream::connect(path).map_err(ControlError::Connect)?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    let reply: Value = serde_json::from_str(&reply).map_err(|_| ControlError::BadReply)?;
+    match reply.get("error").and_then(Value::as_str) {
+        Some(error) => Err(ControlError::Refused(error.to_string())),
+        None => Ok(reply),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_path: &Path, _command: &str) -> Result<Value, ControlError> {
+    Err(ControlError::Unsupported)
+}
@@ -0,0 +1,209 @@
+// fracmd
+// Complex number parsing and printing
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num::complex::Complex;
+
+fn real(s: &str, whole: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .map_err(|_| format!("{:?} is not a complex number like -0.7436+0.1318i", whole))
+}
+
+// Accepts "a+bi", "a - b i", "a+bj", "bi", "a", "a,b" and "(a, b)", with
+// exponents like "1e-5-2.5e-3i"
+pub fn parse(text: &str) -> Result<Complex<f64>, String> {
+    let (re, im) = parts(text)?;
+    Ok(Complex::new(real(&re, text)?, real(&im, text)?))
+}
+
+// The real and imaginary parts as written, so digits past what a float
+// keeps can be handed on
+pub fn parts(text: &str) -> Result<(String, String), String> {
+    let s: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let s = s
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .unwrap_or(&s);
+    if let Some((re, im)) = s.split_once(',') {
+        real(re, text)?;
+        real(im, text)?;
+        return Ok((re.to_string(), im.to_string()));
+    }
+    let imaginary = match s.strip_suffix(['i', 'j']) {
+        Some(rest) => rest,
+        None => {
+            real(s, text)?;
+            return Ok((s.to_string(), String::from("0")));
+        }
+    };
+    // The sign starting the imaginary part, skipping a leading sign and
+    // the ones inside exponents
+    let bytes = imaginary.as_bytes();
+    let split = (1..bytes.len())
+        .rev()
+        .find(|&i| matches!(bytes[i], b'+' | b'-') && !matches!(bytes[i - 1], b'e' | b'E'));
+    let (re, im) = match split {
+        Some(i) => (&imaginary[..i], &imaginary[i..]),
+        None => ("0", imaginary),
+    };
+    let im = match im {
+        "" | "+" => "1",
+        "-" => "-1",
+        im => im,
+    };
+    real(re, text)?;
+    real(im, text)?;
+    Ok((re.to_string(), im.trim_start_matches('+').to_string()))
+}
+
+// --re and --im, kept as written
+pub fn parse_decimal(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    match text.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(text.to_string()),
+        _ => Err(format!("{:?} is not a number like -0.7436", text)),
+    }
+}
+
+// What a part of a coordinate becomes in the renderer
+pub fn part(text: &str) -> f32 {
+    text.parse::<f64>().map_or(f32::NAN, |v| v as f32)
+}
+
+// Significant digits of a decimal, "-0.074360" has 4
+pub fn digits(text: &str) -> usize {
+    let mantissa = text.split(['e', 'E']).next().unwrap_or_default();
+    let mut digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    if mantissa.contains('.') {
+        digits = digits.trim_end_matches('0').to_string();
+    }
+    digits.trim_start_matches('0').len()
+}
+
+pub fn parse_f32(text: &str) -> Result<Complex<f32>, String> {
+    parse(text).map(|c| Complex::new(c.re as f32, c.im as f32))
+}
+
+// "-0.7436+0.1318i", the shortest digits that parse back to the same value
+pub fn format(c: Complex<f32>) -> String {
+    let sign = if c.im.is_sign_negative() { '-' } else { '+' };
+    format!("{}{}{}i", c.re, sign, c.im.abs())
+}
+
+// --zoom-exp, which has to leave a zoom an f32 can hold
+pub fn parse_zoom_exp(text: &str) -> Result<f64, String> {
+    let exp: f64 = text
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} is not a number", text))?;
+    let least = (f32::MIN_POSITIVE as f64).log10().ceil();
+    let most = ((f32::MAX as f64).log10() * 10.0).floor() / 10.0;
+    if !(least..=most).contains(&exp) {
+        return Err(format!(
+            "zooms are f32, so it goes from {} to {}",
+            least, most
+        ));
+    }
+    Ok(exp)
+}
+
+// Zoom as a power of ten and the visible span of the plane, with a warning
+// once neighbouring pixels stop being distinct f32 values
+pub fn describe_scale(origin: Complex<f32>, zoom: f32, width: i32, height: i32) -> String {
+    let span = Complex::new(
+        2.0 / zoom as f64,
+        2.0 * height as f64 / width as f64 / zoom as f64,
+    );
+    let mut out = format!(
+        "zoom {:.3e} (10^{:.2}), view spans {:.3e} x {:.3e}",
+        zoom,
+        (zoom as f64).log10(),
+        span.re,
+        span.im
+    );
+    let pixel = span.re / width as f64;
+    let ulp = origin.re.abs().max(origin.im.abs()).max(1.0) as f64 * f32::EPSILON as f64;
+    if pixel < ulp {
+        out.push_str(", past f32 precision so expect blocky pixels");
+    }
+    out
+}
+
+// 2x2 matrix applied to view offsets, rows in image axes so the first row
+// gives the real part
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Affine(pub [[f32; 2]; 2]);
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine([[1.0, 0.0], [0.0, 1.0]]);
+
+    pub fn rotation(degrees: f32) -> Affine {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Affine([[cos, -sin], [sin, cos]])
+    }
+
+    // Shears the real axis along the imaginary one
+    pub fn skew(degrees: f32) -> Affine {
+        Affine([[1.0, degrees.to_radians().tan()], [0.0, 1.0]])
+    }
+
+    // Scales the real axis against the imaginary one
+    pub fn stretch(factor: f32) -> Affine {
+        Affine([[factor, 0.0], [0.0, 1.0]])
+    }
+
+    pub fn then(self, outer: Affine) -> Affine {
+        let (a, b) = (outer.0, self.0);
+        Affine([0, 1].map(|i| [0, 1].map(|j| a[i][0] * b[0][j] + a[i][1] * b[1][j])))
+    }
+
+    pub fn apply(self, z: Complex<f32>) -> Complex<f32> {
+        let m = self.0;
+        Complex::new(
+            m[0][0] * z.re + m[0][1] * z.im,
+            m[1][0] * z.re + m[1][1] * z.im,
+        )
+    }
+
+    // None for matrices that flatten the view onto a line
+    pub fn inverse(self) -> Option<Affine> {
+        let [[a, b], [c, d]] = self.0;
+        let det = a * d - b * c;
+        (det != 0.0).then_some(Affine([[d / det, -b / det], [-c / det, a / det]]))
+    }
+}
+
+// "a,b,c,d" row by row, brackets optional
+pub fn parse_affine(text: &str) -> Result<Affine, String> {
+    let bad = || format!("{:?} is not a 2x2 matrix like 1,0,0,1", text);
+    let values: Vec<f32> = text
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .map(|v| v.trim_matches(['[', ']', '(', ')']))
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().map_err(|_| bad()))
+        .collect::<Result<_, _>>()?;
+    match values[..] {
+        [a, b, c, d] => Ok(Affine([[a, b], [c, d]])),
+        _ => Err(bad()),
+    }
+}
+
+impl std::fmt::Display for Affine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let [[a, b], [c, d]] = self.0;
+        write!(f, "{},{},{},{}", a, b, c, d)
+    }
+}
@@ -0,0 +1,148 @@
+// fracmd
+// The pixel types a render can be made in
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The renderer works out every pixel as floats and only rounds it when it
+// goes into the image, so the image can be 8 bit, 16 bit or float. All
+// three hold sRGB encoded values from 0 to 1 of their range, float ones
+// included; EXR makes them linear itself. Renders are 16 bit unless asked
+// otherwise, and every encoder takes the depth it writes, converting only
+// when the image isn't already in it. Going through 16 bit and back, as
+// most of them do, changes nothing about an image made there.
+
+use image::{ImageBuffer, Pixel, Rgba};
+use std::borrow::Cow;
+
+use crate::scheduler::Image16;
+
+pub type Buffer<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
+pub type Image8 = ImageBuffer<Rgba<u8>, Vec<u8>>;
+pub type ImageF32 = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+pub trait OutputPixel: Pixel<Subpixel: Send + Sync> + Send + Sync + 'static {
+    // From the finished sRGB encoded channels, 0 to 1
+    fn from_color(channels: [f32; 4]) -> Self;
+
+    fn from_u16(pixel: Rgba<u16>) -> Self;
+
+    fn to_u16(self) -> Rgba<u16>;
+
+    fn to_f32(self) -> Rgba<f32>;
+
+    // The image in 16 bit, borrowed when it already is
+    fn image16(image: &Buffer<Self>) -> Cow<'_, Image16> {
+        Cow::Owned(convert(image, Self::to_u16))
+    }
+
+    fn image_f32(image: &Buffer<Self>) -> Cow<'_, ImageF32> {
+        Cow::Owned(convert(image, Self::to_f32))
+    }
+}
+
+// The same image in another pixel type
+pub fn convert<P: Pixel, Q: Pixel, F: Fn(P) -> Q>(image: &Buffer<P>, f: F) -> Buffer<Q> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        f(*image.get_pixel(x, y))
+    })
+}
+
+// Filters and averages work on colors squared, close to linear light, with
+// alpha left as it is. A 16 bit pixel into that space and back, values
+// past 0 to 1 that a filter rang out to being clamped.
+pub fn squared(pixel: Rgba<u16>) -> [f32; 4] {
+    let [r, g, b, a] = pixel.0.map(|v| v as f32 / u16::MAX as f32);
+    [r * r, g * g, b * b, a]
+}
+
+pub fn unsquared([r, g, b, a]: [f32; 4]) -> Rgba<u16> {
+    let channel = |v: f32| (v.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+    Rgba([
+        channel(r.max(0.0).sqrt()),
+        channel(g.max(0.0).sqrt()),
+        channel(b.max(0.0).sqrt()),
+        channel(a),
+    ])
+}
+
+// A 16 bit image, a checkpoint's say, in the pixel type being rendered
+pub fn from_image16<P: OutputPixel>(image: &Image16) -> Buffer<P> {
+    convert(image, P::from_u16)
+}
+
+impl OutputPixel for Rgba<u8> {
+    fn from_color(channels: [f32; 4]) -> Self {
+        Rgba(channels.map(|v| (v * u8::MAX as f32) as u8))
+    }
+
+    fn from_u16(pixel: Rgba<u16>) -> Self {
+        Rgba(pixel.0.map(|v| (v >> 8) as u8))
+    }
+
+    fn to_u16(self) -> Rgba<u16> {
+        Rgba(self.0.map(|v| v as u16 * 257))
+    }
+
+    fn to_f32(self) -> Rgba<f32> {
+        Rgba(self.0.map(|v| v as f32 / u8::MAX as f32))
+    }
+}
+
+impl OutputPixel for Rgba<u16> {
+    fn from_color(channels: [f32; 4]) -> Self {
+        Rgba(channels.map(|v| (v * u16::MAX as f32) as u16))
+    }
+
+    fn from_u16(pixel: Rgba<u16>) -> Self {
+        pixel
+    }
+
+    fn to_u16(self) -> Rgba<u16> {
+        self
+    }
+
+    fn to_f32(self) -> Rgba<f32> {
+        Rgba(self.0.map(|v| v as f32 / u16::MAX as f32))
+    }
+
+    fn image16(image: &Image16) -> Cow<'_, Image16> {
+        Cow::Borrowed(image)
+    }
+}
+
+impl OutputPixel for Rgba<f32> {
+    fn from_color(channels: [f32; 4]) -> Self {
+        Rgba(channels)
+    }
+
+    fn from_u16(pixel: Rgba<u16>) -> Self {
+        pixel.to_f32()
+    }
+
+    fn to_u16(self) -> Rgba<u16> {
+        Rgba(
+            self.0
+                .map(|v| (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16),
+        )
+    }
+
+    fn to_f32(self) -> Rgba<f32> {
+        self
+    }
+
+    fn image_f32(image: &ImageF32) -> Cow<'_, ImageF32> {
+        Cow::Borrowed(image)
+    }
+}
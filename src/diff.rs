@@ -0,0 +1,128 @@
+// fracmd
+// Comparing two renders
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Colors are compared as stored, 16 bit values scaled to 0..1, and only
+// red, green and blue count towards the error. Alpha only differs where an
+// interrupted render left holes, which the differing pixel count shows.
+
+use err_derive::Error;
+use image::{ImageBuffer, Rgba};
+
+use crate::scheduler::Image16;
+
+#[derive(Debug, Error)]
+pub enum DiffError {
+    #[error(
+        display = "the images are {}x{} and {}x{}, they must be the same size",
+        _0,
+        _1,
+        _2,
+        _3
+    )]
+    Size(u32, u32, u32, u32),
+}
+
+pub struct Metrics {
+    pub mse: f64,
+    // Infinite for identical images
+    pub psnr: f64,
+    pub max_delta: u16,
+    pub differing: usize,
+    pub pixels: usize,
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let psnr = if self.psnr.is_finite() {
+            format!("{:.2} dB", self.psnr)
+        } else {
+            String::from("identical")
+        };
+        writeln!(f, "mse {:.3e}", self.mse)?;
+        writeln!(f, "psnr {}", psnr)?;
+        writeln!(
+            f,
+            "max channel delta {} of {}, {} in 8 bit",
+            self.max_delta,
+            u16::MAX,
+            (self.max_delta as f32 / 257.0).round()
+        )?;
+        write!(
+            f,
+            "{} of {} pixels differ ({:.2}%)",
+            self.differing,
+            self.pixels,
+            100.0 * self.differing as f64 / self.pixels.max(1) as f64
+        )
+    }
+}
+
+fn same_size(a: &Image16, b: &Image16) -> Result<(), DiffError> {
+    if a.dimensions() == b.dimensions() {
+        Ok(())
+    } else {
+        Err(DiffError::Size(
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height(),
+        ))
+    }
+}
+
+pub fn metrics(a: &Image16, b: &Image16) -> Result<Metrics, DiffError> {
+    same_size(a, b)?;
+    let mut squared = 0.0;
+    let mut max_delta = 0;
+    let mut differing = 0;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        if pa != pb {
+            differing += 1;
+        }
+        for c in 0..4 {
+            let delta = pa.0[c].abs_diff(pb.0[c]);
+            max_delta = max_delta.max(delta);
+            if c < 3 {
+                squared += (delta as f64 / u16::MAX as f64).powi(2);
+            }
+        }
+    }
+    let mse = squared / (3 * a.width() as usize * a.height() as usize).max(1) as f64;
+    Ok(Metrics {
+        mse,
+        psnr: -10.0 * mse.log10(),
+        max_delta,
+        differing,
+        pixels: (a.width() * a.height()) as usize,
+    })
+}
+
+// Per channel |a - b| times gain, opaque. Without a gain the biggest
+// difference is stretched to full brightness, so faint ones still show.
+pub fn difference(a: &Image16, b: &Image16, gain: Option<f32>) -> Result<Image16, DiffError> {
+    same_size(a, b)?;
+    let gain = gain.unwrap_or_else(|| {
+        let max = metrics(a, b).map_or(0, |m| m.max_delta);
+        u16::MAX as f32 / max.max(1) as f32
+    });
+    Ok(ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let channel =
+            |c: usize| (pa.0[c].abs_diff(pb.0[c]) as f32 * gain).min(u16::MAX as f32) as u16;
+        Rgba([channel(0), channel(1), channel(2), u16::MAX])
+    }))
+}
@@ -0,0 +1,260 @@
+// fracmd
+// Errors that end a run and their exit statuses
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use std::path::Path;
+
+use crate::{
+    audio::AudioError, batch::BatchError, bookmark::BookmarkError, catalog::CatalogError,
+    checkpoint::CheckpointError, config::ConfigError, diff::DiffError, expmap::ExpmapError,
+    fracdata::FracDataError, fractint::ParError, keyframe::KeyError, kf::KfError,
+    manifest::ManifestError, metadata::MetadataError, palette::PaletteError, plugin::PluginError,
+    preset::PresetError, queue::QueueError, region::RegionError, scheduler, script::ScriptError,
+    sheet::SheetError, sidecar::SidecarError, sweep::SweepError, template::TemplateError,
+    upr::UprError, validate::Invalid, verify::VerifyError, xmp::XmpError,
+};
+#[cfg(feature = "cli")]
+use crate::{
+    control::ControlError, farm::FarmError, gallery::GalleryError, serve::ServeError,
+    viewer::ViewerError,
+};
+
+// Documented in the readme, scripts rely on these
+pub const FAILED: i32 = 1;
+pub const INVALID: i32 = 2;
+pub const IO: i32 = 3;
+pub const INTERRUPTED: i32 = scheduler::INTERRUPTED_STATUS;
+
+#[derive(Debug, Error)]
+pub enum FracError {
+    #[error(display = "{}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "image error: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "gif error: {}", _0)]
+    Gif(#[error(source)] gif::EncodingError),
+    #[error(display = "exr error: {}", _0)]
+    Exr(#[error(source)] exr::error::Error),
+    #[error(display = "tiff error: {}", _0)]
+    Tiff(#[error(source)] tiff::TiffError),
+    #[cfg(feature = "parallel")]
+    #[error(display = "couldn't start the worker threads: {}", _0)]
+    ThreadPool(#[error(source)] rayon::ThreadPoolBuildError),
+    #[error(display = "{}", _0)]
+    Audio(#[error(source)] AudioError),
+    #[error(display = "{}", _0)]
+    Batch(#[error(source)] BatchError),
+    #[error(display = "{}", _0)]
+    Bookmark(#[error(source)] BookmarkError),
+    #[error(display = "{}", _0)]
+    Catalog(#[error(source)] CatalogError),
+    #[error(display = "{}", _0)]
+    Checkpoint(#[error(source)] CheckpointError),
+    #[error(display = "{}", _0)]
+    Config(#[error(source)] ConfigError),
+    #[cfg(feature = "cli")]
+    #[error(display = "{}", _0)]
+    Control(#[error(source)] ControlError),
+    #[error(display = "{}", _0)]
+    Diff(#[error(source)] DiffError),
+    #[error(display = "{}", _0)]
+    Expmap(#[error(source)] ExpmapError),
+    #[cfg(feature = "cli")]
+    #[error(display = "{}", _0)]
+    Farm(#[error(source)] FarmError),
+    #[error(display = "{}", _0)]
+    FracData(#[error(source)] FracDataError),
+    #[error(display = "{}", _0)]
+    Keys(#[error(source)] KeyError),
+    #[error(display = "{}", _0)]
+    Kf(#[error(source)] KfError),
+    #[error(display = "{}", _0)]
+    Manifest(#[error(source)] ManifestError),
+    #[error(display = "{}", _0)]
+    Metadata(#[error(source)] MetadataError),
+    #[error(display = "{}", _0)]
+    Palette(#[error(source)] PaletteError),
+    #[error(display = "{}", _0)]
+    Par(#[error(source)] ParError),
+    #[error(display = "{}", _0)]
+    Plugin(#[error(source)] PluginError),
+    #[error(display = "{}", _0)]
+    Preset(#[error(source)] PresetError),
+    #[cfg(feature = "cli")]
+    #[error(display = "{}", _0)]
+    Gallery(#[error(source)] GalleryError),
+    #[error(display = "{}", _0)]
+    Queue(#[error(source)] QueueError),
+    #[error(display = "{}", _0)]
+    Region(#[error(source)] RegionError),
+    #[error(display = "{}", _0)]
+    Script(#[error(source)] ScriptError),
+    #[cfg(feature = "cli")]
+    #[error(display = "{}", _0)]
+    Serve(#[error(source)] ServeError),
+    #[error(display = "{}", _0)]
+    Sheet(#[error(source)] SheetError),
+    #[error(display = "{}", _0)]
+    Sidecar(#[error(source)] SidecarError),
+    #[error(display = "{}", _0)]
+    Sweep(#[error(source)] SweepError),
+    #[error(display = "{}", _0)]
+    Template(#[error(source)] TemplateError),
+    #[error(display = "{}", _0)]
+    Upr(#[error(source)] UprError),
+    #[error(display = "{}", _0)]
+    Verify(#[error(source)] VerifyError),
+    #[cfg(feature = "cli")]
+    #[error(display = "{}", _0)]
+    Viewer(#[error(source)] ViewerError),
+    #[error(display = "{}", _0)]
+    Xmp(#[error(source)] XmpError),
+    // What the parameters belong to when it isn't the command line, e.g.
+    // "with samples=0, " for a sweep combination
+    #[error(display = "invalid parameters")]
+    Invalid(String, Vec<Invalid>),
+    // A flag a RendererBuilder was given that clap couldn't parse
+    #[error(display = "{}", _0)]
+    Flag(String),
+    #[error(display = "{} can't be streamed to stdout, give --output a path", _0)]
+    CantStream(String),
+    #[error(display = "--dry-run only estimates still renders, sweeps and batches")]
+    NoDryRun,
+    #[error(
+        display = "--reuse only works for plain --zoom-to zooms, without --keys, --spin, --julia-path, --bounce, --shutter or --audio"
+    )]
+    NoReuse,
+    #[error(
+        display = "no Misiurewicz point in view to loop around, zoom out or move the origin onto a spiral or branch point"
+    )]
+    NoLoop,
+    // The pixels a stitch would need, then what --max-pixels allows
+    #[error(
+        display = "the stitched image would be {} pixels, over the {} --max-pixels allows",
+        _0,
+        _1
+    )]
+    TooBig(u64, u64),
+    #[error(display = "{} of {} jobs failed", _0, _1)]
+    JobsFailed(usize, usize),
+    #[error(display = "{} of {} scenes don't match their golden images", _0, _1)]
+    VerifyFailed(usize, usize),
+    #[error(display = "interrupted")]
+    Interrupted,
+    #[error(display = "{}: {}", _0, _1)]
+    At(String, Box<FracError>),
+}
+
+// Names the file an error came from, `.map_err(error::at(path))?`. The
+// innermost name wins, it's the file that actually failed.
+pub fn at<P: AsRef<Path>, E: Into<FracError>>(path: P) -> impl FnOnce(E) -> FracError {
+    let path = path.as_ref().display().to_string();
+    move |e| match e.into() {
+        e @ FracError::At(..) => e,
+        e => FracError::At(path, Box::new(e)),
+    }
+}
+
+impl FracError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FracError::At(_, e) => e.exit_code(),
+            FracError::Invalid(..)
+            | FracError::Flag(_)
+            | FracError::CantStream(_)
+            | FracError::NoDryRun
+            | FracError::NoReuse
+            | FracError::NoLoop
+            | FracError::TooBig(..)
+            | FracError::Audio(
+                AudioError::NotWav(_)
+                | AudioError::Unsupported(..)
+                | AudioError::NotNumeric(_)
+                | AudioError::CantDrive(..)
+                | AudioError::BadValue(..),
+            )
+            | FracError::Catalog(_)
+            | FracError::Diff(_)
+            | FracError::Expmap(_)
+            | FracError::FracData(FracDataError::BadParam(_))
+            | FracError::Keys(KeyError::BadKey(..) | KeyError::Empty)
+            | FracError::Kf(KfError::Syntax(..) | KfError::Missing(_) | KfError::Unsupported(_))
+            | FracError::Manifest(ManifestError::BadFile(_))
+            | FracError::Metadata(MetadataError::BadParam(_))
+            | FracError::Config(
+                ConfigError::Unknown(_)
+                | ConfigError::BadValue(_)
+                | ConfigError::NotObject
+                | ConfigError::Json(_),
+            )
+            | FracError::Region(_)
+            | FracError::Batch(BatchError::BadJob(..))
+            | FracError::Bookmark(BookmarkError::NotFound(_) | BookmarkError::Exists(_))
+            | FracError::Checkpoint(
+                CheckpointError::WrongTiles(..) | CheckpointError::WrongSize(..),
+            )
+            | FracError::Par(ParError::Syntax(_) | ParError::Empty | ParError::NoView)
+            | FracError::Plugin(
+                PluginError::NoEntry
+                | PluginError::Version(..)
+                | PluginError::Empty
+                | PluginError::Unsupported
+                | PluginError::Wasm(_),
+            )
+            | FracError::Preset(PresetError::NotFound(_) | PresetError::BadName(_))
+            | FracError::Queue(QueueError::NotDir)
+            | FracError::Script(ScriptError::Syntax(..) | ScriptError::Empty)
+            | FracError::Sheet(SheetError::BadEntry(..))
+            | FracError::Sweep(_)
+            | FracError::Sidecar(SidecarError::BadFile(_) | SidecarError::NeedDir)
+            | FracError::Upr(UprError::Syntax(..) | UprError::Empty | UprError::Missing(_))
+            | FracError::Verify(VerifyError::BadScene(..)) => INVALID,
+            #[cfg(feature = "cli")]
+            FracError::Viewer(ViewerError::NotTerminal) => INVALID,
+            #[cfg(feature = "cli")]
+            FracError::Control(
+                ControlError::InUse | ControlError::Refused(_) | ControlError::Unsupported,
+            ) => INVALID,
+            #[cfg(feature = "cli")]
+            FracError::Farm(FarmError::Refused(_)) => INVALID,
+            #[cfg(feature = "cli")]
+            FracError::Gallery(GalleryError::NotDir | GalleryError::Empty(_)) => INVALID,
+            FracError::Template(e) if !matches!(e, TemplateError::Io(_)) => INVALID,
+            FracError::JobsFailed(..) | FracError::VerifyFailed(..) => FAILED,
+            #[cfg(feature = "parallel")]
+            FracError::ThreadPool(_) => FAILED,
+            #[cfg(feature = "cli")]
+            FracError::Farm(FarmError::Failed(..)) => FAILED,
+            FracError::Interrupted => INTERRUPTED,
+            _ => IO,
+        }
+    }
+
+    // One line per problem, every one prefixed like clap's own errors
+    pub fn report(&self) {
+        match self {
+            FracError::Invalid(context, problems) => {
+                for problem in problems {
+                    eprintln!("error: {}{}", context, problem);
+                }
+            }
+            // Already explained when the partial render was saved
+            FracError::Interrupted => (),
+            e => eprintln!("error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// fracmd
+// Guessing how long a render takes and how much memory it needs
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The time comes from rendering a sparse grid of real pixels, every sample
+// of them, and scaling up by how many pixels there are per thread. Deep in
+// the set every pixel runs to the limit, so this is usually close. The
+// memory is counted from the buffers the outputs asked for allocate, which
+// is rough but the right size.
+
+use std::{mem, time::Instant};
+
+use crate::{fracdata::PixelData, par::*, progress, Args, Format, Renderer};
+
+// Pixels along each side of the probe grid
+const PROBES: i32 = 48;
+
+pub struct Estimate {
+    pub seconds: f64,
+    pub bytes: u64,
+    pub probes: usize,
+}
+
+impl Estimate {
+    pub fn summary(&self) -> String {
+        format!(
+            "about {} and {} at peak, from {} probe pixels",
+            progress::format_secs(self.seconds),
+            format_bytes(self.bytes),
+            self.probes
+        )
+    }
+}
+
+pub fn estimate(args: &Args, renderer: &Renderer) -> Estimate {
+    let (gw, gh) = (PROBES.min(args.width), PROBES.min(args.height));
+    let seconds: f64 = (0..gw * gh)
+        .into_par_iter()
+        .map(|i| {
+            let x = ((i % gw) as f64 + 0.5) * args.width as f64 / gw as f64;
+            let y = ((i / gw) as f64 + 0.5) * args.height as f64 / gh as f64;
+            let now = Instant::now();
+            renderer.pixel(x as i32 * args.height + y as i32);
+            now.elapsed().as_secs_f64()
+        })
+        .sum();
+    let pixels = args.width as f64 * args.height as f64;
+    let mut per_pixel = seconds / (gw * gh) as f64;
+    // The data pass iterates once more, without the extra samples
+    if args.wants_data() {
+        per_pixel *= 1.0 + 1.0 / args.samples as f64;
+    }
+    Estimate {
+        seconds: per_pixel * pixels / args.threads as f64,
+        bytes: memory(args),
+        probes: (gw * gh) as usize,
+    }
+}
+
+// The render buffer plus whatever the outputs keep alongside it
+pub fn memory(args: &Args) -> u64 {
+    let pixels = args.width as u64 * args.height as u64;
+    // EXR renders in floats, everything else in 16 bit
+    let mut per_pixel = match args.format {
+        Format::Exr => 16,
+        _ => 8,
+    };
+    if args.wants_data() {
+        per_pixel += mem::size_of::<PixelData>() as u64;
+    }
+    per_pixel += match args.format {
+        Format::Png | Format::Tiff => 8,
+        // The four channel planes, then five more for the AOVs
+        Format::Exr => 16 + if args.aovs { 20 } else { 0 },
+        Format::IndexedPng | Format::Gif => 1,
+        // An 8 bit copy, then the encoded file before the metadata goes in
+        Format::Jpeg | Format::Webp => 8,
+        Format::Ppm | Format::Pam | Format::Farbfeld => 0,
+    };
+    let mut bytes = pixels * per_pixel;
+    // Every level of a pyramid is a quarter of the last
+    if args.pyramid.is_some() {
+        bytes += pixels * 8 / 3;
+    }
+    bytes
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+// Warnings for whatever goes over --max-time or --max-memory
+pub fn over_limits(args: &Args, seconds: f64, bytes: u64) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if seconds > args.max_time as f64 {
+        warnings.push(format!(
+            "warning: about {} is over --max-time {}",
+            progress::format_secs(seconds),
+            progress::format_secs(args.max_time as f64)
+        ));
+    }
+    if bytes > args.max_memory * 1024 * 1024 {
+        warnings.push(format!(
+            "warning: {} is over --max-memory {}",
+            format_bytes(bytes),
+            format_bytes(args.max_memory * 1024 * 1024)
+        ));
+    }
+    warnings
+}
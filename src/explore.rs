@@ -0,0 +1,142 @@
+// fracmd
+// Random exploration with interest scoring
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num::complex::Complex;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::{fs, path::Path};
+
+use crate::{scheduler::Image16, Args, Functs};
+
+// Where the whole set fits, and how deep to look. Past 10^4 f32 starts
+// showing its steps.
+const PLANE: ([f32; 2], [f32; 2]) = ([-2.0, 0.6], [-1.2, 1.2]);
+const ZOOM_EXPONENT: (f32, f32) = (0.5, 3.5);
+const TRIES: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub origin: Complex<f32>,
+    pub zoom: f32,
+    pub score: f32,
+}
+
+impl Candidate {
+    pub fn args(&self, base: &Args) -> Args {
+        let mut args = base.clone();
+        args.origin = self.origin;
+        args.zoom = self.zoom;
+        args
+    }
+}
+
+// Iterations before escaping, or None for points that stay
+fn escape(c: Complex<f32>, args: &Args, functs: &Functs) -> Option<f32> {
+    let mut z = functs.fractal.start(c);
+    let mut i = 0.0;
+    let bail = args.bail.unwrap_or_else(|| functs.fractal.bail());
+    while i < args.limit {
+        if z.norm() >= bail {
+            return Some(i);
+        }
+        z = functs.fractal.step(z, c);
+        i += 1.0;
+    }
+    None
+}
+
+// Uniform points mostly land far outside or deep inside where there's
+// nothing to see, so keep drawing until one escapes slowly, which only
+// happens close to the boundary
+pub fn sample<R: Rng>(rng: &mut R, args: &Args, functs: &Functs) -> Candidate {
+    let ([x0, x1], [y0, y1]) = PLANE;
+    let slow = (args.limit / 20.0).max(8.0);
+    let mut origin = Complex::new(0.0, 0.0);
+    for _ in 0..TRIES {
+        origin = Complex::new(rng.gen_range(x0..x1), rng.gen_range(y0..y1));
+        if matches!(escape(origin, args, functs), Some(i) if i >= slow) {
+            break;
+        }
+    }
+    Candidate {
+        origin,
+        zoom: 10f32.powf(rng.gen_range(ZOOM_EXPONENT.0..ZOOM_EXPONENT.1)),
+        score: 0.0,
+    }
+}
+
+fn luma(image: &Image16) -> Vec<f32> {
+    image
+        .pixels()
+        .map(|p| (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 65535.0)
+        .collect()
+}
+
+// Entropy of the brightness histogram times how much of the image is
+// edges. Flat views score 0 for entropy, and the edge term peaks when half
+// the pixels are edges, so aliased noise doesn't win either.
+pub fn score(image: &Image16) -> f32 {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    let luma = luma(image);
+    if w < 2 || h < 2 {
+        return 0.0;
+    }
+    const BINS: usize = 64;
+    let mut histogram = [0usize; BINS];
+    for l in &luma {
+        histogram[((l * BINS as f32) as usize).min(BINS - 1)] += 1;
+    }
+    let entropy = histogram
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f32 / luma.len() as f32;
+            -p * p.log2()
+        })
+        .sum::<f32>()
+        / (BINS as f32).log2();
+    let mut edges = 0;
+    for y in 0..h - 1 {
+        for x in 0..w - 1 {
+            let l = luma[y * w + x];
+            let gradient = (l - luma[y * w + x + 1]).abs() + (l - luma[(y + 1) * w + x]).abs();
+            if gradient > 0.05 {
+                edges += 1;
+            }
+        }
+    }
+    let edges = edges as f32 / ((w - 1) * (h - 1)) as f32;
+    entropy * 4.0 * edges * (1.0 - edges)
+}
+
+// The finds as a `fracmd batch` job file, so they can be rendered again
+// bigger or with more samples
+pub fn save_jobs<P: AsRef<Path>>(path: P, found: &[(String, Candidate)]) -> std::io::Result<()> {
+    let jobs: Vec<Value> = found
+        .iter()
+        .map(|(name, c)| {
+            json!({
+                "name": name,
+                "origin": c.origin.to_string(),
+                // Through the f32 text so it doesn't print as 377.0333557128906
+                "zoom": c.zoom.to_string().parse::<f64>().unwrap_or_default(),
+            })
+        })
+        .collect();
+    let text = serde_json::to_string_pretty(&json!({ "jobs": jobs }))?;
+    fs::write(path, text + "\n")
+}
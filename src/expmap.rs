@@ -0,0 +1,143 @@
+// fracmd
+// Exponential map strips for --projection expmap and `fracmd unroll`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A strip is the view in log-polar coordinates: x goes once round the
+// origin and y down in log radius, from the corners of a square frame at
+// --zoom inwards. Rows are spaced like columns, so a pixel is as tall as
+// it is wide wherever it is, and every row deeper is the same amount more
+// zoomed. A whole zoom into the origin is in the one strip, and unrolling
+// picks each frame back out of it without iterating anything.
+
+use clap::ValueEnum;
+use err_derive::Error;
+use image::{ImageBuffer, Rgba};
+use num::complex::Complex;
+use std::f32::consts::{SQRT_2, TAU};
+
+use crate::{depth, normalize_coords, par::*, scheduler::Image16, Args};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Plane,
+    Expmap,
+}
+
+#[derive(Debug, Error)]
+pub enum ExpmapError {
+    #[error(display = "that isn't an exponential map strip, render one with --projection expmap")]
+    NotStrip,
+    #[error(
+        display = "the strip isn't deep enough for {} pixel wide frames, it needs to be at least {} rows tall",
+        _0,
+        _1
+    )]
+    TooShallow(i32, u32),
+}
+
+// Strip pixels across for each pixel across the frames it unrolls into,
+// which keeps the frame corners as sharp as a direct render
+pub const WIDTH_PER_FRAME: f32 = TAU / SQRT_2;
+
+// Offset from the origin of a point of the strip, given in pixels like
+// the plane's are, so pixel x, y is sampled around x, y
+pub fn offset(x: f32, y: f32, width: i32, zoom: f32) -> Complex<f32> {
+    let step = TAU / width as f32;
+    Complex::from_polar(SQRT_2 / zoom * (-y * step).exp(), x * step)
+}
+
+// Where an offset falls on the strip, the inverse of offset
+fn position(offset: Complex<f32>, width: i32, zoom: f32) -> (f32, f32) {
+    let step = TAU / width as f32;
+    let angle = offset.im.atan2(offset.re).rem_euclid(TAU);
+    (angle / step, -(offset.norm() * zoom / SQRT_2).ln() / step)
+}
+
+// How many times more zoomed the last frame can be than the first, as a
+// natural log, for the innermost rows to still be finer than a pixel
+pub fn depth(strip: &Args, frame_width: i32) -> Result<f32, ExpmapError> {
+    let step = TAU / strip.width as f32;
+    // A frame at zoom z needs radii down to half a pixel, 1 / (z * width)
+    let needed = (SQRT_2 * frame_width as f32).ln();
+    let depth = strip.height as f32 * step - needed;
+    if depth > 0.0 {
+        Ok(depth)
+    } else {
+        let rows = (needed / step).ceil() as u32 + 1;
+        Err(ExpmapError::TooShallow(frame_width, rows))
+    }
+}
+
+fn squared(image: &Image16, x: u32, y: u32) -> [f32; 4] {
+    depth::squared(*image.get_pixel(x, y))
+}
+
+// Bilinear, wrapping round in x and stopping at the top and bottom rows
+fn bilinear(image: &Image16, x: f32, y: f32) -> [f32; 4] {
+    let (w, h) = (image.width() as i64, image.height() as i64);
+    let y = y.clamp(0.0, (h - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let column = |x: i64| x.rem_euclid(w) as u32;
+    let row = |y: i64| y.clamp(0, h - 1) as u32;
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let corners = [
+        (squared(image, column(x0), row(y0)), (1.0 - fx) * (1.0 - fy)),
+        (squared(image, column(x0 + 1), row(y0)), fx * (1.0 - fy)),
+        (squared(image, column(x0), row(y0 + 1)), (1.0 - fx) * fy),
+        (squared(image, column(x0 + 1), row(y0 + 1)), fx * fy),
+    ];
+    let mut out = [0.0; 4];
+    for (color, weight) in corners {
+        for c in 0..4 {
+            out[c] += color[c] * weight;
+        }
+    }
+    out
+}
+
+// One frame at frame.zoom, with the same origin and view as the strip.
+// Towards the center a frame pixel covers more and more of the strip, so
+// it averages a grid of taps spread over what it covers.
+pub fn unroll(strip: &Image16, strip_args: &Args, frame: &Args) -> Image16 {
+    let (w, h) = (frame.width, frame.height);
+    let pixel = 2.0 / (w as f32 * frame.zoom);
+    let pixels: Vec<Rgba<u16>> = (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % w, i / w);
+            let center = normalize_coords(x, y, w, h, frame.zoom);
+            let (sx, sy) = position(center, strip_args.width, strip_args.zoom);
+            let covers = pixel / center.norm().max(pixel) * strip_args.width as f32 / TAU;
+            let taps = (covers.ceil() as i32).clamp(1, 8);
+            let mut sum = [0.0; 4];
+            for ty in 0..taps {
+                for tx in 0..taps {
+                    let spread = |t: i32| ((t as f32 + 0.5) / taps as f32 - 0.5) * covers;
+                    let color = bilinear(strip, sx + spread(tx), sy + spread(ty));
+                    for c in 0..4 {
+                        sum[c] += color[c];
+                    }
+                }
+            }
+            let n = (taps * taps) as f32;
+            depth::unsquared(sum.map(|v| v / n))
+        })
+        .collect();
+    ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+        pixels[(y as i32 * w + x as i32) as usize]
+    })
+}
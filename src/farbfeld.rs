@@ -0,0 +1,37 @@
+// fracmd
+// farbfeld output
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use image::{ImageBuffer, Rgba};
+use std::io::{self, Write};
+
+// "farbfeld", u32 width, u32 height, then 16 bit RGBA rows, all big endian.
+// This is exactly the internal buffer layout, just byte swapped.
+pub fn write_farbfeld<W: Write>(
+    mut out: W,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+) -> io::Result<()> {
+    out.write_all(b"farbfeld")?;
+    out.write_all(&image.width().to_be_bytes())?;
+    out.write_all(&image.height().to_be_bytes())?;
+    let data: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    out.write_all(&data)?;
+    out.flush()
+}
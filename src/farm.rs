@@ -0,0 +1,544 @@
+// fracmd
+// Farming animation frames out to `fracmd worker` for `animate --farm`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Workers connect to the coordinator over tcp, introduce themselves with
+//
+//   {"version": "0.1.0", "token": "..."}
+//
+// and are handed one frame at a time as a json line,
+//
+//   {"version": "0.1.0", "frame": 3, "subframes": [["--zoom=2", ...], ...]}
+//
+// with the flags of every subframe, so a worker needs nothing but the
+// same fracmd version. While rendering it sends {"frame": 3, "heartbeat":
+// true} every few seconds, then answers {"frame": 3, "bytes": 1234} and
+// that many bytes of 16 bit png, or {"frame": 3, "error": "..."}, and is
+// sent {"done": true} once there's nothing left. A worker with the wrong
+// version or --farm-token is sent {"error": "..."} and dropped. A frame a
+// worker fails, drops or goes quiet on for --farm-timeout goes back in the
+// queue for the next free one, and the coordinator passes frames on in
+// order however they arrive. proto/farm.md documents the exchange for
+// workers and coordinators written in other languages.
+
+use err_derive::Error;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{metadata, metrics, scheduler, scheduler::Image16, validate, Args, FracError};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// How often a worker says it's still rendering
+const HEARTBEAT: Duration = Duration::from_secs(5);
+
+// Longest json line either side reads, far more than a frame's flags take
+const MOST_LINE: u64 = 1 << 22;
+
+#[derive(Debug, Error)]
+pub enum FarmError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "image error: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "couldn't listen on {}: {}", _0, _1)]
+    Listen(String, io::Error),
+    #[error(display = "couldn't reach the coordinator at {}: {}", _0, _1)]
+    Connect(String, io::Error),
+    #[error(display = "{}", _0)]
+    Protocol(String),
+    #[error(display = "the worker said: {}", _0)]
+    Worker(String),
+    #[error(display = "the coordinator said: {}", _0)]
+    Refused(String),
+    #[error(display = "nothing from the worker for {} seconds", _0)]
+    Lost(u64),
+    #[error(display = "frame {} failed {} times, last with: {}", _0, _1, _2)]
+    Failed(usize, usize, String),
+}
+
+pub struct Job {
+    pub frame: usize,
+    // The size the worker's png has to be
+    pub width: u32,
+    pub height: u32,
+    pub subframes: Vec<Vec<String>>,
+}
+
+// read_line giving up on lines longer than most bytes rather than reading
+// whatever the other side sends into memory
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+    most: u64,
+) -> io::Result<usize> {
+    let read = reader.by_ref().take(most).read_line(line)?;
+    if read as u64 == most && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a line longer than {} bytes", most),
+        ));
+    }
+    Ok(read)
+}
+
+// Most bytes a 16 bit rgba png of the frame can take: every sample stored,
+// deflate's worst case on top and room for the parameter chunks
+fn most_png(width: u32, height: u32) -> u64 {
+    let raw = (width as u64 * 8 + 1) * height as u64;
+    raw + raw / 8 + (1 << 20)
+}
+
+struct Queue {
+    jobs: Mutex<VecDeque<(Job, usize)>>,
+    // Frames not back yet, in flight ones included
+    remaining: AtomicUsize,
+    retries: usize,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+// Compares every byte whatever the first difference, so how long a wrong
+// token takes to refuse doesn't give away how much of it was right
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Checks the worker's hello, why it's turned away if it is
+fn welcome(queue: &Queue, reader: &mut BufReader<TcpStream>) -> Result<(), String> {
+    let mut line = String::new();
+    match read_line(reader, &mut line, MOST_LINE) {
+        Ok(0) | Err(_) => return Err(String::from("the worker didn't introduce itself")),
+        Ok(_) => (),
+    }
+    let hello: Value =
+        serde_json::from_str(&line).map_err(|_| format!("unexpected hello {}", line.trim()))?;
+    if hello.get("version").and_then(Value::as_str) != Some(VERSION) {
+        return Err(format!("the coordinator needs fracmd {} workers", VERSION));
+    }
+    match (&queue.token, hello.get("token").and_then(Value::as_str)) {
+        (None, _) => Ok(()),
+        (Some(expected), Some(given)) if same_token(given, expected) => Ok(()),
+        (Some(_), Some(_)) => Err(String::from("wrong --token")),
+        (Some(_), None) => Err(String::from("the coordinator needs a --token")),
+    }
+}
+
+// One worker's connection, handing it frames until there are none left
+fn serve(
+    args: Args,
+    stream: TcpStream,
+    queue: Arc<Queue>,
+    results: mpsc::Sender<(usize, Result<Image16, String>)>,
+) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| String::from("a worker"), |a| a.to_string());
+    let Ok(reading) = stream.try_clone() else {
+        return;
+    };
+    let (mut stream, mut reader) = (stream, BufReader::new(reading));
+    if stream.set_read_timeout(Some(queue.timeout)).is_err() {
+        return;
+    }
+    if let Err(e) = welcome(&queue, &mut reader) {
+        args.log(&format!("Turned worker {} away: {}", peer, e));
+        let _ = writeln!(stream, "{}", json!({ "error": e }));
+        return;
+    }
+    args.log(&format!("Worker {} connected", peer));
+    metrics::WORKERS.add(1);
+    hand_out(&args, &peer, &mut stream, &mut reader, &queue, &results);
+    metrics::WORKERS.add(-1);
+}
+
+fn hand_out(
+    args: &Args,
+    peer: &str,
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    queue: &Queue,
+    results: &mpsc::Sender<(usize, Result<Image16, String>)>,
+) {
+    loop {
+        let next = queue.jobs.lock().unwrap().pop_front();
+        let Some((job, failures)) = next else {
+            if queue.remaining.load(Ordering::SeqCst) == 0 {
+                let _ = writeln!(stream, "{}", json!({ "done": true }));
+                return;
+            }
+            // Frames in flight elsewhere may still come back
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        metrics::QUEUE.add(-1);
+        metrics::IN_FLIGHT.add(1);
+        let assigned = assign(stream, reader, &job, queue.timeout);
+        metrics::IN_FLIGHT.add(-1);
+        match assigned {
+            Ok(image) => {
+                queue.remaining.fetch_sub(1, Ordering::SeqCst);
+                metrics::JOBS_DONE.inc();
+                let _ = results.send((job.frame, Ok(image)));
+            }
+            Err(e) => {
+                if failures >= queue.retries {
+                    metrics::JOBS_FAILED.inc();
+                    let _ = results.send((job.frame, Err(e.to_string())));
+                } else {
+                    args.log(&format!(
+                        "Worker {} failed frame {} ({}), it goes back in the queue",
+                        peer,
+                        job.frame + 1,
+                        e
+                    ));
+                    metrics::JOBS_REQUEUED.inc();
+                    metrics::QUEUE.add(1);
+                    queue.jobs.lock().unwrap().push_back((job, failures + 1));
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn assign(
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    job: &Job,
+    timeout: Duration,
+) -> Result<Image16, FarmError> {
+    let message = json!({ "version": VERSION, "frame": job.frame, "subframes": job.subframes });
+    writeln!(stream, "{}", message)?;
+    let lost = |e: io::Error| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => FarmError::Lost(timeout.as_secs()),
+        _ => FarmError::Io(e),
+    };
+    let mut line = String::new();
+    let reply = loop {
+        line.clear();
+        if read_line(reader, &mut line, MOST_LINE).map_err(lost)? == 0 {
+            return Err(FarmError::Protocol(String::from("the worker disconnected")));
+        }
+        let reply: Value = serde_json::from_str(&line)?;
+        if reply.get("heartbeat").and_then(Value::as_bool) != Some(true) {
+            break reply;
+        }
+    };
+    if let Some(error) = reply.get("error").and_then(Value::as_str) {
+        return Err(FarmError::Worker(error.to_string()));
+    }
+    let bytes = reply.get("bytes").and_then(Value::as_u64);
+    let (Some(bytes), true) = (bytes, reply.get("frame") == Some(&json!(job.frame))) else {
+        return Err(FarmError::Protocol(format!(
+            "unexpected reply {}",
+            line.trim()
+        )));
+    };
+    if bytes > most_png(job.width, job.height) {
+        return Err(FarmError::Protocol(format!(
+            "{} bytes is more than a {}x{} png takes",
+            bytes, job.width, job.height
+        )));
+    }
+    let mut png = vec![0; bytes as usize];
+    reader.read_exact(&mut png).map_err(lost)?;
+    decode(&png, job)
+}
+
+// The worker's png, checking its size before decoding it
+fn decode(png: &[u8], job: &Job) -> Result<Image16, FarmError> {
+    let size = image::io::Reader::new(Cursor::new(png))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    if size != (job.width, job.height) {
+        return Err(FarmError::Protocol(format!(
+            "a {}x{} image for a {}x{} frame",
+            size.0, size.1, job.width, job.height
+        )));
+    }
+    Ok(image::load_from_memory(png)?.into_rgba16())
+}
+
+pub struct Farm<'a> {
+    pub listen: &'a str,
+    pub retries: usize,
+    // Workers have to give this to be handed frames, if set
+    pub token: Option<String>,
+    // How long a worker can go without a heartbeat before its frame is
+    // counted lost
+    pub timeout: Duration,
+}
+
+// Farms the jobs out to whoever connects to listen, calling deliver for
+// each frame in the order of jobs. Says whether an interrupt stopped it.
+pub fn coordinate<F>(
+    args: &Args,
+    farm: Farm,
+    jobs: Vec<Job>,
+    mut deliver: F,
+) -> Result<bool, FracError>
+where
+    F: FnMut(usize, Image16) -> Result<(), FracError>,
+{
+    let Farm {
+        listen,
+        retries,
+        token,
+        timeout,
+    } = farm;
+    let listener =
+        TcpListener::bind(listen).map_err(|e| FarmError::Listen(listen.to_string(), e))?;
+    let order: Vec<usize> = jobs.iter().map(|job| job.frame).collect();
+    metrics::QUEUE.set(jobs.len() as i64);
+    let queue = Arc::new(Queue {
+        remaining: AtomicUsize::new(jobs.len()),
+        jobs: Mutex::new(jobs.into_iter().map(|job| (job, 0)).collect()),
+        retries,
+        token,
+        timeout,
+    });
+    let (sender, results) = mpsc::channel();
+    args.log(&format!(
+        "Waiting for workers on {}, start them with `fracmd worker {}{}`",
+        listener.local_addr().map_err(FarmError::Io)?,
+        listen,
+        if queue.token.is_some() {
+            " --token ..."
+        } else {
+            ""
+        }
+    ));
+    let worker_args = args.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (args, queue, sender) = (worker_args.clone(), queue.clone(), sender.clone());
+            thread::spawn(move || serve(args, stream, queue, sender));
+        }
+    });
+    let mut arrived: BTreeMap<usize, Image16> = BTreeMap::new();
+    let mut next = 0;
+    while next < order.len() {
+        if scheduler::interrupted() {
+            return Ok(true);
+        }
+        match results.recv_timeout(Duration::from_millis(200)) {
+            Ok((frame, Ok(image))) => {
+                arrived.insert(frame, image);
+            }
+            Ok((frame, Err(e))) => {
+                return Err(FarmError::Failed(frame + 1, retries + 1, e).into());
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(FarmError::Protocol(String::from("stopped taking workers")).into())
+            }
+        }
+        while let Some(image) = order.get(next).and_then(|frame| arrived.remove(frame)) {
+            args.log(&format!(
+                "Got frame {} ({}/{})",
+                order[next] + 1,
+                next + 1,
+                order.len()
+            ));
+            deliver(order[next], image)?;
+            next += 1;
+        }
+    }
+    Ok(false)
+}
+
+// Renders the frames a coordinator hands out until it says it's done
+pub fn work(args: &Args, coordinator: &str, token: Option<&str>) -> Result<(), FracError> {
+    let mut stream = TcpStream::connect(coordinator)
+        .map_err(|e| FarmError::Connect(coordinator.to_string(), e))?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(FarmError::Io)?);
+    writeln!(stream, "{}", json!({ "version": VERSION, "token": token })).map_err(FarmError::Io)?;
+    args.log(&format!("Connected to {}", coordinator));
+    let mut line = String::new();
+    loop {
+        line.clear();
+        // The coordinator can finish and close before saying so
+        if read_line(&mut reader, &mut line, MOST_LINE).map_err(FarmError::Io)? == 0 {
+            return Ok(());
+        }
+        let job: Value = serde_json::from_str(&line).map_err(FarmError::Json)?;
+        if job.get("done").and_then(Value::as_bool) == Some(true) {
+            args.log("The coordinator has no frames left");
+            return Ok(());
+        }
+        if let Some(error) = job.get("error").and_then(Value::as_str) {
+            return Err(FarmError::Refused(error.to_string()).into());
+        }
+        let frame = job.get("frame").and_then(Value::as_u64).unwrap_or(0);
+        let reply_error = |stream: &mut TcpStream, error: String| -> Result<(), FracError> {
+            writeln!(stream, "{}", json!({ "frame": frame, "error": error }))
+                .map_err(FarmError::Io)?;
+            Err(FarmError::Protocol(error).into())
+        };
+        if job.get("version").and_then(Value::as_str) != Some(VERSION) {
+            let error = format!("the coordinator isn't fracmd {} like this worker", VERSION);
+            return reply_error(&mut stream, error);
+        }
+        let subframes: Option<Vec<Vec<String>>> = job
+            .get("subframes")
+            .and_then(Value::as_array)
+            .and_then(|list| {
+                list.iter()
+                    .map(|s| serde_json::from_value(s.clone()).ok())
+                    .collect()
+            });
+        let moments: Result<Vec<Args>, String> = subframes
+            .unwrap_or_default()
+            .iter()
+            .map(|flags| {
+                let mut moment = Args::try_from_params(&[], flags)?;
+                moment.threads = args.threads;
+                Ok(moment)
+            })
+            .collect();
+        let moments = match moments {
+            Ok(moments) => moments,
+            Err(error) => return reply_error(&mut stream, error),
+        };
+        if moments.is_empty() {
+            return reply_error(&mut stream, String::from("a job without subframes"));
+        }
+        let problems: Vec<String> = moments
+            .iter()
+            .flat_map(validate::check)
+            .map(|p| p.to_string())
+            .collect();
+        if let Some(problem) = problems.first() {
+            return reply_error(&mut stream, problem.clone());
+        }
+        metrics::IN_FLIGHT.set(1);
+        let image = with_heartbeat(&stream, frame, || crate::cli::render_moments(&moments))
+            .map_err(FarmError::Io)?;
+        metrics::IN_FLIGHT.set(0);
+        metrics::JOBS_DONE.inc();
+        let mut png = Vec::new();
+        metadata::write_png(&mut png, &image, &moments[moments.len() / 2])?;
+        writeln!(stream, "{}", json!({ "frame": frame, "bytes": png.len() }))
+            .and_then(|_| stream.write_all(&png))
+            .and_then(|_| stream.flush())
+            .map_err(FarmError::Io)?;
+        args.log(&format!("Rendered frame {}", frame + 1));
+    }
+}
+
+// Runs render, telling the coordinator every HEARTBEAT that frame is still
+// being worked on
+fn with_heartbeat<T, F: FnOnce() -> T>(stream: &TcpStream, frame: u64, render: F) -> io::Result<T> {
+    let mut beating = stream.try_clone()?;
+    let (stop, stopped) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT) {
+                let beat = json!({ "frame": frame, "heartbeat": true });
+                if writeln!(beating, "{}", beat).is_err() {
+                    return;
+                }
+            }
+        });
+        let rendered = render();
+        drop(stop);
+        Ok(rendered)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(width: u32, height: u32) -> Job {
+        Job {
+            frame: 0,
+            width,
+            height,
+            subframes: Vec::new(),
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let args = Args::parse_argv(["fracmd"].into_iter().map(String::from));
+        let mut png = Vec::new();
+        metadata::write_png(&mut png, &Image16::new(width, height), &args).unwrap();
+        png
+    }
+
+    #[test]
+    fn lines_are_read_up_to_the_cap() {
+        let mut reader = Cursor::new(b"{\"frame\": 3}\n{\"done\": true}\n".to_vec());
+        let mut line = String::new();
+        assert_eq!(read_line(&mut reader, &mut line, MOST_LINE).unwrap(), 13);
+        assert_eq!(line, "{\"frame\": 3}\n");
+    }
+
+    #[test]
+    fn overlong_line_is_refused() {
+        let mut reader = Cursor::new(vec![b'x'; MOST_LINE as usize + 10]);
+        let mut line = String::new();
+        let error = read_line(&mut reader, &mut line, MOST_LINE).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(line.len(), MOST_LINE as usize);
+    }
+
+    #[test]
+    fn frame_png_fits_the_cap() {
+        assert!(png(64, 48).len() as u64 <= most_png(64, 48));
+        assert!(most_png(64, 48) < u32::MAX as u64);
+    }
+
+    #[test]
+    fn png_of_the_frame_is_decoded() {
+        let image = decode(&png(6, 4), &job(6, 4)).unwrap();
+        assert_eq!(image.dimensions(), (6, 4));
+    }
+
+    #[test]
+    fn png_of_another_size_is_refused() {
+        assert!(matches!(
+            decode(&png(6, 4), &job(4, 6)),
+            Err(FarmError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_png_is_refused() {
+        let png = png(6, 4);
+        assert!(decode(&png[..png.len() / 2], &job(6, 4)).is_err());
+        assert!(decode(&[], &job(6, 4)).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+// fracmd
+// Raw per-pixel data dumps
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// .fracdata layout, all numbers little endian:
+//
+//   magic       8 bytes   b"FRACDATA"
+//   version     u32       currently 2, 1 had no converged field
+//   width       u32
+//   height      u32
+//   params_len  u32       byte length of the parameter block
+//   params      utf-8     "key=value\n" lines, same keys as the png metadata
+//   records     width * height records in row-major order (x fastest),
+//               each one RECORD_SIZE bytes made of the f32 fields of
+//               PixelData in declaration order
+//
+// A path ending in .kfb is a Kalles Fraktaler map instead, see kf.rs
+
+use crate::{
+    kf::{self, KfError},
+    Args,
+};
+use err_derive::Error;
+use num::complex::Complex;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+pub const MAGIC: &[u8; 8] = b"FRACDATA";
+pub const VERSION: u32 = 2;
+pub const RECORD_SIZE: usize = 8 * 4;
+// Version 1's, without converged
+const RECORD_SIZE_1: usize = 7 * 4;
+
+#[derive(Debug, Error)]
+pub enum FracDataError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "not a fracdata file")]
+    BadMagic,
+    #[error(display = "unsupported fracdata version {}", _0)]
+    BadVersion(u32),
+    #[error(display = "parameter block is not valid utf-8")]
+    BadParams,
+    #[error(display = "bad stored parameter: {}", _0)]
+    BadParam(String),
+    #[error(display = "the header's {}x{} records don't fit in the file", _0, _1)]
+    Truncated(u32, u32),
+    #[error(display = "{}", _0)]
+    Kf(#[error(source)] KfError),
+}
+
+// Everything the iteration pass knows about one point, laid out for
+// plugin colorings to read
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelData {
+    // Iteration count, equal to the limit for points inside the set
+    pub iter: f32,
+    // Sum of exp(-|z|^2) over the orbit, used by the default coloring
+    pub s: f32,
+    // Continuous (normalized) iteration count
+    pub smooth: f32,
+    // Final z
+    pub z: Complex<f32>,
+    // Exterior distance estimate, 0 inside the set
+    pub de: f32,
+    // Closest approach of the orbit to 0
+    pub trap: f32,
+    // 1 for orbits stopped by converging, whose smooth is the smooth
+    // convergence count, 0 for the rest
+    pub converged: f32,
+}
+
+impl PixelData {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let fields = [
+            self.iter,
+            self.s,
+            self.smooth,
+            self.z.re,
+            self.z.im,
+            self.de,
+            self.trap,
+            self.converged,
+        ];
+        let mut out = [0u8; RECORD_SIZE];
+        for (chunk, v) in out.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> PixelData {
+        let f: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        PixelData {
+            iter: f[0],
+            s: f[1],
+            smooth: f[2],
+            z: Complex::new(f[3], f[4]),
+            de: f[5],
+            trap: f[6],
+            converged: f.get(7).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+// What iterating every sample of an image found, before any of it is
+// colored. Row-major like FracData, each pixel's samples one after the
+// other.
+#[derive(Clone, Debug)]
+pub struct Samples {
+    pub width: u32,
+    pub height: u32,
+    pub samples: usize,
+    pub data: Vec<PixelData>,
+}
+
+pub struct FracData {
+    pub width: u32,
+    pub height: u32,
+    pub params: Vec<(String, String)>,
+    pub data: Vec<PixelData>,
+}
+
+impl FracData {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FracDataError> {
+        if kf::is_kfb(path.as_ref()) {
+            return Ok(kf::save_kfb(path.as_ref(), self)?);
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        let params: String = self
+            .params
+            .iter()
+            .map(|(k, v)| format!("{}={}\n", k, v))
+            .collect();
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&(params.len() as u32).to_le_bytes())?;
+        out.write_all(params.as_bytes())?;
+        for d in &self.data {
+            out.write_all(&d.to_bytes())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    // The args the dump was rendered with
+    pub fn args(&self) -> Result<Args, FracDataError> {
+        Args::try_from_stored(&self.params).map_err(FracDataError::BadParam)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FracData, FracDataError> {
+        if kf::is_kfb(path.as_ref()) {
+            return Ok(kf::load_kfb(path.as_ref())?);
+        }
+        let file = File::open(path)?;
+        // What the header says is there can't be more than the file holds,
+        // before anything is allocated for it
+        let mut left = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(FracDataError::BadMagic);
+        }
+        let version = read_u32(&mut input)?;
+        let size = match version {
+            1 => RECORD_SIZE_1,
+            VERSION => RECORD_SIZE,
+            _ => return Err(FracDataError::BadVersion(version)),
+        };
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let params_len = read_u32(&mut input)?;
+        left = left.saturating_sub(MAGIC.len() as u64 + 4 * 4);
+        if params_len as u64 > left {
+            return Err(FracDataError::Truncated(width, height));
+        }
+        left -= params_len as u64;
+        let mut params = vec![0u8; params_len as usize];
+        input.read_exact(&mut params)?;
+        let params = String::from_utf8(params)
+            .map_err(|_| FracDataError::BadParams)?
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let records = (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|n| n.checked_mul(size as u64))
+            .filter(|&n| n <= left)
+            .ok_or(FracDataError::Truncated(width, height))?;
+        let mut records = vec![0u8; records as usize];
+        input.read_exact(&mut records)?;
+        let data = records
+            .chunks_exact(size)
+            .map(PixelData::from_bytes)
+            .collect();
+        Ok(FracData {
+            width,
+            height,
+            params,
+            data,
+        })
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> Result<u32, FracDataError> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    // The header's sizes come from the file, one past what the file holds is
+    // refused before anything is allocated for it
+    #[test]
+    fn sizes_past_the_file_are_refused() {
+        let header = |width: u32, height: u32, params_len: u32| {
+            let mut bytes = MAGIC.to_vec();
+            for v in [VERSION, width, height, params_len] {
+                bytes.extend(v.to_le_bytes());
+            }
+            bytes
+        };
+        let params = TempFile::new("params.fracdata", header(1, 1, u32::MAX));
+        assert!(matches!(
+            FracData::load(&params.0),
+            Err(FracDataError::Truncated(1, 1))
+        ));
+        let records = TempFile::new("records.fracdata", header(u32::MAX, u32::MAX, 0));
+        assert!(matches!(
+            FracData::load(&records.0),
+            Err(FracDataError::Truncated(u32::MAX, u32::MAX))
+        ));
+        let mut one_short = header(1, 1, 0);
+        one_short.extend([0; RECORD_SIZE - 1]);
+        let one_short = TempFile::new("short.fracdata", one_short);
+        assert!(matches!(
+            FracData::load(&one_short.0),
+            Err(FracDataError::Truncated(1, 1))
+        ));
+    }
+}
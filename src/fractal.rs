@@ -0,0 +1,144 @@
+// fracmd
+// The formulas iterated, as a trait anything can implement
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A Fractal is one step of the orbit and its derivatives, the renderer
+// runs the loop, bails out and colors. Implementations are values, so a
+// formula carries its own settings instead of reading them from Args, and
+// the trait stays object safe so Functs can hold any of them.
+
+use num::complex::Complex;
+
+use crate::bailout::{Norm, DEFAULT_BAIL};
+
+// Relative step for derivatives estimated from step
+const STEP: f32 = 1e-3;
+
+pub trait Fractal: Send + Sync {
+    // The next z of the orbit of c
+    fn step(&self, z: Complex<f32>, c: Complex<f32>) -> Complex<f32>;
+
+    // The next derivative of z with respect to c, for distance estimates
+    // and Newton's method: (z, dz, c) -> dz'
+    fn deriv(&self, z: Complex<f32>, dz: Complex<f32>, c: Complex<f32>) -> Complex<f32>;
+
+    // The same with respect to the starting z, for Julia sets
+    fn zderiv(&self, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32>;
+
+    // Where the orbit of c starts
+    fn start(&self, c: Complex<f32>) -> Complex<f32> {
+        c
+    }
+
+    // Applied to each point of the plane before anything else
+    fn map(&self, c: Complex<f32>) -> Complex<f32> {
+        c
+    }
+
+    // How fast escaping orbits grow, |z'| ~ |z|^degree, which smooth
+    // coloring and distance estimates both depend on
+    fn degree(&self) -> f32 {
+        2.0
+    }
+
+    // The bailout it escapes past when --bail isn't given, squared
+    fn bail(&self) -> f32 {
+        DEFAULT_BAIL
+    }
+
+    // What --bail is measured with when --bail-norm isn't given
+    fn bail_norm(&self) -> Norm {
+        Norm::Square
+    }
+
+    // The step size orbits stop at when --converge isn't given, for
+    // formulas that converge instead of or as well as escaping
+    fn converge(&self) -> Option<f32> {
+        None
+    }
+
+    // The formula if it's the built-in one, which backends with a loop of
+    // their own for it look for
+    fn as_multibrot(&self) -> Option<Multibrot> {
+        None
+    }
+}
+
+// z^p + c, the Mandelbrot set at the default power of 2 and multibrots
+// above it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Multibrot {
+    pub power: f32,
+}
+
+impl Default for Multibrot {
+    fn default() -> Multibrot {
+        Multibrot { power: 2.0 }
+    }
+}
+
+// Squaring is exact and much faster than powf, so 2 keeps it
+impl Fractal for Multibrot {
+    fn step(&self, z: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        if self.power == 2.0 {
+            z * z + c
+        } else {
+            z.powf(self.power) + c
+        }
+    }
+
+    fn deriv(&self, z: Complex<f32>, dz: Complex<f32>, _c: Complex<f32>) -> Complex<f32> {
+        self.zderiv(z, dz) + 1.0
+    }
+
+    fn zderiv(&self, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+        if self.power == 2.0 {
+            2.0 * z * dz
+        } else {
+            self.power * z.powf(self.power - 1.0) * dz
+        }
+    }
+
+    fn degree(&self) -> f32 {
+        self.power
+    }
+
+    fn as_multibrot(&self) -> Option<Multibrot> {
+        Some(*self)
+    }
+}
+
+// Along dz with c moving at unit speed, scaled so neither runs off, for
+// formulas that only know how to step
+pub fn estimate_deriv<F: Fractal + ?Sized>(
+    fractal: &F,
+    z: Complex<f32>,
+    dz: Complex<f32>,
+    c: Complex<f32>,
+) -> Complex<f32> {
+    let h = STEP * z.norm().max(1.0) / dz.norm().max(1.0);
+    (fractal.step(z + dz * h, c + h) - fractal.step(z, c)) / h
+}
+
+pub fn estimate_zderiv<F: Fractal + ?Sized>(
+    fractal: &F,
+    z: Complex<f32>,
+    dz: Complex<f32>,
+) -> Complex<f32> {
+    let c = Complex::new(0.0, 0.0);
+    let h = STEP * z.norm().max(1.0) / dz.norm().max(1.0);
+    (fractal.step(z + dz * h, c) - fractal.step(z, c)) / h
+}
@@ -0,0 +1,451 @@
+// fracmd
+// Fractint .par parameter import
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A .par holds entries like
+//
+//   Name { ; comment
+//     reset=2004 type=julia corners=-1.6/1.6/-1.2/1.2
+//     params=-0.8/0.156 maxiter=500 inside=0
+//     colors=000<30>z00<60>zz0<90>zzz<72>000
+//     }
+//
+// key=value words, ; starting a comment and \ joining a line to the next.
+// The view is either corners=xmin/xmax/ymin/ymax or
+// center-mag=x/y/mag[/xmagfactor/rotation/skew], mag 1 spanning 2 from
+// bottom to top of a 4:3 screen. Fractint's formulas keep their meaning but
+// the view grows to a modern size, 1600x1200 unless --width or --height
+// say otherwise, and its imaginary axis points up so imported views get
+// --transform 1,0,0,-1.
+//
+// colors= is the palette, three characters a color, each 0-9, A-Z, _, `
+// and a-z standing for 0 to 63, with <n> making n steps blended from the
+// color before to the one after. colors=@name.map names a map file
+// instead. Either becomes the fractint coloring, one color an iteration
+// as Fractint stepped through them.
+
+use crate::{
+    coloring::Indexed,
+    palette::{Palette, PaletteError},
+    plugin,
+    rgbaf::RgbaF,
+    upr::Imported,
+    Args,
+};
+use err_derive::Error;
+use std::{fs, path::Path, sync::Arc};
+
+#[derive(Debug, Error)]
+pub enum ParError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "{}", _0)]
+    Syntax(String),
+    #[error(display = "no parameter entry in the file")]
+    Empty,
+    #[error(display = "no corners or center-mag in the first entry")]
+    NoView,
+    #[error(display = "{}: {}", _0, _1)]
+    Palette(String, PaletteError),
+}
+
+const DIGITS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_`abcdefghijklmnopqrstuvwxyz";
+
+// Fractint's palettes have 256 entries, a <n> blending past them is
+// refused before anything is allocated for it
+const MOST_COLORS: usize = 256;
+
+// The size imported views get when none is given
+const WIDTH: i32 = 1600;
+const HEIGHT: i32 = 1200;
+
+// Fractint's types that are fracmd's own, (type, power, whether it's the
+// Julia set, where in params the power is)
+const KNOWN: &[(&str, f64, bool, Option<usize>)] = &[
+    ("mandel", 2.0, false, None),
+    ("julia", 2.0, true, None),
+    ("mandel4", 4.0, false, None),
+    ("julia4", 4.0, true, None),
+    ("manzpowr", 2.0, false, Some(2)),
+    ("julzpowr", 2.0, true, Some(2)),
+];
+
+// Keys that don't change how the image looks
+const QUIET: &[&str] = &[
+    "reset",
+    "float",
+    "passes",
+    "periodicity",
+    "symmetry",
+    "video",
+    "cyclerange",
+    "cyclelimit",
+    "fillcolor",
+    "comment",
+];
+
+pub fn is_par(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("par"))
+}
+
+// Every entry as its key=value words, comments dropped and lines joined
+fn entries(text: &str) -> Result<Vec<Vec<(String, String)>>, ParError> {
+    let mut joined = String::new();
+    let mut continued = false;
+    for line in text.lines() {
+        let mut line = line.split(';').next().unwrap_or("").trim_end();
+        if continued {
+            line = line.trim_start();
+        }
+        continued = line.ends_with('\\');
+        match line.strip_suffix('\\') {
+            Some(line) => joined.push_str(line),
+            None => {
+                joined.push_str(line);
+                joined.push('\n');
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    let mut words = None;
+    for word in joined.split_whitespace() {
+        match (&mut words, word) {
+            (None, "{") => words = Some(Vec::new()),
+            (None, _) => (),
+            (Some(_), "}") => entries.extend(words.take()),
+            (Some(words), word) => match word.split_once('=') {
+                Some((key, value)) => words.push((key.to_ascii_lowercase(), value.to_string())),
+                None => {
+                    return Err(ParError::Syntax(format!(
+                        "expected key=value, got {:?}",
+                        word
+                    )))
+                }
+            },
+        }
+    }
+    Ok(entries)
+}
+
+fn numbers(key: &str, value: &str) -> Result<Vec<f64>, ParError> {
+    value
+        .split('/')
+        .map(|v| {
+            v.parse().map_err(|_| {
+                ParError::Syntax(format!("{} isn't numbers split by /: {:?}", key, value))
+            })
+        })
+        .collect()
+}
+
+// colors= as 8 bit r, g, b
+fn colors(value: &str) -> Result<Vec<[f32; 3]>, ParError> {
+    let bad = || ParError::Syntax(format!("colors isn't a Fractint palette: {:?}", value));
+    let mut out: Vec<[f32; 3]> = Vec::new();
+    let mut steps = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('<') {
+            let (n, after) = after.split_once('>').ok_or_else(bad)?;
+            steps = n
+                .parse()
+                .ok()
+                .filter(|&n: &usize| out.len() + n < MOST_COLORS)
+                .ok_or_else(bad)?;
+            rest = after;
+            continue;
+        }
+        let mut color = [0.0; 3];
+        let mut chars = rest.chars();
+        for c in color.iter_mut() {
+            let digit = chars
+                .next()
+                .and_then(|ch| DIGITS.find(ch))
+                .ok_or_else(bad)?;
+            *c = digit as f32 * 255.0 / 63.0;
+        }
+        rest = chars.as_str();
+        if steps > 0 {
+            let from = out.last().copied().unwrap_or([0.0; 3]);
+            for s in 1..=steps {
+                let f = s as f32 / (steps + 1) as f32;
+                out.push([0, 1, 2].map(|i| from[i] + (color[i] - from[i]) * f));
+            }
+            steps = 0;
+        }
+        out.push(color);
+    }
+    Ok(out)
+}
+
+// The first entry's flags, with what didn't carry over. Its palette is
+// registered as the fractint coloring.
+pub fn load(path: &Path, args: &Args) -> Result<Imported, ParError> {
+    let entries = entries(&fs::read_to_string(path)?)?;
+    let entry = entries.first().ok_or(ParError::Empty)?;
+    let find = |key: &str| {
+        entry
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    let mut flags = Vec::new();
+    let mut warnings = Vec::new();
+    if entries.len() > 1 {
+        warnings.push(format!(
+            "the file holds {} entries, only the first is imported",
+            entries.len()
+        ));
+    }
+
+    let (width, height) = match (args.given("width"), args.given("height")) {
+        (true, true) => (args.width, args.height),
+        (true, false) => (args.width, args.width * 3 / 4),
+        (false, true) => (args.height * 4 / 3, args.height),
+        (false, false) => (WIDTH, HEIGHT),
+    };
+    flags.push(format!("--width={}", width));
+    flags.push(format!("--height={}", height));
+
+    // Center, spans across and up, rotation and skew
+    let (center, across, up, rotation, skew) = match (find("corners"), find("center-mag")) {
+        (Some(corners), _) => {
+            let c = numbers("corners", corners)?;
+            if c.len() < 4 {
+                return Err(ParError::Syntax(String::from(
+                    "corners needs xmin/xmax/ymin/ymax",
+                )));
+            }
+            if c.len() > 4 {
+                warnings.push(String::from(
+                    "the third corner of a rotated or skewed view isn't imported",
+                ));
+            }
+            let center = ((c[0] + c[1]) / 2.0, (c[2] + c[3]) / 2.0);
+            (center, c[1] - c[0], c[3] - c[2], 0.0, 0.0)
+        }
+        (None, Some(center_mag)) => {
+            let c = numbers("center-mag", center_mag)?;
+            if c.len() < 3 {
+                return Err(ParError::Syntax(String::from(
+                    "center-mag needs at least x/y/mag",
+                )));
+            }
+            let up = 2.0 / c[2];
+            let across = up * 4.0 / 3.0 / c.get(3).copied().unwrap_or(1.0);
+            let (rotation, skew) = (c.get(4).copied(), c.get(5).copied());
+            (
+                (c[0], c[1]),
+                across,
+                up,
+                rotation.unwrap_or(0.0),
+                skew.unwrap_or(0.0),
+            )
+        }
+        (None, None) => return Err(ParError::NoView),
+    };
+    flags.extend([
+        format!("--re={}", center.0),
+        format!("--im={}", center.1),
+        format!(
+            "--zoom={}",
+            (2.0 * height as f64 / (width as f64 * up)) as f32
+        ),
+        String::from("--transform=1,0,0,-1"),
+    ]);
+    // Fractint's screens were 4:3, other shapes are stretched
+    let stretch = across / (up * 4.0 / 3.0);
+    if (stretch - 1.0).abs() > 1e-3 {
+        flags.push(format!("--stretch={}", stretch));
+    }
+    if rotation != 0.0 {
+        flags.push(format!("--rotate={}", rotation));
+    }
+    if skew != 0.0 {
+        flags.push(format!("--skew={}", skew));
+    }
+    if let Some(maxiter) = find("maxiter") {
+        flags.push(format!("--limit={}", maxiter));
+    }
+    if let Some(bailout) = find("bailout") {
+        let bailout = numbers("bailout", bailout)?[0];
+        // Fractint compares |z|^2 against it
+        flags.push(format!("--bail={}", bailout.sqrt()));
+    }
+    if let Some(test) = find("bailoutest") {
+        let norm = match test.to_ascii_lowercase().as_str() {
+            "mod" => Some("square"),
+            "real" => Some("real"),
+            "imag" => Some("imag"),
+            "or" => Some("max"),
+            "manh" => Some("manhattan"),
+            _ => None,
+        };
+        match norm {
+            Some(norm) => flags.push(format!("--bail-norm={}", norm)),
+            None => warnings.push(format!(
+                "the {} bailout test isn't supported, using mod",
+                test
+            )),
+        }
+    }
+
+    let kind = find("type").unwrap_or("mandel").to_ascii_lowercase();
+    let base = kind.strip_suffix("fp").unwrap_or(&kind);
+    let params = find("params")
+        .map(|p| numbers("params", p))
+        .transpose()?
+        .unwrap_or_default();
+    let param = |i: usize| params.get(i).copied().unwrap_or(0.0);
+    match KNOWN.iter().find(|(t, ..)| *t == base) {
+        Some(&(_, power, julia, power_at)) => {
+            let power = match power_at {
+                Some(i) if params.len() > i => {
+                    if param(i + 1) != 0.0 {
+                        warnings.push(String::from(
+                            "complex powers aren't supported, only the real part is used",
+                        ));
+                    }
+                    param(i)
+                }
+                _ => power,
+            };
+            if power != 2.0 {
+                flags.push(format!("--power={}", power));
+            }
+            if julia {
+                flags.push(format!("--julia={},{}", param(0), param(1)));
+            } else if param(0) != 0.0 || param(1) != 0.0 {
+                warnings.push(String::from(
+                    "the perturbed start in params isn't supported, z starts at 0",
+                ));
+            }
+        }
+        None => {
+            // Left for a --plugin or --script defining it to provide
+            flags.push(format!("--fractal={}", kind));
+            warnings.push(format!(
+                "type {} isn't built in, it needs a --plugin or --script with a fractal called {}",
+                kind, kind
+            ));
+        }
+    }
+
+    let palette = match find("colors") {
+        Some(name) if name.starts_with('@') => {
+            let map = path.with_file_name(&name[1..]);
+            match Palette::load(&map) {
+                Ok(palette) => Some(palette),
+                Err(e) => return Err(ParError::Palette(map.display().to_string(), e)),
+            }
+        }
+        Some(encoded) => Some(Palette {
+            colors: colors(encoded)?
+                .into_iter()
+                .map(|[r, g, b]| RgbaF::new_color(r / 255.0, g / 255.0, b / 255.0))
+                .collect(),
+        }),
+        None => {
+            warnings.push(String::from(
+                "there's no colors palette, the hue coloring is used",
+            ));
+            None
+        }
+    };
+    if let Some(palette) = palette.filter(|p| !p.colors.is_empty()) {
+        // Fractint's default inside color is the second one
+        match find("inside").unwrap_or("1").parse::<usize>() {
+            Ok(i) => {
+                let c = palette.colors[i % palette.colors.len()].to_arr();
+                flags.push(format!(
+                    "--set-color={},{},{},255",
+                    (c[0] * 255.0).round(),
+                    (c[1] * 255.0).round(),
+                    (c[2] * 255.0).round()
+                ));
+            }
+            Err(_) => warnings.push(format!(
+                "inside={} isn't supported, --set-color is used",
+                find("inside").unwrap_or("")
+            )),
+        }
+        let palette = palette.transform(&args.colorspace.matrix_from_srgb());
+        plugin::register_coloring("fractint", Arc::new(Indexed { palette }));
+        flags.push(String::from("--coloring=fractint"));
+    }
+
+    if find("outside").is_some_and(|o| !o.eq_ignore_ascii_case("iter")) {
+        warnings.push(String::from("only outside=iter coloring is supported"));
+    }
+    let handled = [
+        "type",
+        "corners",
+        "center-mag",
+        "params",
+        "maxiter",
+        "bailout",
+        "bailoutest",
+        "colors",
+        "inside",
+        "outside",
+    ];
+    let ignored: Vec<&str> = entry
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .filter(|k| !handled.contains(k) && !QUIET.contains(k))
+        .collect();
+    if !ignored.is_empty() {
+        warnings.push(format!("not imported: {}", ignored.join(", ")));
+    }
+    Ok(Imported { flags, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lines ending in a backslash continue and comments run to the line's end
+    #[test]
+    fn entries_join_lines_and_drop_comments() {
+        let text = "first { ; the view\n  corners=-2/1/\\\n  -1/1 maxiter=80\n}\nsecond {\n}\n";
+        let entries = entries(text).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0],
+            [
+                (String::from("corners"), String::from("-2/1/-1/1")),
+                (String::from("maxiter"), String::from("80")),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_blend_between_entries() {
+        let colors = colors("000<2>zzz").unwrap();
+        assert_eq!(colors.len(), 4);
+        assert_eq!(colors[3], [255.0; 3]);
+        assert_eq!(colors[1], [85.0; 3]);
+    }
+
+    #[test]
+    fn colors_refuse_blends_past_the_palette() {
+        assert!(colors("000<254>zzz").is_ok());
+        assert!(colors("000<255>zzz").is_err());
+        assert!(colors("000<99999999999>zzz").is_err());
+    }
+}
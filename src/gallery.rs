@@ -0,0 +1,265 @@
+// fracmd
+// Static html galleries of rendered images, for `fracmd gallery`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Every png under the directory with fracmd parameters in it gets a card
+// in index.html, newest first: its thumbnail linking to the image, the
+// parameters that differ from the defaults, and the command lines that
+// render it again. The page has no scripts and links the images where
+// they are, so the gallery folder can be published with them beside it or
+// opened straight from disk. Thumbnails are kept in thumbs/ and only made
+// again when the image is newer, so regenerating a large gallery is quick.
+
+use err_derive::Error;
+use image::DynamicImage;
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    metadata, preset, thumbnail,
+    wizard::{command_line, shell_word},
+    Args,
+};
+
+#[derive(Debug, Error)]
+pub enum GalleryError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "image error: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "not a directory")]
+    NotDir,
+    #[error(display = "no images with fracmd parameters in {:?}", _0)]
+    Empty(PathBuf),
+}
+
+pub struct Entry {
+    pub image: PathBuf,
+    pub modified: SystemTime,
+    pub args: Args,
+}
+
+pub struct Gallery {
+    pub entries: Vec<Entry>,
+    // Pngs without parameters fracmd can read
+    pub skipped: usize,
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+}
+
+// Every png under dir, leaving out the gallery's own folder and the
+// thumbnails --thumbnail writes beside renders
+fn pngs(dir: &Path, skip: &Path, found: &mut Vec<PathBuf>) -> Result<(), GalleryError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path == skip {
+            continue;
+        }
+        if path.is_dir() {
+            pngs(&path, skip, found)?;
+        } else if is_png(&path)
+            && !path
+                .file_stem()
+                .is_some_and(|s| s.to_string_lossy().ends_with("_thumb"))
+        {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn scan(dir: &Path, out: &Path) -> Result<Gallery, GalleryError> {
+    if !dir.is_dir() {
+        return Err(GalleryError::NotDir);
+    }
+    let skip = out.canonicalize().unwrap_or_else(|_| out.to_path_buf());
+    let mut found = Vec::new();
+    pngs(&dir.canonicalize()?, &skip, &mut found)?;
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for image in found {
+        // Images from other programs or versions are left out rather than
+        // stopping the gallery
+        let args = metadata::read_params(&image).ok().and_then(|params| {
+            let mut argv = vec![String::from("fracmd")];
+            argv.extend(params.iter().map(|(k, v)| format!("--{}={}", k, v)));
+            Args::try_parse_argv(argv).ok()
+        });
+        match args {
+            Some(args) => entries.push(Entry {
+                modified: fs::metadata(&image)?
+                    .modified()
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                image,
+                args,
+            }),
+            None => skipped += 1,
+        }
+    }
+    if entries.is_empty() {
+        return Err(GalleryError::Empty(dir.to_path_buf()));
+    }
+    entries.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.image.cmp(&b.image)));
+    Ok(Gallery { entries, skipped })
+}
+
+// How to get from the folder from to the path to, both absolute
+fn relative(to: &Path, from: &Path) -> PathBuf {
+    let to: Vec<Component> = to.components().collect();
+    let from: Vec<Component> = from.components().collect();
+    let common = to.iter().zip(&from).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for part in &to[common..] {
+        path.push(part);
+    }
+    path
+}
+
+// Forward slashes whatever the platform, each part percent encoded where
+// a url needs it
+fn url(path: &Path) -> String {
+    path.components()
+        .map(|c| {
+            c.as_os_str()
+                .to_string_lossy()
+                .bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{:02X}", b),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Named after a hash of the image's path, so it's found again however
+// the gallery's order changes
+fn thumb_name(image: &Path) -> String {
+    let hash = image
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    let stem = image.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{:016x}-{}.png", hash, stem)
+}
+
+// Writes the thumbnail unless one at least as new as the image is there
+fn thumb(entry: &Entry, path: &Path, size: u32) -> Result<(), GalleryError> {
+    let fresh = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|made| made >= entry.modified);
+    if fresh {
+        return Ok(());
+    }
+    let image = image::open(&entry.image)?.into_rgba16();
+    DynamicImage::ImageRgba16(thumbnail::thumbnail(&image, size))
+        .into_rgba8()
+        .save(path)?;
+    Ok(())
+}
+
+const STYLE: &str = "body{font-family:sans-serif;background:#111;color:#ddd;margin:2em}
+h1{font-weight:normal}
+main{display:grid;grid-template-columns:repeat(auto-fill,minmax(var(--size),1fr));gap:1.5em}
+figure{margin:0;background:#1c1c1c;padding:.75em;border-radius:4px}
+figure img{display:block;margin:auto;max-width:100%}
+figcaption{margin-top:.5em;word-break:break-all}
+a{color:#8cf}
+table{border-collapse:collapse;font-size:.85em;margin:.5em 0}
+td{padding:0 .5em 0 0;vertical-align:top;word-break:break-all}
+pre{white-space:pre-wrap;word-break:break-all;background:#000;padding:.5em;font-size:.8em}
+@media(prefers-color-scheme:light){body{background:#fafafa;color:#222}figure{background:#eee}a{color:#06c}pre{background:#fff}}";
+
+// Writes index.html and the thumbnails into out, returning the page's path
+pub fn write(
+    gallery: &Gallery,
+    out: &Path,
+    title: &str,
+    size: u32,
+) -> Result<PathBuf, GalleryError> {
+    fs::create_dir_all(out.join("thumbs"))?;
+    let root = out.canonicalize()?;
+    let mut cards = String::new();
+    for entry in &gallery.entries {
+        let thumb_name = thumb_name(&entry.image);
+        thumb(entry, &out.join("thumbs").join(&thumb_name), size)?;
+        let image = relative(&entry.image, &root);
+        let args = &entry.args;
+        let rows: String = preset::changed(args)
+            .iter()
+            .filter(|(k, _)| *k != "output")
+            .map(|(k, v)| format!("<tr><td>{}</td><td>{}</td></tr>", escape(k), escape(v)))
+            .collect();
+        let command = command_line(args);
+        let from_image = format!(
+            "fracmd from-image {}",
+            shell_word(&entry.image.to_string_lossy())
+        );
+        cards += &format!(
+            "<figure>\n<a href=\"{href}\"><img src=\"thumbs/{thumb}\" alt=\"{name}\" loading=\"lazy\"></a>\n\
+             <figcaption><a href=\"{href}\">{name}</a> {w}x{h}, {fractal}\n\
+             <details><summary>Parameters</summary>\n<table>{rows}</table>\n\
+             <pre>{command}</pre>\n<pre>{from_image}</pre>\n</details></figcaption>\n</figure>\n",
+            href = escape(&url(&image)),
+            thumb = escape(&url(Path::new(&thumb_name))),
+            name = escape(&image.file_name().unwrap_or_default().to_string_lossy()),
+            w = args.width,
+            h = args.height,
+            fractal = escape(&args.fractal),
+            rows = rows,
+            command = escape(&command),
+            from_image = escape(&from_image),
+        );
+    }
+    let page = format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"generator\" content=\"fracmd {version}\">\n<title>{title}</title>\n\
+         <style>:root{{--size:{size}px}}\n{style}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>{count} images</p>\n<main>\n{cards}</main>\n</body>\n</html>\n",
+        version = env!("CARGO_PKG_VERSION"),
+        title = escape(title),
+        size = size,
+        style = STYLE,
+        count = gallery.entries.len(),
+        cards = cards,
+    );
+    let index = out.join("index.html");
+    fs::write(&index, page)?;
+    Ok(index)
+}
@@ -0,0 +1,99 @@
+// fracmd
+// Post-render actions
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(feature = "notify")]
+use notify_rust::{Notification, Timeout};
+use std::{path::Path, process};
+
+use crate::Args;
+
+// Runs after a command has written its output. None of these can fail the
+// run, the file is already saved, so problems are only reported.
+pub fn run(args: &Args, path: &Path, message: &str) {
+    notify(args, message);
+    actions(args, path);
+}
+
+#[cfg(feature = "notify")]
+pub fn notify(args: &Args, message: &str) {
+    if !args.no_notify {
+        let shown = Notification::new()
+            .summary("fracmd rendered")
+            .body(message)
+            .timeout(Timeout::Milliseconds(30000))
+            .show();
+        if let Err(e) = shown {
+            eprintln!("Couldn't show a notification ({}), --no-notify skips it", e);
+        }
+    }
+}
+
+// Built without notifications there's nothing to show
+#[cfg(not(feature = "notify"))]
+pub fn notify(_args: &Args, _message: &str) {}
+
+// --open and --exec
+pub fn actions(args: &Args, path: &Path) {
+    if args.open {
+        open(path);
+    }
+    for command in &args.exec {
+        let command = command.replace("{path}", &quote(path));
+        match shell(&command).status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => eprintln!("{:?} exited with {}", command, status),
+            Err(e) => eprintln!("Couldn't run {:?}: {}", command, e),
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+fn open(path: &Path) {
+    if let Err(e) = open::that(path) {
+        eprintln!("Couldn't open {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+fn open(path: &Path) {
+    eprintln!(
+        "Couldn't open {}: fracmd was built without the notify feature",
+        path.display()
+    );
+}
+
+fn shell(command: &str) -> process::Command {
+    if cfg!(windows) {
+        let mut shell = process::Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = process::Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+// Quoted for the shell so paths with spaces survive
+pub fn quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    if cfg!(windows) {
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
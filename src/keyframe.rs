@@ -0,0 +1,305 @@
+// fracmd
+// Keyframed parameters for `animate --keys`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A key file is toml with one [[key]] table per keyframe:
+//
+//   [[key]]
+//   frame = 0
+//   zoom = 0.7
+//
+//   [[key]]
+//   frame = 120
+//   zoom = 5000
+//   origin = "-0.7436+0.1318i"
+//   ease = "exponential"
+//
+// Every other key is a long flag name. Each flag is its own track, so a
+// key only has to list what it changes, and a value is interpolated
+// between the keys either side that set it. The ease of a key shapes the
+// stretch leading up to it. Numbers, complex numbers and comma separated
+// lists like --set-color move smoothly, anything else switches at its key.
+// Flags that only take whole numbers, --height or --limit, are rounded.
+
+use err_derive::Error;
+use num::complex::Complex;
+use std::{fs, path::Path, str::FromStr};
+use toml::Value;
+
+use crate::{config, coords, Args};
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "toml error: {}", _0)]
+    Toml(#[error(source)] toml::de::Error),
+    #[error(display = "key {}: {}", _0, _1)]
+    BadKey(usize, String),
+    #[error(display = "no [[key]] tables")]
+    Empty,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ease {
+    Linear,
+    // Starts and stops gently
+    Smoothstep,
+    // Geometric between positive values, so a zoom seems to move at one
+    // speed throughout, and linear for anything else
+    Exponential,
+}
+
+impl FromStr for Ease {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "linear" => Ok(Ease::Linear),
+            "smoothstep" => Ok(Ease::Smoothstep),
+            "exponential" => Ok(Ease::Exponential),
+            _ => Err(format!(
+                "ease must be linear, smoothstep or exponential, got {:?}",
+                string
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Keyed {
+    Real(f32),
+    List(Vec<f32>),
+    Complex(Complex<f32>),
+    Text(String),
+}
+
+impl Keyed {
+    fn parse(text: &str) -> Keyed {
+        if let Ok(v) = text.trim().parse() {
+            return Keyed::Real(v);
+        }
+        if text.contains(',') {
+            let list: Result<Vec<f32>, _> = text.split(',').map(|v| v.trim().parse()).collect();
+            if let Ok(list) = list {
+                return Keyed::List(list);
+            }
+        }
+        match coords::parse_f32(text) {
+            Ok(c) => Keyed::Complex(c),
+            Err(_) => Keyed::Text(text.to_string()),
+        }
+    }
+
+    fn blend(&self, to: &Keyed, t: f32, ease: Ease) -> Keyed {
+        let mix = |a: f32, b: f32| match ease {
+            Ease::Exponential if a > 0.0 && b > 0.0 => a * (b / a).powf(t),
+            Ease::Smoothstep => a + (b - a) * t * t * (3.0 - 2.0 * t),
+            _ => a + (b - a) * t,
+        };
+        match (self, to) {
+            (Keyed::Real(a), Keyed::Real(b)) => Keyed::Real(mix(*a, *b)),
+            (Keyed::List(a), Keyed::List(b)) if a.len() == b.len() => {
+                Keyed::List(a.iter().zip(b).map(|(a, b)| mix(*a, *b)).collect())
+            }
+            (Keyed::Complex(a), Keyed::Complex(b)) => {
+                Keyed::Complex(Complex::new(mix(a.re, b.re), mix(a.im, b.im)))
+            }
+            // Values that can't be blended hold until the next key
+            _ if t < 1.0 => self.clone(),
+            _ => to.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Keyed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Keyed::Real(v) => write!(f, "{}", v),
+            Keyed::List(list) => {
+                let list: Vec<String> = list.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", list.join(","))
+            }
+            Keyed::Complex(c) => write!(f, "{}", coords::format(*c)),
+            Keyed::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+struct Key {
+    // Where it is in the file, counting from 1 like BadKey
+    number: usize,
+    frame: f32,
+    ease: Ease,
+    values: Vec<(String, Keyed)>,
+}
+
+impl Key {
+    fn get(&self, name: &str) -> Option<(f32, Ease, &Keyed)> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| (self.frame, self.ease, v))
+    }
+}
+
+pub struct Keyframes {
+    keys: Vec<Key>,
+}
+
+impl Keyframes {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyframes, KeyError> {
+        let file: toml::value::Table = toml::from_str(&fs::read_to_string(path)?)?;
+        let tables = match file.get("key") {
+            Some(Value::Array(tables)) if !tables.is_empty() => tables.clone(),
+            _ => return Err(KeyError::Empty),
+        };
+        let mut keys: Vec<Key> = Vec::new();
+        for (i, table) in tables.into_iter().enumerate() {
+            let bad = |msg: String| KeyError::BadKey(i + 1, msg);
+            let Value::Table(mut table) = table else {
+                return Err(bad(String::from("must be a table")));
+            };
+            let frame = match table.remove("frame") {
+                Some(Value::Integer(f)) if f >= 0 => f as f32,
+                Some(Value::Float(f)) if f >= 0.0 => f as f32,
+                _ => return Err(bad(String::from("needs a frame number of 0 or more"))),
+            };
+            if keys.iter().any(|k| k.frame == frame) {
+                return Err(bad(format!("frame {} has a key already", frame)));
+            }
+            let ease = match table.remove("ease") {
+                Some(Value::String(ease)) => ease.parse().map_err(bad)?,
+                Some(_) => return Err(bad(String::from("ease must be a string"))),
+                None => Ease::Linear,
+            };
+            // The same conversion a config file gets, then split back up
+            let flags = config::from_table(table).map_err(|e| bad(e.to_string()))?;
+            // A value clap won't take is caught here rather than on the
+            // frame that first reaches it
+            Args::try_from_params(&[], &flags).map_err(bad)?;
+            let mut values = Vec::new();
+            for flag in flags {
+                let (name, value) = flag
+                    .trim_start_matches('-')
+                    .split_once('=')
+                    .ok_or_else(|| bad(format!("{} can't be keyed, it's a switch", flag)))?;
+                if config::takes_value(name).is_none() {
+                    return Err(bad(format!("{} isn't a fracmd flag", name)));
+                }
+                values.push((name.to_string(), Keyed::parse(value)));
+            }
+            keys.push(Key {
+                number: i + 1,
+                frame,
+                ease,
+                values,
+            });
+        }
+        keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        Ok(Keyframes { keys })
+    }
+
+    // The key a point on the timeline takes its held values from, the one
+    // at or before it or else the first, for naming in errors
+    pub fn key_at(&self, position: f32) -> usize {
+        self.keys
+            .iter()
+            .rev()
+            .find(|k| k.frame <= position)
+            .or(self.keys.first())
+            .map_or(1, |k| k.number)
+    }
+
+    // Frame of the last key, where the timeline ends
+    pub fn last(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.frame)
+    }
+
+    // Flags for a point on the timeline, which can fall between frames
+    pub fn flags_at(&self, position: f32) -> Vec<String> {
+        let mut names: Vec<&str> = Vec::new();
+        for key in &self.keys {
+            for (name, _) in &key.values {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let before = self
+                    .keys
+                    .iter()
+                    .rev()
+                    .filter(|k| k.frame <= position)
+                    .find_map(|k| k.get(name));
+                let after = self
+                    .keys
+                    .iter()
+                    .filter(|k| k.frame > position)
+                    .find_map(|k| k.get(name));
+                let value = match (before, after) {
+                    (Some((from, _, a)), Some((to, ease, b))) => {
+                        a.blend(b, (position - from) / (to - from), ease)
+                    }
+                    (Some((_, _, v)), None) | (None, Some((_, _, v))) => v.clone(),
+                    (None, None) => return None,
+                };
+                Some(match value {
+                    Keyed::Real(v) => config::number_flag(name, v),
+                    value => format!("--{}={}", name, value),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // name keeps tests running at once apart
+    fn load(name: &str, text: &str) -> Result<Keyframes, KeyError> {
+        let file = format!("fracmd-keys-{}-{}.toml", std::process::id(), name);
+        let path = std::env::temp_dir().join(file);
+        fs::write(&path, text).unwrap();
+        let keys = Keyframes::load(&path);
+        fs::remove_file(&path).unwrap();
+        keys
+    }
+
+    #[test]
+    fn bad_value_names_its_key() {
+        let keys = load(
+            "bad",
+            "[[key]]\nframe = 0\nzoom = 0.7\n\n[[key]]\nframe = 10\ncolorspace = \"bogus\"\n",
+        );
+        assert!(matches!(keys, Err(KeyError::BadKey(2, _))));
+    }
+
+    #[test]
+    fn values_are_interpolated() {
+        let keys = load(
+            "lerp",
+            "[[key]]\nframe = 0\ncexp = 1\n\n[[key]]\nframe = 10\ncexp = 2\n",
+        )
+        .unwrap();
+        assert_eq!(keys.flags_at(5.0), vec![config::number_flag("cexp", 1.5)]);
+        assert_eq!(keys.key_at(5.0), 1);
+        assert_eq!(keys.key_at(10.0), 2);
+    }
+}
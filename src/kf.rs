@@ -0,0 +1,363 @@
+// fracmd
+// Kalles Fraktaler .kfr parameter and .kfb iteration map files
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A .kfr is "Key: value" lines. Only the view and the iteration settings
+// carry over, Kalles Fraktaler's palette and coloring methods have no
+// counterpart here. Its Zoom 1 shows a height of 4 where fracmd's zoom 1
+// shows a width of 2, and its imaginary axis points up, so an imported
+// view gets --transform 1,0,0,-1 to come out the same way round.
+//
+// .kfb layout, all numbers little endian:
+//
+//   magic       3 bytes   b"KFB"
+//   width       i32
+//   height      i32
+//   counts      width * height i32 iteration counts, column-major (y
+//               fastest), the limit for points inside the set
+//   iter_div    i32
+//   parts       i32       palette length
+//   palette     parts * 3 bytes of r, g, b
+//   limit       i32
+//   trans       width * height f32 in the same order, the smooth count
+//               being count + 1 - trans
+//   de          optionally width * height f32, ignored when reading
+
+use crate::{
+    fracdata::{FracData, PixelData},
+    Args,
+};
+use err_derive::Error;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+pub const MAGIC: &[u8; 3] = b"KFB";
+
+// Written as the palette of exported maps, fracmd's colorings aren't
+// palettes so these only give Kalles Fraktaler something to start from
+const PALETTE: [[u8; 3]; 6] = [
+    [255, 128, 128],
+    [255, 255, 128],
+    [128, 255, 128],
+    [128, 255, 255],
+    [128, 128, 255],
+    [255, 128, 255],
+];
+
+#[derive(Debug, Error)]
+pub enum KfError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "line {}: {}", _0, _1)]
+    Syntax(usize, String),
+    #[error(display = "no {} in the parameter file", _0)]
+    Missing(&'static str),
+    #[error(display = "{}", _0)]
+    Unsupported(String),
+    #[error(display = "not a Kalles Fraktaler map file")]
+    BadMagic,
+    #[error(display = "a {}x{} map doesn't fit in the file", _0, _1)]
+    Truncated(usize, usize),
+}
+
+pub fn is_kfr(path: &Path) -> bool {
+    has_extension(path, "kfr")
+}
+
+pub fn is_kfb(path: &Path) -> bool {
+    has_extension(path, "kfb")
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+// The flags for a .kfr's view rendered at width x height
+pub fn read_kfr(path: &Path, width: i32, height: i32) -> Result<Vec<String>, KfError> {
+    let text = fs::read_to_string(path)?;
+    let mut keys = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((key, value)) => keys.push((i + 1, key.trim(), value.trim())),
+            None => {
+                return Err(KfError::Syntax(
+                    i + 1,
+                    format!("expected Key: value, got {:?}", line),
+                ))
+            }
+        }
+    }
+    let find = |key: &'static str| keys.iter().find(|(_, k, _)| *k == key);
+    let number = |key: &'static str| -> Result<Option<f64>, KfError> {
+        match find(key) {
+            Some((line, _, value)) => value.parse().map(Some).map_err(|_| {
+                KfError::Syntax(*line, format!("{} isn't a number: {:?}", key, value))
+            }),
+            None => Ok(None),
+        }
+    };
+    let needed = |key: &'static str| number(key)?.ok_or(KfError::Missing(key));
+
+    if number("FractalType")?.unwrap_or(0.0) != 0.0 {
+        return Err(KfError::Unsupported(String::from(
+            "only Kalles Fraktaler's Mandelbrot formulas (FractalType 0) can be imported",
+        )));
+    }
+    let zoom = needed("Zoom")? * height as f64 / width as f64 / 2.0;
+    if !(zoom as f32).is_finite() || zoom as f32 == 0.0 {
+        return Err(KfError::Unsupported(format!(
+            "zoom {:e} is beyond what fracmd's floats can render",
+            zoom
+        )));
+    }
+    let mut flags = vec![
+        format!("--re={}", needed("Re")?),
+        format!("--im={}", needed("Im")?),
+        format!("--zoom={}", zoom as f32),
+        String::from("--transform=1,0,0,-1"),
+    ];
+    if let Some(limit) = number("Iterations")? {
+        flags.push(format!("--limit={}", limit));
+    }
+    if let Some(power) = number("Power")? {
+        flags.push(format!("--power={}", power));
+    }
+    if let Some(rotate) = number("Rotate")? {
+        flags.push(format!("--rotate={}", rotate));
+    }
+    if let Some((line, _, value)) = find("InteriorColor") {
+        let rgb: Vec<&str> = value.split(',').map(str::trim).collect();
+        if rgb.len() < 3 || rgb[..3].iter().any(|v| v.parse::<u8>().is_err()) {
+            return Err(KfError::Syntax(
+                *line,
+                format!("InteriorColor isn't r,g,b: {:?}", value),
+            ));
+        }
+        flags.push(format!("--set-color={},{},{},255", rgb[0], rgb[1], rgb[2]));
+    }
+    Ok(flags)
+}
+
+// Why the view can't be written as a .kfr, if it can't
+pub fn unsupported(args: &Args) -> Option<String> {
+    if args.fractal != "multibrot" || args.julia.is_some() {
+        return Some(String::from(
+            "only plain multibrot renders can be written as a .kfr",
+        ));
+    }
+    if args.power.fract() != 0.0 || args.power < 2.0 {
+        return Some(format!(
+            "Kalles Fraktaler only has whole powers from 2, not {}",
+            args.power
+        ));
+    }
+    None
+}
+
+pub fn write_kfr(path: &Path, args: &Args) -> Result<(), KfError> {
+    if let Some(reason) = unsupported(args) {
+        return Err(KfError::Unsupported(reason));
+    }
+    let set = args.set_color.to_arr().map(|v| (v * 255.0).round() as u8);
+    let colors: String = PALETTE
+        .iter()
+        .map(|[r, g, b]| format!("{},{},{},", r, g, b))
+        .collect();
+    let zoom = args.zoom as f64 * 2.0 * args.width as f64 / args.height as f64;
+    let text = [
+        format!("Re: {}", args.origin.re),
+        format!("Im: {}", args.origin.im),
+        format!("Zoom: {:E}", zoom),
+        format!("Iterations: {}", args.limit as u64),
+        String::from("IterDiv: 1"),
+        String::from("FractalType: 0"),
+        format!("Power: {}", args.power),
+        format!("Rotate: {}", args.rotate),
+        String::from("Ratio: 360"),
+        format!("Colors: {}", colors),
+        format!("InteriorColor: {},{},{}", set[0], set[1], set[2]),
+        String::from("Smooth: 1"),
+    ];
+    fs::write(path, text.join("\r\n") + "\r\n")?;
+    Ok(())
+}
+
+pub fn save_kfb(path: &Path, dump: &FracData) -> Result<(), KfError> {
+    let limit = dump
+        .params
+        .iter()
+        .find(|(k, _)| k == "limit")
+        .and_then(|(_, v)| v.parse::<f32>().ok())
+        .unwrap_or_else(|| dump.data.iter().map(|p| p.iter).fold(0.0, f32::max));
+    let (width, height) = (dump.width as usize, dump.height as usize);
+    let column_major = |f: &dyn Fn(&PixelData) -> [u8; 4]| -> Vec<u8> {
+        let mut out = Vec::with_capacity(width * height * 4);
+        for x in 0..width {
+            for y in 0..height {
+                out.extend(f(&dump.data[y * width + x]));
+            }
+        }
+        out
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(width as i32).to_le_bytes())?;
+    out.write_all(&(height as i32).to_le_bytes())?;
+    out.write_all(&column_major(&|p| match p.iter >= limit {
+        true => (limit as i32).to_le_bytes(),
+        false => (p.iter as i32).to_le_bytes(),
+    }))?;
+    out.write_all(&1i32.to_le_bytes())?;
+    out.write_all(&(PALETTE.len() as i32).to_le_bytes())?;
+    out.write_all(PALETTE.as_flattened())?;
+    out.write_all(&(limit as i32).to_le_bytes())?;
+    out.write_all(&column_major(&|p| match p.iter >= limit {
+        true => 0f32.to_le_bytes(),
+        false => (p.iter.floor() + 1.0 - p.smooth).to_le_bytes(),
+    }))?;
+    out.flush()?;
+    Ok(())
+}
+
+// A map as a dump, with only the iteration counts filled in. s, which the
+// hue coloring reads and Kalles Fraktaler doesn't keep, is the smooth
+// count, which it roughly follows.
+pub fn load_kfb(path: &Path) -> Result<FracData, KfError> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut input = BufReader::new(file);
+    let mut magic = [0u8; 3];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(KfError::BadMagic);
+    }
+    let width = read_i32(&mut input)?.max(0) as usize;
+    let height = read_i32(&mut input)?.max(0) as usize;
+    // Two blocks of four bytes a pixel, the rest of the layout is 23 bytes
+    // and the palette, so nothing gets allocated past what the file holds
+    let fits = |extra: u64| {
+        (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|n| n.checked_mul(8))
+            .and_then(|n| n.checked_add(23 + extra))
+            .is_some_and(|n| n <= len)
+    };
+    if !fits(0) {
+        return Err(KfError::Truncated(width, height));
+    }
+    let counts = read_block(&mut input, width * height)?;
+    let _iter_div = read_i32(&mut input)?;
+    let parts = read_i32(&mut input)?.max(0) as usize;
+    if !fits(parts as u64 * 3) {
+        return Err(KfError::Truncated(width, height));
+    }
+    input.read_exact(&mut vec![0u8; parts * 3])?;
+    let limit = read_i32(&mut input)?;
+    let trans = read_block(&mut input, width * height)?;
+
+    let mut data = vec![PixelData::default(); width * height];
+    for x in 0..width {
+        for y in 0..height {
+            let count = i32::from_le_bytes(counts[x * height + y]);
+            let smooth = match count >= limit {
+                true => limit as f32,
+                false => count as f32 + 1.0 - f32::from_le_bytes(trans[x * height + y]),
+            };
+            data[y * width + x] = PixelData {
+                iter: count.min(limit) as f32,
+                s: smooth,
+                smooth,
+                ..PixelData::default()
+            };
+        }
+    }
+    Ok(FracData {
+        width: width as u32,
+        height: height as u32,
+        params: vec![
+            (String::from("width"), width.to_string()),
+            (String::from("height"), height.to_string()),
+            (String::from("limit"), limit.to_string()),
+        ],
+        data,
+    })
+}
+
+fn read_i32<R: Read>(input: &mut R) -> Result<i32, KfError> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_block<R: Read>(input: &mut R, len: usize) -> Result<Vec<[u8; 4]>, KfError> {
+    let mut bytes = vec![0u8; len * 4];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2], c[3]])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    // Kalles Fraktaler's zoom is across the height and goes far past what
+    // f32 holds
+    #[test]
+    fn kfr_zoom_is_converted_or_refused() {
+        let view = TempFile::new("view.kfr", "Re: -0.75\r\nIm: 0.1\r\nZoom: 4E0\r\n");
+        let flags = read_kfr(&view.0, 640, 360).unwrap();
+        assert_eq!(&flags[..3], ["--re=-0.75", "--im=0.1", "--zoom=1.125"]);
+        let deep = TempFile::new("deep.kfr", "Re: 0\r\nIm: 0\r\nZoom: 1E300\r\n");
+        assert!(matches!(
+            read_kfr(&deep.0, 640, 360),
+            Err(KfError::Unsupported(_))
+        ));
+    }
+
+    // Sizes from the header and the palette length can't claim more than
+    // the file holds
+    #[test]
+    fn kfb_sizes_past_the_file_are_refused() {
+        let header = |values: &[i32]| {
+            let mut bytes = MAGIC.to_vec();
+            for v in values {
+                bytes.extend(v.to_le_bytes());
+            }
+            bytes.extend([0; 16]);
+            bytes
+        };
+        let map = TempFile::new("map.kfb", header(&[i32::MAX, i32::MAX]));
+        let big = i32::MAX as usize;
+        assert!(matches!(load_kfb(&map.0), Err(KfError::Truncated(w, h)) if (w, h) == (big, big)));
+        let palette = TempFile::new("palette.kfb", header(&[1, 1, 0, 1, i32::MAX]));
+        assert!(matches!(
+            load_kfb(&palette.0),
+            Err(KfError::Truncated(1, 1))
+        ));
+    }
+}
@@ -0,0 +1,1974 @@
+// fracmd
+// Rendering core, shared by the fracmd binary and anything embedding it
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Args describes a render and Renderer makes it, with output writing the
+// result in any --format. Everything else is the command line built on
+// top, reached through cli::run. The modules made public here are the ones
+// the public types hand out, the rest can change freely.
+
+// err-derive expands its impls inside an anonymous const block
+#![allow(non_local_definitions)]
+// Much of the library is only reached from the command line
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+use clap::{
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, ValueSource,
+};
+use image::{ImageBuffer, Rgba};
+use num::complex::Complex;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+mod animation;
+#[cfg(feature = "cli")]
+mod api;
+mod audio;
+pub mod backend;
+pub mod bailout;
+mod batch;
+mod bookmark;
+mod catalog;
+mod checkpoint;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod coloring;
+pub mod colorspace;
+mod config;
+mod contour;
+#[cfg(feature = "cli")]
+mod control;
+pub mod coords;
+pub mod depth;
+mod diff;
+pub mod error;
+mod estimate;
+mod explore;
+pub mod expmap;
+mod farbfeld;
+#[cfg(feature = "cli")]
+mod farm;
+mod font;
+pub mod fracdata;
+pub mod fractal;
+mod fractint;
+#[cfg(feature = "cli")]
+mod gallery;
+#[cfg(feature = "cli")]
+mod hooks;
+mod keyframe;
+mod kf;
+mod locate;
+mod manifest;
+mod mesh;
+mod metadata;
+mod metrics;
+mod nucleus;
+mod openexr;
+mod orbit;
+pub mod output;
+pub mod palette;
+mod par;
+pub mod plugin;
+mod pnm;
+mod preset;
+mod progress;
+mod pyramid;
+mod quantize;
+mod queue;
+pub mod recolor;
+pub mod region;
+mod reuse;
+pub mod rgbaf;
+pub mod sampler;
+pub mod scheduler;
+pub mod script;
+#[cfg(feature = "cli")]
+mod serve;
+mod sheet;
+mod sidecar;
+mod sizes;
+mod sweep;
+mod template;
+mod terminal;
+mod thumbnail;
+mod tiles;
+mod upr;
+mod validate;
+mod verify;
+#[cfg(feature = "cli")]
+mod viewer;
+mod wasm;
+#[cfg(feature = "cli")]
+mod websocket;
+#[cfg(feature = "cli")]
+mod window;
+#[cfg(feature = "cli")]
+mod wizard;
+mod xmp;
+use backend::RenderBackend;
+use bailout::Bailout;
+use coloring::{Coloring, Hue};
+use colorspace::Colorspace;
+use depth::{Buffer, OutputPixel};
+pub use error::FracError;
+pub use fracdata::{FracData, PixelData, Samples};
+use fractal::{Fractal, Multibrot};
+pub use palette::{Palette, Transfer};
+use par::*;
+pub use recolor::Recolor;
+pub use rgbaf::RgbaF;
+use sampler::{PixelRng, Sampler};
+pub use scheduler::Image16;
+use scheduler::{Progress, Shared, Tile, TILE_SIZE};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+#[clap(args_override_self = true)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    // Ids of the flags that were given rather than defaulted. Subcommand
+    // fields share this id space, so they must not reuse a flag's name
+    #[clap(skip)]
+    explicit: Vec<String>,
+
+    /// Read flags from a toml file, flags on the command line take precedence
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Read flags from a json object on stdin, over the config file and
+    /// FRACGEN_ variables but under the command line
+    #[clap(long, global = true)]
+    params_stdin: bool,
+
+    /// Apply a saved preset, can be repeated with later ones winning
+    #[clap(long, global = true)]
+    preset: Vec<String>,
+
+    /// Write the effective settings to a toml file and exit
+    #[clap(long, global = true)]
+    write_config: Option<PathBuf>,
+
+    #[clap(short, long, global = true, default_value = "1920")]
+    width: i32,
+
+    #[clap(long, global = true, default_value = "1680")]
+    height: i32,
+
+    #[clap(short, long, global = true, default_value = "mandelbrot")]
+    name: String,
+
+    #[clap(short, long, global = true, default_value_t=((num_cpus::get() as f32) * 0.5).ceil() as usize)]
+    threads: usize,
+
+    /// Center of the view, e.g. -0.7436+0.1318i or -0.7436,0.1318
+    #[clap(short, long, global = true, default_value = "-0.75+0i",
+        allow_hyphen_values = true, value_parser = coords::parse_f32)]
+    origin: Complex<f32>,
+
+    /// Real part of the origin, overriding the one in --origin. Rendering
+    /// uses about 7 digits, the metadata keeps all that are given.
+    #[clap(long, global = true, allow_hyphen_values = true,
+        value_parser = coords::parse_decimal)]
+    re: Option<String>,
+
+    /// Imaginary part of the origin, overriding the one in --origin
+    #[clap(long, global = true, allow_hyphen_values = true,
+        value_parser = coords::parse_decimal)]
+    im: Option<String>,
+
+    /// Render the Julia set of this c instead, the view is then of the
+    /// starting z and --origin 0 centers it
+    #[clap(long, global = true, allow_hyphen_values = true, value_parser = coords::parse_f32)]
+    julia: Option<Complex<f32>>,
+
+    /// Expmap renders a log-polar strip around the origin instead, for
+    /// `fracmd unroll` to turn into a zoom. Its width goes once round, each
+    /// row is the same step deeper, and --zoom is the outermost frame.
+    #[clap(long, global = true, value_enum, default_value = "plane")]
+    projection: expmap::Projection,
+
+    /// Exponent of z in z^p + c, 2 is the Mandelbrot set, can be fractional
+    #[clap(long, global = true, default_value = "2")]
+    power: f32,
+
+    /// Formula to iterate, multibrot (z^power + c) or one a --plugin or
+    /// --script brings
+    #[clap(long, global = true, default_value = "multibrot")]
+    fractal: String,
+
+    /// Coloring of escaped points, hue or one a --plugin or --script brings
+    #[clap(long, global = true, default_value = "hue")]
+    coloring: String,
+
+    /// Shared library or .wasm module adding fractals and colorings, can be
+    /// repeated or comma separated
+    #[clap(long, global = true, value_delimiter = ',')]
+    plugin: Vec<PathBuf>,
+
+    /// Script file defining a fractal, a coloring or both, used unless
+    /// --fractal or --coloring is given, can be repeated or comma separated
+    #[clap(long, global = true, value_delimiter = ',')]
+    script: Vec<PathBuf>,
+
+    /// Magnification, 1 shows a span of 2 across, e.g. .7 or 1e4
+    #[clap(short, long, global = true, default_value = ".7")]
+    zoom: f32,
+
+    /// Zoom as a power of ten, --zoom-exp 4 is --zoom 1e4, up to 38.5
+    #[clap(long, global = true, allow_hyphen_values = true,
+        value_parser = coords::parse_zoom_exp)]
+    zoom_exp: Option<f64>,
+
+    /// Start from a built in location, see `fracmd locations`
+    #[clap(long, global = true)]
+    location: Option<String>,
+
+    /// Makes the anti-aliasing jitter repeatable
+    #[clap(long, global = true)]
+    seed: Option<u64>,
+
+    /// Render the same pixels every run and on any number of threads,
+    /// seeding with 0 unless --seed is given
+    #[clap(long, global = true)]
+    deterministic: bool,
+
+    /// Turns the view around the origin by this many degrees
+    #[clap(long, global = true, default_value = "0", allow_hyphen_values = true)]
+    rotate: f32,
+
+    /// 2x2 matrix applied to the view before rotating, a,b,c,d row by row
+    #[clap(long, global = true, default_value = "1,0,0,1",
+        allow_hyphen_values = true, value_parser = coords::parse_affine)]
+    transform: coords::Affine,
+
+    /// Scales the real axis, folded into --transform, -1 mirrors the view
+    #[clap(long, global = true, allow_hyphen_values = true)]
+    stretch: Option<f32>,
+
+    /// Shears the view by this many degrees, folded into --transform
+    #[clap(long, global = true, allow_hyphen_values = true)]
+    skew: Option<f32>,
+
+    /// Write several sizes from one render, like 1920x1080,512x512. The
+    /// frame that fits them all is rendered and each is cut from its center
+    /// and downsampled, --width and --height are ignored
+    #[clap(long, global = true, use_value_delimiter = true)]
+    sizes: Vec<sizes::Size>,
+
+    /// Only render this crop of the frame, x0,y0,x1,y1 in pixels or as
+    /// fractions like 0.5,0,1.0,0.5
+    #[clap(long, global = true)]
+    region: Option<region::Region>,
+
+    /// Move the origin onto the center of the biggest minibrot or bulb in
+    /// view before rendering
+    #[clap(long, global = true)]
+    autocenter: bool,
+
+    /// Estimate the time and memory a render needs without rendering it
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// Seconds a --dry-run estimate can reach before it warns
+    #[clap(long, global = true, default_value = "3600")]
+    max_time: u64,
+
+    /// MiB a --dry-run estimate can reach before it warns
+    #[clap(long, global = true, default_value = "4096")]
+    max_memory: u64,
+
+    #[clap(short, long, global = true, default_value = "1")]
+    samples: usize,
+
+    #[clap(long, global = true, default_value = "2.0")]
+    sampled: f32,
+
+    /// Where in each pixel its samples are taken
+    #[clap(long, global = true, value_enum, default_value = "random")]
+    sampler: sampler::SamplerKind,
+
+    /// Samples every pixel gets with --sampler adaptive, the rest only
+    /// go where these disagree
+    #[clap(long, global = true, default_value = "4")]
+    adaptive_min: usize,
+
+    /// How far apart, 0 to 1 in any channel, the first adaptive samples'
+    /// colors can be for a pixel to stop at them
+    #[clap(long, global = true, default_value = "0.02")]
+    adaptive_threshold: f32,
+
+    #[clap(short, long, global = true, default_value = "256.0")]
+    limit: f32,
+
+    /// Orbits whose --bail-norm of z reaches this have escaped, the
+    /// fractal's own (16 for the multibrot) when not given
+    #[clap(short, long, global = true)]
+    bail: Option<f32>,
+
+    /// How z is measured against --bail, squared like |z|^2 is
+    #[clap(long, global = true, value_enum)]
+    bail_norm: Option<bailout::Norm>,
+
+    /// Also stop orbits once a step moves z less than this
+    #[clap(long, global = true, value_name = "EPS")]
+    converge: Option<f32>,
+
+    #[clap(short, long, global = true, default_value = "1.0")]
+    cexp: f32,
+
+    #[clap(long, global = true, default_value = "0,0,0,255")]
+    set_color: RgbaF,
+
+    #[clap(long, global = true, value_enum, default_value = "png")]
+    format: Format,
+
+    /// Working and output color space, embedded as an ICC profile
+    #[clap(long, global = true, value_enum, default_value = "srgb")]
+    colorspace: Colorspace,
+
+    /// Palette size for indexed-png and gif output
+    #[clap(long, global = true, default_value = "256")]
+    colors: usize,
+
+    /// JPEG quality, from 1 to 100
+    #[clap(long, global = true, default_value = "90")]
+    quality: u8,
+
+    /// Who made the image, written into its metadata
+    #[clap(long, global = true, value_name = "NAME")]
+    creator: Option<String>,
+
+    /// Use this .map palette as the index instead of quantizing
+    #[clap(long, global = true)]
+    index_palette: Option<PathBuf>,
+
+    /// Output path template, "-" streams the image to stdout
+    #[clap(long, global = true, alias = "out")]
+    output: Option<String>,
+
+    /// What to do when the output path already exists
+    #[clap(long, global = true, value_enum, default_value = "overwrite")]
+    collision: template::Collision,
+
+    /// Add iteration, DE, trap, |z| and escape mask channels to exr output
+    #[clap(long, global = true)]
+    aovs: bool,
+
+    /// Keep rewriting the output file with what's rendered so far
+    #[clap(long, global = true)]
+    live: bool,
+
+    /// Seconds between live output rewrites
+    #[clap(long, global = true, default_value = "2.0")]
+    live_interval: f32,
+
+    /// Draw the render on the terminal as it fills in, Ctrl-C stops it
+    /// with a checkpoint to --resume from
+    #[clap(long, global = true)]
+    live_preview: bool,
+
+    /// Show the render filling in in a window, closing it or pressing Esc
+    /// stops it with a checkpoint to --resume from
+    #[clap(long, global = true)]
+    preview_window: bool,
+
+    /// Progress on stderr, auto shows a bar on terminals and json writes
+    /// one event per line
+    #[clap(long, global = true, value_enum, default_value = "auto")]
+    progress: progress::ProgressMode,
+
+    /// Also cut the image into a deep zoom tile pyramid at this path
+    #[clap(long, global = true)]
+    pyramid: Option<PathBuf>,
+
+    #[clap(long, global = true, value_enum, default_value = "dzi")]
+    pyramid_format: pyramid::PyramidFormat,
+
+    #[clap(long, global = true, default_value = "254")]
+    tile_size: u32,
+
+    /// Base URL the IIIF info.json should advertise as its id
+    #[clap(long, global = true, default_value = "")]
+    iiif_id: String,
+
+    /// Also trace iso-iteration contours into this svg file
+    #[clap(long, global = true)]
+    svg: Option<PathBuf>,
+
+    /// Smooth iterations between svg contour bands
+    #[clap(long, global = true, default_value = "4.0")]
+    svg_spacing: f32,
+
+    /// Draw the orbit of this c over the image, from blue at its start to
+    /// red where it escapes or the limit ends it
+    #[clap(long, global = true, allow_hyphen_values = true, value_parser = coords::parse_f32)]
+    plot_orbit: Option<Complex<f32>>,
+
+    /// Also export a heightmap mesh, .obj or binary .stl by extension
+    #[clap(long, global = true)]
+    mesh: Option<PathBuf>,
+
+    #[clap(long, global = true, value_enum, default_value = "iteration")]
+    mesh_source: mesh::MeshSource,
+
+    /// Height of the tallest point above the base, in pixels
+    #[clap(long, global = true, default_value = "100.0")]
+    mesh_scale: f32,
+
+    /// Thickness of the solid base under the surface, in pixels
+    #[clap(long, global = true, default_value = "5.0")]
+    mesh_base: f32,
+
+    /// Only use every nth pixel as a mesh vertex
+    #[clap(long, global = true, default_value = "1")]
+    mesh_step: usize,
+
+    /// Also write a png no larger than this next to the render
+    #[clap(long, global = true)]
+    thumbnail: Option<u32>,
+
+    /// Also print a small preview inline, auto picks kitty, iterm or sixel
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        min_values = 0,
+        default_missing_value = "auto"
+    )]
+    preview_term: Option<terminal::TermProtocol>,
+
+    /// Longest side of the terminal preview in pixels
+    #[clap(long, global = true, default_value = "480")]
+    preview_size: u32,
+
+    /// Also write raw per-pixel iteration data to this file
+    #[clap(long, global = true)]
+    dump_data: Option<PathBuf>,
+
+    /// Also write the view as a Kalles Fraktaler .kfr parameter file
+    #[clap(long, global = true)]
+    kfr: Option<PathBuf>,
+
+    /// Render every combination of these values instead of one image,
+    /// key=from:to:steps or key=v1,v2,... and repeatable
+    #[clap(long, global = true, value_name = "KEY=VALUES")]
+    sweep: Vec<sweep::Sweep>,
+
+    /// Also tile a sweep into one labeled comparison image
+    #[clap(long, global = true)]
+    sweep_sheet: Option<PathBuf>,
+
+    /// Continue an interrupted render from its checkpoint
+    #[clap(long, global = true)]
+    resume: Option<PathBuf>,
+
+    /// Answer on a Unix socket here while rendering, `fracmd control`
+    /// pauses, resumes, changes threads or stops the render through it
+    #[clap(long, global = true, value_name = "SOCKET")]
+    control: Option<PathBuf>,
+
+    /// What renders the tiles, auto picks the fastest that works here
+    #[clap(long, global = true, value_enum, default_value = "auto")]
+    backend: backend::Backend,
+
+    /// Skip the desktop notification when done, for headless machines
+    #[clap(long, global = true)]
+    no_notify: bool,
+
+    /// Open the output in the default viewer when done
+    #[clap(long, global = true)]
+    open: bool,
+
+    /// Shell command to run when done, {path} is the output file
+    #[clap(long, global = true, value_name = "COMMAND")]
+    exec: Vec<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Png,
+    Exr,
+    Ppm,
+    Pam,
+    Farbfeld,
+    IndexedPng,
+    Gif,
+    Tiff,
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Exr => "exr",
+            Format::Ppm => "ppm",
+            Format::Pam => "pam",
+            Format::Farbfeld => "ff",
+            Format::IndexedPng => "png",
+            Format::Gif => "gif",
+            Format::Tiff => "tiff",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Render a still image, what runs when no command is given
+    Render,
+    /// Render the same image several times and report the speed
+    Bench {
+        #[clap(long, default_value = "3")]
+        runs: usize,
+    },
+    /// Re-render using the parameters embedded in an existing image or
+    /// held in a Kalles Fraktaler .kfr, Ultra Fractal .upr or Fractint
+    /// .par, other flags override them
+    FromImage { image: PathBuf },
+    /// Color a .fracdata dump without iterating again, --cexp and --set-color
+    /// override the stored values and --output defaults to the dump path as .png
+    Recolor {
+        data: PathBuf,
+
+        /// Fractint style .map gradient, the built-in coloring is used if omitted
+        #[clap(short, long)]
+        palette: Option<PathBuf>,
+
+        #[clap(long, value_enum, default_value = "linear")]
+        transfer: Transfer,
+
+        /// Number of times the palette repeats over the full iteration range
+        #[clap(long, default_value = "1.0")]
+        cycles: f32,
+    },
+    /// Color-cycle one render into a looping animation, shifting the
+    /// palette along each frame without iterating again
+    Cycle {
+        /// A --dump-data file to cycle, the view is rendered once if omitted
+        data: Option<PathBuf>,
+
+        #[clap(short, long, default_value = "palettes/fire.map")]
+        palette: PathBuf,
+
+        #[clap(long, value_enum, default_value = "linear")]
+        transfer: Transfer,
+
+        /// Number of times the palette repeats over the full iteration range
+        #[clap(long, default_value = "1.0")]
+        cycles: f32,
+
+        /// Frames in the loop, the palette moves once along its length
+        #[clap(long, default_value = "60")]
+        frames: usize,
+
+        #[clap(long, default_value = "30")]
+        fps: u16,
+
+        /// Quality of .mp4, .mkv, .mov and .webm output, lower is better
+        #[clap(long, default_value = "18")]
+        crf: u8,
+
+        /// Go the other way round the palette
+        #[clap(long)]
+        reverse: bool,
+
+        #[clap(flatten)]
+        audio: audio::AudioArgs,
+    },
+    /// Turn a --projection expmap strip into a zoom animation, from the
+    /// strip's --zoom as deep as it goes, at --width and --height
+    Unroll {
+        strip: PathBuf,
+
+        #[clap(long, default_value = "300")]
+        frames: usize,
+
+        #[clap(long, default_value = "30")]
+        fps: u16,
+
+        /// Quality of .mp4, .mkv, .mov and .webm output, lower is better
+        #[clap(long, default_value = "18")]
+        crf: u8,
+    },
+    /// Render XYZ web map tiles, cached under <cache>/<name>-<params hash>/z/x/y.png
+    Tiles {
+        /// Zoom level or inclusive range of levels
+        #[clap(long = "z", default_value = "0..4")]
+        zooms: tiles::ZoomRange,
+
+        #[clap(long, default_value = "256")]
+        size: u32,
+
+        #[clap(long, default_value = "out/tiles")]
+        cache: PathBuf,
+    },
+    /// Serve a web page exploring the view as a map, rendering tiles into
+    /// the tile cache as the browser asks for them
+    Serve {
+        #[clap(long, default_value = "8080")]
+        port: u16,
+
+        /// Address to listen on, 0.0.0.0 lets other machines in
+        #[clap(long, default_value = "127.0.0.1")]
+        host: String,
+
+        #[clap(long, default_value = "256")]
+        size: u32,
+
+        #[clap(long, default_value = "out/tiles")]
+        cache: PathBuf,
+
+        /// Largest width times height times samples /live can ask for
+        #[clap(long, default_value = "16777216")]
+        max_pixels: u64,
+
+        /// Largest --limit /live can ask for
+        #[clap(long, default_value = "65536")]
+        max_limit: f32,
+
+        #[clap(flatten)]
+        metrics: metrics::MetricsArgs,
+    },
+    /// Answer HTTP requests with renders of json parameter documents, the
+    /// flags given being the defaults they change
+    ServeApi {
+        #[clap(long, default_value = "8081")]
+        port: u16,
+
+        /// Address to listen on, 0.0.0.0 lets other machines in
+        #[clap(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Largest width times height times samples a request can ask for
+        #[clap(long, default_value = "16777216")]
+        max_pixels: u64,
+
+        /// Largest --limit a request can ask for
+        #[clap(long, default_value = "65536")]
+        max_limit: f32,
+
+        /// Finished jobs kept for polling, the oldest are forgotten first
+        #[clap(long, default_value = "100")]
+        keep: usize,
+
+        #[clap(flatten)]
+        metrics: metrics::MetricsArgs,
+    },
+    /// Join one level of the tile cache into a single image, rendering missing tiles
+    Stitch {
+        #[clap(long = "z", default_value = "2", value_parser = tiles::level)]
+        level: u32,
+
+        #[clap(long, default_value = "256")]
+        size: u32,
+
+        #[clap(long, default_value = "out/tiles")]
+        cache: PathBuf,
+
+        /// Largest stitched image, in pixels, held in memory at once
+        #[clap(long, default_value = "268435456")]
+        max_pixels: u64,
+    },
+    /// Render a sequence of frames, --output takes .png/.apng for an animated
+    /// png, .y4m or .rgb for raw video, - to stream y4m to stdout, otherwise a
+    /// directory of frames
+    Animate {
+        #[clap(long, default_value = "60")]
+        frames: usize,
+
+        #[clap(long, default_value = "30")]
+        fps: u16,
+
+        /// Quality of .mp4, .mkv, .mov and .webm output, lower is better
+        #[clap(long, default_value = "18")]
+        crf: u8,
+
+        /// Zoom reached on the last frame, moving exponentially from --zoom
+        #[clap(long)]
+        zoom_to: Option<f32>,
+
+        /// Degrees to turn the view by over the animation, from --rotate.
+        /// Whole turns loop seamlessly.
+        #[clap(long, allow_hyphen_values = true)]
+        spin: Option<f32>,
+
+        /// Move the --julia c along line:A:B, circle:CENTER:RADIUS or
+        /// misiurewicz:K:P:NEAR:RADIUS, a circle around the Misiurewicz point
+        /// of preperiod K and period P nearest NEAR
+        #[clap(long, allow_hyphen_values = true)]
+        julia_path: Option<animation::JuliaPath>,
+
+        /// Play forwards then backwards for a seamless loop
+        #[clap(long)]
+        bounce: bool,
+
+        /// Zoom into the simplest Misiurewicz point in view until the set
+        /// repeats itself, for a zoom that loops forever
+        #[clap(long = "loop", conflicts_with_all = &["zoom-to", "spin", "bounce"])]
+        looping: bool,
+
+        /// Toml file of keyframes, --frames defaults to one past the last
+        #[clap(long)]
+        keys: Option<PathBuf>,
+
+        /// Motion blur, how many degrees of each frame the shutter is open
+        /// for, 180 is the usual film look and 360 blurs frames together
+        #[clap(long, default_value = "0")]
+        shutter: f32,
+
+        /// Renders averaged into each frame while the shutter is open
+        #[clap(long, default_value = "8")]
+        subframes: usize,
+
+        /// Render a --zoom-to zoom from its deepest frame out, shrinking
+        /// each frame into the middle of the next so only the ring around
+        /// it is iterated
+        #[clap(long)]
+        reuse: bool,
+
+        /// Render a quick low resolution version, to check the motion and
+        /// timing before the real thing: frames fit in SIZE, 320x180 by
+        /// default, with one sample and at most 2 subframes
+        #[clap(long, value_name = "SIZE")]
+        preview: Option<Option<sizes::Size>>,
+
+        /// Write each frame's parameters and timing to frame_NNNNN.json, in
+        /// the frame directory or <output>.frames unless DIR is given
+        #[clap(long, value_name = "DIR")]
+        sidecars: Option<Option<PathBuf>>,
+
+        /// Listen on an address like 0.0.0.0:7878 and hand the frames out
+        /// to `fracmd worker` processes instead of rendering them here
+        #[clap(long, conflicts_with = "reuse")]
+        farm: Option<String>,
+
+        /// Times a frame is handed out again after a worker fails it
+        #[clap(long, default_value = "2")]
+        farm_retries: usize,
+
+        /// Only hand frames to workers started with this --token
+        #[clap(long, value_name = "TOKEN", requires = "farm")]
+        farm_token: Option<String>,
+
+        /// Seconds a worker can go without a word before its frame is
+        /// counted lost and handed out again
+        #[clap(long, value_name = "SECONDS", default_value = "30")]
+        farm_timeout: u64,
+
+        #[clap(flatten)]
+        metrics: metrics::MetricsArgs,
+
+        #[clap(flatten)]
+        audio: audio::AudioArgs,
+    },
+    /// Explore the view in the terminal, changing parameters as it redraws,
+    /// and render it at full quality with Enter
+    View,
+    /// Render frames handed out by an `animate --farm` coordinator
+    Worker {
+        /// Address the coordinator listens on, like render-box:7878
+        coordinator: String,
+
+        /// The coordinator's --farm-token
+        #[clap(long)]
+        token: Option<String>,
+
+        #[clap(flatten)]
+        metrics: metrics::MetricsArgs,
+    },
+    /// Print the fractal as colored text at terminal resolution
+    Ansi {
+        #[clap(long, value_enum, default_value = "truecolor")]
+        color: terminal::TextColor,
+
+        /// Defaults to the terminal width
+        #[clap(long)]
+        cols: Option<usize>,
+
+        /// Defaults to the terminal height, minus one for the prompt
+        #[clap(long)]
+        rows: Option<usize>,
+    },
+    /// Save, list or render named presets kept in the user config directory
+    Preset {
+        #[clap(subcommand)]
+        action: PresetAction,
+    },
+    /// List the built in locations --location takes
+    Locations,
+    /// Keep your own locations in the user data directory
+    Bookmark {
+        #[clap(subcommand)]
+        action: BookmarkAction,
+    },
+    /// Render a json list of parameter sets into one labeled contact sheet
+    Sheet {
+        params: PathBuf,
+
+        /// Defaults to roughly square
+        #[clap(long)]
+        columns: Option<usize>,
+
+        #[clap(long, default_value = "320")]
+        cell_width: u32,
+
+        #[clap(long, default_value = "280")]
+        cell_height: u32,
+    },
+    /// Render every parameter set in a json job file, one image each
+    Batch {
+        jobs: PathBuf,
+
+        /// Jobs rendered at the same time, they share --threads
+        #[clap(long, default_value = "1")]
+        parallel: usize,
+
+        /// Also write the summary as json
+        #[clap(long)]
+        report: Option<PathBuf>,
+    },
+    /// Write a static html gallery of the renders under a directory, with
+    /// each one's parameters and the command lines to render it again
+    Gallery {
+        dir: PathBuf,
+
+        /// Longest side of the thumbnails
+        #[clap(long, default_value = "320")]
+        thumb_size: u32,
+
+        /// Heading and title of the page, the directory's name by default
+        #[clap(long)]
+        title: Option<String>,
+    },
+    /// Render job files dropped into a directory as they arrive, moving
+    /// each to done/ or failed/ with a log
+    Queue {
+        dir: PathBuf,
+
+        /// Jobs rendered at the same time, they share --threads
+        #[clap(long, default_value = "1")]
+        parallel: usize,
+
+        /// Seconds between looks at the directory
+        #[clap(long, default_value = "2")]
+        interval: f32,
+
+        /// Render the jobs waiting and stop, rather than watching for more
+        #[clap(long)]
+        once: bool,
+
+        /// Put jobs left in running/ back in line first, for when the queue
+        /// that took them was interrupted rather than still going
+        #[clap(long)]
+        recover: bool,
+    },
+    /// Look at random places near the boundary and render the best ones
+    Explore {
+        /// Candidates to score
+        #[clap(long, default_value = "50")]
+        count: usize,
+
+        /// How many of the best to render at full size
+        #[clap(long, default_value = "8")]
+        keep: usize,
+
+        /// Width of the previews that get scored
+        #[clap(long, default_value = "128")]
+        preview: i32,
+
+        /// Where to write the finds as a batch job file
+        #[clap(long, default_value = "out/explore.json")]
+        jobs_file: PathBuf,
+    },
+    /// Convert between pixels of a render and points of the plane, using
+    /// the image's stored parameters or the given flags
+    Locate {
+        image: Option<PathBuf>,
+
+        /// A pixel to find on the plane, x,y from the top left
+        #[clap(long)]
+        pixel: Vec<locate::Pixel>,
+
+        /// A point of the plane to find in the image
+        #[clap(long, allow_hyphen_values = true, value_parser = coords::parse_f32)]
+        point: Vec<Complex<f32>>,
+    },
+    /// Render the canonical scenes of a suite and compare them with their
+    /// golden images
+    Verify {
+        #[clap(long, default_value = "golden")]
+        suite: PathBuf,
+
+        /// Rewrite the golden images and hashes from this build instead
+        #[clap(long)]
+        update: bool,
+
+        /// How close in dB a render that doesn't match exactly must be
+        #[clap(long, default_value = "40")]
+        min_psnr: f64,
+    },
+    /// Compare two renders, or a render against a fresh one from its
+    /// stored parameters, writing a difference image to --output
+    Diff {
+        a: PathBuf,
+
+        /// Left out to re-render a from its parameters, other flags
+        /// override them
+        b: Option<PathBuf>,
+
+        /// Multiplies the differences in the image, by default the biggest
+        /// is stretched to full brightness
+        #[clap(long)]
+        gain: Option<f32>,
+    },
+    /// Re-render a small preview of a config file every time it's saved,
+    /// flags given as well still win over the file
+    Watch {
+        scene: PathBuf,
+
+        /// Width of the preview, the height keeps the scene's aspect
+        #[clap(long, default_value = "640")]
+        resolution: i32,
+
+        /// Seconds between checks of the file
+        #[clap(long, default_value = "0.5")]
+        interval: f32,
+    },
+    /// Send a command to a render started with --control: progress,
+    /// pause, resume, threads N or stop
+    Control {
+        socket: PathBuf,
+
+        #[clap(required = true)]
+        command: Vec<String>,
+    },
+    /// Answer a few questions, then write a config file and print the
+    /// matching command line
+    Init {
+        /// Skip drawing each view in the terminal before going on
+        #[clap(long)]
+        no_preview: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PresetAction {
+    /// Store the given flags that differ from the defaults
+    Save {
+        #[clap(value_name = "NAME")]
+        preset_name: String,
+    },
+    /// Show every saved preset and its flags
+    List,
+    /// Render with a preset, other flags override it
+    Apply {
+        #[clap(value_name = "NAME")]
+        preset_name: String,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum BookmarkAction {
+    /// Store every render parameter of the given flags under a name
+    Add {
+        #[clap(value_name = "NAME")]
+        bookmark_name: String,
+        /// A few words to show in the list
+        #[clap(long)]
+        note: Option<String>,
+        /// Palette to suggest for recolor and contact sheets
+        #[clap(long, value_name = "FILE")]
+        palette: Option<String>,
+        /// Overwrite a bookmark with the same name
+        #[clap(long)]
+        replace: bool,
+    },
+    /// Show every bookmark's name, date, view and note
+    List,
+    /// Render a bookmark, other flags override it
+    Render {
+        #[clap(value_name = "NAME")]
+        bookmark_name: String,
+    },
+    /// Delete a bookmark
+    Remove {
+        #[clap(value_name = "NAME")]
+        bookmark_name: String,
+    },
+    /// Write every bookmark to a json file
+    Export { file: PathBuf },
+    /// Merge bookmarks from an exported json file
+    Import {
+        file: PathBuf,
+        /// Overwrite bookmarks with the same names
+        #[clap(long)]
+        replace: bool,
+    },
+}
+
+impl Args {
+    // When the image goes to stdout every other message has to stay off it
+    pub fn streaming(&self) -> bool {
+        self.output.as_deref() == Some("-")
+    }
+
+    pub fn log(&self, msg: &str) {
+        if self.streaming() {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    // Like parse_from, but also remembers which flags were given
+    pub fn parse_argv<I: IntoIterator<Item = String>>(argv: I) -> Args {
+        Args::try_parse_argv(argv).unwrap_or_else(|e| e.exit())
+    }
+
+    // parse_argv handing back clap's error rather than exiting with it
+    pub fn try_parse_argv<I: IntoIterator<Item = String>>(argv: I) -> Result<Args, clap::Error> {
+        let matches = Args::command().try_get_matches_from(argv)?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        // Globals given after a subcommand are only marked in its matches
+        let given = |m: &ArgMatches, id: &str| m.value_source(id) == Some(ValueSource::CommandLine);
+        let command = Args::command();
+        let ids = |command: &clap::Command| -> Vec<String> {
+            command
+                .get_arguments()
+                .map(|arg| arg.get_id().to_string())
+                .filter(|id| id != "help" && id != "version")
+                .collect()
+        };
+        let sub = matches.subcommand();
+        args.explicit = ids(&command)
+            .into_iter()
+            .filter(|id| given(&matches, id) || sub.is_some_and(|(_, m)| given(m, id)))
+            .collect();
+        // A subcommand's own flags are only known to its matches
+        if let Some((name, m)) = sub {
+            if let Some(sub_command) = command.find_subcommand(name) {
+                args.explicit
+                    .extend(ids(sub_command).into_iter().filter(|id| given(m, id)));
+            }
+        }
+        // The digits an origin was given with, kept for origin_text. --re
+        // and --im are folded into the origin so nothing else has to look
+        // at them.
+        let raw = |m: &ArgMatches| {
+            given(m, "origin")
+                .then(|| m.get_raw("origin").and_then(|mut v| v.next_back()))
+                .flatten()
+                .and_then(|text| coords::parts(&text.to_string_lossy()).ok())
+        };
+        if let Some((re, im)) = sub.and_then(|(_, m)| raw(m)).or_else(|| raw(&matches)) {
+            args.re.get_or_insert(re);
+            args.im.get_or_insert(im);
+        }
+        if let Some(re) = &args.re {
+            args.origin.re = coords::part(re);
+        }
+        if let Some(im) = &args.im {
+            args.origin.im = coords::part(im);
+        }
+        if let Some(exp) = args.zoom_exp.take() {
+            args.zoom = 10f64.powf(exp) as f32;
+        }
+        if let Some(stretch) = args.stretch.take() {
+            args.transform = coords::Affine::stretch(stretch).then(args.transform);
+        }
+        if let Some(skew) = args.skew.take() {
+            args.transform = coords::Affine::skew(skew).then(args.transform);
+        }
+        Ok(args)
+    }
+
+    pub fn formula(&self) -> Multibrot {
+        Multibrot { power: self.power }
+    }
+
+    // Maps offsets along the image axes onto the plane, --transform then
+    // --rotate
+    pub fn view(&self) -> coords::Affine {
+        self.transform.then(coords::Affine::rotation(self.rotate))
+    }
+
+    pub fn given(&self, id: &str) -> bool {
+        self.explicit.iter().any(|e| e == id)
+    }
+
+    // What's wrong with these settings, one line each, none when they're
+    // fine to render
+    pub fn problems(&self) -> Vec<String> {
+        validate::check(self)
+            .iter()
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    // Parses stored (flag, value) pairs, with raw extra flags taking precedence
+    pub fn from_params(params: &[(String, String)], overrides: &[String]) -> Args {
+        let mut argv = vec![String::from("fracmd")];
+        argv.extend(params.iter().map(|(k, v)| format!("--{}={}", k, v)));
+        argv.extend(overrides.iter().cloned());
+        Args::parse_argv(argv)
+    }
+
+    // from_params handing back the first line of clap's complaint rather
+    // than exiting with it, for parameters that came from a file
+    pub fn try_from_params(
+        params: &[(String, String)],
+        overrides: &[String],
+    ) -> Result<Args, String> {
+        let mut argv = vec![String::from("fracmd")];
+        argv.extend(params.iter().map(|(k, v)| format!("--{}={}", k, v)));
+        argv.extend(overrides.iter().cloned());
+        Args::try_parse_argv(argv).map_err(|e| {
+            let message = e.to_string();
+            let line = message.lines().next().unwrap_or_default();
+            line.trim_start_matches("error: ").to_string()
+        })
+    }
+
+    // try_from_params for pairs read back from a file, refusing any that
+    // known_params would drop and keeping the name to one file name
+    pub fn try_from_stored(params: &[(String, String)]) -> Result<Args, String> {
+        match params
+            .iter()
+            .find(|(k, _)| !PARAM_KEYS.contains(&k.as_str()))
+        {
+            Some((key, _)) => Err(format!("{} isn't a render parameter", key)),
+            None => Args::try_from_params(&Args::known_params(params.to_vec()).0, &[]),
+        }
+    }
+
+    // Whether an output needs the per pixel data pass after the render
+    pub fn wants_data(&self) -> bool {
+        self.dump_data.is_some()
+            || self.svg.is_some()
+            || self.mesh.is_some()
+            || (self.aovs && matches!(self.format, Format::Exr))
+    }
+
+    // --output for commands that aren't templated
+    pub fn output_or(&self, default: &str) -> PathBuf {
+        PathBuf::from(self.output.as_deref().unwrap_or(default))
+    }
+
+    // The origin as coords::format has it, but with each part's digits as
+    // given while the origin is still where they put it
+    pub fn origin_text(&self) -> String {
+        let part = |given: &Option<String>, v: f32| match given {
+            Some(text) if coords::part(text).to_bits() == v.to_bits() => text.clone(),
+            _ => v.to_string(),
+        };
+        let re = part(&self.re, self.origin.re);
+        let im = part(&self.im, self.origin.im);
+        match im.strip_prefix('-') {
+            Some(im) => format!("{}-{}i", re, im),
+            None => format!("{}+{}i", re, im),
+        }
+    }
+
+    // Long flag names paired with values clap can parse back
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("name", self.name.clone()),
+            ("threads", self.threads.to_string()),
+            ("origin", self.origin_text()),
+            ("zoom", self.zoom.to_string()),
+            ("rotate", self.rotate.to_string()),
+            ("transform", self.transform.to_string()),
+            ("samples", self.samples.to_string()),
+            ("sampled", self.sampled.to_string()),
+            ("limit", self.limit.to_string()),
+            ("cexp", self.cexp.to_string()),
+            ("power", self.power.to_string()),
+            ("set-color", self.set_color.to_string()),
+            ("colorspace", value_name(self.colorspace)),
+            ("projection", value_name(self.projection)),
+        ];
+        if self.fractal != "multibrot" {
+            params.push(("fractal", self.fractal.clone()));
+        }
+        if self.coloring != "hue" {
+            params.push(("coloring", self.coloring.clone()));
+        }
+        if let Some(julia) = self.julia {
+            params.push(("julia", coords::format(julia)));
+        }
+        if let Some(seed) = self.seed {
+            params.push(("seed", seed.to_string()));
+        }
+        if let Some(bail) = self.bail {
+            params.push(("bail", bail.to_string()));
+        }
+        if let Some(norm) = self.bail_norm {
+            params.push(("bail-norm", value_name(norm)));
+        }
+        if let Some(eps) = self.converge {
+            params.push(("converge", eps.to_string()));
+        }
+        if self.sampler != sampler::SamplerKind::Random {
+            params.push(("sampler", value_name(self.sampler)));
+        }
+        if self.sampler == sampler::SamplerKind::Adaptive {
+            params.push(("adaptive-min", self.adaptive_min.to_string()));
+            params.push(("adaptive-threshold", self.adaptive_threshold.to_string()));
+        }
+        params
+    }
+
+    // Stored (flag, value) pairs kept to the flags params() can list, then
+    // the keys of the rest. Files someone else wrote go through this so they
+    // can't name --exec, --plugin or --output, and their --name can't lead
+    // the default template's out/{name}_... out of out/.
+    pub fn known_params(params: Vec<(String, String)>) -> (Vec<(String, String)>, Vec<String>) {
+        let (known, rest): (Vec<_>, Vec<_>) = params
+            .into_iter()
+            .partition(|(k, _)| PARAM_KEYS.contains(&k.as_str()));
+        let known = known
+            .into_iter()
+            .map(|(k, v)| match k.as_str() {
+                "name" => (k, v.replace(['/', '\\'], "_").replace("..", "_")),
+                _ => (k, v),
+            })
+            .collect();
+        (known, rest.into_iter().map(|(k, _)| k).collect())
+    }
+}
+
+// Every flag Args::params() can list
+pub const PARAM_KEYS: &[&str] = &[
+    "width",
+    "height",
+    "name",
+    "threads",
+    "origin",
+    "zoom",
+    "rotate",
+    "transform",
+    "samples",
+    "sampled",
+    "limit",
+    "cexp",
+    "power",
+    "set-color",
+    "colorspace",
+    "projection",
+    "fractal",
+    "coloring",
+    "julia",
+    "seed",
+    "bail",
+    "bail-norm",
+    "converge",
+    "sampler",
+    "adaptive-min",
+    "adaptive-threshold",
+];
+
+// Resolves an accumulated sum of squared sRGB colors into an output pixel
+fn resolve<P: OutputPixel>(out: RgbaF) -> P {
+    P::from_color(out.to_RGB().to_arr().map(f32::sqrt))
+}
+
+fn to_pixel(out: RgbaF) -> Rgba<u16> {
+    resolve(out)
+}
+
+// The flag spelling of a clap value enum
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+fn abs(z: Complex<f32>) -> f32 {
+    z.re * z.re + z.im * z.im
+}
+
+fn normalize_coords(x: i32, y: i32, w: i32, h: i32, z: f32) -> Complex<f32> {
+    let nx = 2.0 * (x as f32 / w as f32) - 1.0;
+    let ny = 2.0 * (y as f32 / h as f32) - 1.0;
+    Complex::new(nx / z, ny * (h as f32 / w as f32) / z)
+}
+
+// What a Renderer iterates and how it colors the result. Shared rather
+// than owned, so cloning one for every tile or frame costs nothing.
+#[derive(Clone)]
+pub struct Functs {
+    pub fractal: Arc<dyn Fractal>,
+    pub coloring: Arc<dyn Coloring>,
+}
+
+pub struct Renderer {
+    args: Args,
+    width: i32,
+    height: i32,
+    functs: Functs,
+    // --set-color moved into the working color space
+    set_color: RgbaF,
+    view: coords::Affine,
+    backend: Arc<dyn RenderBackend>,
+    sampler: Arc<dyn Sampler>,
+    bailout: Bailout,
+}
+
+impl Renderer {
+    pub fn new(args: Args, functs: Functs) -> Renderer {
+        Renderer {
+            bailout: Bailout::resolve(&args, functs.fractal.as_ref()),
+            width: args.width,
+            height: args.height,
+            functs,
+            set_color: args
+                .set_color
+                .transform_encoded(&args.colorspace.matrix_from_srgb()),
+            view: args.view(),
+            backend: backend::select(args.backend),
+            sampler: sampler::select(&args),
+            args,
+        }
+    }
+
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder::default()
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    pub fn functs(&self) -> &Functs {
+        &self.functs
+    }
+
+    // Renders with these from now on, keeping the fractal and coloring,
+    // and the backend and sampler unless they ask for others. Unlike the
+    // builder it doesn't check them.
+    pub fn set_args(&mut self, args: Args) {
+        let backend = (args.backend == self.args.backend).then(|| self.backend.clone());
+        let same_sampler = (args.sampler, args.adaptive_min, args.adaptive_threshold)
+            == (
+                self.args.sampler,
+                self.args.adaptive_min,
+                self.args.adaptive_threshold,
+            );
+        let sampler = same_sampler.then(|| self.sampler.clone());
+        *self = Renderer::new(args, self.functs.clone());
+        if let Some(backend) = backend {
+            self.backend = backend;
+        }
+        if let Some(sampler) = sampler {
+            self.sampler = sampler;
+        }
+    }
+
+    pub fn set_functs(&mut self, functs: Functs) {
+        self.bailout = Bailout::resolve(&self.args, functs.fractal.as_ref());
+        self.functs = functs;
+    }
+
+    // When orbits stop, from the flags and the fractal
+    pub fn bailout(&self) -> Bailout {
+        self.bailout
+    }
+
+    pub fn backend(&self) -> &dyn RenderBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn set_backend(&mut self, backend: Arc<dyn RenderBackend>) {
+        self.backend = backend;
+    }
+
+    pub fn sampler(&self) -> &dyn Sampler {
+        self.sampler.as_ref()
+    }
+
+    pub fn set_sampler(&mut self, sampler: Arc<dyn Sampler>) {
+        self.sampler = sampler;
+    }
+
+    // Width and height of what render and render_data return
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn iterate(&self, c: Complex<f32>) -> PixelData {
+        let fractal = &self.functs.fractal;
+        let c = fractal.map(c);
+        // A Julia set starts the orbit at the point and keeps c fixed
+        let (mut z, c) = match self.args.julia {
+            Some(julia) => (c, julia),
+            None => (fractal.start(c), c),
+        };
+        let mut dz = Complex::new(1.0, 0.0);
+        let mut i = 0.0;
+        let mut s = 0.0;
+        let mut trap = abs(z).sqrt();
+        let bailout = self.bailout;
+        let mut converged = None;
+        let mut before = f32::NAN;
+        while bailout.inside(z) && i < self.args.limit {
+            dz = match self.args.julia {
+                Some(_) => fractal.zderiv(z, dz),
+                None => fractal.deriv(z, dz, c),
+            };
+            let last = z;
+            z = fractal.step(z, c);
+            i += 1.0;
+            s += (-(abs(z))).exp();
+            trap = trap.min(abs(z).sqrt());
+            if bailout.converge.is_some() {
+                let step = Bailout::step(last, z);
+                if bailout.converged(step) {
+                    converged = Some((before, step));
+                    break;
+                }
+                before = step;
+            }
+        }
+
+        self.escaped(i, s, z, dz, trap, converged)
+    }
+
+    // What iterate found once the loop has ended. Orbits that converged
+    // bring the squared size of their last two steps, and get the smooth
+    // convergence count; the smooth count and distance estimate are only
+    // for the rest that escaped.
+    fn escaped(
+        &self,
+        i: f32,
+        s: f32,
+        z: Complex<f32>,
+        dz: Complex<f32>,
+        trap: f32,
+        converged: Option<(f32, f32)>,
+    ) -> PixelData {
+        let mut p = PixelData {
+            iter: i,
+            s,
+            smooth: i,
+            z,
+            de: 0.0,
+            trap,
+            converged: 0.0,
+        };
+        if let Some((before, last)) = converged {
+            p.smooth = self.bailout.smooth(i, before, last);
+            p.converged = 1.0;
+        } else if i < self.args.limit {
+            let r = abs(z).sqrt();
+            let power = self.functs.fractal.degree();
+            p.smooth = i + 1.0 - r.ln().log2() / power.log2();
+            p.de = (1.0 / power) * r * r.ln() / abs(dz).sqrt();
+        }
+        p
+    }
+
+    // The points iterate's loop passes through from c, starting one
+    // included, up to the one that escapes or the limit
+    pub fn orbit(&self, c: Complex<f32>) -> Vec<Complex<f32>> {
+        let fractal = &self.functs.fractal;
+        let c = fractal.map(c);
+        let (mut z, c) = match self.args.julia {
+            Some(julia) => (c, julia),
+            None => (fractal.start(c), c),
+        };
+        let mut points = vec![z];
+        while self.bailout.inside(z) && (points.len() as f32) <= self.args.limit {
+            let last = z;
+            z = fractal.step(z, c);
+            points.push(z);
+            if self.bailout.converged(Bailout::step(last, z)) {
+                break;
+            }
+        }
+        points
+    }
+
+    // Offset from the view center of pixel x, y moved by a jitter given
+    // in pixels
+    fn offset(&self, x: i32, y: i32, jitter: (f32, f32)) -> Complex<f32> {
+        match self.args.projection {
+            expmap::Projection::Plane => {
+                let d: Complex<f32> =
+                    normalize_coords(1, 1, self.width, self.height, self.args.zoom)
+                        - normalize_coords(0, 0, self.width, self.height, self.args.zoom);
+                let mut c = normalize_coords(x, y, self.width, self.height, self.args.zoom);
+                c.re += d.re * jitter.0;
+                c.im += d.im * jitter.1;
+                c
+            }
+            expmap::Projection::Expmap => expmap::offset(
+                x as f32 + jitter.0,
+                y as f32 + jitter.1,
+                self.width,
+                self.args.zoom,
+            ),
+        }
+    }
+
+    pub fn pixel(&self, i: i32) -> Rgba<u16> {
+        self.pixel_as(i)
+    }
+
+    pub fn pixel_as<P: OutputPixel>(&self, i: i32) -> P {
+        self.color_pixel(&self.sample_pixels(&[i]))
+    }
+
+    // Every sample of these pixels, placed by the sampler and iterated by
+    // the backend
+    fn sample_pixels(&self, pixels: &[i32]) -> Vec<PixelData> {
+        self.sampler
+            .sample(self, pixels, &|points| self.backend.iterate(self, points))
+    }
+
+    // The coloring stage for one pixel, from what iterating each of its
+    // samples found
+    pub fn color_pixel<P: OutputPixel>(&self, records: &[PixelData]) -> P {
+        let mut out = RgbaF::new(0.0);
+        for p in records {
+            out = out + self.shade(p);
+        }
+        resolve(out / records.len() as f32)
+    }
+
+    // Where pixel i's jitter comes from
+    pub fn rng(&self, i: i32) -> PixelRng {
+        match self.args.seed {
+            // Seeded per pixel so the result doesn't depend on which thread
+            // got there first
+            Some(seed) => PixelRng::new(seed, i),
+            #[cfg(feature = "cli")]
+            None => PixelRng::new(sampler::unseeded(), i),
+            // Nothing to draw a seed from in a browser, so renders there
+            // jitter the same way every time
+            #[cfg(not(feature = "cli"))]
+            None => PixelRng::new(0, i),
+        }
+    }
+
+    // The point of the plane a sample of pixel i at this jitter looks at,
+    // the jitter going from -1 to 1 across --sampled pixels' worth
+    pub fn jittered(&self, i: i32, jitter: (f32, f32)) -> Complex<f32> {
+        let jitter = (jitter.0 / self.args.sampled, jitter.1 / self.args.sampled);
+        self.to_plane(self.offset(i / self.height, i % self.height, jitter))
+    }
+
+    // A pixel index as the samplers take them, x * height + y
+    fn index(&self, x: u32, y: u32) -> i32 {
+        x as i32 * self.height + y as i32
+    }
+
+    // Point of the plane at an offset from the view center, offsets being
+    // measured along the image axes
+    pub fn to_plane(&self, offset: Complex<f32>) -> Complex<f32> {
+        self.view.apply(offset) + self.args.origin
+    }
+
+    // Squared sRGB color of a single point of the plane, independent of the
+    // pixel grid so other samplers can use it
+    pub fn sample_color(&self, c: Complex<f32>) -> RgbaF {
+        self.shade(&self.iterate(c))
+    }
+
+    // Squared sRGB color of what iterating a point found
+    fn shade(&self, p: &PixelData) -> RgbaF {
+        if p.iter < self.args.limit {
+            let color = self.functs.coloring.color(p, self.args.limit).to_sRGB();
+            color * color
+        } else {
+            self.set_color * self.set_color
+        }
+    }
+
+    pub fn render(&self) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+        self.render_with(|_| ())
+    }
+
+    // Like render, in another pixel type
+    pub fn render_as<P: OutputPixel>(&self) -> Buffer<P> {
+        self.render_blank(|_| true)
+    }
+
+    // Renders tile by tile in parallel, calling hook after each one finishes
+    pub fn render_with<F>(&self, hook: F) -> ImageBuffer<Rgba<u16>, Vec<u16>>
+    where
+        F: Fn(Progress) + Sync,
+    {
+        self.render_blank(|progress| {
+            hook(progress);
+            true
+        })
+    }
+
+    fn render_blank<P: OutputPixel, F>(&self, hook: F) -> Buffer<P>
+    where
+        F: Fn(Progress<P>) -> bool + Sync,
+    {
+        let tiles = scheduler::tiles(self.width as u32, self.height as u32, TILE_SIZE);
+        let image = ImageBuffer::new(self.width as u32, self.height as u32);
+        self.render_while(image, vec![false; tiles.len()], hook).0
+    }
+
+    // Like render_with, but skips the tiles already marked done in image.
+    // Stops starting new tiles once interrupted, returning which finished.
+    pub fn render_from<P: OutputPixel, F>(
+        &self,
+        image: Buffer<P>,
+        done: Vec<bool>,
+        hook: F,
+    ) -> (Buffer<P>, Vec<bool>)
+    where
+        F: Fn(Progress<P>) + Sync,
+    {
+        self.render_while(image, done, |progress| {
+            hook(progress);
+            true
+        })
+    }
+
+    // Like render_from, but no new tiles start either once hook has
+    // returned false
+    pub fn render_while<P: OutputPixel, F>(
+        &self,
+        image: Buffer<P>,
+        done: Vec<bool>,
+        hook: F,
+    ) -> (Buffer<P>, Vec<bool>)
+    where
+        F: Fn(Progress<P>) -> bool + Sync,
+    {
+        let tiles = scheduler::tiles(self.width as u32, self.height as u32, TILE_SIZE);
+        let finished: Vec<AtomicBool> = done.iter().map(|&d| AtomicBool::new(d)).collect();
+        let shared = Shared::resume(image, done.iter().filter(|&&d| d).count());
+        let stopped = AtomicBool::new(false);
+        tiles.par_iter().enumerate().for_each(|(i, &tile)| {
+            if done[i] || stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let Some(_turn) = scheduler::turn() else {
+                return;
+            };
+            // Browsers have no clock to time tiles with
+            #[cfg(feature = "cli")]
+            let started = std::time::Instant::now();
+            let pixels = self.render_tile(tile);
+            #[cfg(feature = "cli")]
+            metrics::tile(pixels.len() as u64, started.elapsed());
+            let done = shared.finish(tile, &pixels);
+            finished[i].store(true, Ordering::SeqCst);
+            let go_on = hook(Progress {
+                tile,
+                done,
+                total: tiles.len(),
+                image: &shared.image,
+            });
+            if !go_on {
+                stopped.store(true, Ordering::SeqCst);
+            }
+        });
+        (
+            shared.image.into_inner().unwrap(),
+            finished.into_iter().map(AtomicBool::into_inner).collect(),
+        )
+    }
+
+    // One tile's pixels in row-major order, iterated by the backend then
+    // colored
+    pub fn render_tile<P: OutputPixel>(&self, tile: Tile) -> Vec<P> {
+        self.color_tile(&self.iterate_tile(tile))
+    }
+
+    // The iteration stage for a tile, every sample of every pixel
+    pub fn iterate_tile(&self, tile: Tile) -> Vec<PixelData> {
+        let mut pixels = Vec::with_capacity((tile.w * tile.h) as usize);
+        for y in tile.y..tile.y + tile.h {
+            for x in tile.x..tile.x + tile.w {
+                pixels.push(self.index(x, y));
+            }
+        }
+        self.sample_pixels(&pixels)
+    }
+
+    // The coloring stage for what iterate_tile found
+    pub fn color_tile<P: OutputPixel>(&self, records: &[PixelData]) -> Vec<P> {
+        records
+            .chunks(self.args.samples)
+            .map(|pixel| self.color_pixel(pixel))
+            .collect()
+    }
+
+    // The iteration stage for the whole image, kept so it can be colored
+    // again and again without iterating
+    pub fn iterate_image(&self) -> Samples {
+        let (width, height) = self.dimensions();
+        let samples = self.args.samples;
+        let tiles = scheduler::tiles(width, height, TILE_SIZE);
+        let done: Vec<(Tile, Vec<PixelData>)> = tiles
+            .par_iter()
+            .map(|&tile| (tile, self.iterate_tile(tile)))
+            .collect();
+        let mut data = vec![PixelData::default(); (width * height) as usize * samples];
+        for (tile, records) in done {
+            for (row, records) in records.chunks(tile.w as usize * samples).enumerate() {
+                let at = ((tile.y as usize + row) * width as usize + tile.x as usize) * samples;
+                data[at..at + records.len()].copy_from_slice(records);
+            }
+        }
+        Samples {
+            width,
+            height,
+            samples,
+            data,
+        }
+    }
+
+    // The coloring stage for the whole image, with this renderer's coloring
+    pub fn color_image<P: OutputPixel>(&self, samples: &Samples) -> Buffer<P> {
+        let per = samples.samples;
+        let pixels: Vec<P> = (0..(samples.width * samples.height) as usize)
+            .into_par_iter()
+            .map(|i| self.color_pixel(&samples.data[i * per..(i + 1) * per]))
+            .collect();
+        ImageBuffer::from_fn(samples.width, samples.height, |x, y| {
+            pixels[(y * samples.width + x) as usize]
+        })
+    }
+
+    // One unjittered sample per pixel, in row-major order
+    pub fn render_data(&self) -> Vec<PixelData> {
+        (0..(self.width * self.height))
+            .into_par_iter()
+            .map(|i| {
+                let c = self.offset(i % self.width, i / self.width, (0.0, 0.0));
+                self.iterate(self.to_plane(c))
+            })
+            .collect()
+    }
+}
+
+// A Renderer from Args, flags given by name or both, checked the way the
+// command line checks them. The fractal and coloring are the ones the
+// flags name unless others are given.
+#[derive(Default)]
+pub struct RendererBuilder {
+    args: Option<Args>,
+    flags: Vec<String>,
+    fractal: Option<Arc<dyn Fractal>>,
+    coloring: Option<Arc<dyn Coloring>>,
+}
+
+impl RendererBuilder {
+    // What the flags apply over, the defaults if not given
+    pub fn args(mut self, args: Args) -> RendererBuilder {
+        self.args = Some(args);
+        self
+    }
+
+    // A flag by its long name, an empty value being a switch that's on:
+    // .flag("zoom", "40"), .flag("autocenter", "")
+    pub fn flag(mut self, name: &str, value: &str) -> RendererBuilder {
+        self.flags.push(match value.is_empty() {
+            true => format!("--{}", name),
+            false => format!("--{}={}", name, value),
+        });
+        self
+    }
+
+    pub fn fractal(mut self, fractal: Arc<dyn Fractal>) -> RendererBuilder {
+        self.fractal = Some(fractal);
+        self
+    }
+
+    pub fn coloring(mut self, coloring: Arc<dyn Coloring>) -> RendererBuilder {
+        self.coloring = Some(coloring);
+        self
+    }
+
+    pub fn functs(self, functs: Functs) -> RendererBuilder {
+        self.fractal(functs.fractal).coloring(functs.coloring)
+    }
+
+    pub fn build(self) -> Result<Renderer, FracError> {
+        let mut argv = vec![String::from("fracmd")];
+        if let Some(args) = &self.args {
+            argv.extend(
+                config::settings(args)
+                    .iter()
+                    .map(|(k, v)| config::flag(k, v)),
+            );
+        }
+        argv.extend(self.flags);
+        let args = match (self.args, argv.len()) {
+            (Some(args), 1) => args,
+            _ => Args::try_parse_argv(argv).map_err(|e| {
+                let message = e.to_string();
+                let line = message.lines().next().unwrap_or_default();
+                FracError::Flag(line.trim_start_matches("error: ").to_string())
+            })?,
+        };
+        let problems = validate::check(&args);
+        if !problems.is_empty() {
+            return Err(FracError::Invalid(String::new(), problems));
+        }
+        let defaults = default_functs(&args);
+        let functs = Functs {
+            fractal: self.fractal.unwrap_or(defaults.fractal),
+            coloring: self.coloring.unwrap_or(defaults.coloring),
+        };
+        Ok(Renderer::new(args, functs))
+    }
+}
+
+// The --fractal and --coloring args asks for, z^power + c colored by hue
+// unless a plugin's are named. Names nothing registered are left to
+// validate::check, here they fall back to the built-in ones.
+pub fn default_functs(args: &Args) -> Functs {
+    Functs {
+        fractal: plugin::fractal(&args.fractal).unwrap_or_else(|| Arc::new(args.formula())),
+        coloring: plugin::coloring(&args.coloring)
+            .unwrap_or_else(|| Arc::new(Hue { cexp: args.cexp })),
+    }
+}
+
+// A file in the temp dir holding bytes, removed once dropped. name keeps
+// tests running at once apart.
+#[cfg(test)]
+pub(crate) struct TempFile(pub PathBuf);
+
+#[cfg(test)]
+impl TempFile {
+    pub fn new(name: &str, bytes: impl AsRef<[u8]>) -> TempFile {
+        let file = format!("fracmd-{}-{}", std::process::id(), name);
+        let path = std::env::temp_dir().join(file);
+        std::fs::write(&path, bytes).unwrap();
+        TempFile(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // z halves each step, so from 1 the nth step is 2^-n long
+    struct Halving;
+
+    impl Fractal for Halving {
+        fn step(&self, z: Complex<f32>, _c: Complex<f32>) -> Complex<f32> {
+            z / 2.0
+        }
+
+        fn deriv(&self, _z: Complex<f32>, dz: Complex<f32>, _c: Complex<f32>) -> Complex<f32> {
+            dz / 2.0
+        }
+
+        fn zderiv(&self, _z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+            dz / 2.0
+        }
+    }
+
+    #[test]
+    fn converging_orbits_stop_at_the_threshold() {
+        // 2^-7 is the first step under 0.01 and 2^-10 the first under 0.001
+        for (eps, steps) in [("0.01", 7.0), ("0.001", 10.0)] {
+            let args =
+                Args::parse_argv(["fracmd", &format!("--converge={}", eps)].map(String::from));
+            let functs = Functs {
+                fractal: Arc::new(Halving),
+                ..default_functs(&args)
+            };
+            let p = Renderer::new(args, functs).iterate(Complex::new(1.0, 0.0));
+            assert_eq!((p.iter, p.converged), (steps, 1.0), "{}", eps);
+            assert!(p.smooth > steps - 1.0 && p.smooth <= steps, "{}", p.smooth);
+        }
+    }
+
+    #[test]
+    fn param_keys_cover_params() {
+        let args = Args::parse_argv(
+            [
+                "fracmd",
+                "--fractal=burning-ship",
+                "--coloring=smooth",
+                "--julia=0.3+0.5i",
+                "--seed=7",
+                "--bail=4",
+                "--bail-norm=max",
+                "--converge=0.001",
+                "--sampler=adaptive",
+            ]
+            .into_iter()
+            .map(String::from),
+        );
+        for (key, _) in args.params() {
+            assert!(PARAM_KEYS.contains(&key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn known_params_drop_other_flags() {
+        let params = vec![
+            (String::from("zoom"), String::from("2")),
+            (String::from("exec"), String::from("touch /tmp/pwned")),
+            (String::from("plugin"), String::from("evil.so")),
+        ];
+        let (known, rest) = Args::known_params(params);
+        assert_eq!(known, vec![(String::from("zoom"), String::from("2"))]);
+        assert_eq!(rest, vec!["exec", "plugin"]);
+    }
+
+    #[test]
+    fn stored_names_stay_one_file_name() {
+        let params = vec![(String::from("name"), String::from("../../.ssh/x"))];
+        let (known, _) = Args::known_params(params.clone());
+        assert_eq!(known[0].1, "____.ssh_x");
+        let args = Args::try_from_stored(&params).unwrap();
+        let path = template::expand(template::DEFAULT, &args).unwrap();
+        assert!(path.starts_with("out/____.ssh_x_"), "{}", path);
+    }
+
+    #[test]
+    fn stored_params_refuse_other_flags() {
+        let params = vec![
+            (String::from("zoom"), String::from("2")),
+            (String::from("exec"), String::from("touch /tmp/pwned")),
+        ];
+        let err = Args::try_from_stored(&params).unwrap_err();
+        assert_eq!(err, "exec isn't a render parameter");
+        assert_eq!(Args::try_from_stored(&params[..1]).unwrap().zoom, 2.0);
+    }
+}
@@ -0,0 +1,114 @@
+// fracmd
+// Converting between pixels and the complex plane
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Pixels count from the top left corner, and a whole number is exactly
+// where the renderer puts that pixel's unjittered sample, the same mapping
+// as normalize_coords then the view transform.
+
+use err_derive::Error;
+use num::complex::Complex;
+use std::str::FromStr;
+
+use crate::{coords, Args};
+
+#[derive(Debug, Error)]
+pub enum LocateError {
+    #[error(display = "expected a pixel like 640,360 but got {:?}", _0)]
+    BadPixel(String),
+    #[error(display = "the view transform is singular, no pixel maps back")]
+    Singular,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Pixel {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl FromStr for Pixel {
+    type Err = LocateError;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bad = || LocateError::BadPixel(string.to_string());
+        let (x, y) = string
+            .trim_matches(['(', ')'])
+            .split_once(',')
+            .ok_or_else(bad)?;
+        Ok(Pixel {
+            x: x.trim().parse().map_err(|_| bad())?,
+            y: y.trim().parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+impl std::fmt::Display for Pixel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+pub fn to_plane(args: &Args, pixel: Pixel) -> Complex<f32> {
+    let (w, h, z) = (args.width as f64, args.height as f64, args.zoom as f64);
+    let offset = Complex::new(
+        (2.0 * pixel.x / w - 1.0) / z,
+        (2.0 * pixel.y / h - 1.0) * (h / w) / z,
+    );
+    args.view()
+        .apply(Complex::new(offset.re as f32, offset.im as f32))
+        + args.origin
+}
+
+pub fn to_pixel(args: &Args, c: Complex<f32>) -> Result<Pixel, LocateError> {
+    let offset = args
+        .view()
+        .inverse()
+        .ok_or(LocateError::Singular)?
+        .apply(c - args.origin);
+    let (w, h, z) = (args.width as f64, args.height as f64, args.zoom as f64);
+    Ok(Pixel {
+        x: (offset.re as f64 * z + 1.0) * w / 2.0,
+        y: (offset.im as f64 * z * w / h + 1.0) * h / 2.0,
+    })
+}
+
+pub fn inside(args: &Args, pixel: Pixel) -> bool {
+    (0.0..args.width as f64).contains(&pixel.x) && (0.0..args.height as f64).contains(&pixel.y)
+}
+
+// One line per conversion, "640,360 -> -0.75+0.1i"
+pub fn describe(args: &Args, pixels: &[Pixel], points: &[Complex<f32>]) -> Vec<String> {
+    let mut lines: Vec<String> = pixels
+        .iter()
+        .map(|&p| format!("{} -> {}", p, coords::format(to_plane(args, p))))
+        .collect();
+    for &c in points {
+        lines.push(match to_pixel(args, c) {
+            Ok(p) => format!(
+                "{} -> {:.2},{:.2}{}",
+                coords::format(c),
+                p.x,
+                p.y,
+                if inside(args, p) {
+                    ""
+                } else {
+                    " (outside the frame)"
+                }
+            ),
+            Err(e) => format!("{} -> {}", coords::format(c), e),
+        });
+    }
+    lines
+}
@@ -0,0 +1,194 @@
+// fracmd
+// Frame manifests for resuming `animate` into a frame directory
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The manifest is manifest.json next to the frames:
+//
+//   { "frames": [ { "frame": 0, "status": "done",
+//       "hash": "84f0c2d1e3a5b697", "params": { "zoom": "0.7", ... } } ] }
+//
+// A frame is done once its png is written, and the hash is of its pixels,
+// the same hash `fracmd verify` uses. A frame is only skipped when its
+// parameters are the ones it would be rendered with now and the png on
+// disk still has that hash, so changed frames and broken or missing files
+// are rendered again and everything else is left alone.
+
+use err_derive::Error;
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{scheduler::Image16, verify, Args};
+
+pub const FILE: &str = "manifest.json";
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "{:?} is not a frame manifest", _0)]
+    BadFile(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    params: Vec<(String, String)>,
+    // Set once the frame is written
+    hash: Option<u64>,
+}
+
+pub struct Manifest {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+}
+
+// Everything that changes a frame's pixels, which leaves out --threads,
+// sorted like the json object keeps them
+fn params(args: &Args) -> Vec<(String, String)> {
+    let mut params: Vec<(String, String)> = args
+        .params()
+        .into_iter()
+        .filter(|(k, _)| *k != "threads")
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+    params.sort();
+    params
+}
+
+pub fn frame_path(dir: &Path, frame: usize) -> PathBuf {
+    dir.join(format!("frame_{:05}.png", frame))
+}
+
+impl Manifest {
+    // A directory without a manifest starts an empty one
+    pub fn load(dir: &Path) -> Result<Manifest, ManifestError> {
+        let path = dir.join(FILE);
+        let mut manifest = Manifest {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+        };
+        if !path.exists() {
+            return Ok(manifest);
+        }
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        let bad = || ManifestError::BadFile(path.clone());
+        for entry in json
+            .get("frames")
+            .and_then(Value::as_array)
+            .ok_or_else(bad)?
+        {
+            let frame = entry.get("frame").and_then(Value::as_u64).ok_or_else(bad)? as usize;
+            let params = entry
+                .get("params")
+                .and_then(Value::as_object)
+                .ok_or_else(bad)?
+                .iter()
+                .map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(bad)?;
+            let done = entry.get("status").and_then(Value::as_str) == Some("done");
+            let hash = entry
+                .get("hash")
+                .and_then(Value::as_str)
+                .and_then(|h| u64::from_str_radix(h, 16).ok())
+                .filter(|_| done);
+            if manifest.entries.len() <= frame {
+                manifest.entries.resize(
+                    frame + 1,
+                    Entry {
+                        params: Vec::new(),
+                        hash: None,
+                    },
+                );
+            }
+            manifest.entries[frame] = Entry { params, hash };
+        }
+        Ok(manifest)
+    }
+
+    // Lists every frame of the animation, keeping what's done of frames
+    // whose parameters haven't changed
+    pub fn plan(&mut self, frames: &[Args]) {
+        self.entries = frames
+            .iter()
+            .enumerate()
+            .map(|(i, args)| {
+                let params = params(args);
+                let hash = self
+                    .entries
+                    .get(i)
+                    .filter(|e| e.params == params)
+                    .and_then(|e| e.hash);
+                Entry { params, hash }
+            })
+            .collect();
+    }
+
+    // The frame on disk, if it's the one the manifest says is done
+    pub fn done(&self, frame: usize) -> Option<Image16> {
+        let hash = self.entries.get(frame)?.hash?;
+        let image = image::open(frame_path(&self.dir, frame))
+            .ok()?
+            .into_rgba16();
+        (verify::hash(&image) == hash).then_some(image)
+    }
+
+    pub fn finish(&mut self, frame: usize, image: &Image16) {
+        if let Some(entry) = self.entries.get_mut(frame) {
+            entry.hash = Some(verify::hash(image));
+        }
+    }
+
+    pub fn count_done(&self) -> usize {
+        self.entries.iter().filter(|e| e.hash.is_some()).count()
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(FILE)
+    }
+
+    // Written beside the old one and renamed over it, so an interrupted
+    // save never loses the manifest
+    pub fn save(&self) -> Result<(), ManifestError> {
+        let frames: Vec<Value> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let params: Map<String, Value> = entry
+                    .params
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                    .collect();
+                let mut json = json!({ "frame": i, "status": "pending", "params": params });
+                if let Some(hash) = entry.hash {
+                    json["status"] = json!("done");
+                    json["hash"] = json!(format!("{:016x}", hash));
+                }
+                json
+            })
+            .collect();
+        fs::create_dir_all(&self.dir)?;
+        let partial = self.dir.join(format!("{}.partial", FILE));
+        let text = serde_json::to_string_pretty(&json!({ "frames": frames }))?;
+        fs::write(&partial, text + "\n")?;
+        Ok(fs::rename(partial, self.path())?)
+    }
+}
@@ -0,0 +1,164 @@
+// fracmd
+// Heightmap mesh export
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{fracdata::PixelData, Args};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MeshSource {
+    Iteration,
+    De,
+}
+
+pub struct Mesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    // Closed solid: the height field on top, walls down to z = 0 and a
+    // bottom fanned around its center, all wound counter-clockwise from outside.
+    // Units are pixels, heights are normalized to 0..scale on top of base.
+    pub fn heightmap(data: &[PixelData], args: &Args) -> Mesh {
+        let (w, h) = (args.width as usize, args.height as usize);
+        let (scale, base) = (args.mesh_scale, args.mesh_base);
+        let step = args.mesh_step.max(1);
+        let pixel_size = 2.0 / (args.zoom * args.width as f32);
+        let raw: Vec<f32> = data
+            .iter()
+            .map(|p| match args.mesh_source {
+                MeshSource::Iteration => p.smooth,
+                // Peaks at the boundary, falling off with distance
+                MeshSource::De => 1.0 / (1.0 + p.de / pixel_size),
+            })
+            .map(|v| if v.is_finite() { v } else { 0.0 })
+            .collect();
+        let max = raw.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+
+        let gw = (w - 1) / step + 1;
+        let gh = (h - 1) / step + 1;
+        let mut vertices = Vec::with_capacity(gw * gh + 2 * (gw + gh));
+        // Grid y points up, so pixel rows are read bottom to top
+        for gy in 0..gh {
+            for gx in 0..gw {
+                let (px, py) = (gx * step, h - 1 - gy * step);
+                let z = base + raw[py * w + px] / max * scale;
+                vertices.push([px as f32, (gy * step) as f32, z]);
+            }
+        }
+        let top = |gx: usize, gy: usize| gy * gw + gx;
+        let mut triangles = Vec::with_capacity(2 * gw * gh + 4 * (gw + gh));
+        for gy in 0..gh - 1 {
+            for gx in 0..gw - 1 {
+                let (a, b) = (top(gx, gy), top(gx + 1, gy));
+                let (c, d) = (top(gx + 1, gy + 1), top(gx, gy + 1));
+                triangles.push([a, b, c]);
+                triangles.push([a, c, d]);
+            }
+        }
+
+        // Perimeter loop, counter-clockwise seen from above
+        let mut rim: Vec<usize> = (0..gw).map(|x| top(x, 0)).collect();
+        rim.extend((1..gh).map(|y| top(gw - 1, y)));
+        rim.extend((0..gw - 1).rev().map(|x| top(x, gh - 1)));
+        rim.extend((1..gh - 1).rev().map(|y| top(0, y)));
+        let floor: Vec<usize> = rim
+            .iter()
+            .map(|&i| {
+                let [x, y, _] = vertices[i];
+                vertices.push([x, y, 0.0]);
+                vertices.len() - 1
+            })
+            .collect();
+        let center = vertices.len();
+        vertices.push([(w - 1) as f32 / 2.0, (h - 1) as f32 / 2.0, 0.0]);
+        for i in 0..rim.len() {
+            let j = (i + 1) % rim.len();
+            triangles.push([floor[i], floor[j], rim[j]]);
+            triangles.push([floor[i], rim[j], rim[i]]);
+            triangles.push([center, floor[j], floor[i]]);
+        }
+        Mesh {
+            vertices,
+            triangles,
+        }
+    }
+
+    fn normal(&self, t: &[usize; 3]) -> [f32; 3] {
+        let [a, b, c] = t.map(|i| self.vertices[i]);
+        let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2])
+            .sqrt()
+            .max(f32::EPSILON);
+        n.map(|v| v / len)
+    }
+
+    // Binary stl
+    pub fn write_stl<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut header = [0u8; 80];
+        let text = b"fracmd heightmap";
+        header[..text.len()].copy_from_slice(text);
+        out.write_all(&header)?;
+        out.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        for t in &self.triangles {
+            for v in std::iter::once(self.normal(t)).chain(t.iter().map(|&i| self.vertices[i])) {
+                for c in v {
+                    out.write_all(&c.to_le_bytes())?;
+                }
+            }
+            out.write_all(&[0, 0])?;
+        }
+        out.flush()
+    }
+
+    pub fn write_obj<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "# fracmd heightmap")?;
+        for [x, y, z] in &self.vertices {
+            writeln!(out, "v {} {} {}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            writeln!(out, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        out.flush()
+    }
+
+    // Picks obj or stl from the extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let obj = path
+            .as_ref()
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("obj"));
+        let out = BufWriter::new(File::create(path)?);
+        if obj {
+            self.write_obj(out)
+        } else {
+            self.write_stl(out)
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// fracmd
+// PNG metadata
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use image::{ImageBuffer, Rgba};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{colorspace::Colorspace, Args};
+
+// Every render parameter is stored as its own tEXt chunk, keyed by the long
+// flag name with this prefix, so the value can be handed straight back to clap
+pub const KEY_PREFIX: &str = "fracmd:";
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "png encoding error: {}", _0)]
+    Encoding(#[error(source)] png::EncodingError),
+    #[error(display = "png decoding error: {}", _0)]
+    Decoding(#[error(source)] png::DecodingError),
+    #[error(display = "{:?} has no fracmd parameters", _0)]
+    NoParams(PathBuf),
+    #[error(display = "bad stored parameter: {}", _0)]
+    BadParam(String),
+}
+
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+) -> Result<(), MetadataError> {
+    write_png(BufWriter::new(File::create(path)?), image, args)
+}
+
+// Encoder carrying the parameter chunks and, outside sRGB, the color profile
+pub fn encoder<W: Write>(
+    out: W,
+    width: u32,
+    height: u32,
+    args: &Args,
+) -> Result<png::Encoder<'static, W>, MetadataError> {
+    let mut info = png::Info::with_size(width, height);
+    if args.colorspace != Colorspace::Srgb {
+        info.icc_profile = Some(Cow::Owned(args.colorspace.icc_profile()));
+    }
+    let mut encoder = png::Encoder::with_info(out, info)?;
+    encoder.add_text_chunk(
+        "Software".to_string(),
+        format!("fracmd {}", env!("CARGO_PKG_VERSION")),
+    )?;
+    if let Some(creator) = &args.creator {
+        encoder.add_text_chunk("Author".to_string(), creator.clone())?;
+    }
+    for (key, value) in args.params() {
+        encoder.add_text_chunk(format!("{}{}", KEY_PREFIX, key), value)?;
+    }
+    Ok(encoder)
+}
+
+pub fn write_png<W: Write>(
+    out: W,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+) -> Result<(), MetadataError> {
+    write_png_with(out, image, args, &[])
+}
+
+// write_png with extra text chunks after the parameters
+pub fn write_png_with<W: Write>(
+    out: W,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+    text: &[(String, String)],
+) -> Result<(), MetadataError> {
+    let mut encoder = encoder(out, image.width(), image.height(), args)?;
+    for (key, value) in text {
+        encoder.add_text_chunk(key.clone(), value.clone())?;
+    }
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Sixteen);
+
+    // png wants 16 bit samples in network byte order
+    let data: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Every (keyword, text) chunk in the file
+pub fn read_text<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>, MetadataError> {
+    let file = File::open(&path)?;
+    let reader = png::Decoder::new(BufReader::new(file)).read_info()?;
+    Ok(reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect())
+}
+
+// Returns the (flag, value) pairs written by save_png
+pub fn read_params<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>, MetadataError> {
+    let params: Vec<(String, String)> = read_text(&path)?
+        .into_iter()
+        .filter_map(|(keyword, text)| {
+            keyword
+                .strip_prefix(KEY_PREFIX)
+                .map(|key| (key.to_string(), text))
+        })
+        .collect();
+    if params.is_empty() {
+        return Err(MetadataError::NoParams(path.as_ref().to_path_buf()));
+    }
+    Ok(params)
+}
+
+// The args read_params' parameters parse into
+pub fn read_args<P: AsRef<Path>>(path: P) -> Result<Args, MetadataError> {
+    Args::try_from_stored(&read_params(path)?).map_err(MetadataError::BadParam)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    // Anyone can put a fracmd: chunk in a png, only ones naming render
+    // parameters are taken back
+    #[test]
+    fn chunks_that_arent_params_are_refused() {
+        let args = Args::parse_argv(["fracmd"].map(String::from));
+        let exec = (
+            String::from("fracmd:exec"),
+            String::from("touch /tmp/pwned"),
+        );
+        let mut png = Vec::new();
+        write_png_with(&mut png, &ImageBuffer::new(2, 2), &args, &[exec]).unwrap();
+        let file = TempFile::new("exec.png", png);
+        assert!(read_params(&file.0)
+            .unwrap()
+            .iter()
+            .any(|(k, _)| k == "exec"));
+        assert!(matches!(
+            read_args(&file.0),
+            Err(MetadataError::BadParam(_))
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+// fracmd
+// Prometheus metrics for servers, farms and workers
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Counters for the whole process, bumped by the renderer after every tile
+// and by the api server and farm as jobs move through them. --metrics
+// serves them at /metrics in Prometheus' text format, scraped like any
+// other exporter. Rates are left to the scraper, pixels a second being
+// rate(fracmd_pixels_rendered_total[1m]). Rendering is all on the cpu, so
+// there's no gpu utilization to report.
+
+#[cfg(feature = "cli")]
+use std::{
+    io::{BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use std::{
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+#[cfg(feature = "cli")]
+use crate::{
+    serve::{self, ServeError},
+    Args,
+};
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Counter {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    const fn new() -> Gauge {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, n: i64) {
+        self.0.store(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub static TILES: Counter = Counter::new();
+pub static PIXELS: Counter = Counter::new();
+// Nanoseconds render threads spent on tiles, summed over threads
+pub static TILE_NANOS: Counter = Counter::new();
+pub static JOBS_DONE: Counter = Counter::new();
+pub static JOBS_FAILED: Counter = Counter::new();
+// Farm frames handed out again after a worker failed, dropped or lost them
+pub static JOBS_REQUEUED: Counter = Counter::new();
+// Api jobs or farm frames waiting for a turn
+pub static QUEUE: Gauge = Gauge::new();
+pub static IN_FLIGHT: Gauge = Gauge::new();
+pub static WORKERS: Gauge = Gauge::new();
+pub static THREADS: Gauge = Gauge::new();
+// Seconds since the epoch
+pub static STARTED: Gauge = Gauge::new();
+
+// Counts a finished tile of that many pixels
+pub fn tile(pixels: u64, took: Duration) {
+    TILES.inc();
+    PIXELS.add(pixels);
+    TILE_NANOS.add(took.as_nanos() as u64);
+}
+
+// Everything in Prometheus' text exposition format
+pub fn exposition() -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+        out += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+        for (labels, value) in samples {
+            out += &format!("{}{} {}\n", name, labels, value);
+        }
+    };
+    family(
+        "fracmd_tiles_rendered_total",
+        "counter",
+        "Tiles rendered since the process started.",
+        &[("", TILES.get().to_string())],
+    );
+    family(
+        "fracmd_pixels_rendered_total",
+        "counter",
+        "Pixels rendered since the process started.",
+        &[("", PIXELS.get().to_string())],
+    );
+    family(
+        "fracmd_tile_seconds_total",
+        "counter",
+        "Seconds render threads spent on tiles, summed over threads.",
+        &[("", (TILE_NANOS.get() as f64 / 1e9).to_string())],
+    );
+    family(
+        "fracmd_jobs_total",
+        "counter",
+        "Api jobs or farm frames finished, by how they ended.",
+        &[
+            ("{result=\"done\"}", JOBS_DONE.get().to_string()),
+            ("{result=\"failed\"}", JOBS_FAILED.get().to_string()),
+        ],
+    );
+    family(
+        "fracmd_jobs_requeued_total",
+        "counter",
+        "Farm frames handed out again after a worker failed, dropped or lost them.",
+        &[("", JOBS_REQUEUED.get().to_string())],
+    );
+    family(
+        "fracmd_queue_depth",
+        "gauge",
+        "Api jobs or farm frames waiting to be rendered.",
+        &[("", QUEUE.get().to_string())],
+    );
+    family(
+        "fracmd_jobs_in_flight",
+        "gauge",
+        "Api jobs or farm frames being rendered.",
+        &[("", IN_FLIGHT.get().to_string())],
+    );
+    family(
+        "fracmd_workers",
+        "gauge",
+        "Workers connected to the farm.",
+        &[("", WORKERS.get().to_string())],
+    );
+    family(
+        "fracmd_threads",
+        "gauge",
+        "Render threads.",
+        &[("", THREADS.get().to_string())],
+    );
+    family(
+        "fracmd_start_time_seconds",
+        "gauge",
+        "When the process started, in seconds since the epoch.",
+        &[("", STARTED.get().to_string())],
+    );
+    out
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct MetricsArgs {
+    /// Serve Prometheus metrics at http://ADDRESS/metrics, like
+    /// 0.0.0.0:9464
+    #[clap(long, value_name = "ADDRESS")]
+    pub metrics: Option<String>,
+}
+
+#[cfg(feature = "cli")]
+impl MetricsArgs {
+    // Starts serving the metrics in the background, if asked to
+    pub fn start(&self, args: &Args) -> Result<(), ServeError> {
+        let Some(address) = &self.metrics else {
+            return Ok(());
+        };
+        let listener =
+            TcpListener::bind(address).map_err(|e| ServeError::Listen(address.clone(), e))?;
+        THREADS.set(args.threads as i64);
+        STARTED.set(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64),
+        );
+        args.log(&format!("Serving metrics on http://{}/metrics", address));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = answer(stream);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cli")]
+fn answer(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request = serve::read_request(&mut BufReader::new(stream.try_clone()?))?;
+    let path = request.words.get(1).map_or("", String::as_str);
+    let (status, body) = match path.split('?').next() {
+        Some("/metrics") => ("200 OK", exposition()),
+        _ => ("404 Not Found", String::from("metrics are at /metrics\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
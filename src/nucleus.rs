@@ -0,0 +1,242 @@
+// fracmd
+// Finding minibrot nuclei for --autocenter
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A nucleus of period p is a c where the orbit comes back to exactly 0
+// after p steps, the center of a minibrot or bulb. Newton's method on
+// z_p(c) = 0 finds the one of a given period nearest a starting point. The
+// periods tried are the short ones plus every step where the orbit of the
+// origin came closer to 0 than before, which is where deeper minibrots
+// show themselves.
+
+use num::complex::Complex;
+
+use crate::{coords, fractal::Fractal, Args};
+
+const NEWTON_STEPS: usize = 64;
+// Searched for looping zooms, past these the points are too small to find
+const MAX_PREPERIOD: usize = 16;
+const MAX_CYCLE: usize = 8;
+const SHORT_PERIODS: usize = 64;
+// Close enough, as a fraction of the view width
+const TOLERANCE: f32 = 1e-3;
+
+pub struct Nucleus {
+    pub c: Complex<f32>,
+    pub period: usize,
+}
+
+// Around a Misiurewicz point the set repeats itself, shrunk and turned by
+// the multiplier of the cycle the orbit of 0 lands on
+pub struct Misiurewicz {
+    pub c: Complex<f32>,
+    pub preperiod: usize,
+    pub period: usize,
+    pub multiplier: Complex<f32>,
+}
+
+fn periods(c: Complex<f32>, args: &Args, fractal: &dyn Fractal) -> Vec<usize> {
+    let mut found: Vec<usize> = (1..=SHORT_PERIODS).collect();
+    let mut z = fractal.start(c);
+    let mut closest = f32::INFINITY;
+    let mut n = 1;
+    let bail = args.bail.unwrap_or_else(|| fractal.bail());
+    while (n as f32) <= args.limit && z.norm() < bail {
+        if z.norm() < closest {
+            closest = z.norm();
+            if n > SHORT_PERIODS {
+                found.push(n);
+            }
+        }
+        z = fractal.step(z, c);
+        n += 1;
+    }
+    found
+}
+
+// z_p(c) / z_p'(c), how far Newton would still move c
+fn step(c: Complex<f32>, period: usize, fractal: &dyn Fractal) -> Complex<f32> {
+    let (mut z, mut dz) = (fractal.start(c), Complex::new(1.0, 0.0));
+    for _ in 1..period {
+        dz = fractal.deriv(z, dz, c);
+        z = fractal.step(z, c);
+    }
+    z / dz
+}
+
+fn newton(
+    start: Complex<f32>,
+    period: usize,
+    zoom: f32,
+    fractal: &dyn Fractal,
+) -> Option<Complex<f32>> {
+    let mut c = start;
+    for _ in 0..NEWTON_STEPS {
+        let step = step(c, period, fractal);
+        if !step.is_finite() {
+            return None;
+        }
+        c -= step;
+        // f32 runs out of digits before the steps get tiny when deep
+        if step.norm() * zoom < TOLERANCE * f32::EPSILON.sqrt() {
+            break;
+        }
+    }
+    (step(c, period, fractal).norm() * zoom < TOLERANCE).then_some(c)
+}
+
+// A nucleus of period 3 is also one of periods 6, 9 and so on
+fn true_period(c: Complex<f32>, period: usize, zoom: f32, fractal: &dyn Fractal) -> usize {
+    (1..period)
+        .filter(|&d| period.is_multiple_of(d))
+        .find(|&d| step(c, d, fractal).norm() * zoom < TOLERANCE)
+        .unwrap_or(period)
+}
+
+// (z_(k+p) - z_k) / its derivative, where z_0 = 0, the Newton step
+// towards a Misiurewicz point of preperiod k and period p
+fn misiurewicz_step(
+    c: Complex<f32>,
+    preperiod: usize,
+    period: usize,
+    fractal: &dyn Fractal,
+) -> Complex<f32> {
+    let (mut z, mut dz) = (fractal.start(c), Complex::new(1.0, 0.0));
+    let (mut zk, mut dzk) = (Complex::new(0.0, 0.0), Complex::new(0.0, 0.0));
+    for n in 1..preperiod + period {
+        if n == preperiod {
+            (zk, dzk) = (z, dz);
+        }
+        dz = fractal.deriv(z, dz, c);
+        z = fractal.step(z, c);
+    }
+    (z - zk) / (dz - dzk)
+}
+
+// The Misiurewicz point of that preperiod and period nearest near, where
+// the Julia set is most like the Mandelbrot set around it. A lower
+// preperiod also solves the equation, so landing on one counts as failing.
+pub fn misiurewicz(
+    near: Complex<f32>,
+    preperiod: usize,
+    period: usize,
+    fractal: &dyn Fractal,
+) -> Option<Complex<f32>> {
+    let mut c = near;
+    for _ in 0..NEWTON_STEPS {
+        let step = misiurewicz_step(c, preperiod, period, fractal);
+        if !step.is_finite() {
+            return None;
+        }
+        c -= step;
+        if step.norm() < f32::EPSILON * c.norm().max(1.0) {
+            break;
+        }
+    }
+    let close =
+        |k| misiurewicz_step(c, k, period, fractal).norm() < TOLERANCE * f32::EPSILON.sqrt();
+    (close(preperiod) && (preperiod < 2 || !close(preperiod - 1))).then_some(c)
+}
+
+// The product of the derivatives in z round the cycle
+fn multiplier(
+    c: Complex<f32>,
+    preperiod: usize,
+    period: usize,
+    fractal: &dyn Fractal,
+) -> Complex<f32> {
+    let mut z = fractal.start(c);
+    for _ in 1..preperiod {
+        z = fractal.step(z, c);
+    }
+    let mut dz = Complex::new(1.0, 0.0);
+    for _ in 0..period {
+        dz = fractal.zderiv(z, dz);
+        z = fractal.step(z, c);
+    }
+    dz
+}
+
+// The simplest Misiurewicz point in the view, the nearest if there are
+// several. A zoom into it by the multiplier's size, turning against its
+// angle, ends on the frame it started on.
+pub fn find_misiurewicz(args: &Args, fractal: &dyn Fractal) -> Option<Misiurewicz> {
+    let reach = (1.0 + (args.height as f32 / args.width as f32).powi(2)).sqrt() / args.zoom;
+    let mut found: Vec<Misiurewicz> = Vec::new();
+    for preperiod in 2..=MAX_PREPERIOD {
+        for period in 1..=MAX_CYCLE {
+            let Some(c) = misiurewicz(args.origin, preperiod, period, fractal) else {
+                continue;
+            };
+            let multiplier = multiplier(c, preperiod, period, fractal);
+            // A multiplier inside the unit circle would be an attracting
+            // cycle, and one of size 1 doesn't shrink
+            if (c - args.origin).norm() <= reach && multiplier.norm() > 1.0 + 1e-3 {
+                found.push(Misiurewicz {
+                    c,
+                    preperiod,
+                    period,
+                    multiplier,
+                });
+            }
+        }
+    }
+    found.sort_by(|a, b| {
+        let distance = |m: &Misiurewicz| (m.c - args.origin).norm();
+        (a.preperiod + a.period)
+            .cmp(&(b.preperiod + b.period))
+            .then(distance(a).total_cmp(&distance(b)))
+    });
+    found.into_iter().next()
+}
+
+// The lowest period nucleus in the view, which is its biggest minibrot or
+// bulb, the nearest one if there are several
+pub fn find(args: &Args, fractal: &dyn Fractal) -> Option<Nucleus> {
+    let reach = (1.0 + (args.height as f32 / args.width as f32).powi(2)).sqrt() / args.zoom;
+    let mut found: Vec<Nucleus> = periods(args.origin, args, fractal)
+        .into_iter()
+        .filter_map(|period| {
+            let c = newton(args.origin, period, args.zoom, fractal)?;
+            let period = true_period(c, period, args.zoom, fractal);
+            ((c - args.origin).norm() <= reach).then_some(Nucleus { c, period })
+        })
+        .collect();
+    found.sort_by(|a, b| {
+        let distance = |n: &Nucleus| (n.c - args.origin).norm();
+        a.period
+            .cmp(&b.period)
+            .then(distance(a).total_cmp(&distance(b)))
+    });
+    found.into_iter().next()
+}
+
+// Moves the origin onto the one find picks, describing what it did
+pub fn autocenter(args: &mut Args, fractal: &dyn Fractal) -> String {
+    match find(args, fractal) {
+        Some(nucleus) => {
+            let moved = (nucleus.c - args.origin).norm() * args.zoom / 2.0;
+            args.origin = nucleus.c;
+            format!(
+                "Centered on the period {} nucleus at {}, {:.1}% of the view away",
+                nucleus.period,
+                coords::format(nucleus.c),
+                moved * 100.0
+            )
+        }
+        None => String::from("No minibrot nucleus in view, leaving the origin where it is"),
+    }
+}
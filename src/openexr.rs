@@ -0,0 +1,94 @@
+// fracmd
+// OpenEXR output
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use exr::prelude::*;
+use std::path::Path;
+
+use crate::{depth::ImageF32, fracdata::PixelData, metadata::KEY_PREFIX, Args};
+
+// Linear light, the png path stores sRGB encoded values
+pub fn linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn channel(name: &str, samples: Vec<f32>) -> AnyChannel<FlatSamples> {
+    AnyChannel::new(name, FlatSamples::F32(samples))
+}
+
+// Writes RGBA plus, when given, one f32 channel per PixelData field so the
+// file can be used directly for compositing. The data must be row-major.
+pub fn save_exr<P: AsRef<Path>>(
+    path: P,
+    image: &ImageF32,
+    aovs: Option<&[PixelData]>,
+    args: &Args,
+) -> Result<()> {
+    let size = (image.width() as usize, image.height() as usize);
+    let plane = |c: usize| image.pixels().map(|p| linear(p.0[c])).collect();
+    let mut channels = vec![
+        channel("R", plane(0)),
+        channel("G", plane(1)),
+        channel("B", plane(2)),
+        channel("A", image.pixels().map(|p| p.0[3]).collect()),
+    ];
+    if let Some(data) = aovs {
+        let aov = |f: fn(&PixelData) -> f32| data.iter().map(f).collect();
+        let limit = args.limit;
+        channels.extend([
+            channel("iteration", aov(|p| p.smooth)),
+            channel("de", aov(|p| p.de)),
+            channel("trap", aov(|p| p.trap)),
+            channel("zabs", aov(|p| p.z.norm())),
+            channel(
+                "escaped",
+                data.iter()
+                    .map(|p| {
+                        if p.iter < limit && p.converged == 0.0 {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect(),
+            ),
+            channel("converged", aov(|p| p.converged)),
+        ]);
+    }
+
+    let mut attributes = LayerAttributes::named(args.name.as_str());
+    attributes.software_name = Some(Text::from(
+        format!("fracmd {}", env!("CARGO_PKG_VERSION")).as_str(),
+    ));
+    for (key, value) in args.params() {
+        attributes.other.insert(
+            Text::from(format!("{}{}", KEY_PREFIX, key).as_str()),
+            AttributeValue::Text(Text::from(value.as_str())),
+        );
+    }
+
+    let layer = Layer::new(
+        size,
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
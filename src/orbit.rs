@@ -0,0 +1,103 @@
+// fracmd
+// Orbits of single points drawn over the view, for --plot-orbit and the explorer
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The orbit is every z the render's loop goes through for one point, with
+// the same formula, Julia c and bailout, so it shows exactly why a pixel
+// got its color. Each z is a dot joined to the next by a line, colored
+// along the way from blue to red so the order can be followed where the
+// path crosses itself. Points off the image still join up with the ones
+// on it, but the jump out to an escaped z far away is left off.
+
+use image::Rgba;
+use num::complex::Complex;
+
+use crate::{
+    depth::{Buffer, OutputPixel},
+    rgbaf::RgbaF,
+    Args, Renderer,
+};
+
+// Lines reaching further than this many image sizes off it aren't drawn
+const REACH: f32 = 4.0;
+
+// Where a point of the plane falls on a view of args, across and down from
+// 0 to 1 over the image, with aspect its height over its width
+pub fn place(args: &Args, aspect: f32, z: Complex<f32>) -> Option<(f32, f32)> {
+    let offset = args.view().inverse()?.apply(z - args.origin) * args.zoom;
+    Some(((offset.re + 1.0) / 2.0, (offset.im / aspect + 1.0) / 2.0))
+}
+
+// Color of the ith of n points
+pub fn color(i: usize, n: usize) -> RgbaF {
+    let along = i as f32 / n.saturating_sub(1).max(1) as f32;
+    RgbaF::from_hsv(240.0 * (1.0 - along), 1.0, 1.0, 1.0)
+}
+
+// Calls put for every pixel of a w by h image the orbit through points,
+// given in pixels, goes over. Dots are radius pixels out from each point.
+pub fn trace<F>(points: &[(f32, f32)], w: usize, h: usize, radius: i64, mut put: F)
+where
+    F: FnMut(usize, usize, RgbaF),
+{
+    let mut dot = |x: f32, y: f32, radius: i64, color: RgbaF| {
+        let (x, y) = (x.round() as i64, y.round() as i64);
+        for py in y - radius..=y + radius {
+            for px in x - radius..=x + radius {
+                if (0..w as i64).contains(&px) && (0..h as i64).contains(&py) {
+                    put(px as usize, py as usize, color);
+                }
+            }
+        }
+    };
+    let size = w.max(h) as f32;
+    let near = |(x, y): (f32, f32)| x.abs() < REACH * size && y.abs() < REACH * size;
+    for (i, pair) in points.windows(2).enumerate() {
+        let (a, b) = (pair[0], pair[1]);
+        if !(near(a) && near(b)) {
+            continue;
+        }
+        let color = color(i, points.len());
+        let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.0);
+        for step in 0..steps as usize {
+            let t = step as f32 / steps;
+            dot(a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, radius, color);
+        }
+    }
+    // The dots go over the lines, a size bigger
+    for (i, &(x, y)) in points.iter().enumerate() {
+        if near((x, y)) {
+            dot(x, y, radius + 1, color(i, points.len()));
+        }
+    }
+}
+
+// --plot-orbit over the finished image, pixel x being at x / width across
+pub fn plot<P: OutputPixel>(image: &mut Buffer<P>, renderer: &Renderer, c: Complex<f32>) {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    let aspect = h as f32 / w as f32;
+    let points: Vec<(f32, f32)> = renderer
+        .orbit(c)
+        .into_iter()
+        .filter_map(|z| place(&renderer.args, aspect, z))
+        .map(|(u, v)| (u * w as f32, v * h as f32))
+        .collect();
+    // Thin enough to follow on anything from a thumbnail to a poster
+    let radius = (w.max(h) / 1000) as i64;
+    trace(&points, w, h, radius, |x, y, color| {
+        image.put_pixel(x as u32, y as u32, P::from_u16(Rgba(color.to_arr16())))
+    });
+}
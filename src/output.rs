@@ -0,0 +1,220 @@
+// fracmd
+// Writing finished renders in every --format, with the extra outputs
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// save_output writes just the image, write_outputs everything the flags
+// ask for after a render. Formats that can stream write to any Write, EXR
+// and TIFF need a file to seek in. Besides png, TIFF, JPEG and WebP carry
+// the parameters, as XMP. The image can be in any OutputPixel depth, EXR
+// writes it as floats and the rest as 16 bit.
+
+use image::{ImageBuffer, Rgba};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    colorspace::Colorspace,
+    contour,
+    depth::{Buffer, OutputPixel},
+    error, farbfeld, kf, mesh, metadata, openexr, pnm, pyramid, quantize, sizes, template,
+    terminal, thumbnail, value_name, xmp, Args, Format, FracData, FracError, Palette, PixelData,
+    Renderer,
+};
+
+pub fn save_tiff(
+    name: &str,
+    output: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+) -> tiff::TiffResult<()> {
+    let mut encoder = tiff::encoder::TiffEncoder::new(BufWriter::new(File::create(name)?))?;
+    let mut image =
+        encoder.new_image::<tiff::encoder::colortype::RGBA16>(output.width(), output.height())?;
+    if args.colorspace != Colorspace::Srgb {
+        let icc = args.colorspace.icc_profile();
+        image
+            .encoder()
+            .write_tag(tiff::tags::Tag::Unknown(34675), &icc[..])?;
+    }
+    image
+        .encoder()
+        .write_tag(tiff::tags::Tag::Unknown(700), xmp::packet(args).as_bytes())?;
+    image.write_data(output.as_raw())
+}
+
+fn quantized(
+    args: &Args,
+    output: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+) -> Result<quantize::Indexed, FracError> {
+    Ok(match &args.index_palette {
+        Some(path) => {
+            quantize::with_palette(output, &Palette::load(path).map_err(error::at(path))?)
+        }
+        None => quantize::median_cut(output, args.colors),
+    })
+}
+
+pub fn write_output<P: OutputPixel, W: Write>(
+    args: &Args,
+    out: W,
+    output: &Buffer<P>,
+) -> Result<(), FracError> {
+    let output = &*P::image16(output);
+    match args.format {
+        Format::Png => metadata::write_png(out, output, args)?,
+        Format::Ppm => pnm::write_ppm(out, output)?,
+        Format::Pam => pnm::write_pam(out, output)?,
+        Format::Farbfeld => farbfeld::write_farbfeld(out, output)?,
+        Format::IndexedPng => quantize::write_indexed_png(out, &quantized(args, output)?, args)?,
+        Format::Gif => quantize::write_gif(out, &quantized(args, output)?)?,
+        Format::Jpeg => xmp::write_jpeg(out, output, args)?,
+        Format::Webp => xmp::write_webp(out, output, args)?,
+        Format::Exr | Format::Tiff => {
+            return Err(FracError::CantStream(format!(
+                "{} output",
+                value_name(args.format)
+            )))
+        }
+    }
+    Ok(())
+}
+
+pub fn save_output<P: OutputPixel>(
+    args: &Args,
+    name: &str,
+    output: &Buffer<P>,
+    data: Option<&[PixelData]>,
+) -> Result<(), FracError> {
+    if args.streaming() {
+        write_output(args, io::stdout().lock(), output)?;
+    } else if let Format::Exr = args.format {
+        let aovs = data.filter(|_| args.aovs);
+        openexr::save_exr(name, &P::image_f32(output), aovs, args)?;
+    } else if let Format::Tiff = args.format {
+        save_tiff(name, &P::image16(output), args)?;
+    } else {
+        write_output(args, BufWriter::new(File::create(name)?), output)?;
+    }
+    Ok(())
+}
+
+// Everything written after the image is rendered: the image itself in
+// --format, then the extra outputs the flags ask for
+pub fn write_outputs<P: OutputPixel, S: Fn(&str)>(
+    args: &Args,
+    name: &str,
+    mandelbrot: &Renderer,
+    output: &Buffer<P>,
+    stage: S,
+) -> Result<(), FracError> {
+    let data = if args.wants_data() {
+        stage("data");
+        Some(mandelbrot.render_data())
+    } else {
+        None
+    };
+    stage("save");
+    save_output(args, name, output, data.as_deref()).map_err(error::at(name))?;
+    let output = &*P::image16(output);
+    if let (Some(size), false) = (args.thumbnail, args.streaming()) {
+        let path = thumbnail::path_for(name);
+        metadata::save_png(&path, &thumbnail::thumbnail(output, size), args)
+            .map_err(error::at(&path))?;
+    }
+    if let Some(protocol) = args.preview_term {
+        // Stays off stdout when the image itself is going there
+        if args.streaming() {
+            terminal::preview(io::stderr().lock(), output, protocol, args.preview_size)
+        } else {
+            terminal::preview(io::stdout().lock(), output, protocol, args.preview_size)
+        }?;
+    }
+    if let Some(path) = &args.pyramid {
+        match args.pyramid_format {
+            pyramid::PyramidFormat::Dzi => pyramid::save_dzi(output, path, args.tile_size, 1),
+            pyramid::PyramidFormat::Iiif => {
+                pyramid::save_iiif(output, path, args.tile_size, &args.iiif_id)
+            }
+        }
+        .map_err(error::at(path))?;
+    }
+    if let (Some(path), Some(data)) = (&args.svg, &data) {
+        contour::save_svg(
+            path,
+            data,
+            args.width as usize,
+            args.height as usize,
+            args.limit,
+            args.svg_spacing,
+        )
+        .map_err(error::at(path))?;
+    }
+    if let (Some(path), Some(data)) = (&args.mesh, &data) {
+        mesh::Mesh::heightmap(data, args)
+            .save(path)
+            .map_err(error::at(path))?;
+    }
+    if let Some(path) = &args.kfr {
+        kf::write_kfr(path, args).map_err(error::at(path))?;
+    }
+    if let (Some(path), Some(data)) = (&args.dump_data, data) {
+        FracData {
+            width: args.width as u32,
+            height: args.height as u32,
+            params: args
+                .params()
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            data,
+        }
+        .save(path)
+        .map_err(error::at(path))?;
+    }
+    Ok(())
+}
+
+// The other --sizes, the frame itself having been written already. A
+// template without the size in it gets the size added, so none of them
+// overwrites another.
+pub fn write_sizes<P: OutputPixel>(
+    args: &Args,
+    name: &str,
+    output: &Buffer<P>,
+) -> Result<(), FracError> {
+    let output = &*P::image16(output);
+    for &size in &args.sizes {
+        if (size.width, size.height) == (args.width, args.height) {
+            continue;
+        }
+        let (job, image) = sizes::downsample(args, output, size)?;
+        let template = args.output.as_deref().unwrap_or(template::DEFAULT);
+        let mut path = template::expand(template, &job)?;
+        if path == name {
+            let stem = Path::new(name).with_extension("");
+            path = match Path::new(name).extension() {
+                Some(ext) => format!("{}_{}.{}", stem.display(), size, ext.to_string_lossy()),
+                None => format!("{}_{}", name, size),
+            };
+        }
+        let path = template::prepare(&path, args.collision).map_err(error::at(&path))?;
+        save_output(&job, &path, &image, None).map_err(error::at(&path))?;
+        args.log(&format!("Also written {} to {}", size, path));
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// fracmd
+// Gradient palettes
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use err_derive::Error;
+use std::{fs, path::Path};
+
+use crate::rgbaf::RgbaF;
+
+#[derive(Debug, Error)]
+pub enum PaletteError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "bad color on line {}", _0)]
+    BadLine(usize),
+    #[error(display = "palette has no colors")]
+    Empty,
+}
+
+// Maps an escape value onto palette position before it's normalized
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Linear,
+    Sqrt,
+    Log,
+}
+
+impl Transfer {
+    pub fn apply(self, v: f32) -> f32 {
+        match self {
+            Transfer::Linear => v,
+            Transfer::Sqrt => v.max(0.0).sqrt(),
+            Transfer::Log => v.max(0.0).ln_1p(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub colors: Vec<RgbaF>,
+}
+
+impl Palette {
+    // Fractint style .map file: one "r g b" triple (0-255) per line,
+    // anything after the third number is treated as a comment
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+        let text = fs::read_to_string(path)?;
+        let mut colors = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            let rgb: Vec<f32> = line
+                .split_whitespace()
+                .take(3)
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| PaletteError::BadLine(n + 1))?;
+            if rgb.len() != 3 {
+                return Err(PaletteError::BadLine(n + 1));
+            }
+            colors.push(RgbaF::new_color(
+                rgb[0] / 255.0,
+                rgb[1] / 255.0,
+                rgb[2] / 255.0,
+            ));
+        }
+        if colors.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+        Ok(Palette { colors })
+    }
+
+    // Palettes are written in sRGB, this moves them into another space
+    pub fn transform(self, m: &[[f32; 3]; 3]) -> Palette {
+        Palette {
+            colors: self.colors.into_iter().map(|c| c.transform(m)).collect(),
+        }
+    }
+
+    // Linearly interpolated lookup, t wraps around every 1.0
+    pub fn sample(&self, t: f32) -> RgbaF {
+        let n = self.colors.len();
+        let t = t.rem_euclid(1.0) * n as f32;
+        let i = (t as usize).min(n - 1);
+        let f = t - i as f32;
+        self.colors[i] * (1.0 - f) + self.colors[(i + 1) % n] * f
+    }
+}
@@ -0,0 +1,350 @@
+// fracmd
+// Formulas and colorings registered by name, from shared libraries loaded at runtime
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// --fractal and --coloring pick by name from what's registered here, on
+// top of the built-in multibrot and hue. Rust code embedding the library
+// can register trait objects directly. Everyone else builds a cdylib
+// exporting, in C terms,
+//
+//   uint32_t fracmd_register(const FracmdHost *host);
+//
+// which hands each PluginFractal and PluginColoring it brings to
+// host->fractal and host->coloring, and returns the VERSION it was built
+// for. A plugin for another version is turned away with everything it
+// registered, so it should check host->version before doing anything. The
+// structs are laid out as declared below with complex numbers as
+// { float re, im; } and PixelData's fields in order, see
+// examples/spiral.rs. Names are copied, functions and state must live and
+// be callable from any thread until the process ends: libraries are never
+// unloaded. A .wasm file goes to the sandboxed interpreter in wasm.rs
+// instead and is registered under its file name.
+
+use err_derive::Error;
+use num::complex::Complex;
+use std::{
+    ffi::{c_char, c_void, CStr},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    coloring::Coloring,
+    fracdata::PixelData,
+    fractal::{self, Fractal},
+    rgbaf::RgbaF,
+    wasm, Args, FracError,
+};
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "couldn't load it: {}", _0)]
+    Open(String),
+    #[error(display = "it doesn't export fracmd_register")]
+    NoEntry,
+    // The version it was built for, then the one it needs to be
+    #[error(display = "it was built for plugin version {}, this is {}", _0, _1)]
+    Version(u32, u32),
+    #[error(display = "it didn't register any fractals or colorings")]
+    Empty,
+    #[error(display = "plugins can only be loaded on unix")]
+    Unsupported,
+    #[error(display = "it isn't a usable wasm plugin: {}", _0)]
+    Wasm(String),
+}
+
+type Step = extern "C" fn(*mut c_void, Complex<f32>, Complex<f32>) -> Complex<f32>;
+type Deriv = extern "C" fn(*mut c_void, Complex<f32>, Complex<f32>, Complex<f32>) -> Complex<f32>;
+type Map = extern "C" fn(*mut c_void, Complex<f32>) -> Complex<f32>;
+
+// A Fractal across the C ABI, every function getting state first. deriv
+// and zderiv may be null, they're then estimated from step, and so may
+// start and map, which then leave c as it is.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginFractal {
+    pub name: *const c_char,
+    pub state: *mut c_void,
+    pub step: Step,
+    pub deriv: Option<Deriv>,
+    pub zderiv: Option<Step>,
+    pub start: Option<Map>,
+    pub map: Option<Map>,
+    pub degree: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+// A Coloring across the C ABI
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginColoring {
+    pub name: *const c_char,
+    pub state: *mut c_void,
+    pub color: extern "C" fn(*mut c_void, *const PixelData, f32) -> Color,
+}
+
+#[repr(C)]
+pub struct Host {
+    pub version: u32,
+    pub context: *mut c_void,
+    pub fractal: extern "C" fn(*mut c_void, *const PluginFractal),
+    pub coloring: extern "C" fn(*mut c_void, *const PluginColoring),
+}
+
+// Plugins promise their functions and state can be shared between threads
+unsafe impl Send for PluginFractal {}
+unsafe impl Sync for PluginFractal {}
+unsafe impl Send for PluginColoring {}
+unsafe impl Sync for PluginColoring {}
+
+impl Fractal for PluginFractal {
+    fn step(&self, z: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        (self.step)(self.state, z, c)
+    }
+
+    fn deriv(&self, z: Complex<f32>, dz: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        match self.deriv {
+            Some(deriv) => deriv(self.state, z, dz, c),
+            None => fractal::estimate_deriv(self, z, dz, c),
+        }
+    }
+
+    fn zderiv(&self, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+        match self.zderiv {
+            Some(zderiv) => zderiv(self.state, z, dz),
+            None => fractal::estimate_zderiv(self, z, dz),
+        }
+    }
+
+    fn start(&self, c: Complex<f32>) -> Complex<f32> {
+        self.start.map_or(c, |start| start(self.state, c))
+    }
+
+    fn map(&self, c: Complex<f32>) -> Complex<f32> {
+        self.map.map_or(c, |map| map(self.state, c))
+    }
+
+    fn degree(&self) -> f32 {
+        self.degree
+    }
+}
+
+impl Coloring for PluginColoring {
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF {
+        let Color { r, g, b, a } = (self.color)(self.state, p, limit);
+        RgbaF::new_color_alpha(r, g, b, a)
+    }
+}
+
+type Named<T> = Vec<(String, Arc<T>)>;
+
+static FRACTALS: Mutex<Named<dyn Fractal>> = Mutex::new(Vec::new());
+static COLORINGS: Mutex<Named<dyn Coloring>> = Mutex::new(Vec::new());
+static LOADED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// Registering a name again replaces what it meant
+pub fn register_fractal(name: &str, fractal: Arc<dyn Fractal>) {
+    let mut fractals = FRACTALS.lock().unwrap();
+    fractals.retain(|(n, _)| n != name);
+    fractals.push((name.to_string(), fractal));
+}
+
+pub fn register_coloring(name: &str, coloring: Arc<dyn Coloring>) {
+    let mut colorings = COLORINGS.lock().unwrap();
+    colorings.retain(|(n, _)| n != name);
+    colorings.push((name.to_string(), coloring));
+}
+
+pub fn fractal(name: &str) -> Option<Arc<dyn Fractal>> {
+    let fractals = FRACTALS.lock().unwrap();
+    fractals
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, f)| f.clone())
+}
+
+pub fn coloring(name: &str) -> Option<Arc<dyn Coloring>> {
+    let colorings = COLORINGS.lock().unwrap();
+    colorings
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, c)| c.clone())
+}
+
+// Every name --fractal and --coloring take, built-in ones first
+pub fn fractals() -> Vec<String> {
+    let fractals = FRACTALS.lock().unwrap();
+    let plugged = fractals.iter().map(|(n, _)| n.clone());
+    [String::from("multibrot")]
+        .into_iter()
+        .chain(plugged)
+        .collect()
+}
+
+pub fn colorings() -> Vec<String> {
+    let colorings = COLORINGS.lock().unwrap();
+    let plugged = colorings.iter().map(|(n, _)| n.clone());
+    [String::from("hue")].into_iter().chain(plugged).collect()
+}
+
+#[derive(Default)]
+struct Found {
+    fractals: Vec<(String, PluginFractal)>,
+    colorings: Vec<(String, PluginColoring)>,
+}
+
+fn name_of(name: *const c_char) -> Option<String> {
+    (!name.is_null()).then(|| {
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+extern "C" fn found_fractal(context: *mut c_void, fractal: *const PluginFractal) {
+    let found = unsafe { &mut *(context as *mut Found) };
+    if let Some(fractal) = unsafe { fractal.as_ref() } {
+        if let Some(name) = name_of(fractal.name) {
+            found.fractals.push((name, *fractal));
+        }
+    }
+}
+
+extern "C" fn found_coloring(context: *mut c_void, coloring: *const PluginColoring) {
+    let found = unsafe { &mut *(context as *mut Found) };
+    if let Some(coloring) = unsafe { coloring.as_ref() } {
+        if let Some(name) = name_of(coloring.name) {
+            found.colorings.push((name, *coloring));
+        }
+    }
+}
+
+type Entry = unsafe extern "C" fn(*const Host) -> u32;
+
+#[cfg(unix)]
+fn open(path: &Path) -> Result<Entry, PluginError> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let name = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| PluginError::Open(String::from("the path has a nul in it")))?;
+    unsafe {
+        let handle = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            let error = libc::dlerror();
+            return Err(PluginError::Open(
+                name_of(error).unwrap_or_else(|| String::from("unknown error")),
+            ));
+        }
+        let entry = libc::dlsym(handle, c"fracmd_register".as_ptr());
+        if entry.is_null() {
+            libc::dlclose(handle);
+            return Err(PluginError::NoEntry);
+        }
+        Ok(std::mem::transmute::<*mut c_void, Entry>(entry))
+    }
+}
+
+#[cfg(not(unix))]
+fn open(_path: &Path) -> Result<Entry, PluginError> {
+    Err(PluginError::Unsupported)
+}
+
+// Loads a plugin and registers what it brings, returning the names. The
+// same file twice is only loaded once.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<String>, PluginError> {
+    // dlopen searches the library path for bare names, this is a file
+    let path = fs::canonicalize(path)?;
+    if LOADED.lock().unwrap().contains(&path) {
+        return Ok(Vec::new());
+    }
+    if path.extension().is_some_and(|e| e == "wasm") {
+        return load_wasm(path);
+    }
+    let entry = open(&path)?;
+    let mut found = Found::default();
+    let host = Host {
+        version: VERSION,
+        context: &mut found as *mut Found as *mut c_void,
+        fractal: found_fractal,
+        coloring: found_coloring,
+    };
+    let version = unsafe { entry(&host) };
+    if version != VERSION {
+        return Err(PluginError::Version(version, VERSION));
+    }
+    if found.fractals.is_empty() && found.colorings.is_empty() {
+        return Err(PluginError::Empty);
+    }
+    LOADED.lock().unwrap().push(path);
+    let mut names = Vec::new();
+    for (name, fractal) in found.fractals {
+        register_fractal(&name, Arc::new(fractal));
+        names.push(name);
+    }
+    for (name, coloring) in found.colorings {
+        register_coloring(&name, Arc::new(coloring));
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// One file holds at most one of each, named after the file
+fn load_wasm(path: PathBuf) -> Result<Vec<String>, PluginError> {
+    let (fractal, coloring) = wasm::load(&path)?;
+    if fractal.is_none() && coloring.is_none() {
+        return Err(PluginError::Empty);
+    }
+    LOADED.lock().unwrap().push(path);
+    let mut names = Vec::new();
+    if let Some(fractal) = fractal {
+        let name = fractal.name().to_string();
+        register_fractal(&name, Arc::new(fractal));
+        names.push(name);
+    }
+    if let Some(coloring) = coloring {
+        let name = coloring.name().to_string();
+        register_coloring(&name, Arc::new(coloring));
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// Every --plugin, before anything is checked or rendered
+pub fn load_all(args: &Args) -> Result<(), FracError> {
+    for path in &args.plugin {
+        let names = load(path).map_err(crate::error::at(path))?;
+        if !names.is_empty() {
+            args.log(&format!(
+                "Loaded {} from {}",
+                names.join(", "),
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
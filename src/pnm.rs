@@ -0,0 +1,49 @@
+// fracmd
+// Netpbm output
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use image::{ImageBuffer, Rgba};
+use std::io::{self, Write};
+
+// Binary 16 bit PPM (P6), alpha is dropped
+pub fn write_ppm<W: Write>(mut out: W, image: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n65535\n", image.width(), image.height())?;
+    let mut data = Vec::with_capacity(image.width() as usize * image.height() as usize * 6);
+    for p in image.pixels() {
+        for v in &p.0[..3] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+    out.write_all(&data)?;
+    out.flush()
+}
+
+// 16 bit RGBA PAM (P7)
+pub fn write_pam<W: Write>(mut out: W, image: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> io::Result<()> {
+    write!(
+        out,
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 65535\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+        image.width(),
+        image.height()
+    )?;
+    let data: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    out.write_all(&data)?;
+    out.flush()
+}
@@ -0,0 +1,99 @@
+// fracmd
+// Named parameter presets
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Presets are config files in <config dir>/fracmd/presets/<name>.toml that
+// only hold what differs from the defaults, so several can be stacked
+
+use err_derive::Error;
+use std::{fs, path::PathBuf};
+
+use crate::{
+    config::{self, ConfigError},
+    Args,
+};
+
+#[derive(Debug, Error)]
+pub enum PresetError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "{}", _0)]
+    Config(#[error(source)] ConfigError),
+    #[error(display = "no preset named {:?}", _0)]
+    NotFound(String),
+    #[error(display = "preset names can't contain path separators: {:?}", _0)]
+    BadName(String),
+    #[error(display = "no user config directory on this system")]
+    NoConfigDir,
+}
+
+pub fn dir() -> Result<PathBuf, PresetError> {
+    Ok(dirs::config_dir()
+        .ok_or(PresetError::NoConfigDir)?
+        .join("fracmd")
+        .join("presets"))
+}
+
+fn path(name: &str) -> Result<PathBuf, PresetError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(PresetError::BadName(name.to_string()));
+    }
+    Ok(dir()?.join(format!("{}.toml", name)))
+}
+
+// Settings that differ from a bare `fracmd` run
+pub fn changed(args: &Args) -> Vec<(&'static str, String)> {
+    let defaults = config::settings(&Args::from_params(&[], &[]));
+    config::settings(args)
+        .into_iter()
+        .filter(|pair| !defaults.contains(pair))
+        .collect()
+}
+
+pub fn save(name: &str, args: &Args) -> Result<PathBuf, PresetError> {
+    let path = path(name)?;
+    fs::create_dir_all(dir()?)?;
+    config::write(&path, &changed(args))?;
+    Ok(path)
+}
+
+// The preset as command line flags
+pub fn load(name: &str) -> Result<Vec<String>, PresetError> {
+    let path = path(name)?;
+    if !path.exists() {
+        return Err(PresetError::NotFound(name.to_string()));
+    }
+    Ok(config::load(path)?)
+}
+
+// Names and flags of every saved preset, sorted by name
+pub fn list() -> Result<Vec<(String, Vec<String>)>, PresetError> {
+    let dir = dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| Ok((name.clone(), load(&name)?)))
+        .collect()
+}
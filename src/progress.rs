@@ -0,0 +1,160 @@
+// fracmd
+// Render progress reporting
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use image::Pixel;
+use serde_json::{json, Value};
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::scheduler::{self, Progress, Throttle, TILE_SIZE};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    // A bar when stderr is a terminal, nothing otherwise
+    Auto,
+    Bar,
+    // One json object per line, for wrapping programs
+    Json,
+    None,
+}
+
+const WIDTH: usize = 30;
+
+// Reports on stderr as tiles finish, redrawing a bar in place or appending
+// ndjson events like
+//   {"stage":"render","percent":41.5,"tiles_done":54,"tiles_total":130,...}
+// Stages are render, data, save and done, only render reports a percentage.
+pub struct Reporter {
+    json: bool,
+    pixels: u64,
+    tiles: usize,
+    done: AtomicU64,
+    // Pixels a resumed render already had, left out of the speed
+    skipped: AtomicU64,
+    start: Instant,
+    throttle: Throttle,
+}
+
+impl Reporter {
+    // None when the mode asks for no output
+    pub fn new(mode: ProgressMode, width: u32, height: u32) -> Option<Reporter> {
+        let on = match mode {
+            ProgressMode::Auto => io::stderr().is_terminal(),
+            ProgressMode::Bar | ProgressMode::Json => true,
+            ProgressMode::None => false,
+        };
+        on.then(|| Reporter {
+            json: mode == ProgressMode::Json,
+            pixels: width as u64 * height as u64,
+            tiles: scheduler::tiles(width, height, TILE_SIZE).len(),
+            done: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            start: Instant::now(),
+            throttle: Throttle::new(Duration::from_millis(100)),
+        })
+    }
+
+    // Render hook, called from the worker threads
+    pub fn update<P: Pixel>(&self, progress: &Progress<P>) {
+        let pixels = progress.tile.w as u64 * progress.tile.h as u64;
+        let done = self.done.fetch_add(pixels, Ordering::SeqCst) + pixels;
+        self.throttle
+            .run(|| self.draw(done, progress.done, progress.total));
+    }
+
+    // Counts pixels that were done before this run started
+    pub fn skip(&self, pixels: u64) {
+        self.done.fetch_add(pixels, Ordering::SeqCst);
+        self.skipped.fetch_add(pixels, Ordering::SeqCst);
+    }
+
+    // Always reports the full image, the last throttled update may have
+    // been skipped
+    pub fn finish(&self) {
+        self.draw(self.pixels, self.tiles, self.tiles);
+        if !self.json {
+            eprintln!();
+        }
+    }
+
+    // Marks the work after rendering, only json output shows it
+    pub fn stage(&self, stage: &str) {
+        if self.json {
+            emit(&json!({
+                "stage": stage,
+                "elapsed": self.start.elapsed().as_secs_f64(),
+            }));
+        }
+    }
+
+    fn draw(&self, done: u64, tiles: usize, total: usize) {
+        let fraction = done as f64 / self.pixels.max(1) as f64;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rendered = done.saturating_sub(self.skipped.load(Ordering::SeqCst));
+        let rate = rendered as f64 / elapsed.max(1e-6);
+        let eta = (rendered > 0).then(|| self.pixels.saturating_sub(done) as f64 / rate);
+        if self.json {
+            return emit(&json!({
+                "stage": "render",
+                "percent": fraction * 100.0,
+                "tiles_done": tiles,
+                "tiles_total": total,
+                "pixels_done": done,
+                "pixels_total": self.pixels,
+                "mpixels_per_sec": rate / 1e6,
+                "elapsed": elapsed,
+                "eta": eta,
+            }));
+        }
+        let filled = ((fraction * WIDTH as f64) as usize).min(WIDTH);
+        let mut err = io::stderr().lock();
+        // Progress is best effort, a closed stderr shouldn't stop the render
+        let _ = write!(
+            err,
+            "\r[{}{}] {:3.0}% {}/{} tiles {:.2} Mpixel/s ETA {}  ",
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            fraction * 100.0,
+            tiles,
+            total,
+            rate / 1e6,
+            eta.map_or(String::from("?"), format_secs)
+        );
+        let _ = err.flush();
+    }
+}
+
+fn emit(event: &Value) {
+    let mut err = io::stderr().lock();
+    let _ = writeln!(err, "{}", event);
+    let _ = err.flush();
+}
+
+pub fn format_secs(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
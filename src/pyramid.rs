@@ -0,0 +1,127 @@
+// fracmd
+// Deep zoom tile pyramids
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, ImageBuffer, ImageResult, Rgba};
+use std::{fs, path::Path};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PyramidFormat {
+    Dzi,
+    Iiif,
+}
+
+type Image16 = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+fn save_tile(tile: &Image16, path: &Path) -> ImageResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    DynamicImage::ImageRgba16(tile.clone()).to_rgb8().save(path)
+}
+
+fn downscale(image: &Image16, w: u32, h: u32) -> Image16 {
+    if (w, h) == image.dimensions() {
+        image.clone()
+    } else {
+        imageops::resize(image, w.max(1), h.max(1), imageops::FilterType::Triangle)
+    }
+}
+
+// Writes <base>.dzi and <base>_files/<level>/<col>_<row>.jpg, where level 0 is
+// a single pixel and the last level is the full image
+pub fn save_dzi(image: &Image16, base: &Path, tile: u32, overlap: u32) -> ImageResult<()> {
+    let (w, h) = image.dimensions();
+    let max_level = (w.max(h) as f32).log2().ceil() as u32;
+    let name = base.file_name().unwrap_or_default().to_string_lossy();
+    let files = base.with_file_name(format!("{}_files", name));
+    for level in 0..=max_level {
+        let scale = 1 << (max_level - level);
+        let (lw, lh) = (w.div_ceil(scale), h.div_ceil(scale));
+        let scaled = downscale(image, lw, lh);
+        for row in 0..lh.div_ceil(tile) {
+            for col in 0..lw.div_ceil(tile) {
+                let x0 = (col * tile).saturating_sub(overlap);
+                let y0 = (row * tile).saturating_sub(overlap);
+                let x1 = ((col + 1) * tile + overlap).min(lw);
+                let y1 = ((row + 1) * tile + overlap).min(lh);
+                let t = imageops::crop_imm(&scaled, x0, y0, x1 - x0, y1 - y0).to_image();
+                save_tile(
+                    &t,
+                    &files
+                        .join(level.to_string())
+                        .join(format!("{}_{}.jpg", col, row)),
+                )?;
+            }
+        }
+    }
+    let manifest = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" \
+         Format=\"jpg\" Overlap=\"{}\" TileSize=\"{}\">\n  \
+         <Size Width=\"{}\" Height=\"{}\"/>\n</Image>\n",
+        overlap, tile, w, h
+    );
+    fs::write(base.with_file_name(format!("{}.dzi", name)), manifest)?;
+    Ok(())
+}
+
+// IIIF Image API 3 level 0 layout: <dir>/info.json plus pre-cut
+// <dir>/<x>,<y>,<w>,<h>/<tw>,/0/default.jpg tiles for every scale factor
+pub fn save_iiif(image: &Image16, dir: &Path, tile: u32, id: &str) -> ImageResult<()> {
+    let (w, h) = image.dimensions();
+    let mut factors = vec![1u32];
+    while tile * factors.last().unwrap() < w.max(h) {
+        factors.push(factors.last().unwrap() * 2);
+    }
+    let mut sizes = Vec::new();
+    for &s in &factors {
+        let (lw, lh) = (w.div_ceil(s), h.div_ceil(s));
+        sizes.push(format!("{{\"width\":{},\"height\":{}}}", lw, lh));
+        let scaled = downscale(image, lw, lh);
+        // Full-image request at this size, used by viewers for thumbnails
+        save_tile(&scaled, &dir.join(format!("full/{},/0/default.jpg", lw)))?;
+        let region = tile * s;
+        for y in (0..h).step_by(region as usize) {
+            for x in (0..w).step_by(region as usize) {
+                let (rw, rh) = (region.min(w - x), region.min(h - y));
+                let (tw, th) = (rw.div_ceil(s), rh.div_ceil(s));
+                let (tw, th) = (tw.min(lw - x / s), th.min(lh - y / s));
+                let t = imageops::crop_imm(&scaled, x / s, y / s, tw, th).to_image();
+                let path = format!("{},{},{},{}/{},/0/default.jpg", x, y, rw, rh, tw);
+                save_tile(&t, &dir.join(path))?;
+            }
+        }
+    }
+    let factors: Vec<String> = factors.iter().map(|f| f.to_string()).collect();
+    let info = format!(
+        "{{\n  \"@context\": \"http://iiif.io/api/image/3/context.json\",\n  \
+         \"id\": \"{}\",\n  \"type\": \"ImageService3\",\n  \
+         \"protocol\": \"http://iiif.io/api/image\",\n  \"profile\": \"level0\",\n  \
+         \"width\": {},\n  \"height\": {},\n  \"sizes\": [{}],\n  \
+         \"tiles\": [{{\"width\": {}, \"scaleFactors\": [{}]}}]\n}}\n",
+        id,
+        w,
+        h,
+        sizes.join(","),
+        tile,
+        factors.join(",")
+    );
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("info.json"), info)?;
+    Ok(())
+}
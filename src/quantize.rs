@@ -0,0 +1,168 @@
+// fracmd
+// Palette quantization and indexed output
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use image::{ImageBuffer, Rgba};
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    metadata::{self, MetadataError},
+    palette::Palette,
+    Args,
+};
+
+pub struct Indexed {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>,
+}
+
+fn rgb8(p: &Rgba<u16>) -> [u8; 3] {
+    [
+        (p.0[0] >> 8) as u8,
+        (p.0[1] >> 8) as u8,
+        (p.0[2] >> 8) as u8,
+    ]
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(&x, y)| (x as i32 - y as i32).pow(2) as u32)
+        .sum()
+}
+
+fn histogram(image: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> HashMap<[u8; 3], u32> {
+    let mut counts = HashMap::new();
+    for p in image.pixels() {
+        *counts.entry(rgb8(p)).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Maps every pixel to its nearest palette entry, caching per unique color
+fn index(image: &ImageBuffer<Rgba<u16>, Vec<u16>>, palette: Vec<[u8; 3]>) -> Indexed {
+    let lookup: HashMap<[u8; 3], u8> = histogram(image)
+        .into_keys()
+        .map(|c| {
+            let nearest = (0..palette.len())
+                .min_by_key(|&i| distance(c, palette[i]))
+                .unwrap_or(0);
+            (c, nearest as u8)
+        })
+        .collect();
+    Indexed {
+        width: image.width(),
+        height: image.height(),
+        indices: image.pixels().map(|p| lookup[&rgb8(p)]).collect(),
+        palette,
+    }
+}
+
+// Median cut over the color histogram: keep splitting the box with the
+// widest channel range at its weighted median until there are enough boxes
+pub fn median_cut(image: &ImageBuffer<Rgba<u16>, Vec<u16>>, colors: usize) -> Indexed {
+    let colors = colors.clamp(2, 256);
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![histogram(image).into_iter().collect()];
+    let range = |b: &[([u8; 3], u32)], ch: usize| {
+        let (lo, hi) = b
+            .iter()
+            .fold((255, 0), |(lo, hi), (c, _)| (c[ch].min(lo), c[ch].max(hi)));
+        hi.saturating_sub(lo)
+    };
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let ch = (0..3).max_by_key(|&ch| range(b, ch)).unwrap();
+                (i, ch, range(b, ch))
+            })
+            .max_by_key(|&(_, _, r)| r);
+        let (i, ch) = match widest {
+            Some((i, ch, _)) => (i, ch),
+            None => break,
+        };
+        let mut b = boxes.swap_remove(i);
+        b.sort_unstable_by_key(|(c, _)| c[ch]);
+        let total: u32 = b.iter().map(|(_, n)| n).sum();
+        let mut acc = 0;
+        let split = b
+            .iter()
+            .position(|(_, n)| {
+                acc += n;
+                acc * 2 >= total
+            })
+            .unwrap_or(0)
+            .clamp(0, b.len() - 2)
+            + 1;
+        let rest = b.split_off(split);
+        boxes.push(b);
+        boxes.push(rest);
+    }
+    let palette = boxes
+        .iter()
+        .map(|b| {
+            let total: u64 = b.iter().map(|(_, n)| *n as u64).sum::<u64>().max(1);
+            let mut avg = [0u8; 3];
+            for (ch, v) in avg.iter_mut().enumerate() {
+                let sum: u64 = b.iter().map(|(c, n)| c[ch] as u64 * *n as u64).sum();
+                *v = (sum / total) as u8;
+            }
+            avg
+        })
+        .collect();
+    index(image, palette)
+}
+
+// Uses the rendering palette itself as the index, evenly thinned out when it
+// has more than 256 entries
+pub fn with_palette(image: &ImageBuffer<Rgba<u16>, Vec<u16>>, palette: &Palette) -> Indexed {
+    let n = palette.colors.len();
+    let step = (n as f32 / 256.0).max(1.0);
+    let entries = (0..n.min(256))
+        .map(|i| {
+            let c = palette.colors[(i as f32 * step) as usize].to_arr8();
+            [c[0], c[1], c[2]]
+        })
+        .collect();
+    index(image, entries)
+}
+
+pub fn write_indexed_png<W: Write>(
+    out: W,
+    indexed: &Indexed,
+    args: &Args,
+) -> Result<(), MetadataError> {
+    let mut encoder = metadata::encoder(out, indexed.width, indexed.height, args)?;
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(indexed.palette.concat());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&indexed.indices)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_gif<W: Write>(out: W, indexed: &Indexed) -> Result<(), gif::EncodingError> {
+    let (w, h) = (indexed.width as u16, indexed.height as u16);
+    let palette = indexed.palette.concat();
+    let mut encoder = gif::Encoder::new(out, w, h, &palette)?;
+    let frame = gif::Frame::from_indexed_pixels(w, h, indexed.indices.clone(), None);
+    encoder.write_frame(&frame)
+}
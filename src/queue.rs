@@ -0,0 +1,195 @@
+// fracmd
+// Job files dropped into a directory, for `fracmd queue`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A job is one .json or .toml file of flags, shaped like --params-stdin's
+// object or a config file, over the flags the queue was started with.
+// Jobs are taken oldest first by moving them into running/, so two queues
+// on one directory never take the same file, and end up in done/ or
+// failed/ with a .log beside them saying what happened:
+//
+//   queue/night.json               waiting
+//   queue/running/night.json       being rendered
+//   queue/done/night.json          rendered, night.json.log has the output
+//   queue/failed/night.json        not rendered, night.json.log says why
+//
+// A file changed less than a second ago is left until it settles, so a
+// tool that doesn't write elsewhere and rename still has its file read
+// whole. Hidden files and others are ignored. Jobs left in running/ by a
+// queue that was killed go back in line with --recover; nothing can tell
+// them from another queue's jobs in flight, so it's never done unasked.
+
+use err_derive::Error;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{batch::Outcome, config, template, validate, Args};
+
+pub const RUNNING: &str = "running";
+pub const DONE: &str = "done";
+pub const FAILED: &str = "failed";
+
+// How long a file has to go unchanged before it's taken
+const SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "not a directory")]
+    NotDir,
+}
+
+fn is_job(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_none_or(|n| n.starts_with('.'));
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    !hidden && (ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("toml"))
+}
+
+// Makes the folders
+pub fn prepare(dir: &Path) -> Result<(), QueueError> {
+    if !dir.is_dir() {
+        return Err(QueueError::NotDir);
+    }
+    for folder in [RUNNING, DONE, FAILED] {
+        fs::create_dir_all(dir.join(folder))?;
+    }
+    Ok(())
+}
+
+// Jobs in running/, whether another queue is rendering them or one was
+// killed halfway
+pub fn running(dir: &Path) -> Result<Vec<PathBuf>, QueueError> {
+    let mut jobs = Vec::new();
+    for entry in fs::read_dir(dir.join(RUNNING))? {
+        let path = entry?.path();
+        if is_job(&path) {
+            jobs.push(path);
+        }
+    }
+    Ok(jobs)
+}
+
+// Puts what's in running/ back in line, returning how many there were
+pub fn recover(dir: &Path) -> Result<usize, QueueError> {
+    let jobs = running(dir)?;
+    for path in &jobs {
+        if let Some(name) = path.file_name() {
+            fs::rename(path, dir.join(name))?;
+        }
+    }
+    Ok(jobs.len())
+}
+
+// Jobs waiting, oldest first
+pub fn pending(dir: &Path) -> Result<Vec<PathBuf>, QueueError> {
+    let now = SystemTime::now();
+    let mut jobs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() || !is_job(&path) {
+            continue;
+        }
+        let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+        if now.duration_since(modified).unwrap_or_default() >= SETTLE {
+            jobs.push((modified, path));
+        }
+    }
+    jobs.sort();
+    Ok(jobs.into_iter().map(|(_, path)| path).collect())
+}
+
+// Moves a job into running/, or None if another queue got to it first
+pub fn claim(dir: &Path, job: &Path) -> Option<PathBuf> {
+    let running = dir.join(RUNNING).join(job.file_name()?);
+    fs::rename(job, &running).ok().map(|_| running)
+}
+
+// The job's full parameters over base, named after the file unless it or
+// base names it
+pub fn load(path: &Path, base: &Args) -> Result<Args, String> {
+    let json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let flags = match json {
+        true => fs::File::open(path)
+            .map_err(config::ConfigError::from)
+            .and_then(config::from_json),
+        false => config::load(path),
+    }
+    .map_err(|e| e.to_string())?;
+    let mut argv = vec![String::from("fracmd")];
+    argv.extend(
+        config::settings(base)
+            .iter()
+            .map(|(k, v)| config::flag(k, v)),
+    );
+    if !base.given("name") {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        argv.push(config::flag("name", &stem));
+    }
+    argv.extend(flags);
+    let mut job = Args::try_parse_argv(argv).map_err(|e| {
+        let message = e.to_string();
+        let line = message.lines().next().unwrap_or_default();
+        line.trim_start_matches("error: ").to_string()
+    })?;
+    job.threads = base.threads;
+    match validate::check(&job).first() {
+        Some(problem) => Err(problem.to_string()),
+        None => Ok(job),
+    }
+}
+
+// Moves a finished job to done/ or failed/ with its log, returning where
+// it went
+pub fn finish(dir: &Path, running: &Path, outcome: &Outcome) -> Result<PathBuf, QueueError> {
+    let folder = match outcome.error {
+        Some(_) => FAILED,
+        None => DONE,
+    };
+    let name = running.file_name().unwrap_or_default();
+    let to = dir.join(folder).join(name);
+    fs::rename(running, &to)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut log = format!(
+        "job      {}\nfinished {} {} UTC\nseconds  {:.2}\n",
+        name.to_string_lossy(),
+        template::date(now),
+        template::clock(now),
+        outcome.time.as_secs_f64()
+    );
+    if let Some(output) = &outcome.output {
+        log += &format!("output   {}\n", output);
+    }
+    if let Some(error) = &outcome.error {
+        log += &format!("error    {}\n", error);
+    }
+    let mut log_path = to.clone().into_os_string();
+    log_path.push(".log");
+    fs::write(log_path, log)?;
+    Ok(to)
+}
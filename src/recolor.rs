@@ -0,0 +1,122 @@
+// fracmd
+// Recoloring of dumped iteration data
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A dump is recolored by the renderer's own coloring stage, one record
+// standing for each pixel's samples, so with the coloring it was rendered
+// with the result is that render wherever jitter didn't move the samples.
+
+use std::sync::Arc;
+
+use crate::{
+    coloring::Coloring,
+    default_functs,
+    depth::OutputPixel,
+    fracdata::{FracData, PixelData},
+    par::*,
+    scheduler::Image16,
+    Args, Functs, Renderer,
+};
+
+pub struct Recolor {
+    renderer: Renderer,
+}
+
+impl Recolor {
+    // The dump's stored args, with --set-color and the rest as given
+    pub fn new(args: &Args, coloring: Arc<dyn Coloring>) -> Recolor {
+        let functs = Functs {
+            coloring,
+            ..default_functs(args)
+        };
+        Recolor {
+            renderer: Renderer::new(args.clone(), functs),
+        }
+    }
+
+    // Another coloring for the same dump, a color cycle's next frame say
+    pub fn set_coloring(&mut self, coloring: Arc<dyn Coloring>) {
+        let functs = Functs {
+            coloring,
+            ..self.renderer.functs.clone()
+        };
+        self.renderer.set_functs(functs);
+    }
+
+    pub fn pixel<P: OutputPixel>(&self, p: &PixelData) -> P {
+        self.renderer.color_pixel(std::slice::from_ref(p))
+    }
+
+    pub fn apply(&self, data: &FracData) -> Image16 {
+        let pixels: Vec<_> = data.data.par_iter().map(|p| self.pixel(p)).collect();
+        Image16::from_fn(data.width, data.height, |x, y| {
+            pixels[(y * data.width + x) as usize]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{PixelRng, Sampler};
+
+    // Every sample at the pixel's center, where the dump looks
+    struct Center;
+
+    impl Sampler for Center {
+        fn name(&self) -> &'static str {
+            "center"
+        }
+
+        fn pattern(&self, count: usize, _: &mut PixelRng) -> Vec<(f32, f32)> {
+            vec![(0.0, 0.0); count]
+        }
+    }
+
+    // Rendering, dumping and recoloring with the same coloring has to give
+    // back the render itself
+    fn round_trip(flags: &[&str]) {
+        let mut argv = vec!["fracmd", "--width=48", "--height=32", "--limit=64"];
+        argv.extend(flags);
+        let args = Args::parse_argv(argv.into_iter().map(String::from));
+        let mut renderer = Renderer::new(args.clone(), default_functs(&args));
+        renderer.set_sampler(Arc::new(Center));
+        let dump = FracData {
+            width: args.width as u32,
+            height: args.height as u32,
+            params: Vec::new(),
+            data: renderer.render_data(),
+        };
+        let recolored = Recolor::new(&args, default_functs(&args).coloring).apply(&dump);
+        let rendered = renderer.render();
+        let differing = rendered
+            .pixels()
+            .zip(recolored.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(differing, 0, "{:?}", flags);
+    }
+
+    #[test]
+    fn recolor_matches_render() {
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn recolor_matches_render_with_set_color() {
+        round_trip(&["--set-color=128,128,128,255"]);
+    }
+}
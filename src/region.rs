@@ -0,0 +1,111 @@
+// fracmd
+// Rendering a crop of the frame
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use num::complex::Complex;
+use std::{fmt, str::FromStr};
+
+use crate::Args;
+
+#[derive(Debug, Error)]
+pub enum RegionError {
+    #[error(display = "expected x0,y0,x1,y1 but got {:?}", _0)]
+    BadSpec(String),
+    #[error(display = "region {} is empty or outside the {}x{} frame", _0, _1, _2)]
+    OutOfFrame(Region, i32, i32),
+}
+
+// Corners of the crop, pixels when every value is a whole number and
+// fractions of the frame when any has a decimal point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub corners: [f64; 4],
+    pub normalized: bool,
+}
+
+impl FromStr for Region {
+    type Err = RegionError;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bad = || RegionError::BadSpec(string.to_string());
+        let parts: Vec<&str> = string.split(',').map(str::trim).collect();
+        if parts.len() != 4 {
+            return Err(bad());
+        }
+        let mut corners = [0.0; 4];
+        for (v, part) in corners.iter_mut().zip(&parts) {
+            *v = part.parse().map_err(|_| bad())?;
+        }
+        Ok(Region {
+            corners,
+            normalized: parts.iter().any(|p| p.contains('.')),
+        })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [x0, y0, x1, y1] = self.corners;
+        if self.normalized {
+            write!(f, "{:?},{:?},{:?},{:?}", x0, y0, x1, y1)
+        } else {
+            write!(f, "{},{},{},{}", x0, y0, x1, y1)
+        }
+    }
+}
+
+impl Region {
+    // Pixel bounds within a width by height frame, x1 and y1 exclusive
+    pub fn pixels(&self, width: i32, height: i32) -> Result<[i32; 4], RegionError> {
+        let [x0, y0, x1, y1] = self.corners;
+        let (sx, sy) = if self.normalized {
+            (width as f64, height as f64)
+        } else {
+            (1.0, 1.0)
+        };
+        let bounds = [
+            (x0 * sx).round() as i32,
+            (y0 * sy).round() as i32,
+            (x1 * sx).round() as i32,
+            (y1 * sy).round() as i32,
+        ];
+        let [x0, y0, x1, y1] = bounds;
+        if x0 < 0 || y0 < 0 || x1 > width || y1 > height || x0 >= x1 || y0 >= y1 {
+            return Err(RegionError::OutOfFrame(*self, width, height));
+        }
+        Ok(bounds)
+    }
+
+    // Parameters rendering just the crop, with every pixel landing on the
+    // same point of the plane it would in the full frame. Pixels are spaced
+    // 2 / (width * zoom) apart on both axes, so the crop keeps that spacing
+    // and moves the origin to its own center.
+    pub fn crop(&self, args: &Args) -> Result<Args, RegionError> {
+        let [x0, y0, x1, y1] = self.pixels(args.width, args.height)?;
+        let (w, h) = (x1 - x0, y1 - y0);
+        let scale = args.width as f32 * args.zoom;
+        let mut crop = args.clone();
+        crop.width = w;
+        crop.height = h;
+        crop.zoom = args.zoom * args.width as f32 / w as f32;
+        crop.origin = args.origin
+            + args.view().apply(Complex::new(
+                (2 * x0 + w - args.width) as f32 / scale,
+                (2 * y0 + h - args.height) as f32 / scale,
+            ));
+        Ok(crop)
+    }
+}
@@ -0,0 +1,105 @@
+// fracmd
+// Reusing frames of a zoom for `animate --reuse`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The middle of a zoom frame is the frame after it shrunk down, so a zoom
+// rendered from its deepest frame outwards only has to iterate the ring
+// each frame adds around the last. Shrinking averages every deeper pixel
+// under a pixel, the same as supersampling would, so nothing is lost but
+// the jitter pattern.
+
+use image::{ImageBuffer, Rgba};
+
+use crate::{animation::Animation, depth, par::*, scheduler::Image16, Args, Renderer};
+
+// Only a zoom into the origin keeps the frames nested
+pub fn possible(animation: &Animation) -> bool {
+    animation.zoom_to.is_some()
+        && animation.spin.is_none()
+        && animation.julia_path.is_none()
+        && animation.keys.is_none()
+        && animation.audio.is_none()
+        && !animation.bounce
+        && animation.shutter <= 0.0
+}
+
+// How far along one axis each deeper pixel lies under the span lo..hi,
+// pixel j covering j - 0.5..j + 0.5
+fn overlaps(lo: f32, hi: f32) -> impl Iterator<Item = (u32, f32)> {
+    let first = (lo + 0.5).floor() as i64;
+    let last = (hi - 0.5).ceil() as i64;
+    (first..=last).filter_map(move |j| {
+        let weight = (hi.min(j as f32 + 0.5) - lo.max(j as f32 - 0.5)).max(0.0);
+        (weight > 0.0 && j >= 0).then_some((j as u32, weight))
+    })
+}
+
+// The average of the deeper pixels under pixel x, y of a frame at args, or
+// None where the deeper frame doesn't reach
+fn shrink(deeper: &Image16, deeper_args: &Args, args: &Args, x: i32, y: i32) -> Option<Rgba<u16>> {
+    let (w, h) = (args.width as f32, args.height as f32);
+    let scale = deeper_args.zoom / args.zoom;
+    let to_deeper = |v: i32, size: f32| ((2.0 * v as f32 / size - 1.0) * scale + 1.0) * size / 2.0;
+    let (cx, cy) = (to_deeper(x, w), to_deeper(y, h));
+    let (x0, x1, y0, y1) = (
+        cx - scale / 2.0,
+        cx + scale / 2.0,
+        cy - scale / 2.0,
+        cy + scale / 2.0,
+    );
+    if x0 < -0.5 || y0 < -0.5 || x1 > w - 0.5 || y1 > h - 0.5 {
+        return None;
+    }
+    let mut sum = [0.0; 4];
+    let mut total = 0.0;
+    for (j, wy) in overlaps(y0, y1) {
+        for (i, wx) in overlaps(x0, x1) {
+            let p = depth::squared(*deeper.get_pixel(i.min(w as u32 - 1), j.min(h as u32 - 1)));
+            let weight = wx * wy;
+            for c in 0..4 {
+                sum[c] += weight * p[c];
+            }
+            total += weight;
+        }
+    }
+    Some(depth::unsquared(sum.map(|v| v / total)))
+}
+
+// Renders a frame, taking whatever the deeper frame already covers from
+// it, and says how many pixels that was
+pub fn render(
+    renderer: &Renderer,
+    args: &Args,
+    deeper: Option<(&Image16, &Args)>,
+) -> (Image16, usize) {
+    let (w, h) = (args.width, args.height);
+    let pixels: Vec<(Rgba<u16>, bool)> = (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let shrunk = deeper
+                .and_then(|(image, deeper_args)| shrink(image, deeper_args, args, i / h, i % h));
+            match shrunk {
+                Some(p) => (p, true),
+                None => (renderer.pixel(i), false),
+            }
+        })
+        .collect();
+    let reused = pixels.iter().filter(|(_, reused)| *reused).count();
+    let image = ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+        pixels[(x as i32 * h + y as i32) as usize].0
+    });
+    (image, reused)
+}
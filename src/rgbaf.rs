@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{num::ParseIntError, str::FromStr};
+use std::{fmt, str::FromStr};
 
 #[allow(non_snake_case)]
 #[derive(Clone, Copy, Debug)]
@@ -91,12 +91,12 @@ impl RgbaF {
             r: RgbaF::f_hsv(h, s, v, 5.0),
             g: RgbaF::f_hsv(h, s, v, 3.0),
             b: RgbaF::f_hsv(h, s, v, 1.0),
-            a: a,
+            a,
             sRGB: false,
         }
     }
 
-    pub fn to_sRGB(&self) -> RgbaF {
+    pub fn to_sRGB(self) -> RgbaF {
         RgbaF {
             r: RgbaF::sRGB(self.r, false),
             g: RgbaF::sRGB(self.g, false),
@@ -105,7 +105,7 @@ impl RgbaF {
             sRGB: true,
         }
     }
-    pub fn to_RGB(&self) -> RgbaF {
+    pub fn to_RGB(self) -> RgbaF {
         RgbaF {
             r: RgbaF::sRGB(self.r, true),
             g: RgbaF::sRGB(self.g, true),
@@ -114,10 +114,38 @@ impl RgbaF {
             sRGB: false,
         }
     }
-    pub fn to_arr(&self) -> [f32; 4] {
+    // Applies a linear RGB matrix, alpha is kept
+    pub fn transform(self, m: &[[f32; 3]; 3]) -> RgbaF {
+        let v = [self.r, self.g, self.b];
+        let row = |i: usize| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2];
+        RgbaF {
+            r: row(0),
+            g: row(1),
+            b: row(2),
+            a: self.a,
+            sRGB: self.sRGB,
+        }
+    }
+    // Same for gamma encoded values, clipped to the target gamut
+    pub fn transform_encoded(self, m: &[[f32; 3]; 3]) -> RgbaF {
+        let linear = RgbaF {
+            r: RgbaF::sRGB(self.r, true),
+            g: RgbaF::sRGB(self.g, true),
+            b: RgbaF::sRGB(self.b, true),
+            ..self
+        }
+        .transform(m);
+        RgbaF {
+            r: RgbaF::sRGB(linear.r.clamp(0.0, 1.0), false),
+            g: RgbaF::sRGB(linear.g.clamp(0.0, 1.0), false),
+            b: RgbaF::sRGB(linear.b.clamp(0.0, 1.0), false),
+            ..self
+        }
+    }
+    pub fn to_arr(self) -> [f32; 4] {
         [self.r, self.g, self.b, self.a]
     }
-    pub fn to_arr16(&self) -> [u16; 4] {
+    pub fn to_arr16(self) -> [u16; 4] {
         [
             (self.r * u16::MAX as f32) as u16,
             (self.g * u16::MAX as f32) as u16,
@@ -125,7 +153,7 @@ impl RgbaF {
             (self.a * u16::MAX as f32) as u16,
         ]
     }
-    pub fn to_arr8(&self) -> [u8; 4] {
+    pub fn to_arr8(self) -> [u8; 4] {
         [
             (self.r * u8::MAX as f32) as u8,
             (self.g * u8::MAX as f32) as u8,
@@ -136,12 +164,16 @@ impl RgbaF {
 }
 
 impl FromStr for RgbaF {
-    type Err = ParseIntError;
+    type Err = String;
     fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("{:?} is not a color like 0,0,0,255", string);
         let cols: Vec<f32> = string
             .split(',')
-            .map(|x| x.parse::<f32>().unwrap())
-            .collect();
+            .map(|x| x.trim().parse::<f32>().map_err(|_| bad()))
+            .collect::<Result<_, _>>()?;
+        if cols.len() != 4 {
+            return Err(bad());
+        }
         Ok(RgbaF {
             r: cols[0] / 255.0,
             g: cols[1] / 255.0,
@@ -152,6 +184,19 @@ impl FromStr for RgbaF {
     }
 }
 
+impl fmt::Display for RgbaF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.r * 255.0,
+            self.g * 255.0,
+            self.b * 255.0,
+            self.a * 255.0
+        )
+    }
+}
+
 // RgbaF operators
 impl std::ops::Add<RgbaF> for RgbaF {
     type Output = RgbaF;
@@ -0,0 +1,408 @@
+// fracmd
+// Samplers, where in each pixel the samples are taken
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A sampler picks the --samples points of a pixel, as jitters from -1 to 1
+// either way that the renderer scales by 1 / --sampled, and hands them to
+// the backend in one batch for a whole tile, so every backend works with
+// every sampler.
+//
+//   random      independent uniform jitters, what fracmd always did
+//   stratified  one jitter in each cell of a grid over the pixel
+//   sobol       a Sobol sequence shifted by a random amount per pixel,
+//               evenly spread at any count, best at powers of two
+//   adaptive    the first --adaptive-min of the sobol points, and the rest
+//               only where their colors differ by more than
+//               --adaptive-threshold, so flat areas cost a fraction
+//
+// Each pixel's jitters come from its own generator, so a --seed gives the
+// same image with any sampler however the tiles are split between threads.
+// That's SplitMix64 started from the seed and the pixel, a couple of
+// multiplies a number, since a StdRng built for every pixel cost as much
+// as iterating some of them. Without a seed the start comes from a stream
+// each thread draws once.
+// Adaptive keeps pixels it stopped early the same size in the render by
+// repeating their samples, which is why --samples has to be a multiple of
+// --adaptive-min.
+
+use clap::ValueEnum;
+use num::complex::Complex;
+use rand::{Rng, RngCore};
+#[cfg(feature = "cli")]
+use std::cell::Cell;
+use std::sync::Arc;
+
+use crate::{Args, PixelData, Renderer};
+
+pub trait Sampler: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The jitters of one pixel's count samples, each from -1 to 1
+    fn pattern(&self, count: usize, rng: &mut PixelRng) -> Vec<(f32, f32)>;
+
+    // What iterating every sample of pixels found, pixel after pixel, each
+    // --samples long. The points all go through iterate, so a backend can
+    // take as many at once as it likes.
+    fn sample(
+        &self,
+        renderer: &Renderer,
+        pixels: &[i32],
+        iterate: &dyn Fn(&[Complex<f32>]) -> Vec<PixelData>,
+    ) -> Vec<PixelData> {
+        let count = renderer.args.samples;
+        let mut points = Vec::with_capacity(pixels.len() * count);
+        for &i in pixels {
+            let mut rng = renderer.rng(i);
+            for jitter in self.pattern(count, &mut rng) {
+                points.push(renderer.jittered(i, jitter));
+            }
+        }
+        iterate(&points)
+    }
+}
+
+const GOLDEN: u64 = 0x9e3779b97f4a7c15;
+
+// SplitMix64's output function
+fn mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Where a pixel's jitters come from. The start is mixed so neighboring
+// pixels don't get the same stream one step apart.
+#[derive(Clone, Debug)]
+pub struct PixelRng(u64);
+
+impl PixelRng {
+    pub fn new(seed: u64, i: i32) -> PixelRng {
+        PixelRng(mix(seed ^ mix(i as u64)))
+    }
+}
+
+impl RngCore for PixelRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GOLDEN);
+        mix(self.0)
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cli")]
+thread_local! {
+    static UNSEEDED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// A seed for a pixel of a render without --seed, the next of this
+// thread's stream
+#[cfg(feature = "cli")]
+pub fn unseeded() -> u64 {
+    UNSEEDED.with(|state| {
+        let next = state
+            .get()
+            .unwrap_or_else(rand::random)
+            .wrapping_add(GOLDEN);
+        state.set(Some(next));
+        mix(next)
+    })
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Sobol,
+    Adaptive,
+}
+
+pub struct Random;
+
+impl Sampler for Random {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn pattern(&self, count: usize, rng: &mut PixelRng) -> Vec<(f32, f32)> {
+        (0..count)
+            .map(|_| (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)))
+            .collect()
+    }
+}
+
+pub struct Stratified;
+
+impl Sampler for Stratified {
+    fn name(&self) -> &'static str {
+        "stratified"
+    }
+
+    // The smallest square grid that has a cell for every sample, filled
+    // row by row, so counts that aren't squares leave the last row short
+    fn pattern(&self, count: usize, rng: &mut PixelRng) -> Vec<(f32, f32)> {
+        let side = (count as f32).sqrt().ceil() as usize;
+        let cell = 2.0 / side as f32;
+        (0..count)
+            .map(|k| {
+                let (cx, cy) = ((k % side) as f32, (k / side) as f32);
+                (
+                    -1.0 + (cx + rng.gen_range(0.0..1.0)) * cell,
+                    -1.0 + (cy + rng.gen_range(0.0..1.0)) * cell,
+                )
+            })
+            .collect()
+    }
+}
+
+// Point k of the two dimensional Sobol sequence, from 0 to 1. The first
+// dimension is the van der Corput sequence, the second uses the direction
+// numbers of x + 1.
+fn sobol(k: usize) -> (f32, f32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut v = 1u32 << 31;
+    let mut w = 1u32 << 31;
+    let mut k = k as u32;
+    while k != 0 {
+        if k & 1 == 1 {
+            x ^= v;
+            y ^= w;
+        }
+        k >>= 1;
+        v >>= 1;
+        w ^= w >> 1;
+    }
+    let unit = |n: u32| n as f32 / 4294967296.0;
+    (unit(x), unit(y))
+}
+
+pub struct Sobol;
+
+impl Sampler for Sobol {
+    fn name(&self) -> &'static str {
+        "sobol"
+    }
+
+    // Shifted as a whole and wrapped around, so neighboring pixels don't
+    // line their samples up
+    fn pattern(&self, count: usize, rng: &mut PixelRng) -> Vec<(f32, f32)> {
+        let shift: (f32, f32) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        (0..count)
+            .map(|k| {
+                let (x, y) = sobol(k);
+                (
+                    ((x + shift.0) % 1.0) * 2.0 - 1.0,
+                    ((y + shift.1) % 1.0) * 2.0 - 1.0,
+                )
+            })
+            .collect()
+    }
+}
+
+pub struct Adaptive {
+    // Samples every pixel gets
+    pub min: usize,
+    // Largest difference in any sRGB channel, 0 to 1, between the first
+    // samples' colors that still counts as flat
+    pub threshold: f32,
+}
+
+impl Adaptive {
+    // Whether a pixel's first samples all but agree
+    fn flat(&self, renderer: &Renderer, records: &[PixelData]) -> bool {
+        let colors: Vec<[f32; 4]> = records
+            .iter()
+            .map(|p| renderer.shade(p).to_arr().map(f32::sqrt))
+            .collect();
+        (0..4).all(|c| {
+            let (low, high) = colors.iter().fold((f32::MAX, f32::MIN), |(l, h), color| {
+                (l.min(color[c]), h.max(color[c]))
+            });
+            high - low <= self.threshold
+        })
+    }
+}
+
+impl Sampler for Adaptive {
+    fn name(&self) -> &'static str {
+        "adaptive"
+    }
+
+    fn pattern(&self, count: usize, rng: &mut PixelRng) -> Vec<(f32, f32)> {
+        Sobol.pattern(count, rng)
+    }
+
+    fn sample(
+        &self,
+        renderer: &Renderer,
+        pixels: &[i32],
+        iterate: &dyn Fn(&[Complex<f32>]) -> Vec<PixelData>,
+    ) -> Vec<PixelData> {
+        let count = renderer.args.samples;
+        let first = self.min.min(count);
+        let patterns: Vec<Vec<(f32, f32)>> = pixels
+            .iter()
+            .map(|&i| self.pattern(count, &mut renderer.rng(i)))
+            .collect();
+        let points: Vec<Complex<f32>> = pixels
+            .iter()
+            .zip(&patterns)
+            .flat_map(|(&i, pattern)| {
+                pattern[..first]
+                    .iter()
+                    .map(move |&j| renderer.jittered(i, j))
+            })
+            .collect();
+        let firsts = iterate(&points);
+        let flat: Vec<bool> = firsts
+            .chunks(first)
+            .map(|records| first == count || self.flat(renderer, records))
+            .collect();
+        let points: Vec<Complex<f32>> = pixels
+            .iter()
+            .zip(&patterns)
+            .zip(&flat)
+            .filter(|(_, &flat)| !flat)
+            .flat_map(|((&i, pattern), _)| {
+                pattern[first..]
+                    .iter()
+                    .map(move |&j| renderer.jittered(i, j))
+            })
+            .collect();
+        let mut rest = iterate(&points).into_iter();
+        let mut records = Vec::with_capacity(pixels.len() * count);
+        for (firsts, &flat) in firsts.chunks(first).zip(&flat) {
+            if flat {
+                // The mean of these repeated is their own mean
+                for _ in 0..count / first {
+                    records.extend(firsts);
+                }
+            } else {
+                records.extend(firsts);
+                records.extend(rest.by_ref().take(count - first));
+            }
+        }
+        records
+    }
+}
+
+// The sampler --sampler names, configured from the other flags
+pub fn select(args: &Args) -> Arc<dyn Sampler> {
+    match args.sampler {
+        SamplerKind::Random => Arc::new(Random),
+        SamplerKind::Stratified => Arc::new(Stratified),
+        SamplerKind::Sobol => Arc::new(Sobol),
+        SamplerKind::Adaptive => Arc::new(Adaptive {
+            min: args.adaptive_min,
+            threshold: args.adaptive_threshold,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_functs;
+    use std::cell::RefCell;
+
+    fn renderer(flags: &[&str]) -> Renderer {
+        let argv = ["fracmd", "--width=12", "--height=8", "--seed=1"];
+        let args = Args::parse_argv(argv.iter().chain(flags).map(|f| f.to_string()));
+        Renderer::new(args.clone(), default_functs(&args))
+    }
+
+    // The same seed and pixel give the same jitters on any thread, and a
+    // pixel's stream isn't its neighbor's a step along
+    #[test]
+    fn pixel_streams_are_repeatable_and_apart() {
+        let draw = |seed, i| {
+            let mut rng = PixelRng::new(seed, i);
+            [rng.next_u64(), rng.next_u64()]
+        };
+        assert_eq!(draw(7, 40), draw(7, 40));
+        for i in 0..1000 {
+            let (this, next) = (draw(7, i), draw(7, i + 1));
+            assert!(!this.contains(&next[0]) && this != draw(8, i), "{}", i);
+        }
+    }
+
+    // Every point a sampler picks is within the square --sampled makes of
+    // its pixel, which at the default 2 is the pixel itself
+    #[test]
+    fn samples_stay_inside_the_pixel() {
+        let renderer = renderer(&[]);
+        let adaptive = Adaptive {
+            min: 1,
+            threshold: 0.0,
+        };
+        let samplers: [&dyn Sampler; 4] = [&Random, &Stratified, &Sobol, &adaptive];
+        for sampler in samplers {
+            for count in 1..=17 {
+                for i in 0..12 * 8 {
+                    let (a, b) = (
+                        renderer.jittered(i, (-1.0, -1.0)),
+                        renderer.jittered(i, (1.0, 1.0)),
+                    );
+                    let (low, high) = (
+                        Complex::new(a.re.min(b.re), a.im.min(b.im)),
+                        Complex::new(a.re.max(b.re), a.im.max(b.im)),
+                    );
+                    for jitter in sampler.pattern(count, &mut renderer.rng(i)) {
+                        let point = renderer.jittered(i, jitter);
+                        let inside = (low.re..=high.re).contains(&point.re)
+                            && (low.im..=high.im).contains(&point.im);
+                        assert!(inside, "{} {} {:?}", sampler.name(), count, jitter);
+                    }
+                }
+            }
+        }
+    }
+
+    // Everything counts as flat at 1, so only --adaptive-min samples are
+    // taken; at 0 the edges of the set get all of them
+    #[test]
+    fn the_adaptive_threshold_decides_how_many_samples_are_taken() {
+        let taken = |threshold: f32| {
+            let renderer = renderer(&["--sampler=adaptive", "--samples=16"]);
+            let adaptive = Adaptive { min: 4, threshold };
+            let pixels: Vec<i32> = (0..12 * 8).collect();
+            let count = RefCell::new(0);
+            let iterate = |points: &[Complex<f32>]| {
+                *count.borrow_mut() += points.len();
+                points.iter().map(|&c| renderer.iterate(c)).collect()
+            };
+            let records = adaptive.sample(&renderer, &pixels, &iterate);
+            assert_eq!(records.len(), pixels.len() * 16);
+            count.into_inner()
+        };
+        let (flat, fussy) = (taken(1.0), taken(0.0));
+        assert_eq!(flat, 12 * 8 * 4);
+        assert!(fussy > flat && fussy <= 12 * 8 * 16, "{}", fussy);
+    }
+}
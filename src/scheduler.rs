@@ -0,0 +1,192 @@
+// fracmd
+// Tile scheduling
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use image::{ImageBuffer, Pixel, Rgba};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::depth::Buffer;
+
+pub const TILE_SIZE: u32 = 64;
+
+// Exit status after an interrupted render was saved
+pub const INTERRUPTED_STATUS: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// The first Ctrl-C or SIGTERM lets running tiles finish and stops new ones
+// from starting, a second one quits straight away
+#[cfg(feature = "cli")]
+pub fn handle_interrupts() {
+    let installed = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_STATUS);
+        }
+        eprintln!("\nStopping after the current tiles, interrupt again to quit now");
+    });
+    if let Err(e) = installed {
+        eprintln!("Couldn't handle interrupts: {}", e);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// What a first interrupt does, for the control socket's stop and closing
+// the preview window
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Set from the control socket: tiles that haven't started wait while
+// paused, and no more than the limit render at once. Without a socket
+// neither ever makes a tile wait.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn pause(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+pub fn set_limit(threads: usize) {
+    LIMIT.store(threads, Ordering::SeqCst);
+}
+
+// Held while a tile renders
+pub struct Turn;
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Waits until a tile may start, None if the render was interrupted first
+pub fn turn() -> Option<Turn> {
+    loop {
+        if interrupted() {
+            return None;
+        }
+        let active = ACTIVE.load(Ordering::SeqCst);
+        if !paused()
+            && active < LIMIT.load(Ordering::SeqCst)
+            && ACTIVE
+                .compare_exchange(active, active + 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        {
+            return Some(Turn);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+pub type Image16 = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+// Row-major list of tiles covering the image, edge tiles are cropped
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut out = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            out.push(Tile {
+                x,
+                y,
+                w: size.min(width - x),
+                h: size.min(height - y),
+            });
+        }
+    }
+    out
+}
+
+// Passed to the render hook after every finished tile
+pub struct Progress<'a, P: Pixel = Rgba<u16>> {
+    pub tile: Tile,
+    pub done: usize,
+    pub total: usize,
+    // Everything rendered so far, unrendered pixels are transparent
+    pub image: &'a Mutex<Buffer<P>>,
+}
+
+pub struct Shared<P: Pixel = Rgba<u16>> {
+    pub image: Mutex<Buffer<P>>,
+    pub done: AtomicUsize,
+}
+
+impl<P: Pixel> Shared<P> {
+    // Starts from an image that may already have done tiles in it
+    pub fn resume(image: Buffer<P>, done: usize) -> Shared<P> {
+        Shared {
+            image: Mutex::new(image),
+            done: AtomicUsize::new(done),
+        }
+    }
+
+    // Copies a finished tile in and returns how many tiles are done
+    pub fn finish(&self, tile: Tile, pixels: &[P]) -> usize {
+        let mut image = self.image.lock().unwrap();
+        for y in 0..tile.h {
+            for x in 0..tile.w {
+                image.put_pixel(tile.x + x, tile.y + y, pixels[(y * tile.w + x) as usize]);
+            }
+        }
+        self.done.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+// Rate limits a callback shared between render threads, at most one call
+// runs at a time and calls closer than the interval are skipped
+pub struct Throttle {
+    interval: Duration,
+    last: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Throttle {
+        Throttle {
+            interval,
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn run<F: FnOnce()>(&self, f: F) {
+        if let Ok(mut last) = self.last.try_lock() {
+            if last.elapsed() >= self.interval {
+                f();
+                *last = Instant::now();
+            }
+        }
+    }
+}
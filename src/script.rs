@@ -0,0 +1,999 @@
+// fracmd
+// Formulas and colorings written as scripts, read with --script
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A script is lines of `name = expression` over complex numbers and
+// colors, see the readme for what it can use. Assigning step makes it a
+// fractal and color makes it a coloring, registered under the file's stem
+// like a plugin's would be. Every line is checked when the file is read,
+// so nothing can go wrong mid render, and lines that don't depend on the
+// point are worked out once then. What's left is a small tree walked per
+// step, slower than a plugin but without anything to compile.
+//
+// It's a language of its own rather than an embedded Rhai or Lua because
+// those have no complex numbers, so each operation would be a call on a
+// dynamic value, and because with no loops every step is sure to finish and
+// every mistake is found before rendering. The grammar is in the readme.
+
+use err_derive::Error;
+use num::complex::Complex;
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use crate::{
+    bailout,
+    coloring::Coloring,
+    fracdata::PixelData,
+    fractal::{self, Fractal},
+    plugin,
+    rgbaf::RgbaF,
+    Args, FracError,
+};
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "line {}: {}", _0, _1)]
+    Syntax(usize, String),
+    #[error(display = "it assigns neither step nor color")]
+    Empty,
+}
+
+// Everything a script can read from outside, each entry point gets some
+const INPUTS: [&str; 10] = [
+    "z",
+    "c",
+    "dz",
+    "iter",
+    "smooth",
+    "s",
+    "de",
+    "trap",
+    "converged",
+    "limit",
+];
+// Which of INPUTS each entry point is given
+const ENTRIES: [(&str, &[&str]); 9] = [
+    ("step", &["z", "c"]),
+    ("deriv", &["z", "dz", "c"]),
+    ("zderiv", &["z", "dz"]),
+    ("start", &["c"]),
+    ("map", &["c"]),
+    ("degree", &[]),
+    ("bail", &[]),
+    ("converge", &[]),
+    (
+        "color",
+        &[
+            "iter",
+            "smooth",
+            "s",
+            "z",
+            "de",
+            "trap",
+            "converged",
+            "limit",
+        ],
+    ),
+];
+// Slots an entry point can use, inputs included, so each call's
+// variables fit on the stack. Clearing them is a good part of a step, so
+// entry points that fit in FEW get that many.
+const SLOTS: usize = 32;
+const FEW: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Num(Complex<f32>),
+    Color(RgbaF),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Num,
+    Color,
+}
+
+impl Value {
+    const ZERO: Value = Value::Num(Complex::new(0.0, 0.0));
+
+    fn kind(&self) -> Kind {
+        match self {
+            Value::Num(_) => Kind::Num,
+            Value::Color(_) => Kind::Color,
+        }
+    }
+
+    // Kinds are checked before anything runs, these can't miss
+    fn num(self) -> Complex<f32> {
+        match self {
+            Value::Num(n) => n,
+            Value::Color(_) => Complex::new(0.0, 0.0),
+        }
+    }
+
+    fn color(self) -> RgbaF {
+        match self {
+            Value::Color(c) => c,
+            Value::Num(n) => RgbaF::new(n.re),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Ln,
+    Sqrt,
+    Abs,
+    Norm,
+    Arg,
+    Re,
+    Im,
+    Conj,
+    Floor,
+    Fract,
+    Min,
+    Max,
+    If,
+    Mix,
+    Rgb,
+    Rgba,
+    Hsv,
+}
+
+// Name, arity, what it takes and what it gives. if and mix take None:
+// one number to choose by and two of anything alike, which they give.
+const FUNCS: [(&str, Func, usize, Option<Kind>, Kind); 24] = [
+    ("sin", Func::Sin, 1, Some(Kind::Num), Kind::Num),
+    ("cos", Func::Cos, 1, Some(Kind::Num), Kind::Num),
+    ("tan", Func::Tan, 1, Some(Kind::Num), Kind::Num),
+    ("sinh", Func::Sinh, 1, Some(Kind::Num), Kind::Num),
+    ("cosh", Func::Cosh, 1, Some(Kind::Num), Kind::Num),
+    ("tanh", Func::Tanh, 1, Some(Kind::Num), Kind::Num),
+    ("exp", Func::Exp, 1, Some(Kind::Num), Kind::Num),
+    ("ln", Func::Ln, 1, Some(Kind::Num), Kind::Num),
+    ("sqrt", Func::Sqrt, 1, Some(Kind::Num), Kind::Num),
+    ("abs", Func::Abs, 1, Some(Kind::Num), Kind::Num),
+    ("norm", Func::Norm, 1, Some(Kind::Num), Kind::Num),
+    ("arg", Func::Arg, 1, Some(Kind::Num), Kind::Num),
+    ("re", Func::Re, 1, Some(Kind::Num), Kind::Num),
+    ("im", Func::Im, 1, Some(Kind::Num), Kind::Num),
+    ("conj", Func::Conj, 1, Some(Kind::Num), Kind::Num),
+    ("floor", Func::Floor, 1, Some(Kind::Num), Kind::Num),
+    ("fract", Func::Fract, 1, Some(Kind::Num), Kind::Num),
+    ("min", Func::Min, 2, Some(Kind::Num), Kind::Num),
+    ("max", Func::Max, 2, Some(Kind::Num), Kind::Num),
+    ("if", Func::If, 3, None, Kind::Num),
+    ("mix", Func::Mix, 3, None, Kind::Num),
+    ("rgb", Func::Rgb, 3, Some(Kind::Num), Kind::Color),
+    ("rgba", Func::Rgba, 4, Some(Kind::Num), Kind::Color),
+    ("hsv", Func::Hsv, 3, Some(Kind::Num), Kind::Color),
+];
+
+#[derive(Clone, Debug)]
+enum Node {
+    Const(Value),
+    Var(usize),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+fn re(x: f32) -> Complex<f32> {
+    Complex::new(x, 0.0)
+}
+
+fn truth(b: bool) -> Complex<f32> {
+    re(if b { 1.0 } else { 0.0 })
+}
+
+// Integer powers multiply, they're exact and the usual case
+fn pow(a: Complex<f32>, b: Complex<f32>) -> Complex<f32> {
+    if b.im != 0.0 {
+        a.powc(b)
+    } else if b.re == 2.0 {
+        a * a
+    } else if b.re.fract() == 0.0 && b.re.abs() <= 16.0 {
+        a.powi(b.re as i32)
+    } else {
+        a.powf(b.re)
+    }
+}
+
+fn binary(op: Op, a: Value, b: Value) -> Value {
+    use Value::{Color, Num};
+    match (op, a, b) {
+        (Op::Add, Num(a), Num(b)) => Num(a + b),
+        (Op::Sub, Num(a), Num(b)) => Num(a - b),
+        (Op::Mul, Num(a), Num(b)) => Num(a * b),
+        (Op::Div, Num(a), Num(b)) => Num(a / b),
+        (Op::Pow, Num(a), Num(b)) => Num(pow(a, b)),
+        (Op::Less, Num(a), Num(b)) => Num(truth(a.re < b.re)),
+        (Op::Greater, Num(a), Num(b)) => Num(truth(a.re > b.re)),
+        (Op::LessEq, Num(a), Num(b)) => Num(truth(a.re <= b.re)),
+        (Op::GreaterEq, Num(a), Num(b)) => Num(truth(a.re >= b.re)),
+        (Op::Add, Color(a), Color(b)) => Color(a + b),
+        (Op::Sub, Color(a), Color(b)) => Color(a - b),
+        (Op::Mul, Color(a), Num(b)) | (Op::Mul, Num(b), Color(a)) => Color(a * b.re),
+        (Op::Div, Color(a), Num(b)) => Color(a / b.re),
+        _ => Value::ZERO,
+    }
+}
+
+// What binary gives for these kinds, None if it has nothing for them
+fn binary_kind(op: Op, a: Kind, b: Kind) -> Option<Kind> {
+    match (op, a, b) {
+        (_, Kind::Num, Kind::Num) => Some(Kind::Num),
+        (Op::Add | Op::Sub, Kind::Color, Kind::Color)
+        | (Op::Mul, Kind::Color, Kind::Num)
+        | (Op::Mul, Kind::Num, Kind::Color)
+        | (Op::Div, Kind::Color, Kind::Num) => Some(Kind::Color),
+        _ => None,
+    }
+}
+
+fn call(func: Func, args: &[Value]) -> Value {
+    let n = |i: usize| args[i].num();
+    let r = |i: usize| args[i].num().re;
+    Value::Num(match func {
+        Func::Sin => n(0).sin(),
+        Func::Cos => n(0).cos(),
+        Func::Tan => n(0).tan(),
+        Func::Sinh => n(0).sinh(),
+        Func::Cosh => n(0).cosh(),
+        Func::Tanh => n(0).tanh(),
+        Func::Exp => n(0).exp(),
+        Func::Ln => n(0).ln(),
+        Func::Sqrt => n(0).sqrt(),
+        Func::Abs => re(n(0).norm()),
+        Func::Norm => re(n(0).norm_sqr()),
+        Func::Arg => re(n(0).arg()),
+        Func::Re => re(r(0)),
+        Func::Im => re(n(0).im),
+        Func::Conj => n(0).conj(),
+        Func::Floor => Complex::new(r(0).floor(), n(0).im.floor()),
+        Func::Fract => Complex::new(r(0).rem_euclid(1.0), n(0).im.rem_euclid(1.0)),
+        Func::Min => re(r(0).min(r(1))),
+        Func::Max => re(r(0).max(r(1))),
+        Func::If => return if r(0) > 0.0 { args[1] } else { args[2] },
+        Func::Mix => {
+            let t = r(2);
+            return match (args[0], args[1]) {
+                (Value::Color(a), Value::Color(b)) => Value::Color(a * (1.0 - t) + b * t),
+                (a, b) => Value::Num(a.num() * (1.0 - t) + b.num() * t),
+            };
+        }
+        Func::Rgb => return Value::Color(RgbaF::new_color(r(0), r(1), r(2))),
+        Func::Rgba => return Value::Color(RgbaF::new_color_alpha(r(0), r(1), r(2), r(3))),
+        Func::Hsv => return Value::Color(RgbaF::from_hsv(r(0).rem_euclid(360.0), r(1), r(2), 1.0)),
+    })
+}
+
+impl Node {
+    fn eval(&self, env: &[Value]) -> Value {
+        match self {
+            Node::Const(v) => *v,
+            Node::Var(slot) => env[*slot],
+            Node::Neg(a) => binary(Op::Sub, Value::ZERO, a.eval(env)),
+            Node::Binary(op, a, b) => binary(*op, a.eval(env), b.eval(env)),
+            Node::Call(func, args) => {
+                let mut values = [Value::ZERO; 4];
+                for (v, a) in values.iter_mut().zip(args) {
+                    *v = a.eval(env);
+                }
+                call(*func, &values[..args.len()])
+            }
+        }
+    }
+
+    fn is_const(&self) -> bool {
+        match self {
+            Node::Const(_) => true,
+            Node::Var(_) => false,
+            Node::Neg(a) => a.is_const(),
+            Node::Binary(_, a, b) => a.is_const() && b.is_const(),
+            Node::Call(_, args) => args.iter().all(Node::is_const),
+        }
+    }
+
+    fn vars(&self, found: &mut Vec<usize>) {
+        match self {
+            Node::Const(_) => (),
+            Node::Var(slot) => found.push(*slot),
+            Node::Neg(a) => a.vars(found),
+            Node::Binary(_, a, b) => {
+                a.vars(found);
+                b.vars(found);
+            }
+            Node::Call(_, args) => args.iter().for_each(|a| a.vars(found)),
+        }
+    }
+
+    fn renumber(&mut self, to: &HashMap<usize, usize>) {
+        match self {
+            Node::Const(_) => (),
+            Node::Var(slot) => *slot = to[slot],
+            Node::Neg(a) => a.renumber(to),
+            Node::Binary(_, a, b) => {
+                a.renumber(to);
+                b.renumber(to);
+            }
+            Node::Call(_, args) => args.iter_mut().for_each(|a| a.renumber(to)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f32),
+    Imag(f32),
+    Name(String),
+    Sym(&'static str),
+}
+
+const SYMBOLS: [&str; 13] = [
+    "<=", ">=", "<", ">", "+", "-", "*", "/", "^", "(", ")", ",", "=",
+];
+
+fn tokens(line: &str) -> Result<Vec<Token>, String> {
+    let line = line.split('#').next().unwrap_or("");
+    let mut out = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_ascii_digit() || c == '.' {
+            let mut end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            // An exponent, 1e-3
+            if rest[end..].starts_with('e') {
+                let after = &rest[end + 1..];
+                let sign = usize::from(after.starts_with(['+', '-']));
+                let digits = after[sign..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(after.len() - sign);
+                if digits > 0 {
+                    end += 1 + sign + digits;
+                }
+            }
+            let n: f32 = rest[..end]
+                .parse()
+                .map_err(|_| format!("{} isn't a number", &rest[..end]))?;
+            rest = &rest[end..];
+            let imaginary = rest.starts_with('i')
+                && !rest[1..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+            if imaginary {
+                rest = &rest[1..];
+                out.push(Token::Imag(n));
+            } else {
+                out.push(Token::Num(n));
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            out.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let sym = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(*s))
+                .ok_or_else(|| format!("unexpected {}", c))?;
+            out.push(Token::Sym(sym));
+            rest = &rest[sym.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(out)
+}
+
+// What a name means so far. Names can be assigned again, later lines see
+// the newest.
+#[derive(Clone, Copy)]
+enum Binding {
+    Const(Value),
+    Slot(usize, Kind),
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    at: usize,
+    names: &'a HashMap<String, Binding>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Sym(s)) if *s == sym);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), String> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(t) => format!("expected {} before {}", sym, show(t)),
+                None => format!("expected {} at the end", sym),
+            })
+        }
+    }
+
+    fn op(node: Node, kind: Kind, op: Op, rhs: (Node, Kind)) -> Result<(Node, Kind), String> {
+        let kind = binary_kind(op, kind, rhs.1).ok_or_else(|| {
+            format!(
+                "can't {} a {} and a {}",
+                match op {
+                    Op::Add => "add",
+                    Op::Sub => "subtract",
+                    Op::Mul => "multiply",
+                    Op::Div => "divide",
+                    Op::Pow => "raise",
+                    _ => "compare",
+                },
+                kind_name(kind),
+                kind_name(rhs.1)
+            )
+        })?;
+        Ok((Node::Binary(op, Box::new(node), Box::new(rhs.0)), kind))
+    }
+
+    fn compare(&mut self) -> Result<(Node, Kind), String> {
+        let (node, kind) = self.sum()?;
+        for (sym, op) in [
+            ("<=", Op::LessEq),
+            (">=", Op::GreaterEq),
+            ("<", Op::Less),
+            (">", Op::Greater),
+        ] {
+            if self.eat(sym) {
+                let rhs = self.sum()?;
+                return Parser::op(node, kind, op, rhs);
+            }
+        }
+        Ok((node, kind))
+    }
+
+    fn sum(&mut self) -> Result<(Node, Kind), String> {
+        let (mut node, mut kind) = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                Op::Add
+            } else if self.eat("-") {
+                Op::Sub
+            } else {
+                return Ok((node, kind));
+            };
+            let rhs = self.product()?;
+            (node, kind) = Parser::op(node, kind, op, rhs)?;
+        }
+    }
+
+    fn product(&mut self) -> Result<(Node, Kind), String> {
+        let (mut node, mut kind) = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                Op::Mul
+            } else if self.eat("/") {
+                Op::Div
+            } else {
+                return Ok((node, kind));
+            };
+            let rhs = self.unary()?;
+            (node, kind) = Parser::op(node, kind, op, rhs)?;
+        }
+    }
+
+    // -z^2 is -(z^2), and powers go right to left
+    fn unary(&mut self) -> Result<(Node, Kind), String> {
+        if self.eat("-") {
+            let (node, kind) = self.unary()?;
+            if kind != Kind::Num {
+                return Err(String::from("can't negate a color"));
+            }
+            return Ok((Node::Neg(Box::new(node)), kind));
+        }
+        let (node, kind) = self.atom()?;
+        if self.eat("^") {
+            let rhs = self.unary()?;
+            return Parser::op(node, kind, Op::Pow, rhs);
+        }
+        Ok((node, kind))
+    }
+
+    fn atom(&mut self) -> Result<(Node, Kind), String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| String::from("expected a value at the end"))?;
+        self.at += 1;
+        match token {
+            Token::Num(n) => Ok((Node::Const(Value::Num(re(n))), Kind::Num)),
+            Token::Imag(n) => Ok((Node::Const(Value::Num(Complex::new(0.0, n))), Kind::Num)),
+            Token::Sym("(") => {
+                let inner = self.compare()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Name(name) if self.eat("(") => self.call(&name),
+            Token::Name(name) => match self.names.get(&name) {
+                Some(Binding::Const(v)) => Ok((Node::Const(*v), v.kind())),
+                Some(Binding::Slot(slot, kind)) => Ok((Node::Var(*slot), *kind)),
+                None => Err(format!("{} isn't defined", name)),
+            },
+            t => Err(format!("unexpected {}", show(&t))),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<(Node, Kind), String> {
+        let &(_, func, arity, takes, gives) = FUNCS
+            .iter()
+            .find(|f| f.0 == name)
+            .ok_or_else(|| format!("there's no function {}", name))?;
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.compare()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() != arity {
+            return Err(format!(
+                "{} takes {} argument{}, not {}",
+                name,
+                arity,
+                if arity == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+        let kind = match takes {
+            Some(takes) => {
+                if let Some((_, k)) = args.iter().find(|(_, k)| *k != takes) {
+                    return Err(format!("{} takes numbers, not a {}", name, kind_name(*k)));
+                }
+                gives
+            }
+            // if and mix, the first or last argument is a number
+            None => {
+                let (a, b, t) = match func {
+                    Func::If => (args[1].1, args[2].1, args[0].1),
+                    _ => (args[0].1, args[1].1, args[2].1),
+                };
+                if t != Kind::Num {
+                    return Err(format!("{} needs a number to choose by", name));
+                }
+                if a != b {
+                    return Err(format!("{} needs two numbers or two colors", name));
+                }
+                a
+            }
+        };
+        Ok((
+            Node::Call(func, args.into_iter().map(|a| a.0).collect()),
+            kind,
+        ))
+    }
+}
+
+fn show(token: &Token) -> String {
+    match token {
+        Token::Num(n) => n.to_string(),
+        Token::Imag(n) => format!("{}i", n),
+        Token::Name(n) => n.clone(),
+        Token::Sym(s) => s.to_string(),
+    }
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Num => "number",
+        Kind::Color => "color",
+    }
+}
+
+// One entry point, the lines it needs in order with slots renumbered so
+// its inputs come first, in the order ENTRIES gives them
+#[derive(Debug)]
+struct Program {
+    slots: usize,
+    lines: Vec<(usize, Node)>,
+    out: Node,
+}
+
+impl Program {
+    fn run(&self, given: &[Value]) -> Value {
+        if self.slots <= FEW {
+            self.run_in::<FEW>(given)
+        } else {
+            self.run_in::<SLOTS>(given)
+        }
+    }
+
+    fn run_in<const N: usize>(&self, given: &[Value]) -> Value {
+        let mut env = [Value::ZERO; N];
+        env[..given.len()].copy_from_slice(given);
+        for (slot, node) in &self.lines {
+            env[*slot] = node.eval(&env);
+        }
+        self.out.eval(&env)
+    }
+
+    fn num(&self, given: &[Complex<f32>]) -> Complex<f32> {
+        let mut values = [Value::ZERO; 3];
+        for (v, &g) in values.iter_mut().zip(given) {
+            *v = Value::Num(g);
+        }
+        self.run(&values[..given.len()]).num()
+    }
+}
+
+// A script's fractal, derivatives it leaves out are estimated from step
+#[derive(Debug)]
+pub struct ScriptFractal {
+    step: Program,
+    deriv: Option<Program>,
+    zderiv: Option<Program>,
+    start: Option<Program>,
+    map: Option<Program>,
+    degree: f32,
+    bail: Option<f32>,
+    converge: Option<f32>,
+}
+
+impl Fractal for ScriptFractal {
+    fn step(&self, z: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        self.step.num(&[z, c])
+    }
+
+    fn deriv(&self, z: Complex<f32>, dz: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        match &self.deriv {
+            Some(deriv) => deriv.num(&[z, dz, c]),
+            None => fractal::estimate_deriv(self, z, dz, c),
+        }
+    }
+
+    fn zderiv(&self, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+        match &self.zderiv {
+            Some(zderiv) => zderiv.num(&[z, dz]),
+            None => fractal::estimate_zderiv(self, z, dz),
+        }
+    }
+
+    fn start(&self, c: Complex<f32>) -> Complex<f32> {
+        self.start.as_ref().map_or(c, |start| start.num(&[c]))
+    }
+
+    fn map(&self, c: Complex<f32>) -> Complex<f32> {
+        self.map.as_ref().map_or(c, |map| map.num(&[c]))
+    }
+
+    fn degree(&self) -> f32 {
+        self.degree
+    }
+
+    fn bail(&self) -> f32 {
+        self.bail.unwrap_or(bailout::DEFAULT_BAIL)
+    }
+
+    fn converge(&self) -> Option<f32> {
+        self.converge
+    }
+}
+
+#[derive(Debug)]
+pub struct ScriptColoring {
+    color: Program,
+}
+
+impl Coloring for ScriptColoring {
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF {
+        let n = |x: f32| Value::Num(re(x));
+        self.color
+            .run(&[
+                n(p.iter),
+                n(p.smooth),
+                n(p.s),
+                Value::Num(p.z),
+                n(p.de),
+                n(p.trap),
+                n(p.converged),
+                n(limit),
+            ])
+            .color()
+    }
+}
+
+// What a script defines, either half may be missing but not both
+#[derive(Debug)]
+pub struct Script {
+    pub fractal: Option<ScriptFractal>,
+    pub coloring: Option<ScriptColoring>,
+}
+
+struct Line {
+    number: usize,
+    slot: usize,
+    node: Node,
+    vars: Vec<usize>,
+}
+
+pub fn parse(source: &str) -> Result<Script, ScriptError> {
+    let mut names: HashMap<String, Binding> = HashMap::new();
+    for (slot, name) in INPUTS.iter().enumerate() {
+        names.insert(name.to_string(), Binding::Slot(slot, Kind::Num));
+    }
+    for (name, v) in [
+        ("pi", re(std::f32::consts::PI)),
+        ("e", re(std::f32::consts::E)),
+        ("i", Complex::new(0.0, 1.0)),
+    ] {
+        names.insert(name.to_string(), Binding::Const(Value::Num(v)));
+    }
+    let mut lines: Vec<Line> = Vec::new();
+    // Where each name was last assigned, for errors about entry points
+    let mut assigned: HashMap<String, usize> = HashMap::new();
+    let mut slots = INPUTS.len();
+    for (number, text) in source.lines().enumerate() {
+        let number = number + 1;
+        let tokens = tokens(text).map_err(|e| ScriptError::Syntax(number, e))?;
+        let name = match tokens.as_slice() {
+            [] => continue,
+            [Token::Name(name), Token::Sym("="), ..] => name.clone(),
+            _ => {
+                return Err(ScriptError::Syntax(
+                    number,
+                    String::from("lines have to be name = expression"),
+                ))
+            }
+        };
+        let mut parser = Parser {
+            tokens: &tokens[2..],
+            at: 0,
+            names: &names,
+        };
+        let (node, kind) = parser
+            .compare()
+            .map_err(|e| ScriptError::Syntax(number, e))?;
+        if let Some(t) = parser.peek() {
+            return Err(ScriptError::Syntax(
+                number,
+                format!("unexpected {}", show(t)),
+            ));
+        }
+        let binding = if node.is_const() {
+            Binding::Const(node.eval(&[]))
+        } else {
+            let mut vars = Vec::new();
+            node.vars(&mut vars);
+            lines.push(Line {
+                number,
+                slot: slots,
+                node,
+                vars,
+            });
+            slots += 1;
+            Binding::Slot(slots - 1, kind)
+        };
+        assigned.insert(name.clone(), number);
+        names.insert(name, binding);
+    }
+    let line_of = |name: &str| assigned.get(name).copied().unwrap_or(0);
+
+    let entry = |name: &str| -> Result<Option<(Program, Kind)>, ScriptError> {
+        let allowed = ENTRIES.iter().find(|e| e.0 == name).unwrap().1;
+        match names.get(name) {
+            None => Ok(None),
+            Some(&Binding::Const(v)) => Ok(Some((
+                Program {
+                    slots: 0,
+                    lines: Vec::new(),
+                    out: Node::Const(v),
+                },
+                v.kind(),
+            ))),
+            Some(&Binding::Slot(out, kind)) => {
+                let program = program(&lines, out, name, allowed)?;
+                Ok(Some((program, kind)))
+            }
+        }
+    };
+    let number = |name: &str| -> Result<Option<Program>, ScriptError> {
+        match entry(name)? {
+            Some((_, Kind::Color)) => Err(ScriptError::Syntax(
+                line_of(name),
+                format!("{} has to be a number, not a color", name),
+            )),
+            e => Ok(e.map(|e| e.0)),
+        }
+    };
+
+    let fractal = match number("step")? {
+        Some(step) => {
+            let constant = |name: &str| match names.get(name) {
+                None => Ok(None),
+                Some(Binding::Const(Value::Num(d))) => Ok(Some(d.re)),
+                Some(_) => Err(ScriptError::Syntax(
+                    line_of(name),
+                    format!("{} has to be a number that doesn't change", name),
+                )),
+            };
+            Some(ScriptFractal {
+                step,
+                deriv: number("deriv")?,
+                zderiv: number("zderiv")?,
+                start: number("start")?,
+                map: number("map")?,
+                degree: constant("degree")?.unwrap_or(2.0),
+                bail: constant("bail")?,
+                converge: constant("converge")?,
+            })
+        }
+        None => None,
+    };
+    let coloring = match entry("color")? {
+        Some((color, Kind::Color)) => Some(ScriptColoring { color }),
+        Some((_, Kind::Num)) => {
+            return Err(ScriptError::Syntax(
+                line_of("color"),
+                String::from("color has to be a color, made with rgb, rgba or hsv"),
+            ))
+        }
+        None => None,
+    };
+    if fractal.is_none() && coloring.is_none() {
+        return Err(ScriptError::Empty);
+    }
+    Ok(Script { fractal, coloring })
+}
+
+// The lines out depends on, checking it only reads what the entry is given
+fn program(
+    lines: &[Line],
+    out: usize,
+    name: &str,
+    allowed: &[&str],
+) -> Result<Program, ScriptError> {
+    let index = |slot: usize| lines.iter().position(|l| l.slot == slot);
+    let mut needed = vec![false; lines.len()];
+    let mut todo = vec![out];
+    while let Some(slot) = todo.pop() {
+        if let Some(i) = index(slot) {
+            if !needed[i] {
+                needed[i] = true;
+                todo.extend(&lines[i].vars);
+            }
+        }
+    }
+    let used: Vec<&Line> = lines
+        .iter()
+        .zip(&needed)
+        .filter(|(_, n)| **n)
+        .map(|(l, _)| l)
+        .collect();
+    for line in &used {
+        for &slot in &line.vars {
+            if slot < INPUTS.len() && !allowed.contains(&INPUTS[slot]) {
+                return Err(ScriptError::Syntax(
+                    line.number,
+                    format!(
+                        "{} can't use {}, only {}",
+                        name,
+                        INPUTS[slot],
+                        if allowed.is_empty() {
+                            String::from("constants")
+                        } else {
+                            allowed.join(", ")
+                        }
+                    ),
+                ));
+            }
+        }
+    }
+    let number = used.last().map_or(0, |l| l.number);
+    if allowed.len() + used.len() > SLOTS {
+        return Err(ScriptError::Syntax(
+            number,
+            format!("{} needs more than {} variables", name, SLOTS),
+        ));
+    }
+    let mut to = HashMap::new();
+    for (i, input) in allowed.iter().enumerate() {
+        to.insert(INPUTS.iter().position(|n| n == input).unwrap(), i);
+    }
+    let mut program = Vec::new();
+    for (i, line) in used.iter().enumerate() {
+        to.insert(line.slot, allowed.len() + i);
+    }
+    for line in used {
+        // The same line can feed several entry points, each renumbers its own
+        let mut node = line.node.clone();
+        node.renumber(&to);
+        program.push((to[&line.slot], node));
+    }
+    let (_, out) = program.pop().unwrap();
+    Ok(Program {
+        slots: allowed.len() + program.len(),
+        lines: program,
+        out,
+    })
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Script, ScriptError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+// Every --script, registered under its file stem. One that brings a fractal
+// or coloring is used for it unless --fractal or --coloring says otherwise.
+pub fn load_all(args: &mut Args) -> Result<(), FracError> {
+    for path in args.script.clone() {
+        let script = load(&path).map_err(crate::error::at(&path))?;
+        let name = path.file_stem().map_or_else(
+            || String::from("script"),
+            |s| s.to_string_lossy().into_owned(),
+        );
+        let mut brings = Vec::new();
+        if let Some(fractal) = script.fractal {
+            plugin::register_fractal(&name, Arc::new(fractal));
+            if !args.given("fractal") {
+                args.fractal = name.clone();
+            }
+            brings.push("fractal");
+        }
+        if let Some(coloring) = script.coloring {
+            plugin::register_coloring(&name, Arc::new(coloring));
+            if !args.given("coloring") {
+                args.coloring = name.clone();
+            }
+            brings.push("coloring");
+        }
+        args.log(&format!(
+            "Loaded {} {} from {}",
+            name,
+            brings.join(" and "),
+            path.display()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,781 @@
+// fracmd
+// A web page exploring the view as a map, for `fracmd serve`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The page is a Leaflet map over the same tiles `fracmd tiles` renders,
+// each one rendered the first time a browser asks for it and read from the
+// tile cache after, so a view explored once loads instantly the next time
+// and the cache can be shared with `fracmd stitch`. Requests are plain
+// HTTP/1.1 GETs answered one per connection:
+//
+//   /                           the page
+//   /tiles/<key>/<z>/<x>/<y>.png  a tile, key naming the parameters
+//   /view?x=&y=&z=&w=           the command line rendering what's on screen
+//   /live                       a page rendering the view live instead
+//   /socket                     the WebSocket /live talks to, for the
+//                               server's own pages only, see own_origin
+//
+// The key in tile urls lets browsers cache tiles for good, a server started
+// with other parameters asks for other urls. Connections are answered
+// through accept and read_request, which serve-api shares, so only so many
+// are open at once, quiet ones are dropped and headers are capped.
+//
+// Over the socket the page sends json changes to the view,
+//
+//   {"size": [800, 600]}  {"pan": [dx, dy]}  {"zoom": 1.25, "at": [x, y]}
+//   {"flags": ["--limit=1024", "--cexp=1.5"]}
+//
+// pan being how far the image was dragged and at the pixel to zoom about.
+// The server answers each with {"view": "fracmd ...", "width": w,
+// "height": h}, then binary messages of a 16 byte header, the x, y, width
+// and height of where the image goes as big endian u32s, and an 8 bit png
+// of it: the whole frame coarse first, then every tile as it's done, and
+// {"done": ms} at the end. A change arriving mid render drops the rest of
+// it, and flags that don't parse or validate come back as {"error": "..."}
+// with the view left as it was. Flags are held to the same caps as a size,
+// MOST on each side and --max-pixels and --max-limit like serve-api.
+
+use err_derive::Error;
+use image::ImageBuffer;
+use num::complex::Complex;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config, coords, default_functs, farm,
+    par::*,
+    scheduler::{self, Image16, Tile, TILE_SIZE},
+    terminal, tiles, validate,
+    websocket::{self, Message},
+    wizard, Args, FracError, Renderer,
+};
+
+// The biggest frame a page can ask for on each side
+const MOST: i32 = 4096;
+// Longest request line or header, and most headers, read from a client
+const MOST_HEADER: u64 = 8192;
+const MOST_HEADERS: usize = 100;
+// Connections answered at once, the rest are closed straight away
+const MOST_CONNECTIONS: usize = 64;
+// How long a client can go without sending or taking anything
+const TIMEOUT: Duration = Duration::from_secs(30);
+// The coarse frame is this many times smaller
+const COARSE: i32 = 8;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>fracmd {name}</title>
+<link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css">
+<script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"></script>
+<style>
+html, body, #map { margin: 0; height: 100%; background: #000; }
+#view { position: absolute; bottom: 0; left: 0; z-index: 1000; padding: 4px 8px;
+  background: rgba(0, 0, 0, 0.7); color: #fff; font: 12px monospace; }
+</style>
+</head>
+<body>
+<div id="map"></div>
+<div id="view"></div>
+<script>
+const size = {size};
+const map = L.map("map", { crs: L.CRS.Simple, minZoom: 0, maxZoom: {max}, zoomSnap: 0.25 });
+const bounds = L.latLngBounds([[-size, 0], [0, size]]);
+L.tileLayer("/tiles/{key}/{z}/{x}/{y}.png", {
+  tileSize: size, bounds: bounds, noWrap: true, maxNativeZoom: {max}, maxZoom: {max},
+}).addTo(map);
+map.fitBounds(bounds);
+const show = () => {
+  const center = map.getCenter();
+  const query = `x=${center.lng}&y=${-center.lat}&z=${map.getZoom()}&w=${map.getSize().x}`;
+  fetch(`/view?${query}`).then((r) => r.text()).then((text) => {
+    document.getElementById("view").textContent = text;
+  });
+};
+map.on("moveend", show);
+show();
+</script>
+</body>
+</html>
+"#;
+
+const LIVE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>fracmd {name}</title>
+<style>
+html, body { margin: 0; height: 100%; overflow: hidden; background: #000; }
+canvas { position: absolute; top: 0; left: 0; image-rendering: pixelated; cursor: grab; }
+#bar { position: absolute; bottom: 0; left: 0; right: 0; display: flex; gap: 8px; padding: 4px 8px;
+  background: rgba(0, 0, 0, 0.7); color: #fff; font: 12px monospace; }
+#flags { flex: 0 0 30%; background: #222; color: #fff; border: 1px solid #555; font: inherit; }
+</style>
+</head>
+<body>
+<canvas id="frame"></canvas>
+<div id="bar"><input id="flags" placeholder="--limit=1024 --cexp=1.5, then enter"><span id="view"></span></div>
+<script>
+const canvas = document.getElementById("frame");
+const context = canvas.getContext("2d");
+const view = document.getElementById("view");
+const socket = new WebSocket(`ws://${location.host}/socket`);
+socket.binaryType = "arraybuffer";
+const send = (message) => socket.send(JSON.stringify(message));
+let command = "";
+// In order, so a late coarse frame never covers finished tiles
+let drawing = Promise.resolve();
+socket.onmessage = (event) => drawing = drawing.then(async () => {
+  if (typeof event.data === "string") {
+    const message = JSON.parse(event.data);
+    if (message.view !== undefined) {
+      command = message.view;
+      view.textContent = command;
+      canvas.width = message.width;
+      canvas.height = message.height;
+      canvas.style.transform = "";
+    }
+    if (message.done !== undefined) view.textContent = `${command}  (${message.done}ms)`;
+    if (message.error !== undefined) view.textContent = message.error;
+    return;
+  }
+  const head = new DataView(event.data, 0, 16);
+  const [x, y, w, h] = [0, 4, 8, 12].map((at) => head.getUint32(at));
+  const image = await createImageBitmap(new Blob([event.data.slice(16)], { type: "image/png" }));
+  context.imageSmoothingEnabled = false;
+  context.drawImage(image, x, y, w, h);
+});
+const size = () => send({ size: [innerWidth, innerHeight] });
+socket.onopen = size;
+socket.onclose = () => view.textContent = "The server went away";
+addEventListener("resize", size);
+let dragging = null;
+canvas.onmousedown = (e) => dragging = [e.clientX, e.clientY];
+addEventListener("mousemove", (e) => {
+  if (dragging) canvas.style.transform = `translate(${e.clientX - dragging[0]}px, ${e.clientY - dragging[1]}px)`;
+});
+addEventListener("mouseup", (e) => {
+  if (!dragging) return;
+  const moved = [e.clientX - dragging[0], e.clientY - dragging[1]];
+  dragging = null;
+  if (moved[0] || moved[1]) send({ pan: moved });
+});
+canvas.onwheel = (e) => {
+  e.preventDefault();
+  send({ zoom: e.deltaY < 0 ? 1.25 : 0.8, at: [e.offsetX, e.offsetY] });
+};
+document.getElementById("flags").onkeydown = (e) => {
+  if (e.key === "Enter") send({ flags: e.target.value.split(/\s+/).filter((f) => f) });
+};
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "couldn't listen on {}: {}", _0, _1)]
+    Listen(String, io::Error),
+}
+
+struct Server {
+    args: Args,
+    // What --host it listens on
+    host: String,
+    key: String,
+    dir: PathBuf,
+    size: u32,
+    max_pixels: u64,
+    max_limit: f32,
+}
+
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub kind: &'static str,
+    // Tiles never change under their url
+    pub cache: bool,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(status: &'static str, body: String) -> Response {
+        Response {
+            status,
+            kind: "text/plain; charset=utf-8",
+            cache: false,
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn json(status: &'static str, body: Value) -> Response {
+        Response {
+            status,
+            kind: "application/json",
+            cache: false,
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    // Written as the whole answer, the connection closing after it
+    pub fn send(&self, mut stream: TcpStream) -> io::Result<()> {
+        let cache = match self.cache {
+            true => "public, max-age=31536000, immutable",
+            false => "no-store",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.kind,
+            self.body.len(),
+            cache
+        )?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+// An http request up to its body
+pub(crate) struct Request {
+    // The request line split at spaces, GET /path HTTP/1.1
+    pub words: Vec<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// The request line and headers, giving up on lines longer than MOST_HEADER
+// and more than MOST_HEADERS headers rather than reading whatever a client
+// sends into memory
+pub(crate) fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let mut line = String::new();
+    farm::read_line(reader, &mut line, MOST_HEADER)?;
+    let words = line.split_whitespace().map(String::from).collect();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if farm::read_line(reader, &mut line, MOST_HEADER)? <= 2 {
+            break;
+        }
+        if headers.len() == MOST_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("more than {} headers", MOST_HEADERS),
+            ));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    Ok(Request { words, headers })
+}
+
+// Answers every connection on its own thread with answer, at most
+// MOST_CONNECTIONS at once and each dropped once it's quiet for TIMEOUT
+pub(crate) fn accept<F>(listener: TcpListener, answer: F)
+where
+    F: Fn(TcpStream) -> Result<(), ServeError> + Send + Sync + 'static,
+{
+    let answer = Arc::new(answer);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MOST_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let (answer, connections) = (answer.clone(), connections.clone());
+        thread::spawn(move || {
+            let answered = stream
+                .set_read_timeout(Some(TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+                .map_err(ServeError::from)
+                .and_then(|_| answer(stream));
+            if let Err(e) = answered {
+                eprintln!("Couldn't answer a request: {}", e);
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+// The view a w pixels wide window shows centered on x, y at level z, x
+// and y measured in pixels of the level 0 tile like the map does
+fn view_at(server: &Server, x: f32, y: f32, z: f32, w: f32) -> Args {
+    let args = &server.args;
+    let side = 2.0 / args.zoom;
+    let size = server.size as f32;
+    let center = Complex::new(-side / 2.0 + x / size * side, -side / 2.0 + y / size * side);
+    let mut view = args.clone();
+    view.origin = args.origin + args.view().apply(center);
+    view.zoom = args.zoom * 2f32.powf(z) * size / w.max(1.0);
+    view
+}
+
+// The number in each path part that should be one, None if any isn't
+fn numbers(parts: &[&str]) -> Option<Vec<u32>> {
+    parts.iter().map(|p| p.parse().ok()).collect()
+}
+
+fn route(server: &Server, path: &str) -> Response {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match parts.as_slice() {
+        [""] => Response {
+            status: "200 OK",
+            kind: "text/html; charset=utf-8",
+            cache: false,
+            body: PAGE
+                .replace("{name}", &server.args.name)
+                .replace("{size}", &server.size.to_string())
+                .replace("{max}", &tiles::MAX_LEVEL.to_string())
+                .replace("{key}", &server.key)
+                .into_bytes(),
+        },
+        ["tiles", key, z, x, y] if *key == server.key => {
+            let Some(&[z, x, y]) = numbers(&[z, x, y.trim_end_matches(".png")]).as_deref() else {
+                return Response::text("404 Not Found", String::from("no such tile"));
+            };
+            if z > tiles::MAX_LEVEL || x >= 1 << z || y >= 1 << z {
+                return Response::text("404 Not Found", String::from("no such tile"));
+            }
+            let cached = tiles::tile_path(&server.dir, z, x, y).exists();
+            let read = tiles::render_tile(
+                &server.args,
+                default_functs(&server.args),
+                &server.dir,
+                z,
+                x,
+                y,
+                server.size,
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|path| fs::read(path).map_err(|e| e.to_string()));
+            match read {
+                Ok(body) => {
+                    if !cached {
+                        server.args.log(&format!("Rendered tile {}/{}/{}", z, x, y));
+                    }
+                    Response {
+                        status: "200 OK",
+                        kind: "image/png",
+                        cache: true,
+                        body,
+                    }
+                }
+                Err(e) => Response::text("500 Internal Server Error", e),
+            }
+        }
+        ["live"] => Response {
+            status: "200 OK",
+            kind: "text/html; charset=utf-8",
+            cache: false,
+            body: LIVE.replace("{name}", &server.args.name).into_bytes(),
+        },
+        ["view"] => {
+            let get = |name: &str| {
+                query
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| *k == name)
+                    .and_then(|(_, v)| v.parse::<f32>().ok())
+            };
+            match (get("x"), get("y"), get("z"), get("w")) {
+                (Some(x), Some(y), Some(z), Some(w)) => {
+                    let view = view_at(server, x, y, z, w);
+                    Response::text("200 OK", wizard::command_line(&view))
+                }
+                _ => Response::text("400 Bad Request", String::from("needs x, y, z and w")),
+            }
+        }
+        _ => Response::text("404 Not Found", String::from("nothing here")),
+    }
+}
+
+// Whether a page from origin may open the socket of the server reached as
+// host. Browsers let any page open sockets anywhere and send where it came
+// from, so a page on another site has another origin, and one rebinding
+// its own name to the server reaches it under a name that isn't --host,
+// localhost or an address. Clients that aren't browsers send no origin.
+fn own_origin(listening: &str, origin: Option<&str>, host: Option<&str>) -> bool {
+    let (origin, host) = match (origin, host) {
+        (None, _) => return true,
+        (Some(origin), Some(host)) => (origin, host),
+        (Some(_), None) => return false,
+    };
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let known = name.eq_ignore_ascii_case(listening)
+        || name.eq_ignore_ascii_case("localhost")
+        || name
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok();
+    known && origin.strip_prefix("http://") == Some(host)
+}
+
+fn answer(server: &Server, stream: TcpStream) -> Result<(), ServeError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_request(&mut reader)?;
+    let words: Vec<&str> = request.words.iter().map(String::as_str).collect();
+    let key = request.header("sec-websocket-key");
+    if let (["GET", "/socket", _], Some(key)) = (words.as_slice(), key) {
+        if !own_origin(
+            &server.host,
+            request.header("origin"),
+            request.header("host"),
+        ) {
+            let refusal = String::from("only the server's own pages can open the socket");
+            return Ok(Response::text("403 Forbidden", refusal).send(stream)?);
+        }
+        websocket::accept(&stream, key)?;
+        // A page can sit on a view for as long as it likes
+        stream.set_read_timeout(None)?;
+        return live(server, stream, reader);
+    }
+    let response = match words.as_slice() {
+        ["GET", path, _] => route(server, path),
+        _ => Response::text("405 Method Not Allowed", String::from("only GET")),
+    };
+    Ok(response.send(stream)?)
+}
+
+fn send(out: &Mutex<TcpStream>, message: &Message) -> Result<(), ServeError> {
+    Ok(websocket::write(&*out.lock().unwrap(), message)?)
+}
+
+fn send_json(out: &Mutex<TcpStream>, value: Value) -> Result<(), ServeError> {
+    send(out, &Message::Text(value.to_string()))
+}
+
+// An image to go over the frame at x, y, scaled up to w by h
+fn send_image(out: &Mutex<TcpStream>, at: Tile, image: &Image16) -> Result<(), ServeError> {
+    let mut data: Vec<u8> = [at.x, at.y, at.w, at.h]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+    data.extend(terminal::png8(image)?);
+    send(out, &Message::Binary(data))
+}
+
+// The view after one message from the page
+fn change(server: &Server, args: &Args, message: &Value) -> Result<Args, String> {
+    let pair = |name: &str| -> Option<(f32, f32)> {
+        let pair = message.get(name)?.as_array()?;
+        Some((
+            pair.first()?.as_f64()? as f32,
+            pair.get(1)?.as_f64()? as f32,
+        ))
+    };
+    let mut next = args.clone();
+    if let Some(flags) = message.get("flags").and_then(Value::as_array) {
+        let mut argv: Vec<String> = config::settings(args)
+            .iter()
+            .map(|(k, v)| config::flag(k, v))
+            .collect();
+        argv.extend(flags.iter().filter_map(Value::as_str).map(String::from));
+        next = Args::try_from_params(&[], &argv)?;
+        next.threads = args.threads;
+    }
+    if let Some((w, h)) = pair("size") {
+        next.width = w as i32;
+        next.height = h as i32;
+    }
+    next.width = next.width.clamp(1, MOST);
+    next.height = next.height.clamp(1, MOST);
+    let pixels = next.width as u64 * next.height as u64 * next.samples as u64;
+    if pixels > server.max_pixels {
+        return Err(format!(
+            "{}x{} with {} samples is over the {} pixels --max-pixels allows",
+            next.width, next.height, next.samples, server.max_pixels
+        ));
+    }
+    if next.limit > server.max_limit {
+        return Err(format!(
+            "a limit of {} is over the {} --max-limit allows",
+            next.limit, server.max_limit
+        ));
+    }
+    let (w, h) = (next.width as f32, next.height as f32);
+    if let Some((dx, dy)) = pair("pan") {
+        let moved = Complex::new(2.0 * dx / w, 2.0 * dy / w) / next.zoom;
+        next.origin -= next.view().apply(moved);
+    }
+    if let Some(factor) = message.get("zoom").and_then(Value::as_f64) {
+        let (x, y) = pair("at").unwrap_or((w / 2.0, h / 2.0));
+        let offset =
+            |zoom: f32| Complex::new(2.0 * x / w - 1.0, (2.0 * y / h - 1.0) * h / w) / zoom;
+        let point = next.origin + next.view().apply(offset(next.zoom));
+        next.zoom *= factor as f32;
+        next.origin = point - next.view().apply(offset(next.zoom));
+    }
+    match validate::check(&next).first() {
+        Some(problem) => Err(problem.to_string()),
+        None => Ok(next),
+    }
+}
+
+// Streams the frame coarse and then tile by tile, giving up once the page
+// has sent something newer than started
+fn stream(
+    args: &Args,
+    out: &Mutex<TcpStream>,
+    latest: &AtomicUsize,
+    started: usize,
+) -> Result<(), ServeError> {
+    let stale = || latest.load(Ordering::SeqCst) != started;
+    let now = Instant::now();
+    let (w, h) = (args.width as u32, args.height as u32);
+    send_json(
+        out,
+        json!({ "view": wizard::command_line(args), "width": w, "height": h }),
+    )?;
+    let mut coarse = args.clone();
+    coarse.width = (args.width / COARSE).max(1);
+    coarse.height = (args.height / COARSE).max(1);
+    coarse.samples = 1;
+    let image = Renderer::new(coarse.clone(), default_functs(&coarse)).render();
+    if stale() {
+        return Ok(());
+    }
+    send_image(out, Tile { x: 0, y: 0, w, h }, &image)?;
+    let renderer = Renderer::new(args.clone(), default_functs(args));
+    scheduler::tiles(w, h, TILE_SIZE)
+        .par_iter()
+        .try_for_each(|&tile| {
+            if stale() {
+                return Ok(());
+            }
+            let pixels = renderer.render_tile(tile);
+            let image =
+                ImageBuffer::from_fn(tile.w, tile.h, |x, y| pixels[(y * tile.w + x) as usize]);
+            send_image(out, tile, &image)
+        })?;
+    if !stale() {
+        send_json(out, json!({ "done": now.elapsed().as_millis() as u64 }))?;
+    }
+    Ok(())
+}
+
+// One page's live view, until it closes the socket
+fn live(
+    server: &Server,
+    socket: TcpStream,
+    mut reader: BufReader<TcpStream>,
+) -> Result<(), ServeError> {
+    let out = Arc::new(Mutex::new(socket));
+    // Bumped by every message, so the render in progress knows it's stale
+    let latest = Arc::new(AtomicUsize::new(0));
+    let (sender, messages) = mpsc::channel();
+    let (reading_out, reading_latest) = (out.clone(), latest.clone());
+    thread::spawn(move || {
+        loop {
+            match websocket::read(&mut reader) {
+                Ok(Message::Text(text)) => {
+                    reading_latest.fetch_add(1, Ordering::SeqCst);
+                    if sender.send(text).is_err() {
+                        break;
+                    }
+                }
+                Ok(Message::Ping(data)) => {
+                    let _ = send(&reading_out, &Message::Pong(data));
+                }
+                Ok(Message::Close) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        reading_latest.fetch_add(1, Ordering::SeqCst);
+    });
+    let mut args = server.args.clone();
+    while let Ok(text) = messages.recv() {
+        // Everything sent during the last render, before the next
+        for text in [text].into_iter().chain(messages.try_iter()) {
+            let changed = serde_json::from_str(&text)
+                .map_err(|e: serde_json::Error| e.to_string())
+                .and_then(|message| change(server, &args, &message));
+            match changed {
+                Ok(next) => args = next,
+                Err(e) => send_json(&out, json!({ "error": e }))?,
+            }
+        }
+        stream(&args, &out, &latest, latest.load(Ordering::SeqCst))?;
+    }
+    let _ = send(&out, &Message::Close);
+    Ok(())
+}
+
+pub struct Serve<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub size: u32,
+    pub cache: PathBuf,
+    // Caps on what /live pages can ask for
+    pub max_pixels: u64,
+    pub max_limit: f32,
+}
+
+pub fn run(args: &Args, serve: Serve) -> Result<(), FracError> {
+    let Serve {
+        host,
+        port,
+        size,
+        cache,
+        max_pixels,
+        max_limit,
+    } = serve;
+    let address = format!("{}:{}", host, port);
+    let listener =
+        TcpListener::bind(&address).map_err(|e| ServeError::Listen(address.clone(), e))?;
+    let key = tiles::cache_key(args);
+    let server = Arc::new(Server {
+        args: args.clone(),
+        host: host.to_string(),
+        dir: cache.join(&key),
+        key,
+        size,
+        max_pixels,
+        max_limit,
+    });
+    args.log(&format!(
+        "Serving {} at {} on http://{}",
+        args.name,
+        coords::format(args.origin),
+        address
+    ));
+    accept(listener, move |stream| answer(&server, stream));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        let args = Args::parse_argv(["fracmd", "--width=64", "--height=48"].map(String::from));
+        Server {
+            args,
+            host: String::from("127.0.0.1"),
+            key: String::new(),
+            dir: PathBuf::new(),
+            size: 256,
+            max_pixels: 1 << 20,
+            max_limit: 4096.0,
+        }
+    }
+
+    fn flags(flags: &[&str]) -> Value {
+        json!({ "flags": flags })
+    }
+
+    #[test]
+    fn flags_are_applied() {
+        let server = server();
+        let next = change(&server, &server.args, &flags(&["--limit=1024"])).unwrap();
+        assert_eq!(next.limit, 1024.0);
+    }
+
+    #[test]
+    fn sizes_from_flags_are_clamped() {
+        let server = server();
+        let next = change(&server, &server.args, &flags(&["--width=100000"])).unwrap();
+        assert_eq!(next.width, MOST);
+    }
+
+    #[test]
+    fn flags_past_the_caps_are_refused() {
+        let server = server();
+        for over in [
+            &["--samples=1000"][..],
+            &["--width=4096", "--height=4096"],
+            &["--limit=100000"],
+        ] {
+            assert!(
+                change(&server, &server.args, &flags(over)).is_err(),
+                "{:?}",
+                over
+            );
+        }
+    }
+
+    #[test]
+    fn bad_flags_are_an_error() {
+        let server = server();
+        assert!(change(&server, &server.args, &flags(&["--limit=lots"])).is_err());
+        assert!(change(&server, &server.args, &flags(&["--no-such-flag"])).is_err());
+    }
+
+    #[test]
+    fn requests_are_read_up_to_the_caps() {
+        let mut reader = io::Cursor::new("GET / HTTP/1.1\r\nHost: a:1\r\n\r\nbody");
+        let request = read_request(&mut reader).unwrap();
+        assert_eq!(request.words, ["GET", "/", "HTTP/1.1"]);
+        assert_eq!(request.header("host"), Some("a:1"));
+
+        let long = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "x".repeat(MOST_HEADER as usize)
+        );
+        assert!(read_request(&mut io::Cursor::new(long)).is_err());
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: x\r\n".repeat(MOST_HEADERS + 1)
+        );
+        assert!(read_request(&mut io::Cursor::new(many)).is_err());
+    }
+
+    #[test]
+    fn sockets_are_only_for_the_servers_own_pages() {
+        let own = |origin, host| own_origin("127.0.0.1", origin, host);
+        assert!(own(Some("http://127.0.0.1:8080"), Some("127.0.0.1:8080")));
+        assert!(own(Some("http://localhost:8080"), Some("localhost:8080")));
+        assert!(own(Some("http://[::1]:8080"), Some("[::1]:8080")));
+        assert!(own(None, Some("127.0.0.1:8080")));
+        assert!(!own(Some("https://evil.example"), Some("127.0.0.1:8080")));
+        assert!(!own(
+            Some("http://evil.example:8080"),
+            Some("evil.example:8080")
+        ));
+        assert!(!own(Some("http://127.0.0.1:8080"), None));
+        assert!(own_origin(
+            "box.lan",
+            Some("http://box.lan:80"),
+            Some("box.lan:80")
+        ));
+    }
+}
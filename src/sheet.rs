@@ -0,0 +1,218 @@
+// fracmd
+// Contact sheets of labeled previews
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Sheet files are json, either a bare list of entries or
+//
+//   { "columns": 3, "entries": [ ... ] }
+//
+// where every entry is an object of long flag names to values, e.g.
+//
+//   { "label": "seahorse valley", "origin": "-0.745+0.11i", "zoom": 40 }
+//
+// Flags not given fall back to the ones passed before `sheet`. Besides the
+// render flags an entry can set "label", and "palette", "transfer" and
+// "cycles" to color the cell the way the recolor command would.
+
+use err_derive::Error;
+use image::{imageops, ImageBuffer, Rgba};
+use serde_json::{Map, Value};
+use std::{fs, path::Path, sync::Arc};
+
+use crate::{
+    coloring::Gradient,
+    coords, default_functs,
+    font::{self, GLYPH_H},
+    palette::{Palette, PaletteError, Transfer},
+    scheduler::Image16,
+    Args, Renderer,
+};
+
+const LABEL_SCALE: u32 = 2;
+const PADDING: u32 = 4;
+const BACKGROUND: Rgba<u16> = Rgba([0x1800, 0x1800, 0x1800, u16::MAX]);
+const TEXT: Rgba<u16> = Rgba([0xe000, 0xe000, 0xe000, u16::MAX]);
+
+#[derive(Debug, Error)]
+pub enum SheetError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "palette error: {}", _0)]
+    Palette(#[error(source)] PaletteError),
+    #[error(display = "entry {}: {}", _0, _1)]
+    BadEntry(usize, String),
+}
+
+pub struct Cell {
+    pub args: Args,
+    pub label: String,
+    pub palette: Option<Palette>,
+    pub transfer: Transfer,
+    pub cycles: f32,
+}
+
+pub struct Sheet {
+    pub columns: Option<usize>,
+    pub cells: Vec<Cell>,
+}
+
+// Json scalars as the text clap would get on the command line
+fn flag_value(index: usize, key: &str, value: &Value) -> Result<String, SheetError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(SheetError::BadEntry(
+            index,
+            format!("\"{}\" must be a string or a number", key),
+        )),
+    }
+}
+
+impl Cell {
+    fn parse(
+        index: usize,
+        entry: &Map<String, Value>,
+        base: &Args,
+        width: u32,
+        height: u32,
+    ) -> Result<Cell, SheetError> {
+        let mut label = None;
+        let mut palette = None;
+        let mut transfer = Transfer::Linear;
+        let mut cycles = 1.0;
+        let mut overrides = Vec::new();
+        for (key, value) in entry {
+            let text = flag_value(index, key, value)?;
+            match key.as_str() {
+                "label" => label = Some(text),
+                "palette" => palette = Some(text),
+                "transfer" => {
+                    transfer = clap::ValueEnum::from_str(&text, true)
+                        .map_err(|e| SheetError::BadEntry(index, e))?
+                }
+                "cycles" => {
+                    cycles = text.parse().map_err(|_| {
+                        SheetError::BadEntry(index, format!("bad cycles \"{}\"", text))
+                    })?
+                }
+                _ => overrides.push(format!("--{}={}", key, text)),
+            }
+        }
+        // Every cell is the same size whatever the entry says
+        overrides.push(format!("--width={}", width));
+        overrides.push(format!("--height={}", height));
+
+        let params: Vec<(String, String)> = base
+            .params()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        let args = Args::try_from_params(&params, &overrides)
+            .map_err(|e| SheetError::BadEntry(index, e))?;
+        let palette = match palette {
+            Some(path) => Some(Palette::load(path)?.transform(&args.colorspace.matrix_from_srgb())),
+            None => None,
+        };
+        Ok(Cell {
+            label: label
+                .unwrap_or_else(|| format!("{} x{}", coords::format(args.origin), args.zoom)),
+            args,
+            palette,
+            transfer,
+            cycles,
+        })
+    }
+
+    pub fn render(&self) -> Image16 {
+        let mut functs = default_functs(&self.args);
+        if let Some(palette) = &self.palette {
+            functs.coloring = Arc::new(Gradient {
+                palette: palette.clone(),
+                transfer: self.transfer,
+                cycles: self.cycles,
+                phase: 0.0,
+            });
+        }
+        Renderer::new(self.args.clone(), functs).render()
+    }
+}
+
+impl Sheet {
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        base: &Args,
+        width: u32,
+        height: u32,
+    ) -> Result<Sheet, SheetError> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let (columns, entries) = match &json {
+            Value::Array(entries) => (None, entries),
+            Value::Object(sheet) => match sheet.get("entries") {
+                Some(Value::Array(entries)) => (
+                    sheet
+                        .get("columns")
+                        .and_then(Value::as_u64)
+                        .map(|c| c as usize),
+                    entries,
+                ),
+                _ => return Err(SheetError::BadEntry(0, "no \"entries\" list".to_string())),
+            },
+            _ => return Err(SheetError::BadEntry(0, "not a list or object".to_string())),
+        };
+        let cells = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| match entry {
+                Value::Object(entry) => Cell::parse(i, entry, base, width, height),
+                _ => Err(SheetError::BadEntry(i, "not an object".to_string())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Sheet { columns, cells })
+    }
+}
+
+// Lays rendered cells out in a grid, each with its label in a strip below.
+// Labels are centered, and cut short when too wide for the cell.
+pub fn compose(cells: &[(Image16, String)], columns: usize, width: u32, height: u32) -> Image16 {
+    let columns = columns.clamp(1, cells.len().max(1));
+    let rows = cells.len().div_ceil(columns);
+    let strip = GLYPH_H * LABEL_SCALE + 2 * PADDING;
+    let (cell_w, cell_h) = (width + PADDING, height + strip);
+    let mut sheet = ImageBuffer::from_pixel(
+        columns as u32 * cell_w + PADDING,
+        rows as u32 * cell_h + PADDING,
+        BACKGROUND,
+    );
+    for (i, (image, label)) in cells.iter().enumerate() {
+        let x = (i % columns) as u32 * cell_w + PADDING;
+        let y = (i / columns) as u32 * cell_h + PADDING;
+        imageops::overlay(&mut sheet, image, x as i64, y as i64);
+        let fit = (width / (font::ADVANCE * LABEL_SCALE)) as usize;
+        let label: String = label.chars().take(fit).collect();
+        let indent = (width - font::text_width(&label, LABEL_SCALE)) / 2;
+        font::draw_text(
+            &mut sheet,
+            x + indent,
+            y + height + PADDING,
+            &label,
+            LABEL_SCALE,
+            TEXT,
+        );
+    }
+    sheet
+}
@@ -0,0 +1,160 @@
+// fracmd
+// Json sidecars with each animation frame's parameters for --sidecars
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// frame_00003.json is written beside frame_00003.png as the frame is:
+//
+//   { "frame": 3, "frames": 60, "fps": 30, "time": 0.1, "position": 3.0,
+//     "along": 0.0508, "subframes": [2.75, 3.25],
+//     "params": { "zoom": "1.2", ... } }
+//
+// time is seconds into the video, position is where the frame falls along
+// the animation in frames, which audio driven speed moves off the frame
+// number, along is that from 0 to 1 and subframes are the positions motion
+// blur averaged. params are the same ones a png frame embeds, so
+// `fracmd from-image frame_00003.json` renders the frame again on its own.
+
+use err_derive::Error;
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    animation::{Animation, FrameSink},
+    manifest,
+    metadata::MetadataError,
+    scheduler::Image16,
+    Args,
+};
+
+#[derive(Debug, Error)]
+pub enum SidecarError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "json error: {}", _0)]
+    Json(#[error(source)] serde_json::Error),
+    #[error(display = "{:?} is not a frame sidecar", _0)]
+    BadFile(PathBuf),
+    #[error(display = "--sidecars needs a directory when the animation streams to stdout")]
+    NeedDir,
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+// The frame directory for one, and <output>.frames beside anything else
+pub fn dir_for(output: &Path, given: Option<&Path>) -> Result<PathBuf, SidecarError> {
+    match given {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None if output == Path::new("-") => Err(SidecarError::NeedDir),
+        None if crate::animation::writes_frames(output) => Ok(output.to_path_buf()),
+        None => Ok(PathBuf::from(format!("{}.frames", output.display()))),
+    }
+}
+
+// Through the shortest text for the f32, which keeps 0.1 from being
+// written as 0.10000000149011612
+fn number(v: f32) -> f64 {
+    v.to_string().parse().unwrap_or(0.0)
+}
+
+fn sidecar(animation: &Animation, frame: usize, args: &Args) -> Value {
+    let position = animation.position(frame);
+    let subframes: Vec<f64> = animation.subframes(frame).into_iter().map(number).collect();
+    let params: Map<String, Value> = args
+        .params()
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), Value::String(v)))
+        .collect();
+    json!({
+        "frame": frame,
+        "frames": animation.frames,
+        "fps": animation.fps,
+        "time": frame as f64 / animation.fps.max(1) as f64,
+        "position": number(position),
+        "along": number(animation.time(position)),
+        "subframes": subframes,
+        "params": params,
+    })
+}
+
+pub fn read_params(path: &Path) -> Result<Vec<(String, String)>, SidecarError> {
+    let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    json.get("params")
+        .and_then(Value::as_object)
+        .filter(|params| !params.is_empty())
+        .ok_or_else(|| SidecarError::BadFile(path.to_path_buf()))?
+        .iter()
+        .map(|(k, v)| match v {
+            Value::String(s) => Ok((k.clone(), s.clone())),
+            _ => Err(SidecarError::BadFile(path.to_path_buf())),
+        })
+        .collect()
+}
+
+// Passes frames on, writing each one's sidecar once it's through
+pub struct Sidecars<'a> {
+    pub inner: Box<dyn FrameSink + 'a>,
+    pub dir: PathBuf,
+    pub animation: &'a Animation,
+}
+
+impl FrameSink for Sidecars<'_> {
+    fn frame(&mut self, index: usize, image: Image16, args: &Args) -> Result<(), MetadataError> {
+        self.inner.frame(index, image, args)?;
+        fs::create_dir_all(&self.dir)?;
+        let path = manifest::frame_path(&self.dir, index).with_extension("json");
+        let text = serde_json::to_string_pretty(&sidecar(self.animation, index, args))
+            .map_err(std::io::Error::from)?;
+        Ok(fs::write(path, text + "\n")?)
+    }
+
+    fn finish(&mut self) -> Result<(), MetadataError> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    // Only a non-empty object of strings under "params" is a sidecar
+    #[test]
+    fn params_that_arent_strings_are_refused() {
+        for (i, json) in [
+            r#"{"frame": 3}"#,
+            r#"{"params": {}}"#,
+            r#"{"params": ["zoom", "2"]}"#,
+            r#"{"params": {"zoom": "2", "limit": 80}}"#,
+        ]
+        .iter()
+        .enumerate()
+        {
+            let file = TempFile::new(&format!("sidecar-{}.json", i), json);
+            assert!(
+                matches!(read_params(&file.0), Err(SidecarError::BadFile(_))),
+                "{}",
+                json
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// fracmd
+// Several output sizes from one render for --sizes
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The frame is the smallest size every output fits inside, so nothing is
+// ever scaled up. Each output is the biggest crop of the frame's center with
+// its own shape, filtered down in the squared space the samples are
+// averaged in, the same as supersampling it at that size would give.
+
+use err_derive::Error;
+use image::{imageops, ImageBuffer, Rgba};
+use std::{fmt, str::FromStr};
+
+use crate::{depth, region::Region, scheduler::Image16, Args, FracError};
+
+#[derive(Debug, Error)]
+pub enum SizeError {
+    #[error(display = "expected a size like 1920x1080 but got {:?}", _0)]
+    BadSize(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl FromStr for Size {
+    type Err = SizeError;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bad = || SizeError::BadSize(string.to_string());
+        let (w, h) = string.trim().split_once(['x', 'X']).ok_or_else(bad)?;
+        let size = Size {
+            width: w.parse().map_err(|_| bad())?,
+            height: h.parse().map_err(|_| bad())?,
+        };
+        (size.width > 0 && size.height > 0)
+            .then_some(size)
+            .ok_or_else(bad)
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+pub fn frame(sizes: &[Size]) -> Size {
+    Size {
+        width: sizes.iter().map(|s| s.width).max().unwrap_or(1),
+        height: sizes.iter().map(|s| s.height).max().unwrap_or(1),
+    }
+}
+
+// The centered crop of the frame with the same shape as size
+fn crop(frame: Size, size: Size) -> [i32; 4] {
+    let (fw, fh) = (frame.width as i64, frame.height as i64);
+    let (sw, sh) = (size.width as i64, size.height as i64);
+    let (w, h) = if fw * sh > fh * sw {
+        ((fh * sw / sh).max(1), fh)
+    } else {
+        (fw, (fw * sh / sw).max(1))
+    };
+    let (x0, y0) = ((fw - w) / 2, (fh - h) / 2);
+    [x0, y0, x0 + w, y0 + h].map(|v| v as i32)
+}
+
+// The parameters that would render the output directly, which are what
+// its metadata gets, and the output itself
+pub fn downsample(args: &Args, image: &Image16, size: Size) -> Result<(Args, Image16), FracError> {
+    let frame = Size {
+        width: args.width,
+        height: args.height,
+    };
+    let [x0, y0, x1, y1] = crop(frame, size);
+    let region = Region {
+        corners: [x0, y0, x1, y1].map(|v| v as f64),
+        normalized: false,
+    };
+    let mut job = region.crop(args)?;
+    job.width = size.width;
+    job.height = size.height;
+    let (w, h) = ((x1 - x0) as u32, (y1 - y0) as u32);
+    let squared: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::from_fn(w, h, |x, y| {
+        Rgba(depth::squared(
+            *image.get_pixel(x + x0 as u32, y + y0 as u32),
+        ))
+    });
+    let small = imageops::resize(
+        &squared,
+        size.width as u32,
+        size.height as u32,
+        imageops::Lanczos3,
+    );
+    // Lanczos rings a little below 0 next to sharp edges
+    let out = ImageBuffer::from_fn(small.width(), small.height(), |x, y| {
+        depth::unsquared(small.get_pixel(x, y).0)
+    });
+    Ok((job, out))
+}
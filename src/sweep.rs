@@ -0,0 +1,118 @@
+// fracmd
+// Parameter sweeps
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use err_derive::Error;
+use std::str::FromStr;
+
+use crate::{config, Args};
+
+#[derive(Debug, Error)]
+pub enum SweepError {
+    #[error(display = "expected key=a:b:steps or key=v1,v2,... but got {:?}", _0)]
+    BadSpec(String),
+    // The combination, "samples=2.5", and what's wrong with it
+    #[error(display = "sweep {}: {}", _0, _1)]
+    BadValue(String, String),
+}
+
+// One swept flag, "cexp=0.5:2.0:8" is 8 evenly spaced values from 0.5 to
+// 2.0 inclusive and "samples=1,4,16" lists them
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+impl FromStr for Sweep {
+    type Err = SweepError;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bad = || SweepError::BadSpec(string.to_string());
+        let (key, spec) = string.split_once('=').ok_or_else(bad)?;
+        let key = key.trim().trim_start_matches("--").to_string();
+        let range: Vec<&str> = spec.split(':').collect();
+        let values: Vec<String> = match range[..] {
+            [from, to, steps] => {
+                let from: f64 = from.trim().parse().map_err(|_| bad())?;
+                let to: f64 = to.trim().parse().map_err(|_| bad())?;
+                let steps: usize = steps.trim().parse().map_err(|_| bad())?;
+                (0..steps)
+                    .map(|i| {
+                        let t = if steps > 1 {
+                            i as f64 / (steps - 1) as f64
+                        } else {
+                            0.0
+                        };
+                        // Through f32 so 0.1 steps don't print as 0.30000000000000004
+                        ((from + (to - from) * t) as f32).to_string()
+                    })
+                    .collect()
+            }
+            [_] => spec.split(',').map(|v| v.trim().to_string()).collect(),
+            _ => return Err(bad()),
+        };
+        if key.is_empty() || values.is_empty() || values.iter().any(|v| v.is_empty()) {
+            return Err(bad());
+        }
+        Ok(Sweep { key, values })
+    }
+}
+
+pub struct Combination {
+    pub args: Args,
+    // "cexp=0.5 samples=4"
+    pub label: String,
+}
+
+// The cartesian product, the last sweep changing fastest. Each combination
+// gets the swept values appended to --name so templated outputs don't
+// collide.
+pub fn combinations(base: &Args, sweeps: &[Sweep]) -> Result<Vec<Combination>, SweepError> {
+    let mut sets: Vec<Vec<(&str, &str)>> = vec![Vec::new()];
+    for sweep in sweeps {
+        sets = sets
+            .into_iter()
+            .flat_map(|set| {
+                sweep.values.iter().map(move |value| {
+                    let mut set = set.clone();
+                    set.push((sweep.key.as_str(), value.as_str()));
+                    set
+                })
+            })
+            .collect();
+    }
+    let inherited: Vec<String> = config::settings(base)
+        .iter()
+        .map(|(key, value)| config::flag(key, value))
+        .collect();
+    sets.into_iter()
+        .map(|set| {
+            let label = set
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut flags = inherited.clone();
+            flags.extend(set.iter().map(|(key, value)| config::flag(key, value)));
+            let mut args = Args::try_from_params(&[], &flags)
+                .map_err(|e| SweepError::BadValue(label.clone(), e))?;
+            for (key, value) in &set {
+                args.name = format!("{}_{}{}", args.name, key, value);
+            }
+            Ok(Combination { args, label })
+        })
+        .collect()
+}
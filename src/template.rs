@@ -0,0 +1,183 @@
+// fracmd
+// Output filename templates
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Templates are paths with {key} or {key:spec} fields. Keys are the long
+// render flags (dashes or underscores), plus ext, format, date, time and
+// timestamp. The spec is [0][width][.precision][e|f] like Rust's own, e.g.
+// {zoom:.2e} or {limit:06.0f}; numeric specs need a numeric value.
+
+use clap::ValueEnum;
+use err_derive::Error;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{value_name, Args};
+
+pub const DEFAULT: &str = "out/{name}_{width}x{height}-{zoom}_s{samples}-{sampled}.{ext}";
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "unknown template key {{{}}}", _0)]
+    UnknownKey(String),
+    #[error(display = "bad format spec in {{{}}}", _0)]
+    BadSpec(String),
+    #[error(display = "unclosed {{ in template")]
+    Unclosed,
+    #[error(display = "{:?} already exists", _0)]
+    Exists(PathBuf),
+}
+
+// What to do when the expanded path is already taken
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Collision {
+    Overwrite,
+    // Append _1, _2, ... before the extension
+    Suffix,
+    Error,
+}
+
+// Days since the epoch to (year, month, day), proleptic Gregorian
+fn civil(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+// UTC calendar date of a unix timestamp, 2022-03-14
+pub fn date(now: u64) -> String {
+    let (y, m, d) = civil((now / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+// UTC time of day of a unix timestamp, 15:09:26
+pub fn clock(now: u64) -> String {
+    let secs = now % 86400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// Date and time fields are UTC
+fn lookup(key: &str, args: &Args, now: u64) -> Option<String> {
+    let key = key.replace('_', "-");
+    let secs = now % 86400;
+    match key.as_str() {
+        "ext" => Some(args.format.extension().to_string()),
+        "format" => Some(value_name(args.format)),
+        "timestamp" => Some(now.to_string()),
+        "date" => Some(date(now)),
+        "time" => Some(format!(
+            "{:02}-{:02}-{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )),
+        _ => args
+            .params()
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v),
+    }
+}
+
+fn apply_spec(field: &str, value: String, spec: &str) -> Result<String, TemplateError> {
+    let bad = || TemplateError::BadSpec(field.to_string());
+    let (spec, kind) = match spec.strip_suffix('e') {
+        Some(rest) => (rest, 'e'),
+        None => (spec.strip_suffix('f').unwrap_or(spec), 'f'),
+    };
+    let zero = spec.starts_with('0');
+    let (width, precision) = match spec.split_once('.') {
+        Some((w, p)) => (w, Some(p.parse::<usize>().map_err(|_| bad())?)),
+        None => (spec, None),
+    };
+    let width = match width {
+        "" => 0,
+        w => w.parse::<usize>().map_err(|_| bad())?,
+    };
+    let v: f64 = value.parse().map_err(|_| bad())?;
+    let text = match (kind, precision) {
+        ('e', Some(p)) => format!("{:.*e}", p, v),
+        ('e', None) => format!("{:e}", v),
+        (_, Some(p)) => format!("{:.*}", p, v),
+        (_, None) => value,
+    };
+    Ok(match (zero, text.strip_prefix('-')) {
+        (true, Some(digits)) => format!("-{:0>1$}", digits, width.saturating_sub(1)),
+        (true, None) => format!("{:0>1$}", text, width),
+        (false, _) => format!("{:>1$}", text, width),
+    })
+}
+
+pub fn expand(template: &str, args: &Args) -> Result<String, TemplateError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or(TemplateError::Unclosed)? + start;
+        let field = &rest[start + 1..end];
+        let (key, spec) = field.split_once(':').unwrap_or((field, ""));
+        let value =
+            lookup(key, args, now).ok_or_else(|| TemplateError::UnknownKey(key.to_string()))?;
+        out.push_str(&if spec.is_empty() {
+            value
+        } else {
+            apply_spec(field, value, spec)?
+        });
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Creates the parent directories and applies the collision policy
+pub fn prepare(path: &str, collision: Collision) -> Result<String, TemplateError> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !path.exists() {
+        return Ok(path.to_string_lossy().into_owned());
+    }
+    match collision {
+        Collision::Overwrite => Ok(path.to_string_lossy().into_owned()),
+        Collision::Error => Err(TemplateError::Exists(path.to_path_buf())),
+        Collision::Suffix => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = path
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            let taken = (1..)
+                .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, ext)))
+                .find(|p| !p.exists())
+                .unwrap();
+            Ok(taken.to_string_lossy().into_owned())
+        }
+    }
+}
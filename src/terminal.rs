@@ -0,0 +1,361 @@
+// fracmd
+// Inline image previews for terminals
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use num::complex::Complex;
+use std::{env, io::Write, sync::Mutex};
+
+use crate::{par::*, quantize, scheduler::Image16, to_pixel, Renderer};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TermProtocol {
+    Auto,
+    Sixel,
+    Kitty,
+    Iterm,
+}
+
+impl TermProtocol {
+    // Guesses from the variables terminals export, sixel is the most widely
+    // supported fallback
+    pub fn detect(self) -> TermProtocol {
+        if self != TermProtocol::Auto {
+            return self;
+        }
+        let var = |name| env::var(name).unwrap_or_default();
+        if env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM").contains("kitty") {
+            TermProtocol::Kitty
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") {
+            TermProtocol::Iterm
+        } else {
+            TermProtocol::Sixel
+        }
+    }
+}
+
+// Fits the image inside size x size, keeping the aspect ratio
+pub fn downscale(image: &Image16, size: u32) -> Image16 {
+    let scale = (size as f32 / image.width().max(image.height()) as f32).min(1.0);
+    let w = ((image.width() as f32 * scale) as u32).max(1);
+    let h = ((image.height() as f32 * scale) as u32).max(1);
+    imageops::resize(image, w, h, FilterType::Triangle)
+}
+
+pub fn preview<W: Write>(
+    mut out: W,
+    image: &Image16,
+    protocol: TermProtocol,
+    size: u32,
+) -> std::io::Result<()> {
+    let image = downscale(image, size);
+    match protocol.detect() {
+        TermProtocol::Kitty => write_kitty(&mut out, &image)?,
+        TermProtocol::Iterm => write_iterm(&mut out, &image)?,
+        _ => write_sixel(&mut out, &image)?,
+    }
+    writeln!(out)?;
+    out.flush()
+}
+
+// Rows are drawn in bands of six pixels, one pass over the band per color
+// with each character being 63 plus a bitmask of the rows that use it
+pub fn write_sixel<W: Write>(out: &mut W, image: &Image16) -> std::io::Result<()> {
+    let indexed = quantize::median_cut(image, 256);
+    let (w, h) = (indexed.width as usize, indexed.height as usize);
+    write!(out, "\x1bP0;1q\"1;1;{};{}", w, h)?;
+    for (i, [r, g, b]) in indexed.palette.iter().enumerate() {
+        let pct = |v: u8| v as u32 * 100 / 255;
+        write!(out, "#{};2;{};{};{}", i, pct(*r), pct(*g), pct(*b))?;
+    }
+    for band in (0..h).step_by(6) {
+        let rows = 6.min(h - band);
+        let mut used = vec![false; indexed.palette.len()];
+        for y in band..band + rows {
+            for &c in &indexed.indices[y * w..(y + 1) * w] {
+                used[c as usize] = true;
+            }
+        }
+        for color in (0..used.len()).filter(|&c| used[c]) {
+            write!(out, "#{}", color)?;
+            let sixels: Vec<u8> = (0..w)
+                .map(|x| {
+                    let bits = (0..rows)
+                        .filter(|&r| indexed.indices[(band + r) * w + x] as usize == color)
+                        .fold(0, |bits, r| bits | 1 << r);
+                    63 + bits
+                })
+                .collect();
+            // Run length encode repeats
+            let mut x = 0;
+            while x < w {
+                let run = sixels[x..].iter().take_while(|&&s| s == sixels[x]).count();
+                if run > 3 {
+                    write!(out, "!{}{}", run, sixels[x] as char)?;
+                } else {
+                    out.write_all(&sixels[x..x + run])?;
+                }
+                x += run;
+            }
+            write!(out, "$")?;
+        }
+        write!(out, "-")?;
+    }
+    write!(out, "\x1b\\")
+}
+
+// Kitty takes base64 png in chunks of at most 4096 bytes
+pub fn write_kitty<W: Write>(out: &mut W, image: &Image16) -> std::io::Result<()> {
+    let data = base64(&png8(image)?);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            write!(out, "\x1b_Gf=100,a=T,m={};", more)?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+pub fn write_iterm<W: Write>(out: &mut W, image: &Image16) -> std::io::Result<()> {
+    let png = png8(image)?;
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={}px;height={}px:{}\x07",
+        png.len(),
+        image.width(),
+        image.height(),
+        base64(&png)
+    )
+}
+
+// Terminals don't need 16 bit depth, 8 bit keeps the escape sequence short
+pub fn png8(image: &Image16) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = image.as_raw().iter().map(|v| (v >> 8) as u8).collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(data)
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextColor {
+    Truecolor,
+    Ansi256,
+    // Plain characters, brightness picks the glyph
+    Ascii,
+}
+
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+// Terminal cells are about twice as tall as wide, so the colored modes split
+// each one into two square half-block pixels
+fn cell_rows(color: TextColor) -> usize {
+    match color {
+        TextColor::Ascii => 1,
+        _ => 2,
+    }
+}
+
+// The point at (u, v) in [0, 1] across the renderer's view, with the
+// aspect ratio of the text grid rather than --width / --height
+pub fn point(renderer: &Renderer, u: f32, v: f32, aspect: f32) -> Complex<f32> {
+    let c = Complex::new(2.0 * u - 1.0, (2.0 * v - 1.0) * aspect) / renderer.args.zoom;
+    renderer.to_plane(c)
+}
+
+// 8 bit sRGB of the point at (u, v)
+pub fn sample(renderer: &Renderer, u: f32, v: f32, aspect: f32) -> [u8; 3] {
+    let p = to_pixel(renderer.sample_color(point(renderer, u, v, aspect))).0;
+    [p[0], p[1], p[2]].map(|v| (v >> 8) as u8)
+}
+
+// Nearest entry of the 6x6x6 cube or the gray ramp
+fn ansi256([r, g, b]: [u8; 3]) -> u8 {
+    let level = |v: u8| ((v as u32 * 5 + 127) / 255) as u8;
+    let cube = [level(r), level(g), level(b)];
+    let cube_rgb = cube.map(|l| if l == 0 { 0 } else { 55 + 40 * l as u32 });
+    let gray = ((r as u32 + g as u32 + b as u32) / 3)
+        .saturating_sub(3)
+        .min(233)
+        / 10;
+    let gray_rgb = 8 + 10 * gray;
+    let dist = |c: [u32; 3]| -> u32 {
+        c.iter()
+            .zip([r, g, b])
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32)
+            .sum()
+    };
+    if dist([gray_rgb; 3]) < dist(cube_rgb) {
+        232 + gray as u8
+    } else {
+        16 + 36 * cube[0] + 6 * cube[1] + cube[2]
+    }
+}
+
+pub fn escape(rgb: [u8; 3], color: TextColor, layer: u8) -> String {
+    match color {
+        TextColor::Truecolor => format!("\x1b[{};2;{};{};{}m", layer, rgb[0], rgb[1], rgb[2]),
+        _ => format!("\x1b[{};5;{}m", layer, ansi256(rgb)),
+    }
+}
+
+pub fn write_text<W: Write>(
+    mut out: W,
+    renderer: &Renderer,
+    cols: usize,
+    rows: usize,
+    color: TextColor,
+) -> std::io::Result<()> {
+    let (w, h) = (cols, rows * cell_rows(color));
+    let aspect = 2.0 * rows as f32 / cols as f32;
+    let pixels: Vec<[u8; 3]> = (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let u = ((i % w) as f32 + 0.5) / w as f32;
+            let v = ((i / w) as f32 + 0.5) / h as f32;
+            sample(renderer, u, v, aspect)
+        })
+        .collect();
+    for row in 0..rows {
+        let mut line = String::new();
+        for x in 0..cols {
+            match color {
+                TextColor::Ascii => {
+                    let [r, g, b] = pixels[row * w + x];
+                    let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+                    let i = (luma / 256.0 * RAMP.len() as f32) as usize;
+                    line.push(RAMP[i.min(RAMP.len() - 1)] as char);
+                }
+                _ => {
+                    let top = pixels[2 * row * w + x];
+                    let bottom = pixels[(2 * row + 1) * w + x];
+                    line.push_str(&escape(top, color, 38));
+                    line.push_str(&escape(bottom, color, 48));
+                    line.push('\u{2580}');
+                }
+            }
+        }
+        if color != TextColor::Ascii {
+            line.push_str("\x1b[0m");
+        }
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()
+}
+
+// Columns and rows of the terminal, 80x24 when it can't tell
+pub fn size() -> (usize, usize) {
+    terminal_size::terminal_size().map_or((80, 24), |(w, h)| (w.0 as usize, h.0 as usize))
+}
+
+// The most half block cells a width x height image fills inside cols x
+// rows at its own aspect, a cell being two pixels tall
+pub fn fit(width: i32, height: i32, cols: usize, rows: usize) -> (usize, usize) {
+    let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+    let rows = rows.max(1);
+    let cols = cols.min(rows * 2 * width / height).max(1);
+    (cols, (cols * height / width).div_ceil(2).clamp(1, rows))
+}
+
+// The render so far as truecolor half blocks, redrawn in place over itself.
+// Tiles not done yet are left as the terminal's own background.
+pub struct LivePreview {
+    cols: usize,
+    rows: usize,
+    drawn: Mutex<bool>,
+}
+
+impl LivePreview {
+    // Fits the image's aspect inside the terminal, keeping a line free for
+    // the progress bar and one for the prompt
+    pub fn new(width: i32, height: i32) -> LivePreview {
+        let (max_cols, max_rows) = size();
+        let (cols, rows) = fit(width, height, max_cols, max_rows.saturating_sub(2));
+        LivePreview {
+            cols,
+            rows,
+            drawn: Mutex::new(false),
+        }
+    }
+
+    pub fn draw<W: Write>(&self, mut out: W, image: &Image16) -> std::io::Result<()> {
+        let Ok(mut drawn) = self.drawn.lock() else {
+            return Ok(());
+        };
+        let (w, h) = (image.width() as usize, image.height() as usize);
+        // Nearest pixel is enough for a glimpse and keeps this cheap
+        let pixel = |x: usize, y: usize| {
+            let p = image.get_pixel(
+                (x * w / self.cols).min(w - 1) as u32,
+                (y * h / (self.rows * 2)).min(h - 1) as u32,
+            );
+            (p.0[3] > 0).then(|| [p.0[0], p.0[1], p.0[2]].map(|v| (v >> 8) as u8))
+        };
+        let color = |rgb, layer| escape(rgb, TextColor::Truecolor, layer);
+        // From the start of the line the progress bar may be on, clearing
+        // whatever's left of it past the image
+        let mut text = String::from("\r");
+        if *drawn {
+            text.push_str(&format!("\x1b[{}A", self.rows));
+        }
+        for row in 0..self.rows {
+            for x in 0..self.cols {
+                let cell = match (pixel(x, 2 * row), pixel(x, 2 * row + 1)) {
+                    (Some(top), Some(bottom)) => {
+                        format!("{}{}\u{2580}", color(top, 38), color(bottom, 48))
+                    }
+                    (Some(top), None) => format!("{}\x1b[49m\u{2580}", color(top, 38)),
+                    (None, Some(bottom)) => format!("{}\x1b[49m\u{2584}", color(bottom, 38)),
+                    (None, None) => String::from("\x1b[0m "),
+                };
+                text.push_str(&cell);
+            }
+            text.push_str("\x1b[0m\x1b[K\n");
+        }
+        out.write_all(text.as_bytes())?;
+        *drawn = true;
+        out.flush()
+    }
+}
@@ -0,0 +1,63 @@
+// fracmd
+// Downscaled companion images
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use image::{
+    imageops::{self, FilterType},
+    ImageBuffer, Rgba,
+};
+use std::path::{Path, PathBuf};
+
+use crate::{openexr::linear, scheduler::Image16};
+
+fn encode(v: f32) -> u16 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * u16::MAX as f32).round() as u16
+}
+
+// Lanczos filtered in linear light, so thin bright filaments keep their
+// brightness instead of going dark the way they do when averaging sRGB
+pub fn thumbnail(image: &Image16, size: u32) -> Image16 {
+    let scale = (size as f32 / image.width().max(image.height()) as f32).min(1.0);
+    let w = ((image.width() as f32 * scale).round() as u32).max(1);
+    let h = ((image.height() as f32 * scale).round() as u32).max(1);
+    let float: ImageBuffer<Rgba<f32>, Vec<f32>> =
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b, a] = image.get_pixel(x, y).0;
+            let [r, g, b, a] = [r, g, b, a].map(|v| v as f32 / u16::MAX as f32);
+            // Premultiplied, so transparent pixels don't bleed their color
+            Rgba([linear(r) * a, linear(g) * a, linear(b) * a, a])
+        });
+    let small = imageops::resize(&float, w, h, FilterType::Lanczos3);
+    ImageBuffer::from_fn(w, h, |x, y| {
+        let [r, g, b, a] = small.get_pixel(x, y).0;
+        let a = a.clamp(0.0, 1.0);
+        let un = |v: f32| if a > 0.0 { encode(v / a) } else { 0 };
+        Rgba([un(r), un(g), un(b), (a * u16::MAX as f32).round() as u16])
+    })
+}
+
+// Next to the render: out/name.png -> out/name_thumb.png
+pub fn path_for<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_thumb.png", stem))
+}
@@ -0,0 +1,129 @@
+// fracmd
+// Slippy map tiles
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num::complex::Complex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{metadata, Args, Functs, Renderer};
+
+// Past this, neighbouring pixels of a tile are closer together than f32
+// can tell points of the plane apart
+pub const MAX_LEVEL: u32 = 16;
+
+// Inclusive zoom level range, "3" or "0..12", up to MAX_LEVEL
+#[derive(Clone, Copy, Debug)]
+pub struct ZoomRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+// One zoom level, stitch's --z
+pub fn level(string: &str) -> Result<u32, String> {
+    match string.trim().parse() {
+        Ok(z) if z <= MAX_LEVEL => Ok(z),
+        Ok(_) => Err(format!("zoom levels go up to {}", MAX_LEVEL)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl FromStr for ZoomRange {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (min, max) = match string.split_once("..") {
+            Some((a, b)) => (level(a)?, level(b)?),
+            None => (level(string)?, level(string)?),
+        };
+        if min > max {
+            return Err(format!("{} is past {}", min, max));
+        }
+        Ok(ZoomRange { min, max })
+    }
+}
+
+// Tile 0/0/0 is the square of side 2 / zoom centered on the origin, the same
+// span a square render of the base parameters covers. Tile z/x/y is the
+// x-th column and y-th row of its 2^z by 2^z subdivision, rows following
+// image rows like every other render. With --rotate or --transform the
+// whole grid moves with the view.
+pub fn tile_args(args: &Args, z: u32, x: u32, y: u32, size: u32) -> Args {
+    let n = (1u64 << z) as f32;
+    let side = 2.0 / args.zoom;
+    let center = Complex::new(
+        -side / 2.0 + (x as f32 + 0.5) * side / n,
+        -side / 2.0 + (y as f32 + 0.5) * side / n,
+    );
+    let mut tile = args.clone();
+    tile.width = size as i32;
+    tile.height = size as i32;
+    tile.zoom = args.zoom * n;
+    tile.origin = args.origin + args.view().apply(center);
+    tile
+}
+
+// FNV-1a over every parameter that changes pixels, so caches made with
+// different settings never mix
+pub fn cache_key(args: &Args) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (k, v) in args.params() {
+        if matches!(k, "width" | "height" | "name" | "threads") {
+            continue;
+        }
+        for b in k.bytes().chain(v.bytes()).chain([0]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{}-{:016x}", args.name, hash)
+}
+
+// Pixels across a whole level stitched from tiles of size
+pub fn stitched_side(z: u32, size: u32) -> u64 {
+    (1u64 << z) * size as u64
+}
+
+pub fn tile_path(dir: &Path, z: u32, x: u32, y: u32) -> PathBuf {
+    dir.join(z.to_string())
+        .join(x.to_string())
+        .join(format!("{}.png", y))
+}
+
+// Renders the tile unless the cache already has it, returning its path
+pub fn render_tile(
+    args: &Args,
+    functs: Functs,
+    dir: &Path,
+    z: u32,
+    x: u32,
+    y: u32,
+    size: u32,
+) -> Result<PathBuf, metadata::MetadataError> {
+    let path = tile_path(dir, z, x, y);
+    if !path.exists() {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tile = tile_args(args, z, x, y, size);
+        let image = Renderer::new(tile.clone(), functs).render();
+        // Renamed into place so `fracmd serve` never reads half a tile
+        let partial = path.with_extension("png.partial");
+        metadata::save_png(&partial, &image, &tile)?;
+        fs::rename(&partial, &path)?;
+    }
+    Ok(path)
+}
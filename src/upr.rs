@@ -0,0 +1,325 @@
+// fracmd
+// Ultra Fractal .upr parameter import
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A .upr holds entries like
+//
+//   Name {
+//   fractal:
+//     title="Name" width=640 height=480 layers=1
+//   mapping:
+//     center=-0.75/0.1 magn=20 angle=30
+//   formula:
+//     maxiter=500 filename="Standard.ufm" entry="Mandelbrot" p_power=2/0
+//   ...
+//   }
+//
+// sections of key=value pairs, complex values written re/im. The first
+// entry's size, view, iteration limit and formula carry over, everything
+// fracmd can't do becomes a warning rather than a refusal, since the view
+// is usually what a shared location is about. Magnification 1 shows a
+// span of 3 along the shorter side and the imaginary axis points up, as
+// with Kalles Fraktaler imported views get --transform 1,0,0,-1.
+
+use crate::Args;
+use err_derive::Error;
+use std::{fs, path::Path};
+
+#[derive(Debug, Error)]
+pub enum UprError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "line {}: {}", _0, _1)]
+    Syntax(usize, String),
+    #[error(display = "no parameter entry in the file")]
+    Empty,
+    #[error(display = "no {} in the first entry", _0)]
+    Missing(&'static str),
+}
+
+// Formulas that are fracmd's own, (file, entry, fractal, whether the
+// entry is the formula's Julia set)
+const KNOWN: &[(&str, &str, &str, bool)] = &[
+    ("standard.ufm", "mandelbrot", "multibrot", false),
+    ("standard.ufm", "julia", "multibrot", true),
+];
+
+// Formula parameters the known formulas' flags take care of
+const HANDLED: &[&str] = &["p_power", "p_bailout", "p_seed", "p_start"];
+
+pub struct Imported {
+    pub flags: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+pub fn is_upr(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("upr"))
+}
+
+struct Value<'a> {
+    line: usize,
+    section: &'a str,
+    key: &'a str,
+    value: &'a str,
+}
+
+// The key=value pairs of the first entry, and how many entries there are
+fn parse(text: &str) -> Result<(Vec<Value<'_>>, usize), UprError> {
+    let mut values = Vec::new();
+    let mut entries = 0;
+    let mut section = "";
+    let mut inside = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if !inside {
+            if line.ends_with('{') {
+                inside = true;
+                entries += 1;
+            }
+            continue;
+        }
+        if line == "}" {
+            inside = false;
+            continue;
+        }
+        if entries > 1 {
+            continue;
+        }
+        if let Some(name) = line.strip_suffix(':').filter(|n| !n.contains([' ', '='])) {
+            section = name;
+            continue;
+        }
+        let mut rest = line;
+        while !rest.is_empty() {
+            let Some((key, after)) = rest.split_once('=') else {
+                return Err(UprError::Syntax(
+                    i + 1,
+                    format!("expected key=value, got {:?}", rest),
+                ));
+            };
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => match quoted.split_once('"') {
+                    Some(split) => split,
+                    None => return Err(UprError::Syntax(i + 1, String::from("unclosed quote"))),
+                },
+                None => after.split_once(' ').unwrap_or((after, "")),
+            };
+            values.push(Value {
+                line: i + 1,
+                section,
+                key: key.trim(),
+                value,
+            });
+            rest = after.trim_start();
+        }
+    }
+    match entries {
+        0 => Err(UprError::Empty),
+        n => Ok((values, n)),
+    }
+}
+
+// The flags for the first entry, with what didn't carry over. Flags given
+// in args win over the entry's size when converting its magnification.
+pub fn read(path: &Path, args: &Args) -> Result<Imported, UprError> {
+    let text = fs::read_to_string(path)?;
+    let (values, entries) = parse(&text)?;
+    let find = |section: &str, key: &str| {
+        values
+            .iter()
+            .find(|v| v.section == section && v.key.eq_ignore_ascii_case(key))
+    };
+    let number = |v: &Value| -> Result<f64, UprError> {
+        v.value.parse().map_err(|_| {
+            UprError::Syntax(v.line, format!("{} isn't a number: {:?}", v.key, v.value))
+        })
+    };
+    let complex = |v: &Value| -> Result<(f64, f64), UprError> {
+        let (re, im) = v.value.split_once('/').unwrap_or((v.value, "0"));
+        match (re.parse(), im.parse()) {
+            (Ok(re), Ok(im)) => Ok((re, im)),
+            _ => Err(UprError::Syntax(
+                v.line,
+                format!("{} isn't a re/im number: {:?}", v.key, v.value),
+            )),
+        }
+    };
+    let optional = |section: &str, key: &str| find(section, key).map(number).transpose();
+
+    let mut flags = Vec::new();
+    let mut warnings = Vec::new();
+    if entries > 1 {
+        warnings.push(format!(
+            "the file holds {} entries, only the first is imported",
+            entries
+        ));
+    }
+    if let Some(layers) = optional("fractal", "layers")?.filter(|&l| l > 1.0) {
+        warnings.push(format!(
+            "only the first of the {} layers is imported",
+            layers
+        ));
+    }
+
+    let mut size = (args.width, args.height);
+    for (key, given) in [("width", &mut size.0), ("height", &mut size.1)] {
+        match optional("fractal", key)? {
+            Some(v) if !args.given(key) => {
+                *given = v as i32;
+                flags.push(format!("--{}={}", key, v as i32));
+            }
+            _ => (),
+        }
+    }
+    let (re, im) = complex(find("mapping", "center").ok_or(UprError::Missing("center"))?)?;
+    let magn = optional("mapping", "magn")?.unwrap_or(1.0);
+    let (width, height) = (size.0.max(1) as f64, size.1.max(1) as f64);
+    let zoom = 2.0 * magn * width.min(height) / (3.0 * width);
+    flags.extend([
+        format!("--re={}", re),
+        format!("--im={}", im),
+        format!("--zoom={}", zoom as f32),
+        String::from("--transform=1,0,0,-1"),
+    ]);
+    if let Some(angle) = optional("mapping", "angle")? {
+        flags.push(format!("--rotate={}", angle));
+    }
+    for (key, default) in [("stretch", 1.0), ("skew", 0.0)] {
+        if optional("mapping", key)?.is_some_and(|v| v != default) {
+            warnings.push(format!("the view's {} isn't imported", key));
+        }
+    }
+    if let Some(maxiter) = optional("formula", "maxiter")? {
+        flags.push(format!("--limit={}", maxiter));
+    }
+
+    let file = find("formula", "filename").map_or("", |v| v.value);
+    let entry = find("formula", "entry")
+        .filter(|v| !v.value.is_empty())
+        .ok_or(UprError::Missing("formula entry"))?;
+    let known = KNOWN
+        .iter()
+        .find(|(f, e, _, _)| f.eq_ignore_ascii_case(file) && e.eq_ignore_ascii_case(entry.value));
+    match known {
+        Some(&(_, _, fractal, julia)) => {
+            if fractal != "multibrot" {
+                flags.push(format!("--fractal={}", fractal));
+            }
+            if let Some(v) = find("formula", "p_power") {
+                let (power, power_im) = complex(v)?;
+                flags.push(format!("--power={}", power));
+                if power_im != 0.0 {
+                    warnings.push(String::from(
+                        "complex powers aren't supported, only the real part is used",
+                    ));
+                }
+            }
+            if let Some(bailout) = optional("formula", "p_bailout")? {
+                // Ultra Fractal compares |z|^2 against it
+                flags.push(format!("--bail={}", bailout.sqrt()));
+            }
+            match (julia, find("formula", "p_seed")) {
+                (true, Some(v)) => {
+                    let (re, im) = complex(v)?;
+                    flags.push(format!("--julia={},{}", re, im));
+                }
+                (true, None) => return Err(UprError::Missing("p_seed")),
+                (false, _) => (),
+            }
+            if find("formula", "p_start")
+                .map(complex)
+                .transpose()?
+                .is_some_and(|s| s != (0.0, 0.0))
+            {
+                warnings.push(String::from(
+                    "the perturbed start p_start isn't supported, z starts at 0",
+                ));
+            }
+            let extra: Vec<&str> = values
+                .iter()
+                .filter(|v| v.section == "formula" && v.key.starts_with("p_"))
+                .map(|v| v.key)
+                .filter(|k| !HANDLED.iter().any(|h| h.eq_ignore_ascii_case(k)))
+                .collect();
+            if !extra.is_empty() {
+                warnings.push(format!(
+                    "formula parameters {} aren't imported",
+                    extra.join(", ")
+                ));
+            }
+        }
+        None => {
+            // Left for a --plugin or --script defining it to provide
+            let name: String = entry
+                .value
+                .to_lowercase()
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            flags.push(format!("--fractal={}", name));
+            warnings.push(format!(
+                "{} from {} isn't built in, it needs a --plugin or --script with a fractal called {}",
+                entry.value, file, name
+            ));
+        }
+    }
+    if values.iter().any(|v| v.section == "transform") {
+        warnings.push(String::from("transformations aren't imported"));
+    }
+    if values
+        .iter()
+        .any(|v| matches!(v.section, "inside" | "outside" | "gradient"))
+    {
+        warnings.push(String::from(
+            "colorings and gradients aren't imported, fracmd colors the view its own way",
+        ));
+    }
+    Ok(Imported { flags, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempFile;
+
+    const ENTRY: &str = "Name {\n\
+        fractal:\n  title=\"Name\" width=640 height=480 layers=1\n\
+        mapping:\n  center=-0.75/0.1 magn=3 angle=30\n\
+        formula:\n  maxiter=500 filename=\"Standard.ufm\" entry=\"Mandelbrot\" p_power=3/0\n\
+        }\n";
+
+    // Anything cut before the formula's entry closes lacks something, and an
+    // empty entry counts as none
+    #[test]
+    fn truncated_entries_are_refused() {
+        let args = Args::parse_argv(["fracmd"].map(String::from));
+        let whole = ENTRY.find("Mandelbrot\"").unwrap() + "Mandelbrot".len();
+        for end in 0..whole {
+            let file = TempFile::new(&format!("truncated-{}.upr", end), &ENTRY[..end]);
+            assert!(read(&file.0, &args).is_err(), "{}", end);
+        }
+        let empty = TempFile::new("empty.upr", ENTRY.replace("\"Mandelbrot\"", "\"\""));
+        assert!(matches!(
+            read(&empty.0, &args),
+            Err(UprError::Missing("formula entry"))
+        ));
+    }
+}
@@ -0,0 +1,252 @@
+// fracmd
+// Checking parameters before anything renders
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Clap only checks that values parse. These are the ones that parse fine
+// but would panic halfway through a render or quietly give a black image.
+
+use err_derive::Error;
+
+use crate::{backend::Backend, coords, expmap::Projection, kf, plugin, sampler::SamplerKind, Args};
+
+#[derive(Debug, Error)]
+pub enum Invalid {
+    #[error(display = "--width and --height must be at least 1, got {}x{}", _0, _1)]
+    Size(i32, i32),
+    #[error(
+        display = "--zoom must be more than 0, got {}, use --rotate 180 to turn the view over",
+        _0
+    )]
+    Zoom(f32),
+    #[error(display = "--{} must be a finite number", _0)]
+    NotFinite(&'static str),
+    #[error(display = "--samples must be at least 1")]
+    Samples,
+    #[error(
+        display = "--sampled must be more than 0, got {}, 2 is the default",
+        _0
+    )]
+    Sampled(f32),
+    #[error(
+        display = "--samples {} isn't a multiple of --adaptive-min {}, which has to be at least 1",
+        _0,
+        _1
+    )]
+    AdaptiveMin(usize, usize),
+    #[error(display = "--adaptive-threshold must be 0 or more, got {}", _0)]
+    AdaptiveThreshold(f32),
+    #[error(display = "--limit must be at least 1 iteration, got {}", _0)]
+    Limit(f32),
+    #[error(
+        display = "--bail must be more than 1 for smooth coloring, got {}, 16 is the default",
+        _0
+    )]
+    Bail(f32),
+    #[error(display = "--converge must be more than 0, got {}", _0)]
+    Converge(f32),
+    #[error(display = "--threads must be at least 1")]
+    Threads,
+    #[error(display = "--transform {} flattens the view onto a line", _0)]
+    Transform(String),
+    #[error(display = "--colors must be between 2 and 256, got {}", _0)]
+    Colors(usize),
+    #[error(display = "--quality must be between 1 and 100, got {}", _0)]
+    Quality(u8),
+    #[error(display = "--tile-size must be at least 1")]
+    TileSize,
+    #[error(display = "--live-interval must be more than 0 seconds, got {}", _0)]
+    LiveInterval(f32),
+    #[error(display = "--svg-spacing must be more than 0, got {}", _0)]
+    SvgSpacing(f32),
+    #[error(display = "--sizes and --region can't be used together")]
+    SizesRegion,
+    #[error(display = "--power must be more than 1, got {}", _0)]
+    Power(f32),
+    #[error(display = "--projection expmap strips can't be cut with --region or --sizes")]
+    ExpmapCrop,
+    #[error(display = "--plot-orbit draws on the plane, not on --projection expmap strips")]
+    ExpmapOrbit,
+    #[error(display = "--autocenter finds Mandelbrot minibrots, it can't be used with --julia")]
+    JuliaAutocenter,
+    #[error(
+        display = "--backend gpu isn't available, there's no gpu backend yet, use auto, simd or scalar"
+    )]
+    Gpu,
+    #[error(display = "--kfr: {}", _0)]
+    Kfr(String),
+    #[error(display = "no fractal called {}, the ones loaded are {}", _0, _1)]
+    Fractal(String, String),
+    #[error(display = "no coloring called {}, the ones loaded are {}", _0, _1)]
+    Coloring(String, String),
+}
+
+// Every problem at once, so fixing a command line takes one round
+pub fn check(args: &Args) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    let finite = [
+        (
+            "origin",
+            args.origin.re.is_finite() && args.origin.im.is_finite(),
+        ),
+        ("zoom", args.zoom.is_finite()),
+        ("rotate", args.rotate.is_finite()),
+        ("cexp", args.cexp.is_finite()),
+        ("power", args.power.is_finite()),
+        ("limit", args.limit.is_finite()),
+        ("bail", args.bail.is_none_or(f32::is_finite)),
+        ("converge", args.converge.is_none_or(f32::is_finite)),
+        ("sampled", args.sampled.is_finite()),
+        ("adaptive-threshold", args.adaptive_threshold.is_finite()),
+        (
+            "julia",
+            args.julia
+                .is_none_or(|c| c.re.is_finite() && c.im.is_finite()),
+        ),
+        (
+            "plot-orbit",
+            args.plot_orbit
+                .is_none_or(|c| c.re.is_finite() && c.im.is_finite()),
+        ),
+    ];
+    for (key, ok) in finite {
+        if !ok {
+            problems.push(Invalid::NotFinite(key));
+        }
+    }
+    if args.width < 1 || args.height < 1 {
+        problems.push(Invalid::Size(args.width, args.height));
+    }
+    if args.zoom.is_finite() && args.zoom <= 0.0 {
+        problems.push(Invalid::Zoom(args.zoom));
+    }
+    if args.samples < 1 {
+        problems.push(Invalid::Samples);
+    }
+    if args.sampled.is_finite() && args.sampled <= 0.0 {
+        problems.push(Invalid::Sampled(args.sampled));
+    }
+    if args.sampler == SamplerKind::Adaptive {
+        if args.adaptive_min < 1 || !args.samples.is_multiple_of(args.adaptive_min) {
+            problems.push(Invalid::AdaptiveMin(args.samples, args.adaptive_min));
+        }
+        if args.adaptive_threshold < 0.0 {
+            problems.push(Invalid::AdaptiveThreshold(args.adaptive_threshold));
+        }
+    }
+    if args.limit.is_finite() && args.limit < 1.0 {
+        problems.push(Invalid::Limit(args.limit));
+    }
+    if let Some(bail) = args.bail.filter(|b| b.is_finite() && *b <= 1.0) {
+        problems.push(Invalid::Bail(bail));
+    }
+    if let Some(eps) = args.converge.filter(|e| e.is_finite() && *e <= 0.0) {
+        problems.push(Invalid::Converge(eps));
+    }
+    if args.power.is_finite() && args.power <= 1.0 {
+        problems.push(Invalid::Power(args.power));
+    }
+    if args.threads < 1 {
+        problems.push(Invalid::Threads);
+    }
+    if args.view().inverse().is_none() || args.transform.0.iter().flatten().any(|v| !v.is_finite())
+    {
+        problems.push(Invalid::Transform(args.transform.to_string()));
+    }
+    if !(2..=256).contains(&args.colors) {
+        problems.push(Invalid::Colors(args.colors));
+    }
+    if !(1..=100).contains(&args.quality) {
+        problems.push(Invalid::Quality(args.quality));
+    }
+    if args.backend == Backend::Gpu {
+        problems.push(Invalid::Gpu);
+    }
+    if args.tile_size < 1 {
+        problems.push(Invalid::TileSize);
+    }
+    if !(args.live_interval > 0.0 && args.live_interval.is_finite()) {
+        problems.push(Invalid::LiveInterval(args.live_interval));
+    }
+    if !(args.svg_spacing > 0.0 && args.svg_spacing.is_finite()) {
+        problems.push(Invalid::SvgSpacing(args.svg_spacing));
+    }
+    if !args.sizes.is_empty() && args.region.is_some() {
+        problems.push(Invalid::SizesRegion);
+    }
+    if args.projection == Projection::Expmap && (args.region.is_some() || !args.sizes.is_empty()) {
+        problems.push(Invalid::ExpmapCrop);
+    }
+    if args.projection == Projection::Expmap && args.plot_orbit.is_some() {
+        problems.push(Invalid::ExpmapOrbit);
+    }
+    if args.autocenter && args.julia.is_some() {
+        problems.push(Invalid::JuliaAutocenter);
+    }
+    if let Some(reason) = args.kfr.as_ref().and_then(|_| kf::unsupported(args)) {
+        problems.push(Invalid::Kfr(reason));
+    }
+    let fractals = plugin::fractals();
+    if !fractals.contains(&args.fractal) {
+        problems.push(Invalid::Fractal(args.fractal.clone(), fractals.join(", ")));
+    }
+    let colorings = plugin::colorings();
+    if !colorings.contains(&args.coloring) {
+        problems.push(Invalid::Coloring(
+            args.coloring.clone(),
+            colorings.join(", "),
+        ));
+    }
+    problems
+}
+
+// Digits of the origin past what an f32 keeps only go into the metadata,
+// which is worth saying since the render can't tell them apart
+pub fn precision(args: &Args) -> Vec<String> {
+    [
+        ("re", &args.re, args.origin.re),
+        ("im", &args.im, args.origin.im),
+    ]
+    .into_iter()
+    .filter_map(|(flag, text, v)| {
+        let text = text.as_ref()?;
+        let kept = v.to_string();
+        (coords::digits(text) > coords::digits(&kept)).then(|| {
+            let flag = if args.given(flag) { flag } else { "origin" };
+            format!(
+                "warning: --{} has {} significant digits but renders in f32, which keeps {}",
+                flag,
+                coords::digits(text),
+                kept
+            )
+        })
+    })
+    .collect()
+}
+
+// More threads than cores only slows things down, and an image rendered on
+// a bigger machine still has to reproduce here, so that's capped rather
+// than refused
+pub fn cap_threads(args: &mut Args) -> Option<String> {
+    let cores = num_cpus::get();
+    (args.threads > cores).then(|| {
+        let message = format!(
+            "Using {} threads rather than {}, the number of cores here",
+            cores, args.threads
+        );
+        args.threads = cores;
+        message
+    })
+}
@@ -0,0 +1,187 @@
+// fracmd
+// Checking renders against golden images for `fracmd verify`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A suite directory holds suite.toml, one [[scene]] table per scene, plus
+// <name>.png for each scene and hashes.txt of "<name> <hash>" lines. A hash
+// match passes straight away. Otherwise the render is compared with the
+// golden image, since the float math is allowed to differ in the last bits
+// between platforms and compilers, and passes if it's close enough.
+
+use err_derive::Error;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+use crate::{
+    config::{self, ConfigError},
+    diff,
+    metadata::{self, MetadataError},
+    scheduler::Image16,
+    Args,
+};
+
+pub const SUITE: &str = "suite.toml";
+pub const HASHES: &str = "hashes.txt";
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "toml error: {}", _0)]
+    Toml(#[error(source)] toml::de::Error),
+    #[error(display = "{}", _0)]
+    Config(#[error(source)] ConfigError),
+    #[error(display = "{}", _0)]
+    Metadata(#[error(source)] MetadataError),
+    #[error(display = "scene {}: {}", _0, _1)]
+    BadScene(usize, String),
+}
+
+pub struct Scene {
+    pub name: String,
+    pub flags: Vec<String>,
+}
+
+pub enum Outcome {
+    Exact,
+    // Within the tolerance, with the PSNR it got
+    Close(f64),
+    Different(diff::Metrics),
+    Missing,
+    Resized,
+    // Flags clap wouldn't take, so nothing was rendered
+    BadFlags(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Exact | Outcome::Close(_))
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Outcome::Exact => write!(f, "ok"),
+            Outcome::Close(psnr) => write!(f, "ok, {:.2} dB from the golden image", psnr),
+            Outcome::Different(m) => write!(
+                f,
+                "FAILED, {:.2} dB from the golden image, max channel delta {}, {} pixels differ",
+                m.psnr, m.max_delta, m.differing
+            ),
+            Outcome::Missing => write!(f, "FAILED, no golden image, run verify --update"),
+            Outcome::Resized => write!(f, "FAILED, the golden image is a different size"),
+            Outcome::BadFlags(e) => write!(f, "FAILED, {}", e),
+        }
+    }
+}
+
+pub fn load_suite(dir: &Path) -> Result<Vec<Scene>, VerifyError> {
+    let suite: toml::value::Table = toml::from_str(&fs::read_to_string(dir.join(SUITE))?)?;
+    let tables = match suite.get("scene") {
+        Some(Value::Array(tables)) => tables.clone(),
+        _ => Vec::new(),
+    };
+    let mut scenes = Vec::new();
+    for (i, table) in tables.into_iter().enumerate() {
+        let bad = |msg: &str| VerifyError::BadScene(i + 1, msg.to_string());
+        let Value::Table(mut table) = table else {
+            return Err(bad("must be a table"));
+        };
+        let name = match table.remove("name") {
+            Some(Value::String(name)) if !name.is_empty() && !name.contains(['/', '\\']) => name,
+            _ => return Err(bad("needs a name that can be a file name")),
+        };
+        if scenes.iter().any(|s: &Scene| s.name == name) {
+            return Err(bad(&format!("{} is used twice", name)));
+        }
+        scenes.push(Scene {
+            name,
+            flags: config::from_table(table)?,
+        });
+    }
+    Ok(scenes)
+}
+
+// The scene's parameters, made repeatable, on the given number of threads,
+// or the outcome of a scene whose flags don't parse
+pub fn scene_args(scene: &Scene, threads: usize) -> Result<Args, Outcome> {
+    let mut args = Args::try_from_params(&[], &scene.flags).map_err(Outcome::BadFlags)?;
+    args.seed.get_or_insert(0);
+    args.threads = threads;
+    Ok(args)
+}
+
+// FNV-1a over the pixels, so the file's metadata doesn't count
+pub fn hash(image: &Image16) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for value in image.as_raw() {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+pub fn read_hashes(dir: &Path) -> Vec<(String, u64)> {
+    fs::read_to_string(dir.join(HASHES))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (name, hash) = line.split_once(' ')?;
+            Some((name.to_string(), u64::from_str_radix(hash.trim(), 16).ok()?))
+        })
+        .collect()
+}
+
+pub fn golden_path(dir: &Path, scene: &Scene) -> PathBuf {
+    dir.join(format!("{}.png", scene.name))
+}
+
+pub fn check(
+    dir: &Path,
+    scene: &Scene,
+    image: &Image16,
+    hashes: &[(String, u64)],
+    min_psnr: f64,
+) -> Outcome {
+    if hashes.contains(&(scene.name.clone(), hash(image))) {
+        return Outcome::Exact;
+    }
+    let golden = match image::open(golden_path(dir, scene)) {
+        Ok(golden) => golden.into_rgba16(),
+        Err(_) => return Outcome::Missing,
+    };
+    match diff::metrics(&golden, image) {
+        Ok(m) if m.psnr >= min_psnr => Outcome::Close(m.psnr),
+        Ok(m) => Outcome::Different(m),
+        Err(_) => Outcome::Resized,
+    }
+}
+
+// Writes every scene's golden image and the hashes of all of them
+pub fn update(dir: &Path, rendered: &[(&Scene, Args, Image16)]) -> Result<(), VerifyError> {
+    let mut hashes = String::new();
+    for (scene, args, image) in rendered {
+        metadata::save_png(golden_path(dir, scene), image, args)?;
+        hashes.push_str(&format!("{} {:016x}\n", scene.name, hash(image)));
+    }
+    Ok(fs::write(dir.join(HASHES), hashes)?)
+}
@@ -0,0 +1,1120 @@
+// fracmd
+// The interactive explorer for `fracmd view`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The explorer takes over the terminal with the alternate screen and stty
+// and draws the view in half blocks at the terminal's own resolution, at
+// the aspect of --width and --height. Each view is drawn in coarse blocks
+// first and refined, and a key pressed mid pass throws the rest of it away,
+// so changes show straight away even at high limits. The passes keep what
+// iterating found rather than colors, so picking a palette, transfer or
+// cycles recolors the view at once without iterating again. Dragging with the
+// mouse pans, the wheel zooms about the point under it and a click opens
+// the Julia set of the point in a pane beside the view. A box dragged out
+// with the right button zooms into just what's inside, and the arrows and
+// brackets pan and zoom from the keyboard where there's no mouse, as over
+// some ssh clients. Enter hands the view to a normal `fracmd render` with
+// the explorer's settings, followed by `fracmd recolor` of its data when a
+// palette is picked, so the full quality render is exactly what running
+// those commands would give.
+//
+// It stands in for a GUI explorer. Living in the terminal it needs no window
+// toolkit and works over ssh, but a knob only moves a step per key, the view
+// is as sharp as the terminal's cells and there's no starting it without a
+// terminal.
+
+use err_derive::Error;
+use image::Rgba;
+use num::complex::Complex;
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    coloring::{self, Gradient},
+    config, coords, default_functs, error,
+    fracdata::PixelData,
+    orbit,
+    palette::{Palette, Transfer},
+    par::*,
+    preset,
+    recolor::Recolor,
+    template,
+    terminal::{self, TextColor},
+    validate, value_name, wizard, Args, FracError, Renderer,
+};
+
+// Block sizes of the passes, in half block pixels
+const PASSES: [usize; 4] = [8, 4, 2, 1];
+// Lines under the image for the parameters, the slider and the cursor
+const PANEL: usize = 3;
+// How much a notch of the scroll wheel zooms
+const SCROLL: f32 = 1.25;
+// Where the whole of a Julia set fits
+const JULIA_ZOOM: f32 = 0.6;
+const SLIDER: usize = 24;
+const TRANSFERS: [Transfer; 3] = [Transfer::Linear, Transfer::Sqrt, Transfer::Log];
+
+// Set by Ctrl-C or SIGTERM while the explorer has the terminal, which
+// only happens while a full render runs or when it's sent from elsewhere
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Error)]
+pub enum ViewerError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "fracmd view needs a terminal on stdin and stdout")]
+    NotTerminal,
+    #[error(display = "stty failed: {}", _0)]
+    Stty(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    BackTab,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Esc,
+    // Ctrl-C, which stty leaves as a key rather than a signal
+    Interrupt,
+    // What the mouse did, at a cell counted from 0 at the top left
+    Mouse(Mouse, usize, usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mouse {
+    // Buttons are 0 for left, 1 for middle and 2 for right
+    Press(u8),
+    Drag(u8),
+    Release,
+    Move,
+    ScrollUp,
+    ScrollDown,
+}
+
+// SGR reports, ESC [ < button ; column ; row then M, or m for a release
+fn mouse(params: &str, last: u8) -> Option<Key> {
+    let mut fields = params
+        .strip_prefix('<')?
+        .split(';')
+        .map(|f| f.parse::<usize>());
+    let (Some(Ok(code)), Some(Ok(x)), Some(Ok(y))) = (fields.next(), fields.next(), fields.next())
+    else {
+        return None;
+    };
+    let button = (code & 3) as u8;
+    let event = match (last, code & 64 != 0, code & 32 != 0) {
+        (b'm', ..) => Mouse::Release,
+        (_, true, _) if button == 0 => Mouse::ScrollUp,
+        (_, true, _) => Mouse::ScrollDown,
+        (_, _, true) if button == 3 => Mouse::Move,
+        (_, _, true) => Mouse::Drag(button),
+        _ => Mouse::Press(button),
+    };
+    Some(Key::Mouse(event, x.saturating_sub(1), y.saturating_sub(1)))
+}
+
+// The end of an escape sequence, ESC [ then parameters then one letter
+fn csi(params: &str, last: u8) -> Option<Key> {
+    match (params, last) {
+        ("", b'A') => Some(Key::Up),
+        ("", b'B') => Some(Key::Down),
+        ("", b'C') => Some(Key::Right),
+        ("", b'D') => Some(Key::Left),
+        ("", b'Z') => Some(Key::BackTab),
+        ("5", b'~') => Some(Key::PageUp),
+        ("6", b'~') => Some(Key::PageDown),
+        (_, b'M' | b'm') => mouse(params, last),
+        _ => None,
+    }
+}
+
+// One read's worth of input, which holds whole escape sequences, so an
+// ESC with nothing after it is the key itself
+pub fn keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'[') {
+            let start = i + 2;
+            let end = (start..bytes.len()).find(|&j| (0x40..=0x7e).contains(&bytes[j]));
+            let Some(end) = end else {
+                break;
+            };
+            let params = String::from_utf8_lossy(&bytes[start..end]);
+            keys.extend(csi(&params, bytes[end]));
+            i = end + 1;
+            continue;
+        }
+        keys.extend(match bytes[i] {
+            0x1b => Some(Key::Esc),
+            b'\r' | b'\n' => Some(Key::Enter),
+            b'\t' => Some(Key::Tab),
+            3 => Some(Key::Interrupt),
+            b @ 0x20..=0x7e => Some(Key::Char(b as char)),
+            _ => None,
+        });
+        i += 1;
+    }
+    keys
+}
+
+fn stty(args: &[&str]) -> Result<String, ViewerError> {
+    let out = process::Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !out.status.success() {
+        let message = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err(ViewerError::Stty(message));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+// Keys as they're typed and nothing echoed while it lives, then the
+// terminal as it was
+struct Screen {
+    saved: String,
+}
+
+impl Screen {
+    fn enter() -> Result<Screen, ViewerError> {
+        let screen = Screen {
+            saved: stty(&["-g"])?,
+        };
+        screen.resume()?;
+        Ok(screen)
+    }
+
+    fn resume(&self) -> Result<(), ViewerError> {
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+        let mut out = io::stdout().lock();
+        // Every mouse move, reported as SGR
+        out.write_all(b"\x1b[?1049h\x1b[?25l\x1b[?1003h\x1b[?1006h")?;
+        Ok(out.flush()?)
+    }
+
+    fn suspend(&self) {
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(b"\x1b[?1003l\x1b[?1006l\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.suspend();
+    }
+}
+
+// Reads keys on a thread of its own, raising pending as each one comes in
+// so a pass in progress can give up. Moving the mouse only changes the
+// panel, so it waits for the pass instead.
+fn listen(pending: Arc<AtomicBool>) -> Receiver<Key> {
+    let (sender, keys) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buffer = [0; 256];
+        while let Ok(n @ 1..) = stdin.read(&mut buffer) {
+            for key in self::keys(&buffer[..n]) {
+                if !matches!(key, Key::Mouse(Mouse::Move, ..)) {
+                    pending.store(true, Ordering::SeqCst);
+                }
+                if sender.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    keys
+}
+
+// What the sliders move
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Knob {
+    Zoom,
+    Limit,
+    Samples,
+    Cexp,
+    Power,
+    Palette,
+    Transfer,
+    Cycles,
+}
+
+const KNOBS: [Knob; 8] = [
+    Knob::Zoom,
+    Knob::Limit,
+    Knob::Samples,
+    Knob::Cexp,
+    Knob::Power,
+    Knob::Palette,
+    Knob::Transfer,
+    Knob::Cycles,
+];
+
+// How the iterations are colored, which changes without iterating again
+struct Coloring {
+    // The built-in coloring first, then the .map files in palettes/
+    palettes: Vec<Option<(PathBuf, Palette)>>,
+    palette: usize,
+    transfer: Transfer,
+    cycles: f32,
+}
+
+impl Coloring {
+    fn new(args: &Args) -> Coloring {
+        let mut paths: Vec<PathBuf> = fs::read_dir("palettes")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "map"))
+            .collect();
+        paths.sort();
+        let to_space = args.colorspace.matrix_from_srgb();
+        let loaded = paths.into_iter().filter_map(|path| {
+            let palette = Palette::load(&path).ok()?.transform(&to_space);
+            Some(Some((path, palette)))
+        });
+        Coloring {
+            palettes: [None].into_iter().chain(loaded).collect(),
+            palette: 0,
+            transfer: Transfer::Linear,
+            cycles: 1.0,
+        }
+    }
+
+    fn transfer_at(&self) -> usize {
+        TRANSFERS
+            .iter()
+            .position(|&t| t == self.transfer)
+            .unwrap_or(0)
+    }
+
+    fn picked(&self) -> Option<&(PathBuf, Palette)> {
+        self.palettes[self.palette].as_ref()
+    }
+
+    // The view's pixels from what iterating found, inside the set as the
+    // renderer colors it
+    fn paint(&self, args: &Args, data: &[PixelData]) -> Vec<[u8; 3]> {
+        let coloring: Arc<dyn coloring::Coloring> = match self.picked() {
+            Some((_, palette)) => Arc::new(Gradient {
+                palette: palette.clone(),
+                transfer: self.transfer,
+                cycles: self.cycles,
+                phase: 0.0,
+            }),
+            None => default_functs(args).coloring,
+        };
+        let recolor = Recolor::new(args, coloring);
+        data.par_iter()
+            .map(|p| recolor.pixel::<Rgba<u16>>(p).0)
+            .map(|[r, g, b, _]| [r, g, b].map(|v| (v >> 8) as u8))
+            .collect()
+    }
+}
+
+impl Knob {
+    fn name(self) -> &'static str {
+        match self {
+            Knob::Zoom => "zoom",
+            Knob::Limit => "limit",
+            Knob::Samples => "samples",
+            Knob::Cexp => "cexp",
+            Knob::Power => "power",
+            Knob::Palette => "palette",
+            Knob::Transfer => "transfer",
+            Knob::Cycles => "cycles",
+        }
+    }
+
+    // Whether turning it only recolors the view
+    fn recolors(self) -> bool {
+        matches!(
+            self,
+            Knob::Cexp | Knob::Palette | Knob::Transfer | Knob::Cycles
+        )
+    }
+
+    fn value(self, args: &Args, coloring: &Coloring) -> String {
+        match self {
+            Knob::Zoom => format!("{:.4}", args.zoom)
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string(),
+            Knob::Limit => args.limit.to_string(),
+            Knob::Samples => args.samples.to_string(),
+            Knob::Cexp => args.cexp.to_string(),
+            Knob::Power => args.power.to_string(),
+            Knob::Palette => match coloring.picked() {
+                Some((path, _)) => path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                None => String::from("builtin"),
+            },
+            Knob::Transfer => value_name(coloring.transfer),
+            Knob::Cycles => coloring.cycles.to_string(),
+        }
+    }
+
+    // Rounded so stepping up and back down lands where it started
+    fn step(self, args: &mut Args, coloring: &mut Coloring, up: bool) {
+        let sign = if up { 1.0 } else { -1.0 };
+        let round = |v: f32| (v * 100.0).round() / 100.0;
+        let next = |i: usize, n: usize| match up {
+            true => (i + 1).min(n - 1),
+            false => i.saturating_sub(1),
+        };
+        match self {
+            Knob::Zoom => args.zoom *= 1.25f32.powf(sign),
+            Knob::Limit => args.limit = (args.limit * 2f32.powf(sign)).round(),
+            Knob::Samples => args.samples = (args.samples as i64 + sign as i64).max(1) as usize,
+            Knob::Cexp => args.cexp = round(args.cexp + 0.1 * sign),
+            Knob::Power => args.power = round(args.power + 0.25 * sign),
+            Knob::Palette => coloring.palette = next(coloring.palette, coloring.palettes.len()),
+            Knob::Transfer => {
+                coloring.transfer = TRANSFERS[next(coloring.transfer_at(), TRANSFERS.len())]
+            }
+            Knob::Cycles => coloring.cycles = round(coloring.cycles + 0.25 * sign).max(0.25),
+        }
+    }
+
+    // Where the value sits along its slider, from 0 to 1
+    fn along(self, args: &Args, coloring: &Coloring) -> f32 {
+        let along = match self {
+            Knob::Zoom => (args.zoom.log10() + 1.0) / 7.0,
+            Knob::Limit => (args.limit.log2() - 4.0) / 12.0,
+            Knob::Samples => (args.samples as f32).log2() / 6.0,
+            Knob::Cexp => args.cexp / 4.0,
+            Knob::Power => (args.power - 1.0) / 7.0,
+            Knob::Palette => coloring.palette as f32 / (coloring.palettes.len() - 1).max(1) as f32,
+            Knob::Transfer => coloring.transfer_at() as f32 / (TRANSFERS.len() - 1) as f32,
+            Knob::Cycles => coloring.cycles / 8.0,
+        };
+        along.clamp(0.0, 1.0)
+    }
+}
+
+// What iterating finds over the view at cols x rows cells, in blocks of
+// step half block pixels, or None if a key came in first
+fn pass(
+    renderer: &Renderer,
+    cols: usize,
+    rows: usize,
+    step: usize,
+    pending: &AtomicBool,
+) -> Option<Vec<PixelData>> {
+    let (w, h) = (cols, rows * 2);
+    let (bw, bh) = (w.div_ceil(step), h.div_ceil(step));
+    let aspect = h as f32 / w as f32;
+    let blocks: Vec<PixelData> = (0..bw * bh)
+        .into_par_iter()
+        .map(|i| {
+            if pending.load(Ordering::Relaxed) {
+                return PixelData::default();
+            }
+            let middle = |block: usize, size: usize| {
+                ((block * step) as f32 + step as f32 / 2.0).min(size as f32 - 0.5) / size as f32
+            };
+            renderer.iterate(terminal::point(
+                renderer,
+                middle(i % bw, w),
+                middle(i / bw, h),
+                aspect,
+            ))
+        })
+        .collect();
+    if pending.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(
+        (0..w * h)
+            .map(|i| blocks[(i / w / step) * bw + i % w / step])
+            .collect(),
+    )
+}
+
+struct Explorer {
+    args: Args,
+    // Where it started, for going back
+    home: Args,
+    selected: usize,
+    status: String,
+    // Cells of the image, as last fitted to the terminal
+    layout: (usize, usize),
+    // What's under the mouse, and the cell a drag was last at
+    hover: Option<Hover>,
+    dragging: Option<(usize, usize)>,
+    dragged: bool,
+    // The c of the Julia set shown beside the view
+    julia: Option<Complex<f32>>,
+    // Corners of a zoom box being dragged out, and the view as last drawn
+    // for drawing it over
+    zoom_box: Option<((usize, usize), (usize, usize))>,
+    shown: Vec<[u8; 3]>,
+    coloring: Coloring,
+    // What the last passes of the view and the pane found, to recolor
+    data: Vec<PixelData>,
+    pane_data: Vec<PixelData>,
+    // Whether the orbit of the point under the mouse is drawn over the view
+    orbit: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Hover {
+    View(Complex<f32>),
+    // A starting z in the Julia pane
+    Julia(Complex<f32>),
+}
+
+enum Action {
+    Redraw,
+    // Only the panel changed
+    Panel,
+    // The zoom box moved over the view drawn already
+    Overlay,
+    // Only the coloring changed
+    Recolor,
+    Render(Box<Args>),
+    Quit,
+}
+
+impl Explorer {
+    // Offset from the view center at cell position x, y, which are
+    // fractional so the middle of a cell can be asked for
+    fn offset(&self, x: f32, y: f32, zoom: f32) -> Complex<f32> {
+        let (cols, rows) = (self.layout.0 as f32, self.layout.1 as f32);
+        let aspect = 2.0 * rows / cols;
+        Complex::new(2.0 * x / cols - 1.0, (2.0 * y / rows - 1.0) * aspect) / zoom
+    }
+
+    fn point_of(&self, args: &Args, x: usize, y: usize) -> Complex<f32> {
+        let (fx, fy) = (x as f32 + 0.5, y as f32 + 0.5);
+        args.view().apply(self.offset(fx, fy, args.zoom)) + args.origin
+    }
+
+    // The point of the plane under a cell, None off the image
+    fn point(&self, x: usize, y: usize) -> Option<Complex<f32>> {
+        (x < self.layout.0 && y < self.layout.1).then(|| self.point_of(&self.args, x, y))
+    }
+
+    // The point whose orbit is drawn, the view's center when the mouse
+    // isn't over it
+    fn orbit_of(&self) -> Option<Complex<f32>> {
+        match self.hover {
+            _ if !self.orbit => None,
+            Some(Hover::View(point)) => Some(point),
+            _ => Some(self.args.origin),
+        }
+    }
+
+    // The Julia pane, the same as the view but for starting at the whole
+    // set of its c
+    fn pane(&self) -> Option<Args> {
+        let mut args = self.args.clone();
+        args.julia = Some(self.julia?);
+        args.origin = Complex::new(0.0, 0.0);
+        args.zoom = JULIA_ZOOM;
+        Some(args)
+    }
+
+    // The column the pane starts at, one past a gap after the view
+    fn pane_column(&self) -> usize {
+        self.layout.0 + 1
+    }
+
+    fn hover(&self, x: usize, y: usize) -> Option<Hover> {
+        if let Some(point) = self.point(x, y) {
+            return Some(Hover::View(point));
+        }
+        let pane = self.pane()?;
+        let x = x.checked_sub(self.pane_column())?;
+        (x < self.layout.0 && y < self.layout.1).then(|| Hover::Julia(self.point_of(&pane, x, y)))
+    }
+
+    // A change that would break the render is refused and explained
+    fn change(&mut self, args: Args) {
+        match validate::check(&args).first() {
+            Some(problem) => self.status = problem.to_string(),
+            None => {
+                self.args = args;
+                self.status.clear();
+            }
+        }
+    }
+
+    // Moves the view by eighths of its size, at any rotation
+    fn pan(&mut self, right: f32, down: f32) {
+        let (cols, rows) = (self.layout.0 as f32, self.layout.1 as f32);
+        let zoom = self.args.zoom;
+        let moved = self.offset(
+            cols / 2.0 + right * cols / 8.0,
+            rows / 2.0 + down * rows / 8.0,
+            zoom,
+        ) - self.offset(cols / 2.0, rows / 2.0, zoom);
+        let mut args = self.args.clone();
+        args.origin += args.view().apply(moved);
+        self.change(args);
+    }
+
+    // Zooms by factor keeping the point under the cell where it is
+    fn zoom_at(&mut self, x: usize, y: usize, factor: f32) {
+        let Some(point) = self.point(x, y) else {
+            return;
+        };
+        let mut args = self.args.clone();
+        args.zoom *= factor;
+        let (fx, fy) = (x as f32 + 0.5, y as f32 + 0.5);
+        args.origin = point - args.view().apply(self.offset(fx, fy, args.zoom));
+        self.change(args);
+    }
+
+    // The view that just fits the box, centered on it
+    fn frame(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) {
+        let (x0, x1, y0, y1) = (x0.min(x1), x0.max(x1), y0.min(y1), y0.max(y1));
+        let (w, h) = ((x1 - x0 + 1) as f32, (y1 - y0 + 1) as f32);
+        if w < 2.0 && h < 2.0 {
+            self.status = String::from("Drag a bigger box to zoom into");
+            return;
+        }
+        let middle = ((x0 + x1 + 1) as f32 / 2.0, (y0 + y1 + 1) as f32 / 2.0);
+        let mut args = self.args.clone();
+        let offset = self.offset(middle.0, middle.1, args.zoom);
+        args.origin += args.view().apply(offset);
+        args.zoom *= (self.layout.0 as f32 / w).min(self.layout.1 as f32 / h);
+        self.change(args);
+        if self.status.is_empty() {
+            self.status = format!(
+                "Framed the box as --origin={} --zoom={}",
+                coords::format(self.args.origin),
+                self.args.zoom
+            );
+        }
+    }
+
+    fn mouse(&mut self, event: Mouse, x: usize, y: usize) -> Action {
+        self.hover = self.hover(x, y);
+        // The box stays on the view however far the mouse goes
+        let clamped = (
+            x.min(self.layout.0.saturating_sub(1)),
+            y.min(self.layout.1.saturating_sub(1)),
+        );
+        match event {
+            Mouse::Press(2) if self.point(x, y).is_some() => {
+                self.zoom_box = Some(((x, y), (x, y)));
+                return Action::Overlay;
+            }
+            Mouse::Drag(2) => {
+                if let Some((_, end)) = &mut self.zoom_box {
+                    *end = clamped;
+                    return Action::Overlay;
+                }
+            }
+            Mouse::Release if self.zoom_box.is_some() => {
+                let (start, _) = self.zoom_box.take().unwrap_or_default();
+                self.frame(start, clamped);
+                return Action::Redraw;
+            }
+            Mouse::Press(0) if self.point(x, y).is_some() => {
+                self.dragging = Some((x, y));
+                self.dragged = false;
+            }
+            Mouse::Drag(0) => {
+                let Some((from_x, from_y)) = self.dragging.replace((x, y)) else {
+                    return Action::Panel;
+                };
+                self.dragged = true;
+                let moved = self.offset(x as f32, y as f32, self.args.zoom)
+                    - self.offset(from_x as f32, from_y as f32, self.args.zoom);
+                let mut args = self.args.clone();
+                args.origin -= args.view().apply(moved);
+                self.change(args);
+                return Action::Redraw;
+            }
+            // A click without a drag opens the Julia set of the point
+            Mouse::Release => {
+                let clicked = self.dragging.take().is_some() && !self.dragged;
+                if let (true, Some(point)) = (clicked, self.point(x, y)) {
+                    if self.args.julia.is_some() {
+                        self.status = String::from("The view is a Julia set already");
+                        return Action::Panel;
+                    }
+                    self.julia = Some(point);
+                    self.status.clear();
+                    return Action::Redraw;
+                }
+            }
+            Mouse::ScrollUp => {
+                self.zoom_at(x, y, SCROLL);
+                return Action::Redraw;
+            }
+            Mouse::ScrollDown => {
+                self.zoom_at(x, y, 1.0 / SCROLL);
+                return Action::Redraw;
+            }
+            _ => (),
+        }
+        // The orbit follows the mouse
+        match self.orbit {
+            true => Action::Overlay,
+            false => Action::Panel,
+        }
+    }
+
+    fn key(&mut self, key: Key) -> Action {
+        let knob = KNOBS[self.selected];
+        let turn = |explorer: &mut Explorer, up: bool| {
+            let mut args = explorer.args.clone();
+            knob.step(&mut args, &mut explorer.coloring, up);
+            explorer.change(args);
+            match knob.recolors() {
+                true => Action::Recolor,
+                false => Action::Redraw,
+            }
+        };
+        match key {
+            Key::Esc if self.zoom_box.is_some() => {
+                self.zoom_box = None;
+                return Action::Overlay;
+            }
+            Key::Char('q') | Key::Esc | Key::Interrupt => return Action::Quit,
+            Key::Enter => return Action::Render(Box::new(self.args.clone())),
+            Key::Char('J') => match self.pane() {
+                Some(pane) => return Action::Render(Box::new(pane)),
+                None => {
+                    self.status = String::from("Click a point of the view to open its Julia set")
+                }
+            },
+            Key::Char('j') => self.julia = None,
+            Key::Char('o') => {
+                self.orbit = !self.orbit;
+                return Action::Overlay;
+            }
+            Key::Left => self.pan(-1.0, 0.0),
+            Key::Right => self.pan(1.0, 0.0),
+            Key::Up => self.pan(0.0, -1.0),
+            Key::Down => self.pan(0.0, 1.0),
+            Key::Char(']') | Key::PageUp => {
+                let mut args = self.args.clone();
+                args.zoom *= SCROLL;
+                self.change(args);
+            }
+            Key::Char('[') | Key::PageDown => {
+                let mut args = self.args.clone();
+                args.zoom /= SCROLL;
+                self.change(args);
+            }
+            Key::Char('0') => {
+                let mut args = self.args.clone();
+                args.origin = self.home.origin;
+                args.zoom = self.home.zoom;
+                self.change(args);
+            }
+            Key::Mouse(event, x, y) => return self.mouse(event, x, y),
+            Key::Tab => self.selected = (self.selected + 1) % KNOBS.len(),
+            Key::BackTab => self.selected = (self.selected + KNOBS.len() - 1) % KNOBS.len(),
+            Key::Char('+' | '=') => return turn(self, true),
+            Key::Char('-' | '_') => return turn(self, false),
+            _ => (),
+        }
+        Action::Redraw
+    }
+
+    // The parameter line, the slider of the selected one or the status,
+    // then where the mouse is
+    fn panel(&self, cols: usize) -> [String; PANEL] {
+        let texts: Vec<String> = KNOBS
+            .iter()
+            .map(|knob| {
+                format!(
+                    " {} {} ",
+                    knob.name(),
+                    knob.value(&self.args, &self.coloring)
+                )
+            })
+            .collect();
+        // The ones before scroll off to keep the selected one on the line
+        let width = |texts: &[String]| texts.iter().map(|t| t.chars().count()).sum::<usize>();
+        let first = (0..=self.selected)
+            .find(|&i| width(&texts[i..=self.selected]) <= cols)
+            .unwrap_or(self.selected);
+        let mut line = String::new();
+        let mut width = 0;
+        for (i, text) in texts.iter().enumerate().skip(first) {
+            width += text.chars().count();
+            if width > cols {
+                break;
+            }
+            if i == self.selected {
+                line.push_str(&format!("\x1b[7m{}\x1b[27m", text));
+            } else {
+                line.push_str(text);
+            }
+        }
+        let knob = KNOBS[self.selected];
+        let status = if self.status.is_empty() {
+            let at =
+                (knob.along(&self.args, &self.coloring) * (SLIDER - 1) as f32).round() as usize;
+            let slider: String = (0..SLIDER)
+                .map(|i| match i.cmp(&at) {
+                    std::cmp::Ordering::Less => '\u{2501}',
+                    std::cmp::Ordering::Equal => '\u{25cf}',
+                    std::cmp::Ordering::Greater => '\u{2500}',
+                })
+                .collect();
+            format!(
+                " {} {}  -/+ change  tab next  enter render  q quit",
+                knob.name(),
+                slider
+            )
+        } else {
+            format!(" {}", self.status)
+        };
+        let cursor = match (self.hover, self.julia) {
+            (Some(Hover::View(point)), _) if self.orbit => {
+                let renderer = Renderer::new(self.args.clone(), default_functs(&self.args));
+                let steps = renderer.orbit(point).len() - 1;
+                let fate = match steps as f32 >= self.args.limit {
+                    true => format!("stays in for all {} iterations", steps),
+                    false => format!("escapes after {} iterations", steps),
+                };
+                format!(" orbit of {} {}  o hides it", coords::format(point), fate)
+            }
+            (Some(Hover::View(point)), _) => format!(
+                " {} at zoom {}  drag to pan, scroll or right drag a box to zoom, click for its Julia set",
+                coords::format(point),
+                Knob::Zoom.value(&self.args, &self.coloring)
+            ),
+            (Some(Hover::Julia(z)), Some(c)) => format!(
+                " z {} of the Julia set of {}  J renders it, j closes it",
+                coords::format(z),
+                coords::format(c)
+            ),
+            _ => format!(
+                " {} at zoom {}  arrows pan, [ ] zoom, 0 goes back, o shows orbits",
+                coords::format(self.args.origin),
+                Knob::Zoom.value(&self.args, &self.coloring)
+            ),
+        };
+        let fit = |text: String| text.chars().take(cols).collect();
+        [line, fit(status), fit(cursor)]
+    }
+
+    fn draw_panel(&self, screen: (usize, usize)) {
+        let mut text = format!("\x1b[{};1H", screen.1.saturating_sub(PANEL) + 1);
+        text.push_str(&self.panel(screen.0).join("\x1b[K\n"));
+        text.push_str("\x1b[K");
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(text.as_bytes()).and_then(|_| out.flush());
+    }
+
+    // The line drawing character of the zoom box at a cell, if it's on it
+    fn box_edge(&self, x: usize, y: usize) -> Option<char> {
+        let ((x0, y0), (x1, y1)) = self.zoom_box?;
+        let (x0, x1, y0, y1) = (x0.min(x1), x0.max(x1), y0.min(y1), y0.max(y1));
+        let (left, right, top, bottom) = (x == x0, x == x1, y == y0, y == y1);
+        let inside = (x0..=x1).contains(&x) && (y0..=y1).contains(&y);
+        Some(match (left, right, top, bottom) {
+            _ if !inside => return None,
+            (true, _, true, _) => '\u{250c}',
+            (_, true, true, _) => '\u{2510}',
+            (true, _, _, true) => '\u{2514}',
+            (_, true, _, true) => '\u{2518}',
+            (true, ..) | (_, true, ..) => '\u{2502}',
+            (.., true, _) | (.., true) => '\u{2500}',
+            _ => return None,
+        })
+    }
+
+    // The view's pixels with the orbit drawn over them
+    fn traced(&self, pixels: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        let mut pixels = pixels.to_vec();
+        let Some(c) = self.orbit_of() else {
+            return pixels;
+        };
+        let (w, h) = (self.layout.0, self.layout.1 * 2);
+        let aspect = h as f32 / w as f32;
+        let points: Vec<(f32, f32)> = Renderer::new(self.args.clone(), default_functs(&self.args))
+            .orbit(c)
+            .into_iter()
+            .filter_map(|z| orbit::place(&self.args, aspect, z))
+            .map(|(u, v)| (u * w as f32 - 0.5, v * h as f32 - 0.5))
+            .collect();
+        orbit::trace(&points, w, h, 0, |x, y, color| {
+            let [r, g, b, _] = color.to_arr8();
+            pixels[y * w + x] = [r, g, b];
+        });
+        pixels
+    }
+
+    // What Enter runs for args: the render, and with a palette picked a
+    // recolor of its data over the image after
+    fn commands(&self, args: &Args) -> Result<Vec<Vec<String>>, FracError> {
+        let mut render: Vec<String> = [String::from("render")]
+            .into_iter()
+            .chain(
+                preset::changed(args)
+                    .iter()
+                    .map(|(k, v)| config::flag(k, v)),
+            )
+            .collect();
+        let Some((palette, _)) = self.coloring.picked() else {
+            return Ok(vec![render]);
+        };
+        // Named here so the recolor knows where the data went
+        let template = args.output.as_deref().unwrap_or(template::DEFAULT);
+        let path = template::expand(template, args)?;
+        let name = template::prepare(&path, args.collision).map_err(error::at(&path))?;
+        let data = Path::new(&name).with_extension("fracdata");
+        render.extend([
+            format!("--output={}", name),
+            String::from("--collision=overwrite"),
+            format!("--dump-data={}", data.display()),
+        ]);
+        let recolor = vec![
+            String::from("recolor"),
+            data.display().to_string(),
+            format!("--palette={}", palette.display()),
+            format!("--transfer={}", value_name(self.coloring.transfer)),
+            format!("--cycles={}", self.coloring.cycles),
+            format!(
+                "--output={}",
+                Path::new(&name).with_extension("png").display()
+            ),
+        ];
+        Ok(vec![render, recolor])
+    }
+
+    // Both images again from what they were last found to be
+    fn recolor(&mut self, screen: (usize, usize)) {
+        let (cols, rows) = self.layout;
+        if self.data.len() == 2 * cols * rows {
+            self.shown = self.coloring.paint(&self.args, &self.data);
+            self.draw(&self.shown, 0, screen);
+        }
+        if let (Some(pane), true) = (self.pane(), self.pane_data.len() == 2 * cols * rows) {
+            let pixels = self.coloring.paint(&pane, &self.pane_data);
+            self.draw(&pixels, self.pane_column(), screen);
+        }
+    }
+
+    // An image at column, the view's with the zoom box and orbit over it or
+    // the pane's
+    fn draw(&self, pixels: &[[u8; 3]], column: usize, screen: (usize, usize)) {
+        let (cols, rows) = self.layout;
+        let pixels = match column {
+            0 => self.traced(pixels),
+            _ => pixels.to_vec(),
+        };
+        let mut text = String::new();
+        for row in 0..rows {
+            text.push_str(&format!("\x1b[{};{}H", row + 1, column + 1));
+            for x in 0..cols {
+                let top = pixels[2 * row * cols + x];
+                let bottom = pixels[(2 * row + 1) * cols + x];
+                let edge = (column == 0).then(|| self.box_edge(x, row)).flatten();
+                let (fg, glyph) = match edge {
+                    Some(glyph) => ([255; 3], glyph),
+                    None => (top, '\u{2580}'),
+                };
+                text.push_str(&terminal::escape(fg, TextColor::Truecolor, 38));
+                text.push_str(&terminal::escape(bottom, TextColor::Truecolor, 48));
+                text.push(glyph);
+            }
+            text.push_str("\x1b[0m");
+        }
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(text.as_bytes()).and_then(|_| out.flush());
+        self.draw_panel(screen);
+    }
+}
+
+// Runs the commands in turn, with the terminal given back meanwhile
+fn hand_off(
+    screen: &Screen,
+    commands: &[Vec<String>],
+    keys: &Receiver<Key>,
+) -> Result<String, FracError> {
+    let exe = env::current_exe().map_err(ViewerError::Io)?;
+    screen.suspend();
+    let mut status = Ok(None);
+    for command in commands {
+        println!("fracmd {}", command.join(" "));
+        status = process::Command::new(&exe)
+            .args(command)
+            .status()
+            .map(Some)
+            .map_err(ViewerError::Io);
+        if !matches!(status, Ok(Some(s)) if s.success()) {
+            break;
+        }
+    }
+    // The Ctrl-C was for the render
+    QUIT.store(false, Ordering::SeqCst);
+    println!("\nPress a key to go back to the explorer");
+    screen.resume()?;
+    while keys.try_recv().is_ok() {}
+    // Moving the mouse over the terminal isn't pressing a key
+    while let Ok(Key::Mouse(Mouse::Move, ..)) = keys.recv() {}
+    Ok(match status? {
+        Some(s) if !s.success() => format!("The full render stopped with {}", s),
+        _ => String::from("The full render finished"),
+    })
+}
+
+pub fn run(args: &Args) -> Result<(), FracError> {
+    if !(io::stdin().is_terminal() && io::stdout().is_terminal()) {
+        return Err(ViewerError::NotTerminal.into());
+    }
+    if let Err(e) = ctrlc::set_handler(|| QUIT.store(true, Ordering::SeqCst)) {
+        eprintln!("Couldn't handle interrupts: {}", e);
+    }
+    let screen = Screen::enter()?;
+    let pending = Arc::new(AtomicBool::new(false));
+    let keys = listen(pending.clone());
+    let mut explorer = Explorer {
+        args: args.clone(),
+        home: args.clone(),
+        selected: 0,
+        status: String::new(),
+        layout: (1, 1),
+        hover: None,
+        dragging: None,
+        dragged: false,
+        julia: None,
+        zoom_box: None,
+        shown: Vec::new(),
+        coloring: Coloring::new(args),
+        data: Vec::new(),
+        pane_data: Vec::new(),
+        orbit: false,
+    };
+    let mut drawn = None;
+    'view: loop {
+        let size = terminal::size();
+        // The pane takes half the width, past a gap
+        let width = match explorer.julia {
+            Some(_) => size.0.saturating_sub(1) / 2,
+            None => size.0,
+        };
+        explorer.layout = terminal::fit(
+            explorer.args.width,
+            explorer.args.height,
+            width,
+            size.1.saturating_sub(PANEL),
+        );
+        let (cols, rows) = explorer.layout;
+        // The old view and pane can be anywhere
+        if Some((size, explorer.julia.is_some())) != drawn {
+            print!("\x1b[2J");
+            drawn = Some((size, explorer.julia.is_some()));
+        }
+        let renderer = Renderer::new(explorer.args.clone(), default_functs(&explorer.args));
+        let pane = explorer
+            .pane()
+            .map(|args| Renderer::new(args.clone(), default_functs(&args)));
+        // Both coarse before either is sharp
+        'passes: for step in PASSES {
+            let renderers = [
+                Some((&renderer, 0)),
+                pane.as_ref().map(|r| (r, explorer.pane_column())),
+            ];
+            for (renderer, column) in renderers.into_iter().flatten() {
+                let Some(data) = pass(renderer, cols, rows, step, &pending) else {
+                    break 'passes;
+                };
+                let pixels = explorer.coloring.paint(&renderer.args, &data);
+                explorer.draw(&pixels, column, size);
+                if column == 0 {
+                    (explorer.shown, explorer.data) = (pixels, data);
+                } else {
+                    explorer.pane_data = data;
+                }
+            }
+        }
+        loop {
+            let key = match keys.recv_timeout(Duration::from_millis(200)) {
+                Ok(key) => key,
+                Err(RecvTimeoutError::Timeout) if QUIT.load(Ordering::SeqCst) => break 'view,
+                Err(RecvTimeoutError::Timeout) if terminal::size() != size => continue 'view,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break 'view,
+            };
+            // Everything typed while the last view was drawn, before the next
+            let mut redraw = false;
+            for key in [key].into_iter().chain(keys.try_iter()) {
+                match explorer.key(key) {
+                    Action::Redraw => redraw = true,
+                    Action::Panel => (),
+                    Action::Overlay if explorer.shown.len() == 2 * cols * rows => {
+                        explorer.draw(&explorer.shown, 0, size)
+                    }
+                    Action::Overlay => (),
+                    Action::Recolor => explorer.recolor(size),
+                    Action::Quit => break 'view,
+                    Action::Render(args) => match explorer.commands(&args) {
+                        Ok(commands) => {
+                            explorer.status = hand_off(&screen, &commands, &keys)?;
+                            drawn = None;
+                            redraw = true;
+                            break;
+                        }
+                        Err(e) => explorer.status = e.to_string(),
+                    },
+                }
+            }
+            pending.store(false, Ordering::SeqCst);
+            if redraw {
+                continue 'view;
+            }
+            explorer.draw_panel(size);
+        }
+    }
+    drop(screen);
+    // The view it ended on, to render or keep
+    println!("{}", wizard::command_line(&explorer.args));
+    if let Some((palette, _)) = explorer.coloring.picked() {
+        println!(
+            "colored by `fracmd recolor` of its --dump-data with --palette={} --transfer={} --cycles={}",
+            palette.display(),
+            value_name(explorer.coloring.transfer),
+            explorer.coloring.cycles
+        );
+    }
+    Ok(())
+}
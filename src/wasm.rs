@@ -0,0 +1,1720 @@
+// fracmd
+// WebAssembly plugins, run sandboxed by a small interpreter
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A --plugin ending in .wasm is a WebAssembly module instead of a shared
+// library, so it can only touch its own memory: it may not import
+// anything, every access is bounds checked and each call has a budget of
+// work, after which it's stopped like any other trap. The fracmd-wasm
+// crate writes the exports for it, see its readme for the interface.
+//
+// The MVP instruction set is supported along with the sign extension,
+// saturating conversion and bulk memory copy and fill that rustc emits by
+// default, the readme lists exactly what, and anything else is refused
+// when the module loads. Functions are checked and turned into a flat list of
+// instructions with branch targets and stack heights worked out, which
+// is then interpreted. Each thread gets its own copy of the instance.
+
+use num::{complex::Complex, Float};
+use std::{
+    cell::RefCell,
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    coloring::Coloring,
+    fracdata::PixelData,
+    fractal::{self, Fractal},
+    plugin::PluginError,
+    rgbaf::RgbaF,
+};
+
+// The interface version modules have to give, its own rather than native
+// plugins'. 2 added converged to fracmd_color.
+pub const VERSION: u32 = 2;
+
+const PAGE: usize = 65536;
+// 256MiB, plugins asking for more are told memory.grow failed
+const MAX_PAGES: u32 = 4096;
+// Calls and branches one call may take, far more than any formula needs
+const FUEL: u64 = 5_000_000;
+const MAX_FRAMES: usize = 4096;
+const MAX_STACK: usize = 1 << 20;
+// Floats exchanged through the module's fracmd_io buffer
+const IO: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    I32,
+    I64,
+    F32,
+    F64,
+    Ref,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FuncType {
+    params: Vec<Type>,
+    results: Vec<Type>,
+}
+
+// Where a branch goes, and the stack height it leaves with arity values
+// kept on top
+#[derive(Clone, Copy, Debug)]
+struct Target {
+    to: u32,
+    height: u32,
+    arity: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Unreachable,
+    Jump(u32),
+    // Jumps when the condition is zero
+    If(u32),
+    Br(Target),
+    BrIf(Target),
+    BrTable(u32),
+    Return,
+    ReturnIf,
+    Call(u32),
+    CallIndirect(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Load(u8, u32),
+    Store(u8, u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    Const(u64),
+    // Numeric instructions keep their opcode, 0xfc ones their subopcode
+    Unary(u8),
+    Binary(u8),
+    Sat(u8),
+}
+
+#[derive(Debug)]
+struct Func {
+    ty: usize,
+    // Parameters included
+    locals: usize,
+    code: Vec<Op>,
+    // br_table targets, the default last
+    tables: Vec<Vec<Target>>,
+}
+
+#[derive(Debug, Default)]
+struct Module {
+    types: Vec<FuncType>,
+    funcs: Vec<Func>,
+    table: Vec<Option<u32>>,
+    globals: Vec<u64>,
+    memory: Option<(u32, u32)>,
+    data: Vec<(usize, Vec<u8>)>,
+    exports: Vec<(String, u32)>,
+    start: Option<u32>,
+}
+
+type Parse<T> = Result<T, String>;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn done(&self) -> bool {
+        self.at >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Parse<u8> {
+        let b = *self
+            .bytes
+            .get(self.at)
+            .ok_or_else(|| String::from("it ends too soon"))?;
+        self.at += 1;
+        Ok(b)
+    }
+
+    fn peek(&self) -> Parse<u8> {
+        self.bytes
+            .get(self.at)
+            .copied()
+            .ok_or_else(|| String::from("it ends too soon"))
+    }
+
+    fn bytes(&mut self, n: usize) -> Parse<&'a [u8]> {
+        let end = self
+            .at
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| String::from("it ends too soon"))?;
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Parse<u32> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return u32::try_from(result).map_err(|_| String::from("an integer is too big"));
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(String::from("an integer is too long"));
+            }
+        }
+    }
+
+    fn signed(&mut self, bits: u32) -> Parse<i64> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as i64) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+            if shift >= bits.min(64) {
+                return Err(String::from("an integer is too long"));
+            }
+        }
+    }
+
+    fn len(&mut self) -> Parse<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn name(&mut self) -> Parse<String> {
+        let n = self.len()?;
+        String::from_utf8(self.bytes(n)?.to_vec()).map_err(|_| String::from("a name isn't utf-8"))
+    }
+
+    fn value_type(&mut self) -> Parse<Type> {
+        match self.byte()? {
+            0x7f => Ok(Type::I32),
+            0x7e => Ok(Type::I64),
+            0x7d => Ok(Type::F32),
+            0x7c => Ok(Type::F64),
+            0x70 | 0x6f => Ok(Type::Ref),
+            0x7b => Err(String::from("simd isn't supported")),
+            b => Err(format!("unknown value type 0x{:02x}", b)),
+        }
+    }
+
+    fn limits(&mut self) -> Parse<(u32, Option<u32>)> {
+        match self.byte()? {
+            0 => Ok((self.u32()?, None)),
+            1 => Ok((self.u32()?, Some(self.u32()?))),
+            2 | 3 => Err(String::from("shared memory isn't supported")),
+            4..=7 => Err(String::from("64 bit memory isn't supported")),
+            b => Err(format!("unsupported limits 0x{:02x}", b)),
+        }
+    }
+
+    // A constant expression, for global values and segment offsets
+    fn constant(&mut self, globals: &[u64]) -> Parse<u64> {
+        let value = match self.byte()? {
+            0x41 => self.signed(32)? as i32 as u32 as u64,
+            0x42 => self.signed(64)? as u64,
+            0x43 => u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as u64,
+            0x44 => u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()),
+            0x23 => *globals
+                .get(self.len()?)
+                .ok_or_else(|| String::from("a constant reads a global that isn't there"))?,
+            0xd0 => {
+                self.byte()?;
+                u64::MAX
+            }
+            0xd2 => self.u32()? as u64,
+            b => return Err(format!("unsupported constant instruction 0x{:02x}", b)),
+        };
+        match self.byte()? {
+            0x0b => Ok(value),
+            _ => Err(String::from(
+                "constant expressions can only be one instruction",
+            )),
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Parse<Module> {
+    let mut r = Reader { bytes, at: 0 };
+    if r.bytes(8).ok() != Some(b"\0asm\x01\0\0\0".as_slice()) {
+        return Err(String::from("it isn't a version 1 wasm module"));
+    }
+    let mut m = Module::default();
+    let mut func_types = Vec::new();
+    while !r.done() {
+        let id = r.byte()?;
+        let size = r.len()?;
+        let mut s = Reader {
+            bytes: r.bytes(size)?,
+            at: 0,
+        };
+        match id {
+            0 | 12 => (),
+            1 => {
+                for _ in 0..s.len()? {
+                    if s.byte()? != 0x60 {
+                        return Err(String::from("a type isn't a function type"));
+                    }
+                    let params = (0..s.len()?)
+                        .map(|_| s.value_type())
+                        .collect::<Parse<_>>()?;
+                    let results = (0..s.len()?)
+                        .map(|_| s.value_type())
+                        .collect::<Parse<_>>()?;
+                    m.types.push(FuncType { params, results });
+                }
+            }
+            2 => {
+                if s.len()? > 0 {
+                    let module = s.name()?;
+                    let name = s.name()?;
+                    return Err(format!(
+                        "it imports {}.{}, wasm plugins can't import anything",
+                        module, name
+                    ));
+                }
+            }
+            3 => {
+                for _ in 0..s.len()? {
+                    let ty = s.len()?;
+                    if ty >= m.types.len() {
+                        return Err(String::from("a function has a type that isn't there"));
+                    }
+                    func_types.push(ty);
+                }
+            }
+            4 => {
+                for i in 0..s.len()? {
+                    s.value_type()?;
+                    let (min, _) = s.limits()?;
+                    if i > 0 {
+                        return Err(String::from("only one table is supported"));
+                    }
+                    if min > 1 << 20 {
+                        return Err(String::from("the table is too big"));
+                    }
+                    m.table = vec![None; min as usize];
+                }
+            }
+            5 => {
+                for i in 0..s.len()? {
+                    let (min, max) = s.limits()?;
+                    if i > 0 {
+                        return Err(String::from("only one memory is supported"));
+                    }
+                    let max_pages = max.unwrap_or(MAX_PAGES).min(MAX_PAGES);
+                    if min > max_pages {
+                        return Err(format!(
+                            "it needs {} pages of memory, at most {} are allowed",
+                            min, max_pages
+                        ));
+                    }
+                    m.memory = Some((min, max_pages));
+                }
+            }
+            6 => {
+                for _ in 0..s.len()? {
+                    s.value_type()?;
+                    s.byte()?;
+                    let value = s.constant(&m.globals)?;
+                    m.globals.push(value);
+                }
+            }
+            7 => {
+                for _ in 0..s.len()? {
+                    let name = s.name()?;
+                    let kind = s.byte()?;
+                    let index = s.u32()?;
+                    if kind == 0 {
+                        m.exports.push((name, index));
+                    }
+                }
+            }
+            8 => m.start = Some(s.u32()?),
+            9 => elements(&mut s, &mut m)?,
+            10 => {
+                let count = s.len()?;
+                if count != func_types.len() {
+                    return Err(String::from(
+                        "there's code for a different number of functions",
+                    ));
+                }
+                let mut bodies = Vec::new();
+                for _ in 0..count {
+                    let size = s.len()?;
+                    bodies.push(s.bytes(size)?);
+                }
+                for (i, body) in bodies.into_iter().enumerate() {
+                    let mut b = Reader { bytes: body, at: 0 };
+                    let func = compile(&mut b, &m, &func_types, func_types[i])
+                        .map_err(|e| format!("function {}: {}", i, e))?;
+                    m.funcs.push(func);
+                }
+            }
+            11 => {
+                for _ in 0..s.len()? {
+                    let flags = s.u32()?;
+                    let offset = match flags {
+                        0 => Some(s.constant(&m.globals)?),
+                        1 => None,
+                        2 => {
+                            s.u32()?;
+                            Some(s.constant(&m.globals)?)
+                        }
+                        _ => return Err(format!("unsupported data segment {}", flags)),
+                    };
+                    let n = s.len()?;
+                    let bytes = s.bytes(n)?.to_vec();
+                    // Passive segments are only for memory.init, which isn't supported
+                    if let Some(offset) = offset {
+                        m.data.push((offset as u32 as usize, bytes));
+                    }
+                }
+            }
+            _ => return Err(format!("unknown section {}", id)),
+        }
+    }
+    if m.funcs.len() != func_types.len() {
+        return Err(String::from("functions are declared without any code"));
+    }
+    if let Some(start) = m.start {
+        if start as usize >= m.funcs.len() {
+            return Err(String::from("the start function isn't there"));
+        }
+    }
+    Ok(m)
+}
+
+// Element segments fill the table call_indirect goes through
+fn elements(s: &mut Reader, m: &mut Module) -> Parse<()> {
+    for _ in 0..s.len()? {
+        let flags = s.u32()?;
+        if flags > 7 {
+            return Err(format!("unsupported element segment {}", flags));
+        }
+        let active = flags & 1 == 0;
+        if flags & 2 != 0 && active {
+            s.u32()?;
+        }
+        let offset = if active {
+            s.constant(&m.globals)? as u32 as usize
+        } else {
+            0
+        };
+        if flags & 3 != 0 {
+            // elemkind or reftype
+            s.byte()?;
+        }
+        let mut funcs = Vec::new();
+        for _ in 0..s.len()? {
+            let f = if flags & 4 != 0 {
+                s.constant(&m.globals)?
+            } else {
+                s.u32()? as u64
+            };
+            funcs.push(u32::try_from(f).ok());
+        }
+        if active {
+            let end = offset
+                .checked_add(funcs.len())
+                .filter(|&end| end <= m.table.len())
+                .ok_or_else(|| String::from("an element segment is out of the table"))?;
+            m.table[offset..end].copy_from_slice(&funcs);
+        }
+    }
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+}
+
+struct Label {
+    kind: Kind,
+    // Stack height under the block's parameters
+    base: usize,
+    params: usize,
+    results: usize,
+    start: usize,
+    // Branches to patch with the end
+    patches: Vec<Patch>,
+    if_at: Option<usize>,
+    dead: bool,
+}
+
+enum Patch {
+    Op(usize),
+    Table(usize, usize),
+}
+
+// Turns a function body into Ops, checking only that the stack never
+// runs out, indices are in range and block results add up: that's
+// everything the interpreter relies on not to go wrong
+fn compile(r: &mut Reader, m: &Module, func_types: &[usize], ty: usize) -> Parse<Func> {
+    let sig = &m.types[ty];
+    let mut locals = sig.params.len();
+    for _ in 0..r.len()? {
+        let n = r.len()?;
+        r.value_type()?;
+        locals = locals
+            .checked_add(n)
+            .filter(|&l| l <= 50000)
+            .ok_or_else(|| String::from("it has too many locals"))?;
+    }
+    let func_sig = |f: usize| -> Parse<&FuncType> {
+        func_types
+            .get(f)
+            .map(|&t| &m.types[t])
+            .ok_or_else(|| format!("it calls function {}, which isn't there", f))
+    };
+    let mut code = Vec::new();
+    let mut tables: Vec<Vec<Target>> = Vec::new();
+    let mut labels = vec![Label {
+        kind: Kind::Block,
+        base: 0,
+        params: 0,
+        results: sig.results.len(),
+        start: 0,
+        patches: Vec::new(),
+        if_at: None,
+        dead: false,
+    }];
+    let mut height = 0usize;
+
+    macro_rules! pop {
+        ($n:expr) => {{
+            let label = labels.last().unwrap();
+            let n: usize = $n;
+            if height < label.base + n {
+                if !label.dead {
+                    return Err(String::from("it pops more than it pushed"));
+                }
+                height = label.base;
+            } else {
+                height -= n;
+            }
+        }};
+    }
+    macro_rules! dead {
+        () => {{
+            let label = labels.last_mut().unwrap();
+            label.dead = true;
+            height = label.base;
+        }};
+    }
+
+    // A branch depth's target, None for the function's own block
+    let target = |labels: &[Label], depth: usize, height: usize| -> Parse<Option<Target>> {
+        let index = labels
+            .len()
+            .checked_sub(depth + 1)
+            .ok_or_else(|| String::from("a branch goes further out than the function"))?;
+        let label = &labels[index];
+        let arity = if label.kind == Kind::Loop {
+            label.params
+        } else {
+            label.results
+        };
+        let current = labels.last().unwrap();
+        if height < current.base + arity && !current.dead {
+            return Err(String::from("a branch needs more values than there are"));
+        }
+        Ok((index > 0).then_some(Target {
+            to: if label.kind == Kind::Loop {
+                label.start as u32
+            } else {
+                u32::MAX
+            },
+            height: label.base as u32,
+            arity: arity as u32,
+        }))
+    };
+    let block_type = |r: &mut Reader| -> Parse<(usize, usize)> {
+        match r.peek()? {
+            0x40 => {
+                r.byte()?;
+                Ok((0, 0))
+            }
+            0x7f | 0x7e | 0x7d | 0x7c | 0x70 | 0x6f => {
+                r.byte()?;
+                Ok((0, 1))
+            }
+            _ => {
+                let t = m
+                    .types
+                    .get(r.signed(33)? as usize)
+                    .ok_or_else(|| String::from("a block has a type that isn't there"))?;
+                Ok((t.params.len(), t.results.len()))
+            }
+        }
+    };
+
+    loop {
+        let op = r.byte()?;
+        match op {
+            0x00 => {
+                code.push(Op::Unreachable);
+                dead!();
+            }
+            0x01 => (),
+            0x02..=0x04 => {
+                let (params, results) = block_type(r)?;
+                if op == 0x04 {
+                    pop!(1);
+                }
+                pop!(params);
+                let kind = match op {
+                    0x02 => Kind::Block,
+                    0x03 => Kind::Loop,
+                    _ => Kind::If,
+                };
+                let if_at = (kind == Kind::If).then(|| {
+                    code.push(Op::If(u32::MAX));
+                    code.len() - 1
+                });
+                labels.push(Label {
+                    kind,
+                    base: height,
+                    params,
+                    results,
+                    start: code.len(),
+                    patches: Vec::new(),
+                    if_at,
+                    dead: false,
+                });
+                height += params;
+            }
+            0x05 => {
+                let label = labels.last_mut().unwrap();
+                if label.kind != Kind::If || label.if_at.is_none() {
+                    return Err(String::from("else without if"));
+                }
+                if !label.dead && height != label.base + label.results {
+                    return Err(String::from("an if leaves the wrong number of values"));
+                }
+                code.push(Op::Jump(u32::MAX));
+                label.patches.push(Patch::Op(code.len() - 1));
+                let at = label.if_at.take().unwrap();
+                code[at] = Op::If(code.len() as u32);
+                label.dead = false;
+                height = label.base + label.params;
+            }
+            0x0b => {
+                let label = labels.pop().unwrap();
+                if !label.dead && height != label.base + label.results {
+                    return Err(String::from("a block leaves the wrong number of values"));
+                }
+                if label.kind == Kind::If && label.if_at.is_some() && label.params != label.results
+                {
+                    return Err(String::from("an if without else changes the stack"));
+                }
+                let end = code.len() as u32;
+                if let Some(at) = label.if_at {
+                    code[at] = Op::If(end);
+                }
+                for patch in label.patches {
+                    match patch {
+                        Patch::Op(at) => match &mut code[at] {
+                            Op::Jump(to)
+                            | Op::Br(Target { to, .. })
+                            | Op::BrIf(Target { to, .. }) => *to = end,
+                            _ => unreachable!(),
+                        },
+                        Patch::Table(table, i) => tables[table][i].to = end,
+                    }
+                }
+                height = label.base + label.results;
+                if labels.is_empty() {
+                    code.push(Op::Return);
+                    break;
+                }
+            }
+            0x0c | 0x0d => {
+                let depth = r.len()?;
+                if op == 0x0d {
+                    pop!(1);
+                }
+                match target(&labels, depth, height)? {
+                    Some(t) => {
+                        code.push(if op == 0x0c { Op::Br(t) } else { Op::BrIf(t) });
+                        if t.to == u32::MAX {
+                            let index = labels.len() - 1 - depth;
+                            labels[index].patches.push(Patch::Op(code.len() - 1));
+                        }
+                    }
+                    None if op == 0x0c => code.push(Op::Return),
+                    None => code.push(Op::ReturnIf),
+                }
+                if op == 0x0c {
+                    dead!();
+                }
+            }
+            0x0e => {
+                let n = r.len()?;
+                let depths = (0..=n).map(|_| r.len()).collect::<Parse<Vec<_>>>()?;
+                pop!(1);
+                let mut table = Vec::new();
+                let mut arity = None;
+                for (i, &depth) in depths.iter().enumerate() {
+                    let t = match target(&labels, depth, height)? {
+                        Some(t) => t,
+                        None => {
+                            let results = sig.results.len();
+                            Target {
+                                to: u32::MAX - 1,
+                                height: 0,
+                                arity: results as u32,
+                            }
+                        }
+                    };
+                    if *arity.get_or_insert(t.arity) != t.arity {
+                        return Err(String::from("br_table targets take different values"));
+                    }
+                    if t.to == u32::MAX {
+                        let index = labels.len() - 1 - depth;
+                        labels[index].patches.push(Patch::Table(tables.len(), i));
+                    }
+                    table.push(t);
+                }
+                code.push(Op::BrTable(tables.len() as u32));
+                tables.push(table);
+                dead!();
+            }
+            0x0f => {
+                // Checks there are results to return, like a branch out
+                target(&labels, labels.len() - 1, height)?;
+                code.push(Op::Return);
+                dead!();
+            }
+            0x10 => {
+                let f = r.len()?;
+                let callee = func_sig(f)?;
+                pop!(callee.params.len());
+                height += callee.results.len();
+                code.push(Op::Call(f as u32));
+            }
+            0x11 => {
+                let t = r.len()?;
+                if r.u32()? != 0 || m.table.is_empty() {
+                    return Err(String::from("call_indirect needs the table"));
+                }
+                let callee = m
+                    .types
+                    .get(t)
+                    .ok_or_else(|| String::from("call_indirect has a type that isn't there"))?;
+                pop!(1);
+                pop!(callee.params.len());
+                height += callee.results.len();
+                code.push(Op::CallIndirect(t as u32));
+            }
+            0x1a => {
+                pop!(1);
+                code.push(Op::Drop);
+            }
+            0x1b | 0x1c => {
+                if op == 0x1c {
+                    for _ in 0..r.len()? {
+                        r.value_type()?;
+                    }
+                }
+                pop!(3);
+                height += 1;
+                code.push(Op::Select);
+            }
+            0x20..=0x22 => {
+                let i = r.len()?;
+                if i >= locals {
+                    return Err(format!("local {} isn't there", i));
+                }
+                match op {
+                    0x20 => height += 1,
+                    0x21 => pop!(1),
+                    _ => {
+                        pop!(1);
+                        height += 1;
+                    }
+                }
+                code.push(match op {
+                    0x20 => Op::LocalGet(i as u32),
+                    0x21 => Op::LocalSet(i as u32),
+                    _ => Op::LocalTee(i as u32),
+                });
+            }
+            0x23 | 0x24 => {
+                let i = r.len()?;
+                if i >= m.globals.len() {
+                    return Err(format!("global {} isn't there", i));
+                }
+                if op == 0x23 {
+                    height += 1;
+                    code.push(Op::GlobalGet(i as u32));
+                } else {
+                    pop!(1);
+                    code.push(Op::GlobalSet(i as u32));
+                }
+            }
+            0x28..=0x3e => {
+                r.u32()?;
+                let offset = r.u32()?;
+                if m.memory.is_none() {
+                    return Err(String::from("it uses memory it doesn't have"));
+                }
+                if op <= 0x35 {
+                    pop!(1);
+                    height += 1;
+                    code.push(Op::Load(op, offset));
+                } else {
+                    pop!(2);
+                    code.push(Op::Store(op, offset));
+                }
+            }
+            0x3f | 0x40 => {
+                r.byte()?;
+                if m.memory.is_none() {
+                    return Err(String::from("it uses memory it doesn't have"));
+                }
+                if op == 0x3f {
+                    height += 1;
+                    code.push(Op::MemorySize);
+                } else {
+                    pop!(1);
+                    height += 1;
+                    code.push(Op::MemoryGrow);
+                }
+            }
+            0x41 => {
+                height += 1;
+                code.push(Op::Const(r.signed(32)? as i32 as u32 as u64));
+            }
+            0x42 => {
+                height += 1;
+                code.push(Op::Const(r.signed(64)? as u64));
+            }
+            0x43 => {
+                height += 1;
+                code.push(Op::Const(
+                    u32::from_le_bytes(r.bytes(4)?.try_into().unwrap()) as u64,
+                ));
+            }
+            0x44 => {
+                height += 1;
+                code.push(Op::Const(u64::from_le_bytes(
+                    r.bytes(8)?.try_into().unwrap(),
+                )));
+            }
+            0x45..=0xc4 if is_binary(op) => {
+                pop!(2);
+                height += 1;
+                code.push(Op::Binary(op));
+            }
+            0x45..=0xc4 => {
+                pop!(1);
+                height += 1;
+                code.push(Op::Unary(op));
+            }
+            0xfc => {
+                let sub = r.u32()?;
+                match sub {
+                    0..=7 => {
+                        pop!(1);
+                        height += 1;
+                        code.push(Op::Sat(sub as u8));
+                    }
+                    10 | 11 => {
+                        r.bytes(if sub == 10 { 2 } else { 1 })?;
+                        if m.memory.is_none() {
+                            return Err(String::from("it uses memory it doesn't have"));
+                        }
+                        pop!(3);
+                        code.push(if sub == 10 {
+                            Op::MemoryCopy
+                        } else {
+                            Op::MemoryFill
+                        });
+                    }
+                    _ => return Err(unsupported(op, Some(sub))),
+                }
+            }
+            _ => return Err(unsupported(op, None)),
+        }
+    }
+    if !r.done() {
+        return Err(String::from("there's more after its end"));
+    }
+    Ok(Func {
+        ty,
+        locals,
+        code,
+        tables,
+    })
+}
+
+// Names the proposal an instruction left out comes from, so the author
+// knows which target feature to turn off
+fn unsupported(op: u8, sub: Option<u32>) -> String {
+    let from = match (op, sub) {
+        (0x06..=0x0a | 0x18 | 0x19, _) => Some("exception handling"),
+        (0x12 | 0x13, _) => Some("tail calls"),
+        (0x25 | 0x26 | 0xd0..=0xd2, _) | (0xfc, Some(12..=17)) => Some("reference types"),
+        (0xfc, Some(8 | 9)) => Some("passive data segments"),
+        (0xfd, _) => Some("simd"),
+        (0xfe, _) => Some("threads"),
+        _ => None,
+    };
+    let instruction = match sub {
+        Some(sub) => format!("0x{:02x} {}", op, sub),
+        None => format!("0x{:02x}", op),
+    };
+    match from {
+        Some(from) => format!(
+            "instruction {} is from {}, which wasm plugins can't use",
+            instruction, from
+        ),
+        None => format!("unknown instruction {}", instruction),
+    }
+}
+
+fn is_binary(op: u8) -> bool {
+    matches!(
+        op,
+        0x46..=0x4f | 0x51..=0x5a | 0x5b..=0x66 | 0x6a..=0x78 | 0x7c..=0x8a | 0x92..=0x98 | 0xa0..=0xa6
+    )
+}
+
+type Trap = &'static str;
+
+// NaN if either is, and -0 below 0
+fn fmin<F: Float>(a: F, b: F) -> F {
+    if a.is_nan() || b.is_nan() {
+        F::nan()
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.min(b)
+    }
+}
+
+fn fmax<F: Float>(a: F, b: F) -> F {
+    if a.is_nan() || b.is_nan() {
+        F::nan()
+    } else if a == b {
+        if a.is_sign_positive() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.max(b)
+    }
+}
+
+// Truncation that traps outside [min, max)
+fn trunc(x: f64, min: f64, max: f64) -> Result<f64, Trap> {
+    if x.is_nan() {
+        return Err("invalid conversion to integer");
+    }
+    let t = x.trunc();
+    if t >= min && t < max {
+        Ok(t)
+    } else {
+        Err("integer overflow")
+    }
+}
+
+fn numeric(op: u8, a: u64, b: u64) -> Result<u64, Trap> {
+    let (ai, bi) = (a as u32 as i32, b as u32 as i32);
+    let (au, bu) = (a as u32, b as u32);
+    let (al, bl) = (a as i64, b as i64);
+    let (af, bf) = (f32::from_bits(au), f32::from_bits(bu));
+    let (ad, bd) = (f64::from_bits(a), f64::from_bits(b));
+    let i = |x: i32| x as u32 as u64;
+    let f = |x: f32| x.to_bits() as u64;
+    let d = |x: f64| x.to_bits();
+    let t = |x: bool| x as u64;
+    const I32: (f64, f64) = (-2147483648.0, 2147483648.0);
+    const U32: (f64, f64) = (0.0, 4294967296.0);
+    const I64: (f64, f64) = (-9223372036854775808.0, 9223372036854775808.0);
+    const U64: (f64, f64) = (0.0, 18446744073709551616.0);
+    Ok(match op {
+        0x45 => t(au == 0),
+        0x46 => t(au == bu),
+        0x47 => t(au != bu),
+        0x48 => t(ai < bi),
+        0x49 => t(au < bu),
+        0x4a => t(ai > bi),
+        0x4b => t(au > bu),
+        0x4c => t(ai <= bi),
+        0x4d => t(au <= bu),
+        0x4e => t(ai >= bi),
+        0x4f => t(au >= bu),
+        0x50 => t(a == 0),
+        0x51 => t(a == b),
+        0x52 => t(a != b),
+        0x53 => t(al < bl),
+        0x54 => t(a < b),
+        0x55 => t(al > bl),
+        0x56 => t(a > b),
+        0x57 => t(al <= bl),
+        0x58 => t(a <= b),
+        0x59 => t(al >= bl),
+        0x5a => t(a >= b),
+        0x5b => t(af == bf),
+        0x5c => t(af != bf),
+        0x5d => t(af < bf),
+        0x5e => t(af > bf),
+        0x5f => t(af <= bf),
+        0x60 => t(af >= bf),
+        0x61 => t(ad == bd),
+        0x62 => t(ad != bd),
+        0x63 => t(ad < bd),
+        0x64 => t(ad > bd),
+        0x65 => t(ad <= bd),
+        0x66 => t(ad >= bd),
+        0x67 => au.leading_zeros() as u64,
+        0x68 => au.trailing_zeros() as u64,
+        0x69 => au.count_ones() as u64,
+        0x6a => i(ai.wrapping_add(bi)),
+        0x6b => i(ai.wrapping_sub(bi)),
+        0x6c => i(ai.wrapping_mul(bi)),
+        0x6d | 0x6f if bi == 0 => return Err("integer divide by zero"),
+        0x6d if ai == i32::MIN && bi == -1 => return Err("integer overflow"),
+        0x6d => i(ai / bi),
+        0x6e | 0x70 if bu == 0 => return Err("integer divide by zero"),
+        0x6e => (au / bu) as u64,
+        0x6f => i(ai.wrapping_rem(bi)),
+        0x70 => (au % bu) as u64,
+        0x71 => (au & bu) as u64,
+        0x72 => (au | bu) as u64,
+        0x73 => (au ^ bu) as u64,
+        0x74 => au.wrapping_shl(bu) as u64,
+        0x75 => i(ai.wrapping_shr(bu)),
+        0x76 => au.wrapping_shr(bu) as u64,
+        0x77 => au.rotate_left(bu % 32) as u64,
+        0x78 => au.rotate_right(bu % 32) as u64,
+        0x79 => a.leading_zeros() as u64,
+        0x7a => a.trailing_zeros() as u64,
+        0x7b => a.count_ones() as u64,
+        0x7c => a.wrapping_add(b),
+        0x7d => a.wrapping_sub(b),
+        0x7e => a.wrapping_mul(b),
+        0x7f | 0x81 if b == 0 => return Err("integer divide by zero"),
+        0x7f if al == i64::MIN && bl == -1 => return Err("integer overflow"),
+        0x7f => (al / bl) as u64,
+        0x80 | 0x82 if b == 0 => return Err("integer divide by zero"),
+        0x80 => a / b,
+        0x81 => al.wrapping_rem(bl) as u64,
+        0x82 => a % b,
+        0x83 => a & b,
+        0x84 => a | b,
+        0x85 => a ^ b,
+        0x86 => a.wrapping_shl(bu),
+        0x87 => al.wrapping_shr(bu) as u64,
+        0x88 => a.wrapping_shr(bu),
+        0x89 => a.rotate_left((b % 64) as u32),
+        0x8a => a.rotate_right((b % 64) as u32),
+        0x8b => (au & 0x7fff_ffff) as u64,
+        0x8c => (au ^ 0x8000_0000) as u64,
+        0x8d => f(af.ceil()),
+        0x8e => f(af.floor()),
+        0x8f => f(af.trunc()),
+        0x90 => f(af.round_ties_even()),
+        0x91 => f(af.sqrt()),
+        0x92 => f(af + bf),
+        0x93 => f(af - bf),
+        0x94 => f(af * bf),
+        0x95 => f(af / bf),
+        0x96 => f(fmin(af, bf)),
+        0x97 => f(fmax(af, bf)),
+        0x98 => f(af.copysign(bf)),
+        0x99 => a & 0x7fff_ffff_ffff_ffff,
+        0x9a => a ^ 0x8000_0000_0000_0000,
+        0x9b => d(ad.ceil()),
+        0x9c => d(ad.floor()),
+        0x9d => d(ad.trunc()),
+        0x9e => d(ad.round_ties_even()),
+        0x9f => d(ad.sqrt()),
+        0xa0 => d(ad + bd),
+        0xa1 => d(ad - bd),
+        0xa2 => d(ad * bd),
+        0xa3 => d(ad / bd),
+        0xa4 => d(fmin(ad, bd)),
+        0xa5 => d(fmax(ad, bd)),
+        0xa6 => d(ad.copysign(bd)),
+        0xa7 => au as u64,
+        0xa8 => i(trunc(af as f64, I32.0, I32.1)? as i32),
+        0xa9 => trunc(af as f64, U32.0, U32.1)? as u32 as u64,
+        0xaa => i(trunc(ad, I32.0, I32.1)? as i32),
+        0xab => trunc(ad, U32.0, U32.1)? as u32 as u64,
+        0xac => ai as i64 as u64,
+        0xad => au as u64,
+        0xae => trunc(af as f64, I64.0, I64.1)? as i64 as u64,
+        0xaf => trunc(af as f64, U64.0, U64.1)? as u64,
+        0xb0 => trunc(ad, I64.0, I64.1)? as i64 as u64,
+        0xb1 => trunc(ad, U64.0, U64.1)? as u64,
+        0xb2 => f(ai as f32),
+        0xb3 => f(au as f32),
+        0xb4 => f(al as f32),
+        0xb5 => f(a as f32),
+        0xb6 => f(ad as f32),
+        0xb7 => d(ai as f64),
+        0xb8 => d(au as f64),
+        0xb9 => d(al as f64),
+        0xba => d(a as f64),
+        0xbb => d(af as f64),
+        // Reinterpretations, the bits are already where they need to be
+        0xbc | 0xbe => au as u64,
+        0xbd | 0xbf => a,
+        0xc0 => i(a as u8 as i8 as i32),
+        0xc1 => i(a as u16 as i16 as i32),
+        0xc2 => a as u8 as i8 as i64 as u64,
+        0xc3 => a as u16 as i16 as i64 as u64,
+        0xc4 => a as u32 as i32 as i64 as u64,
+        _ => return Err("unsupported instruction"),
+    })
+}
+
+// The trunc_sat family, float to int casts already saturate like these
+fn saturate(sub: u8, a: u64) -> u64 {
+    let (af, ad) = (f32::from_bits(a as u32), f64::from_bits(a));
+    match sub {
+        0 => af as i32 as u32 as u64,
+        1 => af as u32 as u64,
+        2 => ad as i32 as u32 as u64,
+        3 => ad as u32 as u64,
+        4 => af as i64 as u64,
+        5 => af as u64,
+        6 => ad as i64 as u64,
+        _ => ad as u64,
+    }
+}
+
+struct Frame {
+    func: usize,
+    pc: usize,
+    fp: usize,
+}
+
+#[derive(Clone)]
+struct Instance {
+    memory: Vec<u8>,
+    max_pages: u32,
+    globals: Vec<u64>,
+    stack: Vec<u64>,
+}
+
+fn bounds(memory: &[u8], addr: u64, offset: u32, len: u64) -> Result<usize, Trap> {
+    let start = addr as u32 as u64 + offset as u64;
+    if start + len > memory.len() as u64 {
+        Err("out of bounds memory access")
+    } else {
+        Ok(start as usize)
+    }
+}
+
+fn read(memory: &[u8], op: u8, addr: u64, offset: u32) -> Result<u64, Trap> {
+    let len = match op {
+        0x29 | 0x2b => 8,
+        0x28 | 0x2a | 0x34 | 0x35 => 4,
+        0x2e | 0x2f | 0x32 | 0x33 => 2,
+        _ => 1,
+    };
+    let at = bounds(memory, addr, offset, len)?;
+    let mut bytes = [0u8; 8];
+    bytes[..len as usize].copy_from_slice(&memory[at..at + len as usize]);
+    let raw = u64::from_le_bytes(bytes);
+    Ok(match op {
+        0x2c => raw as u8 as i8 as i32 as u32 as u64,
+        0x2e => raw as u16 as i16 as i32 as u32 as u64,
+        0x30 => raw as u8 as i8 as i64 as u64,
+        0x32 => raw as u16 as i16 as i64 as u64,
+        0x34 => raw as u32 as i32 as i64 as u64,
+        _ => raw,
+    })
+}
+
+fn write(memory: &mut [u8], op: u8, addr: u64, offset: u32, value: u64) -> Result<(), Trap> {
+    let len = match op {
+        0x37 | 0x39 => 8,
+        0x36 | 0x38 | 0x3e => 4,
+        0x3b | 0x3d => 2,
+        _ => 1,
+    };
+    let at = bounds(memory, addr, offset, len as u64)?;
+    memory[at..at + len].copy_from_slice(&value.to_le_bytes()[..len]);
+    Ok(())
+}
+
+impl Instance {
+    fn new(m: &Module) -> Result<Instance, String> {
+        let (pages, max_pages) = m.memory.unwrap_or((0, 0));
+        let mut memory = vec![0; pages as usize * PAGE];
+        for (offset, bytes) in &m.data {
+            let end = offset
+                .checked_add(bytes.len())
+                .filter(|&end| end <= memory.len())
+                .ok_or_else(|| String::from("a data segment is out of memory"))?;
+            memory[*offset..end].copy_from_slice(bytes);
+        }
+        let mut instance = Instance {
+            memory,
+            max_pages,
+            globals: m.globals.clone(),
+            stack: Vec::new(),
+        };
+        if let Some(start) = m.start {
+            instance
+                .call(m, start, &[])
+                .map_err(|e| format!("its start function stopped: {}", e))?;
+        }
+        Ok(instance)
+    }
+
+    // Runs a function to its end, returning its first result
+    fn call(&mut self, m: &Module, func: u32, args: &[u64]) -> Result<Option<u64>, Trap> {
+        let stack = &mut self.stack;
+        stack.clear();
+        stack.extend_from_slice(args);
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut fuel = FUEL;
+        let mut current = func as usize;
+        let mut f = &m.funcs[current];
+        let mut fp = 0;
+        stack.resize(f.locals, 0);
+        let mut base = f.locals;
+        let mut pc = 0;
+        macro_rules! pop {
+            () => {
+                stack.pop().unwrap_or(0)
+            };
+        }
+        macro_rules! burn {
+            () => {
+                fuel -= 1;
+                if fuel == 0 {
+                    return Err("it ran too long");
+                }
+            };
+        }
+        macro_rules! branch {
+            ($t:expr) => {{
+                let t: Target = $t;
+                let to = base + t.height as usize;
+                let arity = t.arity as usize;
+                let len = stack.len();
+                stack.copy_within(len - arity..len, to);
+                stack.truncate(to + arity);
+                pc = t.to as usize;
+            }};
+        }
+        macro_rules! ret {
+            () => {{
+                let arity = m.types[f.ty].results.len();
+                let len = stack.len();
+                stack.copy_within(len - arity..len, fp);
+                stack.truncate(fp + arity);
+                match frames.pop() {
+                    None => return Ok(stack.first().copied()),
+                    Some(frame) => {
+                        current = frame.func;
+                        f = &m.funcs[current];
+                        pc = frame.pc;
+                        fp = frame.fp;
+                        base = fp + f.locals;
+                    }
+                }
+            }};
+        }
+        macro_rules! enter {
+            ($callee:expr) => {{
+                let callee: usize = $callee;
+                burn!();
+                if frames.len() >= MAX_FRAMES || stack.len() > MAX_STACK {
+                    return Err("its call stack overflowed");
+                }
+                frames.push(Frame {
+                    func: current,
+                    pc,
+                    fp,
+                });
+                current = callee;
+                f = &m.funcs[current];
+                fp = stack.len() - m.types[f.ty].params.len();
+                stack.resize(fp + f.locals, 0);
+                base = fp + f.locals;
+                pc = 0;
+            }};
+        }
+        loop {
+            let op = f.code[pc];
+            pc += 1;
+            match op {
+                Op::Unreachable => return Err("it reached unreachable code"),
+                Op::Jump(to) => pc = to as usize,
+                Op::If(to) => {
+                    if pop!() as u32 == 0 {
+                        pc = to as usize;
+                    }
+                }
+                Op::Br(t) => {
+                    burn!();
+                    branch!(t);
+                }
+                Op::BrIf(t) => {
+                    if pop!() as u32 != 0 {
+                        burn!();
+                        branch!(t);
+                    }
+                }
+                Op::BrTable(table) => {
+                    burn!();
+                    let targets = &f.tables[table as usize];
+                    let i = (pop!() as u32 as usize).min(targets.len() - 1);
+                    let t = targets[i];
+                    if t.to == u32::MAX - 1 {
+                        // Out of the function
+                        pc = f.code.len() - 1;
+                    } else {
+                        branch!(t);
+                    }
+                }
+                Op::Return => ret!(),
+                Op::ReturnIf => {
+                    if pop!() as u32 != 0 {
+                        ret!()
+                    }
+                }
+                Op::Call(callee) => enter!(callee as usize),
+                Op::CallIndirect(ty) => {
+                    let i = pop!() as u32 as usize;
+                    let callee = *m.table.get(i).ok_or("undefined table element")?;
+                    let callee = callee.ok_or("uninitialized table element")? as usize;
+                    let callee_ty = m.funcs.get(callee).map(|c| &m.types[c.ty]);
+                    if callee_ty != Some(&m.types[ty as usize]) {
+                        return Err("indirect call type mismatch");
+                    }
+                    enter!(callee)
+                }
+                Op::Drop => {
+                    pop!();
+                }
+                Op::Select => {
+                    let c = pop!() as u32;
+                    let b = pop!();
+                    let a = pop!();
+                    stack.push(if c != 0 { a } else { b });
+                }
+                Op::LocalGet(i) => stack.push(stack[fp + i as usize]),
+                Op::LocalSet(i) => stack[fp + i as usize] = pop!(),
+                Op::LocalTee(i) => stack[fp + i as usize] = *stack.last().unwrap_or(&0),
+                Op::GlobalGet(i) => stack.push(self.globals[i as usize]),
+                Op::GlobalSet(i) => self.globals[i as usize] = pop!(),
+                Op::Load(op, offset) => {
+                    let addr = pop!();
+                    stack.push(read(&self.memory, op, addr, offset)?);
+                }
+                Op::Store(op, offset) => {
+                    let value = pop!();
+                    let addr = pop!();
+                    write(&mut self.memory, op, addr, offset, value)?;
+                }
+                Op::MemorySize => stack.push((self.memory.len() / PAGE) as u64),
+                Op::MemoryGrow => {
+                    let pages = (self.memory.len() / PAGE) as u64;
+                    let delta = pop!() as u32 as u64;
+                    if pages + delta > self.max_pages as u64 {
+                        stack.push(u32::MAX as u64);
+                    } else {
+                        self.memory.resize((pages + delta) as usize * PAGE, 0);
+                        stack.push(pages);
+                    }
+                }
+                Op::MemoryCopy => {
+                    let n = pop!() as u32 as u64;
+                    let src = bounds(&self.memory, pop!(), 0, n)?;
+                    let dst = bounds(&self.memory, pop!(), 0, n)?;
+                    self.memory.copy_within(src..src + n as usize, dst);
+                }
+                Op::MemoryFill => {
+                    let n = pop!() as u32 as u64;
+                    let value = pop!() as u8;
+                    let dst = bounds(&self.memory, pop!(), 0, n)?;
+                    self.memory[dst..dst + n as usize].fill(value);
+                }
+                Op::Const(v) => stack.push(v),
+                // Float arithmetic is most of what formulas do, so it
+                // skips the general case
+                Op::Binary(op @ 0x92..=0x95) => {
+                    let b = f32::from_bits(pop!() as u32);
+                    let a = f32::from_bits(pop!() as u32);
+                    let r = match op {
+                        0x92 => a + b,
+                        0x93 => a - b,
+                        0x94 => a * b,
+                        _ => a / b,
+                    };
+                    stack.push(r.to_bits() as u64);
+                }
+                Op::Binary(op) => {
+                    let b = pop!();
+                    let a = pop!();
+                    stack.push(numeric(op, a, b)?);
+                }
+                Op::Unary(op) => {
+                    let a = pop!();
+                    stack.push(numeric(op, a, 0)?);
+                }
+                Op::Sat(sub) => {
+                    let a = pop!();
+                    stack.push(saturate(sub, a));
+                }
+            }
+        }
+    }
+}
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Each thread's instance of each plugin, by Plugin::id
+    static INSTANCES: RefCell<Vec<Option<Instance>>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Plugin {
+    name: String,
+    module: Module,
+    id: usize,
+    // As it was after instantiating, copied for each thread and after traps
+    pristine: Instance,
+    io: usize,
+    trapped: AtomicBool,
+}
+
+impl Plugin {
+    fn export(&self, name: &str, params: &[Type], results: &[Type]) -> Result<Option<u32>, String> {
+        let Some(&(_, f)) = self.module.exports.iter().find(|(n, _)| n == name) else {
+            return Ok(None);
+        };
+        let ty = self
+            .module
+            .funcs
+            .get(f as usize)
+            .map(|f| &self.module.types[f.ty])
+            .ok_or_else(|| format!("{} isn't a function", name))?;
+        if ty.params != params || ty.results != results {
+            return Err(format!(
+                "{} should take {} and return {}",
+                name,
+                types(params),
+                types(results)
+            ));
+        }
+        Ok(Some(f))
+    }
+
+    // Calls func in this thread's instance with floats, then reads the io
+    // buffer into out. The first trap is reported and stops the plugin for
+    // good, every call after it is None straight away.
+    fn run(&self, func: u32, args: &[f32], out: &mut [f32]) -> Option<u64> {
+        if self.trapped.load(Ordering::Relaxed) {
+            return None;
+        }
+        INSTANCES.with(|instances| {
+            let mut instances = instances.borrow_mut();
+            if instances.len() <= self.id {
+                instances.resize_with(self.id + 1, || None);
+            }
+            let instance = instances[self.id].get_or_insert_with(|| self.pristine.clone());
+            let mut values = [0u64; 8];
+            for (v, a) in values.iter_mut().zip(args) {
+                *v = a.to_bits() as u64;
+            }
+            match instance.call(&self.module, func, &values[..args.len()]) {
+                Ok(result) => {
+                    for (i, o) in out.iter_mut().enumerate() {
+                        let at = self.io + i * 4;
+                        *o = f32::from_le_bytes(instance.memory[at..at + 4].try_into().unwrap());
+                    }
+                    Some(result.unwrap_or(0))
+                }
+                Err(trap) => {
+                    if !self.trapped.swap(true, Ordering::Relaxed) {
+                        eprintln!("warning: wasm plugin {} stopped: {}", self.name, trap);
+                    }
+                    None
+                }
+            }
+        })
+    }
+
+    fn complex(&self, func: u32, args: &[f32]) -> Complex<f32> {
+        let mut out = [0.0; 2];
+        match self.run(func, args, &mut out) {
+            Some(_) => Complex::new(out[0], out[1]),
+            // Escapes at once, so a broken plugin doesn't run to the limit
+            None => Complex::new(f32::INFINITY, 0.0),
+        }
+    }
+}
+
+fn types(types: &[Type]) -> String {
+    if types.is_empty() {
+        return String::from("nothing");
+    }
+    types
+        .iter()
+        .map(|t| match t {
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::F32 => "f32",
+            Type::F64 => "f64",
+            Type::Ref => "a reference",
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub struct WasmFractal {
+    plugin: Arc<Plugin>,
+    step: u32,
+    deriv: Option<u32>,
+    zderiv: Option<u32>,
+    start: Option<u32>,
+    map: Option<u32>,
+    degree: f32,
+}
+
+impl Fractal for WasmFractal {
+    fn step(&self, z: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        self.plugin.complex(self.step, &[z.re, z.im, c.re, c.im])
+    }
+
+    fn deriv(&self, z: Complex<f32>, dz: Complex<f32>, c: Complex<f32>) -> Complex<f32> {
+        match self.deriv {
+            Some(deriv) => self
+                .plugin
+                .complex(deriv, &[z.re, z.im, dz.re, dz.im, c.re, c.im]),
+            None => fractal::estimate_deriv(self, z, dz, c),
+        }
+    }
+
+    fn zderiv(&self, z: Complex<f32>, dz: Complex<f32>) -> Complex<f32> {
+        match self.zderiv {
+            Some(zderiv) => self.plugin.complex(zderiv, &[z.re, z.im, dz.re, dz.im]),
+            None => fractal::estimate_zderiv(self, z, dz),
+        }
+    }
+
+    fn start(&self, c: Complex<f32>) -> Complex<f32> {
+        self.start
+            .map_or(c, |start| self.plugin.complex(start, &[c.re, c.im]))
+    }
+
+    fn map(&self, c: Complex<f32>) -> Complex<f32> {
+        self.map
+            .map_or(c, |map| self.plugin.complex(map, &[c.re, c.im]))
+    }
+
+    fn degree(&self) -> f32 {
+        self.degree
+    }
+}
+
+pub struct WasmColoring {
+    plugin: Arc<Plugin>,
+    color: u32,
+}
+
+impl Coloring for WasmColoring {
+    fn color(&self, p: &PixelData, limit: f32) -> RgbaF {
+        let mut out = [0.0; 4];
+        let args = [
+            p.iter,
+            p.s,
+            p.smooth,
+            p.z.re,
+            p.z.im,
+            p.de,
+            p.trap,
+            p.converged,
+            limit,
+        ];
+        match self.plugin.run(self.color, &args, &mut out) {
+            Some(_) => RgbaF::new_color_alpha(out[0], out[1], out[2], out[3]),
+            None => RgbaF::new_alpha(0.0, 0.0),
+        }
+    }
+}
+
+// Reads a module and checks its exports, named after the file it came from
+pub fn load(path: &Path) -> Result<(Option<WasmFractal>, Option<WasmColoring>), PluginError> {
+    let bytes = fs::read(path)?;
+    let name = path.file_stem().map_or_else(
+        || String::from("wasm"),
+        |s| s.to_string_lossy().into_owned(),
+    );
+    let module = parse(&bytes).map_err(PluginError::Wasm)?;
+    let pristine = Instance::new(&module).map_err(PluginError::Wasm)?;
+    let mut plugin = Plugin {
+        name,
+        module,
+        id: NEXT.fetch_add(1, Ordering::Relaxed),
+        pristine,
+        io: 0,
+        trapped: AtomicBool::new(false),
+    };
+    use Type::{F32, I32};
+    let export = |plugin: &Plugin, name: &str, params: &[Type], results: &[Type]| {
+        plugin
+            .export(name, params, results)
+            .map_err(PluginError::Wasm)
+    };
+    let version = export(&plugin, "fracmd_version", &[], &[I32])?.ok_or(PluginError::Wasm(
+        String::from("it doesn't export fracmd_version"),
+    ))?;
+    let io = export(&plugin, "fracmd_io", &[], &[I32])?.ok_or(PluginError::Wasm(String::from(
+        "it doesn't export fracmd_io",
+    )))?;
+    let mut instance = plugin.pristine.clone();
+    let call = |instance: &mut Instance, plugin: &Plugin, f: u32| {
+        instance
+            .call(&plugin.module, f, &[])
+            .map_err(|e| PluginError::Wasm(format!("it stopped: {}", e)))
+    };
+    let found = call(&mut instance, &plugin, version)?.unwrap_or(0) as u32;
+    if found != VERSION {
+        return Err(PluginError::Version(found, VERSION));
+    }
+    plugin.io = call(&mut instance, &plugin, io)?.unwrap_or(0) as u32 as usize;
+    if plugin.io + IO * 4 > plugin.pristine.memory.len() {
+        return Err(PluginError::Wasm(String::from(
+            "fracmd_io points outside its memory",
+        )));
+    }
+    let degree = match export(&plugin, "fracmd_degree", &[], &[F32])? {
+        Some(f) => f32::from_bits(call(&mut instance, &plugin, f)?.unwrap_or(0) as u32),
+        None => 2.0,
+    };
+    let step = export(&plugin, "fracmd_step", &[F32; 4], &[])?;
+    let deriv = export(&plugin, "fracmd_deriv", &[F32; 6], &[])?;
+    let zderiv = export(&plugin, "fracmd_zderiv", &[F32; 4], &[])?;
+    let start = export(&plugin, "fracmd_start", &[F32; 2], &[])?;
+    let map = export(&plugin, "fracmd_map", &[F32; 2], &[])?;
+    let color = export(&plugin, "fracmd_color", &[F32; 9], &[])?;
+    let plugin = Arc::new(plugin);
+    let fractal = step.map(|step| WasmFractal {
+        plugin: plugin.clone(),
+        step,
+        deriv,
+        zderiv,
+        start,
+        map,
+        degree,
+    });
+    let coloring = color.map(|color| WasmColoring {
+        plugin: plugin.clone(),
+        color,
+    });
+    Ok((fractal, coloring))
+}
+
+impl WasmFractal {
+    pub fn name(&self) -> &str {
+        &self.plugin.name
+    }
+}
+
+impl WasmColoring {
+    pub fn name(&self) -> &str {
+        &self.plugin.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A module exporting fracmd_version giving version and fracmd_io giving 0
+    fn module(version: u8) -> Vec<u8> {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        bytes.extend([1, 5, 1, 0x60, 0, 1, 0x7f]);
+        bytes.extend([3, 3, 2, 0, 0]);
+        bytes.extend([7, 30, 2, 14]);
+        bytes.extend(b"fracmd_version\0\0\x09fracmd_io\0\x01");
+        bytes.extend([10, 11, 2, 4, 0, 0x41, version, 0x0b, 4, 0, 0x41, 0, 0x0b]);
+        bytes
+    }
+
+    #[test]
+    fn modules_for_another_version_are_refused() {
+        let path = std::env::temp_dir().join(format!("fracmd-v1-{}.wasm", std::process::id()));
+        fs::write(&path, module(1)).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(PluginError::Version(1, VERSION))));
+    }
+
+    #[test]
+    fn instructions_left_out_are_refused_by_name() {
+        let mut bytes = module(VERSION as u8);
+        // fracmd_version's i32.const becomes return_call 0
+        let at = bytes.len() - 8;
+        bytes[at..at + 2].copy_from_slice(&[0x12, 0]);
+        let error = parse(&bytes).unwrap_err();
+        assert_eq!(
+            error,
+            "function 0: instruction 0x12 is from tail calls, which wasm plugins can't use"
+        );
+    }
+
+    #[test]
+    fn truncated_modules_are_refused() {
+        let bytes = module(VERSION as u8);
+        for end in [0, 4, 10, 12, bytes.len() - 1] {
+            assert!(parse(&bytes[..end]).is_err(), "{}", end);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+// fracmd
+// Just enough WebSocket for `fracmd serve` to stream renders to a page
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// RFC 6455 server side, without extensions: the handshake answer, whole
+// frames in and out, and fragmented messages joined up. Browsers mask
+// what they send and servers don't, only text, binary, ping and close
+// come up. SHA-1 is only here because the handshake is defined with it.
+
+use std::io::{self, Read, Write};
+
+use crate::terminal;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Nothing a page sends is anywhere near this
+const MOST: u64 = 1 << 20;
+
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    let mut out = [0; 20];
+    for (bytes, s) in out.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&s.to_be_bytes());
+    }
+    out
+}
+
+// Answers the upgrade request carrying this Sec-WebSocket-Key
+pub fn accept<W: Write>(mut out: W, key: &str) -> io::Result<()> {
+    let accept = terminal::base64(&sha1(format!("{}{}", key.trim(), GUID).as_bytes()));
+    write!(
+        out,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    out.flush()
+}
+
+fn too_big() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "websocket message too big")
+}
+
+fn frame<R: Read>(input: &mut R) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    input.read_exact(&mut head)?;
+    let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0f, head[1] & 0x80 != 0);
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            input.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            input.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MOST {
+        return Err(too_big());
+    }
+    let mut mask = [0; 4];
+    if masked {
+        input.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    input.read_exact(&mut payload)?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((fin, opcode, payload))
+}
+
+// The next whole message, pieces of a fragmented one joined
+pub fn read<R: Read>(input: &mut R) -> io::Result<Message> {
+    let mut message: Option<(u8, Vec<u8>)> = None;
+    loop {
+        let (fin, opcode, payload) = frame(input)?;
+        match opcode {
+            0x8 => return Ok(Message::Close),
+            0x9 => return Ok(Message::Ping(payload)),
+            0xa => return Ok(Message::Pong(payload)),
+            _ => (),
+        }
+        let (opcode, payload) = match (opcode, message.take()) {
+            (0x0, Some((opcode, mut joined))) => {
+                if (joined.len() + payload.len()) as u64 > MOST {
+                    return Err(too_big());
+                }
+                joined.extend(payload);
+                (opcode, joined)
+            }
+            (opcode, _) => (opcode, payload),
+        };
+        if !fin {
+            message = Some((opcode, payload));
+            continue;
+        }
+        return match opcode {
+            0x1 => String::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            _ => Ok(Message::Binary(payload)),
+        };
+    }
+}
+
+pub fn write<W: Write>(mut out: W, message: &Message) -> io::Result<()> {
+    let (opcode, payload) = match message {
+        Message::Text(text) => (0x1, text.as_bytes()),
+        Message::Binary(data) => (0x2, data.as_slice()),
+        Message::Ping(data) => (0x9, data.as_slice()),
+        Message::Pong(data) => (0xa, data.as_slice()),
+        Message::Close => (0x8, &[][..]),
+    };
+    let mut head = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => head.push(len as u8),
+        len @ 126..=0xffff => {
+            head.push(126);
+            head.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            head.push(127);
+            head.extend((len as u64).to_be_bytes());
+        }
+    }
+    out.write_all(&head)?;
+    out.write_all(payload)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A masked frame as a browser sends it
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut out = vec![(fin as u8) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => out.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                out.push(0x80 | 126);
+                out.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(0x80 | 127);
+                out.extend((len as u64).to_be_bytes());
+            }
+        }
+        out.extend(mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    fn text(message: Message) -> String {
+        match message {
+            Message::Text(text) => text,
+            _ => panic!("not a text message"),
+        }
+    }
+
+    #[test]
+    fn accept_answers_the_rfc_example() {
+        let mut out = Vec::new();
+        accept(&mut out, "dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn masked_text_is_read() {
+        let input = masked(true, 0x1, br#"{"zoom": 1.25}"#);
+        assert_eq!(text(read(&mut &input[..]).unwrap()), r#"{"zoom": 1.25}"#);
+    }
+
+    #[test]
+    fn fragments_are_joined() {
+        let mut input = masked(false, 0x1, b"{\"size\": ");
+        input.extend(masked(false, 0x0, b"[8, "));
+        input.extend(masked(true, 0x0, b"6]}"));
+        assert_eq!(text(read(&mut &input[..]).unwrap()), r#"{"size": [8, 6]}"#);
+    }
+
+    #[test]
+    fn written_frames_read_back() {
+        for len in [0, 125, 126, 0x10000] {
+            let mut out = Vec::new();
+            write(&mut out, &Message::Binary(vec![7; len])).unwrap();
+            assert!(
+                matches!(read(&mut &out[..]).unwrap(), Message::Binary(data) if data.len() == len)
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_length_is_refused() {
+        let mut input = vec![0x82, 0x80 | 127];
+        input.extend(u64::MAX.to_be_bytes());
+        let error = read(&mut &input[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_joined_message_is_refused() {
+        let piece = vec![b'x'; MOST as usize / 2 + 1];
+        let mut input = masked(false, 0x1, &piece);
+        input.extend(masked(true, 0x0, &piece));
+        let error = read(&mut &input[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let input = masked(true, 0x1, b"hello");
+        for end in [1, 3, input.len() - 1] {
+            assert!(read(&mut &input[..end]).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_refused() {
+        let input = masked(true, 0x1, &[0xff, 0xfe]);
+        assert!(read(&mut &input[..]).is_err());
+    }
+}
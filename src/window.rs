@@ -0,0 +1,174 @@
+// fracmd
+// The --preview-window window
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A minifb window belongs to the thread that opened it, so the window gets a
+// thread of its own and is sent frames as tiles finish. Closing it or
+// pressing Esc stops the render the way Ctrl-C does, with a checkpoint to
+// --resume from, and it closes by itself once the render's saved.
+
+#[cfg(feature = "window")]
+use minifb::{Key, Window, WindowOptions};
+#[cfg(feature = "window")]
+use std::{
+    sync::mpsc::{self, SyncSender, TryRecvError},
+    thread::{self, JoinHandle},
+};
+
+#[cfg(feature = "window")]
+use crate::scheduler;
+use crate::scheduler::Image16;
+
+// The most of the screen the window takes, bigger renders are shrunk into it
+#[cfg(feature = "window")]
+const MOST: (usize, usize) = (1024, 768);
+// Squares of the checkerboard shown where tiles aren't done yet
+#[cfg(feature = "window")]
+const CHECKER: usize = 8;
+
+#[cfg(feature = "window")]
+pub struct Preview {
+    size: (usize, usize),
+    frames: Option<SyncSender<Vec<u32>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "window")]
+impl Preview {
+    pub fn open(width: i32, height: i32) -> Result<Preview, String> {
+        let (w, h) = fit(width, height);
+        // Only the latest frame matters, one waiting is enough
+        let (frames, received) = mpsc::sync_channel::<Vec<u32>>(1);
+        let (opened, status) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut window = match Window::new("fracmd", w, h, WindowOptions::default()) {
+                Ok(window) => window,
+                Err(e) => return drop(opened.send(Err(e.to_string()))),
+            };
+            let _ = opened.send(Ok(()));
+            window.set_target_fps(30);
+            let mut shown = frame(None, w, h);
+            loop {
+                match received.try_recv() {
+                    Ok(frame) => shown = frame,
+                    Err(TryRecvError::Empty) => (),
+                    Err(TryRecvError::Disconnected) => return,
+                }
+                if !window.is_open() || window.is_key_down(Key::Escape) {
+                    if !scheduler::interrupted() {
+                        eprintln!("\nStopping after the current tiles");
+                        scheduler::interrupt();
+                    }
+                    return;
+                }
+                if window.update_with_buffer(&shown, w, h).is_err() {
+                    return;
+                }
+            }
+        });
+        match status.recv() {
+            Ok(Ok(())) => Ok(Preview {
+                size: (w, h),
+                frames: Some(frames),
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(String::from("the window's thread stopped")),
+        }
+    }
+
+    // Frames are dropped rather than waited for when the window's behind
+    pub fn draw(&self, image: &Image16) {
+        if let Some(frames) = &self.frames {
+            let _ = frames.try_send(frame(Some(image), self.size.0, self.size.1));
+        }
+    }
+}
+
+#[cfg(feature = "window")]
+impl Drop for Preview {
+    fn drop(&mut self) {
+        self.frames.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Built without the window there's nothing to open
+#[cfg(not(feature = "window"))]
+pub struct Preview;
+
+#[cfg(not(feature = "window"))]
+impl Preview {
+    pub fn open(_width: i32, _height: i32) -> Result<Preview, String> {
+        Err(String::from("fracmd was built without the window feature"))
+    }
+
+    pub fn draw(&self, _image: &Image16) {}
+}
+
+// The largest size with the image's aspect inside MOST, never bigger than
+// the image itself
+#[cfg(feature = "window")]
+fn fit(width: i32, height: i32) -> (usize, usize) {
+    let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+    let scale = (MOST.0 as f64 / w).min(MOST.1 as f64 / h).min(1.0);
+    (((w * scale) as usize).max(1), ((h * scale) as usize).max(1))
+}
+
+// 0RGB pixels for minifb, the nearest pixel of the image for each, and a
+// checkerboard where nothing's rendered yet
+#[cfg(feature = "window")]
+fn frame(image: Option<&Image16>, width: usize, height: usize) -> Vec<u32> {
+    let pixel = |x: usize, y: usize| {
+        let image = image?;
+        let (w, h) = (image.width() as usize, image.height() as usize);
+        let p = image.get_pixel(
+            (x * w / width).min(w - 1) as u32,
+            (y * h / height).min(h - 1) as u32,
+        );
+        (p.0[3] > 0).then(|| {
+            let [r, g, b] = [p.0[0], p.0[1], p.0[2]].map(|v| (v >> 8) as u32);
+            r << 16 | g << 8 | b
+        })
+    };
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            pixel(x, y).unwrap_or(match (x / CHECKER + y / CHECKER) % 2 {
+                0 => 0x303030,
+                _ => 0x404040,
+            })
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "window"))]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn frames_shrink_the_render_and_check_what_isnt_done() {
+        assert_eq!(fit(4096, 1024), (1024, 256));
+        assert_eq!(fit(100, 50), (100, 50));
+        let mut image: Image16 = ImageBuffer::new(4, 2);
+        image.put_pixel(0, 0, Rgba([0xffff, 0x8000, 0, 0xffff]));
+        let frame = frame(Some(&image), 2, 1);
+        assert_eq!(frame, [0xff8000, 0x303030]);
+    }
+}
@@ -0,0 +1,278 @@
+// fracmd
+// Interactive setup for `fracmd init`
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Questions go to stderr and only the finished command line to stdout, so
+// `fracmd init > render.sh` works. Every question has a default taken on an
+// empty answer or the end of input, so piping answers in works too.
+
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bookmark, catalog, config, coords, default_functs, hooks, preset,
+    terminal::{self, TextColor},
+    Args, Renderer,
+};
+
+const SIZES: &[(&str, i32, i32)] = &[
+    ("HD, 1920x1080", 1920, 1080),
+    ("4K, 3840x2160", 3840, 2160),
+    ("square, 1080x1080", 1080, 1080),
+    ("phone, 1080x2340", 1080, 2340),
+];
+
+// (label, samples, limit)
+const QUALITIES: &[(&str, usize, f32)] = &[
+    ("draft: 1 sample, 256 iterations", 1, 256.0),
+    ("normal: 4 samples, 512 iterations", 4, 512.0),
+    ("high: 16 samples, 1024 iterations", 16, 1024.0),
+    ("ultra: 64 samples, 4096 iterations", 64, 4096.0),
+];
+
+pub struct Wizard<R: BufRead, W: Write> {
+    input: R,
+    out: W,
+    // Whether a text preview of the view is worth drawing
+    pub preview: bool,
+}
+
+pub struct Answers {
+    pub args: Args,
+    // A palette is applied with `recolor` on the dumped data
+    pub palette: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+}
+
+impl<R: BufRead, W: Write> Wizard<R, W> {
+    pub fn new(input: R, out: W, preview: bool) -> Self {
+        Wizard {
+            input,
+            out,
+            preview,
+        }
+    }
+
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        write!(self.out, "{} [{}]: ", question, default)?;
+        self.out.flush()?;
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        let line = line.trim();
+        Ok(if line.is_empty() { default } else { line }.to_string())
+    }
+
+    fn yes(&mut self, question: &str) -> io::Result<bool> {
+        let answer = self.ask(question, "Y/n")?;
+        Ok(!answer.to_lowercase().starts_with('n'))
+    }
+
+    // A numbered list, answered by number or by the start of a label
+    fn choose(&mut self, question: &str, options: &[String], default: usize) -> io::Result<usize> {
+        writeln!(self.out, "{}", question)?;
+        for (i, option) in options.iter().enumerate() {
+            writeln!(self.out, "{:>3}) {}", i + 1, option)?;
+        }
+        loop {
+            let answer = self.ask("Choice", &(default + 1).to_string())?;
+            let picked = match answer.parse::<usize>() {
+                Ok(n) => (1..=options.len()).contains(&n).then(|| n - 1),
+                Err(_) => options.iter().position(|o| o.starts_with(&answer)),
+            };
+            match picked {
+                Some(i) => return Ok(i),
+                None => writeln!(self.out, "Pick 1 to {}", options.len())?,
+            }
+        }
+    }
+
+    // Asks until the answer parses
+    fn ask_parsed<T, F: Fn(&str) -> Result<T, String>>(
+        &mut self,
+        question: &str,
+        default: &str,
+        parse: F,
+    ) -> io::Result<T> {
+        loop {
+            match parse(&self.ask(question, default)?) {
+                Ok(value) => return Ok(value),
+                Err(e) => writeln!(self.out, "{}", e)?,
+            }
+        }
+    }
+
+    // Origin, zoom and maybe a suggested limit and palette
+    fn location(&mut self) -> io::Result<(Vec<String>, Option<String>)> {
+        let locations = catalog::all();
+        let bookmarks = bookmark::store()
+            .and_then(|path| bookmark::read(&path))
+            .unwrap_or_default();
+        let mut options: Vec<String> = locations
+            .iter()
+            .map(|l| format!("{}, {}", l.name, l.title))
+            .collect();
+        options.extend(bookmarks.iter().map(|b| format!("{}, a bookmark", b.name)));
+        options.push(String::from("somewhere else, give the origin and zoom"));
+        let i = self.choose("Where?", &options, 0)?;
+        if let Some(location) = locations.get(i) {
+            return Ok((location.flags(), location.palette.clone()));
+        }
+        if let Some(mark) = bookmarks.get(i - locations.len()) {
+            let keep = ["origin", "zoom", "rotate", "transform", "limit"];
+            let flags = mark
+                .params
+                .iter()
+                .filter(|(k, _)| keep.contains(&k.as_str()))
+                .map(|(k, v)| config::flag(k, v))
+                .collect();
+            return Ok((flags, mark.palette.clone()));
+        }
+        let origin = self.ask_parsed("Origin", "-0.75+0i", coords::parse_f32)?;
+        let zoom = self.ask_parsed("Zoom, 1 spans 2 across", "0.7", |s| {
+            s.parse::<f32>()
+                .ok()
+                .filter(|z| *z > 0.0 && z.is_finite())
+                .ok_or_else(|| format!("{:?} isn't a zoom above 0", s))
+        })?;
+        Ok((
+            vec![
+                format!("--origin={}", coords::format(origin)),
+                format!("--zoom={}", zoom),
+            ],
+            None,
+        ))
+    }
+
+    fn size(&mut self) -> io::Result<Vec<String>> {
+        let mut options: Vec<String> = SIZES.iter().map(|s| s.0.to_string()).collect();
+        options.push(String::from("other"));
+        let i = self.choose("Image size?", &options, 0)?;
+        let (width, height) = match SIZES.get(i) {
+            Some(&(_, w, h)) => (w, h),
+            None => self.ask_parsed("Width x height", "1920x1080", |s| {
+                let bad = || format!("{:?} isn't a size like 1920x1080", s);
+                let (w, h) = s.split_once(['x', 'X']).ok_or_else(bad)?;
+                let w: i32 = w.trim().parse().map_err(|_| bad())?;
+                let h: i32 = h.trim().parse().map_err(|_| bad())?;
+                (w > 0 && h > 0).then_some((w, h)).ok_or_else(bad)
+            })?,
+        };
+        Ok(vec![
+            format!("--width={}", width),
+            format!("--height={}", height),
+        ])
+    }
+
+    fn quality(&mut self, limit: Option<f32>) -> io::Result<Vec<String>> {
+        let options: Vec<String> = QUALITIES.iter().map(|q| q.0.to_string()).collect();
+        let (_, samples, quality_limit) = QUALITIES[self.choose("Quality?", &options, 1)?];
+        // A location that needs more iterations keeps them
+        let limit = limit.map_or(quality_limit, |l| l.max(quality_limit));
+        Ok(vec![
+            format!("--samples={}", samples),
+            format!("--limit={}", limit),
+        ])
+    }
+
+    fn palette(&mut self, suggested: Option<String>) -> io::Result<Option<PathBuf>> {
+        let mut palettes: Vec<PathBuf> = fs::read_dir("palettes")
+            .map(|dir| {
+                dir.filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().is_some_and(|e| e == "map"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if let Some(suggested) = suggested.map(PathBuf::from) {
+            if !palettes.contains(&suggested) {
+                palettes.push(suggested);
+            }
+        }
+        palettes.sort();
+        let mut options = vec![String::from("the built in hue coloring")];
+        options.extend(palettes.iter().map(|p| p.display().to_string()));
+        let i = self.choose("Colors?", &options, 0)?;
+        Ok(i.checked_sub(1).map(|i| palettes[i].clone()))
+    }
+
+    fn show(&mut self, args: &Args) -> io::Result<()> {
+        let renderer = Renderer::new(args.clone(), default_functs(args));
+        terminal::write_text(&mut self.out, &renderer, 64, 20, TextColor::Truecolor)
+    }
+
+    pub fn run(&mut self) -> io::Result<Answers> {
+        writeln!(
+            self.out,
+            "Setting up a render, press enter to take the [default]. The formula is z^2 + c, the only one built in."
+        )?;
+        let (view, palette) = loop {
+            let (view, palette) = self.location()?;
+            if !self.preview {
+                break (view, palette);
+            }
+            let mut args = Args::from_params(&[], &view);
+            args.samples = 1;
+            self.show(&args)?;
+            if self.yes("Use this view?")? {
+                break (view, palette);
+            }
+        };
+        let stored = Args::from_params(&[], &view);
+        let mut flags = view;
+        flags.extend(self.size()?);
+        let given_limit = flags.iter().any(|f| f.starts_with("--limit="));
+        flags.extend(self.quality(given_limit.then_some(stored.limit))?);
+        let palette = self.palette(palette)?;
+        let name = self.ask("Name for the output", "mandelbrot")?;
+        flags.push(config::flag("name", &name));
+        if palette.is_some() {
+            flags.push(config::flag("dump-data", &format!("{}.fracdata", name)));
+        }
+        let config = match self.ask("Save as a config file, or - to skip", "fracmd.toml")? {
+            path if path == "-" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        Ok(Answers {
+            args: Args::from_params(&[], &flags),
+            palette,
+            config,
+        })
+    }
+}
+
+// Quoted only when the shell would otherwise split or expand it
+pub fn shell_word(word: &str) -> String {
+    if word
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+-_.,:/=".contains(c))
+    {
+        word.to_string()
+    } else {
+        hooks::quote(Path::new(word))
+    }
+}
+
+// Only what differs from a bare run
+pub fn command_line(args: &Args) -> String {
+    let mut line = String::from("fracmd");
+    for (key, value) in preset::changed(args) {
+        line.push(' ');
+        line.push_str(&shell_word(&config::flag(key, &value)));
+    }
+    line
+}
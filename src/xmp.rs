@@ -0,0 +1,311 @@
+// fracmd
+// XMP metadata for the formats without png's text chunks
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// TIFF, JPEG and WebP renders carry an XMP packet: the software and date
+// under xmp:, --creator as dc:creator, and every parameter png keeps in
+// fracmd: text chunks as a fracmd: property, <fracmd:zoom>40</fracmd:zoom>.
+// Asset managers keep XMP they don't understand, though some rewrite
+// simple properties as attributes of rdf:Description, so reading accepts
+// fracmd:zoom="40" too. The packet is stored uncompressed in all three, so
+// reading just looks for it in the file's bytes rather than parsing each
+// container.
+//
+// JPEG gets it as an APP1 segment after the SOI marker, and the ICC
+// profile of a colorspace other than srgb as APP2. WebP gets a VP8X header
+// flagging them, then ICCP, the image and an XMP chunk. TIFF keeps it in
+// tag 700.
+
+use err_derive::Error;
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    ColorType, ImageBuffer, Rgba,
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{colorspace::Colorspace, template, Args};
+
+const NAMESPACE: &str = "https://github.com/kognise/fracgen/ns/xmp/1.0/";
+const OPEN: &str = "<x:xmpmeta";
+const CLOSE: &str = "</x:xmpmeta>";
+// What a JPEG APP1 segment of XMP starts with
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// Largest segment body, less the length and signature
+const JPEG_MOST: usize = 65533;
+
+#[derive(Debug, Error)]
+pub enum XmpError {
+    #[error(display = "io error: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "image error: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "{:?} has no fracmd XMP metadata", _0)]
+    NoParams(PathBuf),
+    #[error(display = "not an encoded {}", _0)]
+    Malformed(&'static str),
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// The whole packet for a render with these parameters
+pub fn packet(args: &Args) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut properties = vec![
+        format!(
+            "<xmp:CreatorTool>fracmd {}</xmp:CreatorTool>",
+            env!("CARGO_PKG_VERSION")
+        ),
+        format!(
+            "<xmp:CreateDate>{}T{}Z</xmp:CreateDate>",
+            template::date(now),
+            template::clock(now)
+        ),
+    ];
+    if let Some(creator) = &args.creator {
+        properties.push(format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            escape(creator)
+        ));
+    }
+    for (key, value) in args.params() {
+        properties.push(format!("<fracmd:{0}>{1}</fracmd:{0}>", key, escape(&value)));
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         {} xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         <rdf:Description rdf:about=\"\"\n \
+         xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n \
+         xmlns:fracmd=\"{}\">\n \
+         {}\n\
+         </rdf:Description>\n\
+         </rdf:RDF>\n\
+         {}\n\
+         <?xpacket end=\"w\"?>",
+        OPEN,
+        NAMESPACE,
+        properties.join("\n "),
+        CLOSE
+    )
+}
+
+// The fracmd: properties of the first packet in bytes, as elements or as
+// attributes
+pub fn parse(bytes: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(bytes);
+    let Some(start) = text.find(OPEN) else {
+        return Vec::new();
+    };
+    let end = text[start..].find(CLOSE).map_or(text.len(), |e| start + e);
+    let packet = &text[start..end];
+    let mut params: Vec<(String, String)> = Vec::new();
+    let mut rest = packet;
+    while let Some(at) = rest.find("fracmd:") {
+        let element = rest[..at].ends_with('<');
+        let after = &rest[at + "fracmd:".len()..];
+        let key: String = after
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        let body = &after[key.len()..];
+        rest = body;
+        let value = if element {
+            let Some(body) = body.strip_prefix('>') else {
+                continue;
+            };
+            let close = format!("</fracmd:{}>", key);
+            body.find(&close).map(|e| &body[..e])
+        } else {
+            body.strip_prefix("=\"")
+                .and_then(|v| v.find('"').map(|e| &v[..e]))
+        };
+        if let (Some(value), false) = (value, key.is_empty()) {
+            if !params.iter().any(|(k, _)| *k == key) {
+                params.push((key, unescape(value)));
+            }
+        }
+    }
+    params
+}
+
+// The formats the parameters are read back from as XMP rather than png
+// text chunks
+pub fn is_xmp(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        ["tif", "tiff", "jpg", "jpeg", "webp"]
+            .iter()
+            .any(|x| e.eq_ignore_ascii_case(x))
+    })
+}
+
+// Returns the (flag, value) pairs a render's XMP holds
+pub fn read_params<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>, XmpError> {
+    let params = parse(&fs::read(&path)?);
+    if params.is_empty() {
+        return Err(XmpError::NoParams(path.as_ref().to_path_buf()));
+    }
+    Ok(params)
+}
+
+fn segment(marker: u8, body: &[&[u8]]) -> Vec<u8> {
+    let len: usize = body.iter().map(|b| b.len()).sum::<usize>() + 2;
+    let mut out = vec![0xff, marker];
+    out.extend((len as u16).to_be_bytes());
+    for part in body {
+        out.extend(*part);
+    }
+    out
+}
+
+// An encoded JPEG with the packet and profile put in after its SOI marker.
+// A packet too long for one segment is left out, extended XMP being more
+// than a render's parameters ever need.
+fn into_jpeg(jpeg: Vec<u8>, args: &Args) -> Result<Vec<u8>, XmpError> {
+    let (soi, image) = match jpeg.get(..2) {
+        Some([0xff, 0xd8]) => jpeg.split_at(2),
+        _ => return Err(XmpError::Malformed("jpeg")),
+    };
+    let mut out = soi.to_vec();
+    let packet = packet(args);
+    if packet.len() + JPEG_XMP.len() <= JPEG_MOST {
+        out.extend(segment(0xe1, &[JPEG_XMP, packet.as_bytes()]));
+    }
+    if args.colorspace != Colorspace::Srgb {
+        let icc = args.colorspace.icc_profile();
+        // One chunk of one, which every profile fracmd has fits in
+        out.extend(segment(0xe2, &[b"ICC_PROFILE\0", &[1, 1], &icc]));
+    }
+    out.extend(image);
+    Ok(out)
+}
+
+fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = fourcc.to_vec();
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+// An encoded simple (lossless) WebP remade as an extended one carrying the
+// packet and profile
+fn into_webp(webp: Vec<u8>, width: u32, height: u32, args: &Args) -> Result<Vec<u8>, XmpError> {
+    if webp.len() < 20 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(XmpError::Malformed("webp"));
+    }
+    let image = &webp[12..];
+    let icc = (args.colorspace != Colorspace::Srgb).then(|| args.colorspace.icc_profile());
+    // Alpha and XMP, and ICC if there's a profile
+    let flags = 0x10 | 0x04 | if icc.is_some() { 0x20 } else { 0 };
+    let mut header = vec![flags, 0, 0, 0];
+    header.extend(&(width - 1).to_le_bytes()[..3]);
+    header.extend(&(height - 1).to_le_bytes()[..3]);
+    let mut body = b"WEBP".to_vec();
+    body.extend(chunk(b"VP8X", &header));
+    if let Some(icc) = &icc {
+        body.extend(chunk(b"ICCP", icc));
+    }
+    body.extend(image);
+    body.extend(chunk(b"XMP ", packet(args).as_bytes()));
+    let mut out = b"RIFF".to_vec();
+    out.extend((body.len() as u32).to_le_bytes());
+    out.extend(body);
+    Ok(out)
+}
+
+// Neither format has 16 bit samples
+fn to_rgba8(image: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> Vec<u8> {
+    image.as_raw().iter().map(|v| (v >> 8) as u8).collect()
+}
+
+// At --quality, with the alpha channel dropped
+pub fn write_jpeg<W: Write>(
+    mut out: W,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+) -> Result<(), XmpError> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, args.quality).encode(
+        &to_rgba8(image),
+        image.width(),
+        image.height(),
+        ColorType::Rgba8,
+    )?;
+    out.write_all(&into_jpeg(jpeg, args)?)?;
+    Ok(())
+}
+
+// Lossless, the only WebP encoder image has without libwebp
+pub fn write_webp<W: Write>(
+    mut out: W,
+    image: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+    args: &Args,
+) -> Result<(), XmpError> {
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp).encode(
+        &to_rgba8(image),
+        image.width(),
+        image.height(),
+        ColorType::Rgba8,
+    )?;
+    out.write_all(&into_webp(webp, image.width(), image.height(), args)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A packet cut short gives back only values it holds in full
+    #[test]
+    fn truncated_packets_lose_only_unfinished_values() {
+        let args = Args::parse_argv(
+            ["fracmd", "--zoom=2", "--name=<a & b>"]
+                .iter()
+                .map(|f| f.to_string()),
+        );
+        let bytes = packet(&args).into_bytes();
+        let full = parse(&bytes);
+        for end in 0..bytes.len() {
+            for param in parse(&bytes[..end]) {
+                assert!(full.contains(&param), "{}: {:?}", end, param);
+            }
+        }
+    }
+}